@@ -0,0 +1,94 @@
+use crate::{AuthResult, Authenticate, Strategy};
+use core::{BoxFuture, Context};
+use http::header::HeaderName;
+use std::{future::Future, sync::Arc};
+use subtle::ConstantTimeEq;
+
+pub struct ApiKeyStrategy<F> {
+    pub(crate) header: HeaderName,
+    pub(crate) query: Option<&'static str>,
+    pub(crate) resolve: F,
+}
+
+pub struct Keys<U> {
+    entries: Vec<(String, U)>,
+}
+
+pub fn api_keys<K, U>(
+    keys: impl IntoIterator<Item = (K, U)>,
+) -> Authenticate<ApiKeyStrategy<impl Fn(String) -> BoxFuture<Option<U>> + Send + Sync + 'static>>
+where
+    K: Into<String>,
+    U: Clone + Send + Sync + 'static,
+{
+    let keys = Arc::new(Keys::new(keys));
+
+    crate::api_key(move |key: String| {
+        let principal = keys.resolve(&key);
+        Box::pin(async { principal }) as BoxFuture<Option<U>>
+    })
+}
+
+fn parse(context: &Context, header: &HeaderName, query: Option<&str>) -> Option<String> {
+    if let Some(value) = context.headers().get(header) {
+        return value.to_str().ok().map(str::to_owned);
+    }
+
+    crate::query(context, query?)
+}
+
+impl<U: Clone> Keys<U> {
+    pub fn new<K: Into<String>>(keys: impl IntoIterator<Item = (K, U)>) -> Self {
+        Keys {
+            entries: keys.into_iter().map(|(k, u)| (k.into(), u)).collect(),
+        }
+    }
+
+    pub fn resolve(&self, key: &str) -> Option<U> {
+        let mut principal = None;
+
+        // Every entry is compared so the time taken doesn't reveal which key matched.
+        for (candidate, value) in &self.entries {
+            if bool::from(candidate.as_bytes().ct_eq(key.as_bytes())) {
+                principal = Some(value.clone());
+            }
+        }
+
+        principal
+    }
+}
+
+impl<F> Authenticate<ApiKeyStrategy<F>>
+where
+    ApiKeyStrategy<F>: Strategy,
+{
+    pub fn header(mut self, name: &'static str) -> Self {
+        self.strategy.header = HeaderName::from_static(name);
+        self
+    }
+
+    pub fn query(mut self, name: &'static str) -> Self {
+        self.strategy.query = Some(name);
+        self
+    }
+}
+
+impl<F, T, U> Strategy for ApiKeyStrategy<F>
+where
+    F: Fn(String) -> T + Send + Sync + 'static,
+    T: Future<Output = Option<U>> + Send + 'static,
+    U: Send + Sync + 'static,
+{
+    type Future = BoxFuture<AuthResult<U>>;
+    type User = U;
+
+    fn authenticate(&self, context: &Context) -> Self::Future {
+        match parse(context, &self.header, self.query) {
+            Some(key) if !key.is_empty() => {
+                let future = (self.resolve)(key);
+                Box::pin(async { Ok(future.await) })
+            }
+            _ => Box::pin(async { Ok(None) }),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{AuthResult, Strategy};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use core::{BoxFuture, Context, Result};
 use http::header::AUTHORIZATION;
 use std::future::Future;
@@ -13,15 +14,21 @@ where
 }
 
 fn decode(encoded: &str) -> Result<String> {
-    Ok(String::from_utf8(base64::decode(encoded)?)?)
+    Ok(String::from_utf8(STANDARD.decode(encoded)?)?)
 }
 
 fn parse(context: &Context) -> Option<(String, String)> {
     let header = context.headers().get(AUTHORIZATION)?.to_str().ok()?;
-    let decoded = decode(header.split_terminator("Basic").nth(1)?.trim()).ok()?;
-    let mut iter = decoded.split_terminator(':');
+    let (scheme, encoded) = header.trim().split_once(' ')?;
 
-    Some((iter.next()?.to_owned(), iter.next()?.to_owned()))
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = decode(encoded.trim()).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+
+    Some((username.to_owned(), password.to_owned()))
 }
 
 impl<F, T, U> Strategy for BasicStrategy<F, T, U>
@@ -40,4 +47,8 @@ where
             Box::pin(async { Ok(None) })
         }
     }
+
+    fn challenge(&self) -> Option<&'static str> {
+        Some("Basic")
+    }
 }
@@ -0,0 +1,42 @@
+use crate::{AuthResult, Strategy};
+use core::{BoxFuture, Context};
+use http::header::AUTHORIZATION;
+use std::future::Future;
+
+pub struct BearerStrategy<F> {
+    pub(crate) verify: F,
+}
+
+fn parse(context: &Context) -> Option<String> {
+    let header = context.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = header.trim().split_once(' ')?;
+    let token = token.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") && !token.is_empty() {
+        Some(token.to_owned())
+    } else {
+        None
+    }
+}
+
+impl<F, T, U> Strategy for BearerStrategy<F>
+where
+    F: Fn(String) -> T + Send + Sync + 'static,
+    T: Future<Output = AuthResult<U>> + Send + 'static,
+    U: Send + Sync + 'static,
+{
+    type Future = BoxFuture<AuthResult<U>>;
+    type User = U;
+
+    fn authenticate(&self, context: &Context) -> Self::Future {
+        if let Some(token) = parse(context) {
+            Box::pin((self.verify)(token))
+        } else {
+            Box::pin(async { Ok(None) })
+        }
+    }
+
+    fn challenge(&self) -> Option<&'static str> {
+        Some("Bearer")
+    }
+}
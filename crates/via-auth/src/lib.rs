@@ -1,19 +1,27 @@
+pub mod api_key;
 pub mod basic;
+pub mod bearer;
 pub mod login;
+pub mod oauth;
 pub mod prelude {
     pub use super::{ContextExt, Session};
 }
 
-use core::{BoxFuture, Context, Error, Middleware, Next, Result};
-use std::future::Future;
+use core::{BoxFuture, Context, Error, Middleware, Next, Respond, Result};
+use percent_encoding::percent_decode_str;
+use std::{any::type_name, future::Future, sync::Arc};
 
-use self::basic::BasicStrategy;
+use self::{api_key::ApiKeyStrategy, basic::BasicStrategy, bearer::BearerStrategy};
 
-type AuthResult<T = CurrentUser> = Result<Option<T>, Error>;
-type CurrentUser = ();
+pub type AuthResult<T> = Result<Option<T>, Error>;
 
 pub trait ContextExt {
-    fn session(&self) -> Result<&Session>;
+    /// Returns the user that was authenticated by an `Authenticate` middleware
+    /// earlier in the stack. Fails with 401 Unauthorized if the request is
+    /// anonymous.
+    fn current_user<U: Send + Sync + 'static>(&self) -> Result<&U>;
+
+    fn session<U: Send + Sync + 'static>(&self) -> Result<&Session<U>>;
 }
 
 pub trait Strategy: Send + Sync + 'static {
@@ -21,32 +29,107 @@ pub trait Strategy: Send + Sync + 'static {
     type User: Send + Sync + 'static;
 
     fn authenticate(&self, context: &Context) -> Self::Future;
+
+    /// The value of the `WWW-Authenticate` header that is sent when a request
+    /// can't be authenticated.
+    fn challenge(&self) -> Option<&'static str> {
+        None
+    }
 }
 
 pub struct Authenticate<T: Strategy> {
+    optional: bool,
     strategy: T,
 }
 
-#[derive(Clone, Default)]
-pub struct Session {
-    user: Option<CurrentUser>,
+/// The user that made the current request, if it was authenticated.
+pub struct Session<U> {
+    user: Option<Arc<U>>,
 }
 
-pub fn basic<F, T, U>(login: F) -> Authenticate<impl Strategy>
+pub fn api_key<F, T, U>(resolve: F) -> Authenticate<ApiKeyStrategy<F>>
+where
+    F: Fn(String) -> T + Send + Sync + 'static,
+    T: Future<Output = Option<U>> + Send + 'static,
+    U: Send + Sync + 'static,
+{
+    Authenticate::new(ApiKeyStrategy {
+        header: http::header::HeaderName::from_static("x-api-key"),
+        query: None,
+        resolve,
+    })
+}
+
+pub fn basic<F, T, U>(login: F) -> Authenticate<impl Strategy<User = U>>
 where
     F: Fn(String, String) -> T + Send + Sync + 'static,
     T: Future<Output = AuthResult<U>> + Send + 'static,
     U: Send + Sync + 'static,
 {
-    Authenticate {
-        strategy: BasicStrategy { login },
+    Authenticate::new(BasicStrategy { login })
+}
+
+/// Authenticates requests with an `Authorization: Bearer <token>` header.
+/// `verify` is called with the raw token and resolves to the user that it
+/// belongs to, or `None` if the token is not valid.
+pub fn bearer<F, T, U>(verify: F) -> Authenticate<BearerStrategy<F>>
+where
+    F: Fn(String) -> T + Send + Sync + 'static,
+    T: Future<Output = AuthResult<U>> + Send + 'static,
+    U: Send + Sync + 'static,
+{
+    Authenticate::new(BearerStrategy { verify })
+}
+
+/// Returns the percent-decoded value of the query param `name`.
+pub(crate) fn query(context: &Context, name: &str) -> Option<String> {
+    context.uri().query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+
+        if key == name {
+            let value = value.replace('+', " ");
+            Some(percent_decode_str(&value).decode_utf8().ok()?.into_owned())
+        } else {
+            None
+        }
+    })
+}
+
+fn unauthorized() -> Error {
+    let message = error::Message {
+        value: "Unauthorized".to_owned(),
+    };
+
+    Error::from(message).status(401)
+}
+
+impl<T: Strategy> Authenticate<T> {
+    pub fn new(strategy: T) -> Self {
+        Authenticate {
+            optional: false,
+            strategy,
+        }
+    }
+
+    /// Continues with an empty session instead of responding with 401 when a
+    /// request can't be authenticated.
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
     }
 }
 
 impl ContextExt for Context {
-    fn session(&self) -> Result<&Session> {
-        match self.get::<Session>() {
-            Err(_) => error::bail!("TODO(@zacharygolba): add error message"),
+    fn current_user<U: Send + Sync + 'static>(&self) -> Result<&U> {
+        self.session::<U>()?.user().ok_or_else(unauthorized)
+    }
+
+    fn session<U: Send + Sync + 'static>(&self) -> Result<&Session<U>> {
+        match self.get::<Session<U>>() {
+            Err(_) => error::bail!(
+                "no session for {}. is an Authenticate middleware included earlier in the stack?",
+                type_name::<U>()
+            ),
             result => result,
         }
     }
@@ -54,27 +137,62 @@ impl ContextExt for Context {
 
 impl<T: Strategy> Middleware for Authenticate<T> {
     fn call(&self, mut context: Context, next: Next) -> BoxFuture<Result> {
+        let challenge = self.strategy.challenge();
         let future = self.strategy.authenticate(&context);
+        let optional = self.optional;
+
+        Box::pin(async move {
+            if let Some(user) = future.await? {
+                context.insert(Session::new(user));
+                return next.call(context).await;
+            }
+
+            if optional {
+                context.insert(Session::<T::User>::empty());
+                return next.call(context).await;
+            }
 
-        context.insert(Session::empty());
-        unimplemented!()
-        // Box::pin(async move {
-        //     if let Some(user) = future.await? {
-        //         context.insert(Session::new(user));
-        //         next.call(context).await
-        //     } else {
-        //         error::status!(401, "Unauthorized")
-        //     }
-        // })
+            match challenge {
+                Some(challenge) => "Unauthorized"
+                    .status(401)
+                    .header("www-authenticate", challenge)
+                    .respond(),
+                None => "Unauthorized".status(401).respond(),
+            }
+        })
     }
 }
 
-impl Session {
+impl<U> Session<U> {
     fn empty() -> Self {
         Session { user: None }
     }
 
-    fn new(user: impl Send + Sync + 'static) -> Self {
-        Session { user: Some(()) }
+    fn new(user: U) -> Self {
+        Session {
+            user: Some(Arc::new(user)),
+        }
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.user.is_some()
+    }
+
+    pub fn user(&self) -> Option<&U> {
+        self.user.as_deref()
+    }
+}
+
+impl<U> Clone for Session<U> {
+    fn clone(&self) -> Self {
+        Session {
+            user: self.user.clone(),
+        }
+    }
+}
+
+impl<U> Default for Session<U> {
+    fn default() -> Self {
+        Session::empty()
     }
 }
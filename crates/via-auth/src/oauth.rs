@@ -0,0 +1,457 @@
+use crate::{query, AuthResult, Authenticate, Session, Strategy};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use core::{error::ResultExt, BoxFuture, Context, Middleware, Next, Respond, Result};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    future::{ready, Future, Ready},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use subtle::ConstantTimeEq;
+
+/// How long a user has to finish signing in with the provider.
+const GRANT_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How long a login lasts by default before the user has to sign in again.
+const SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The number of inserts between sweeps for expired entries.
+const SWEEP_INTERVAL: u32 = 256;
+
+const SESSION_COOKIE: &str = "via-oauth-session";
+const STATE_COOKIE: &str = "via-oauth-state";
+
+pub trait Client: Send + Sync + 'static {
+    fn get(&self, url: &str) -> BoxFuture<Result<Vec<u8>>>;
+    fn post_form(&self, url: &str, form: Vec<(&'static str, String)>)
+        -> BoxFuture<Result<Vec<u8>>>;
+}
+
+/// Stores the grant of each login that is in progress, keyed by its state.
+/// Grants should expire if they aren't taken in time.
+pub trait Store: Send + Sync + 'static {
+    fn insert(&self, state: String, grant: Grant);
+    fn take(&self, state: &str) -> Option<Grant>;
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Grant {
+    pub verifier: String,
+}
+
+pub struct MemoryStore {
+    grants: Mutex<Expiring<Grant>>,
+}
+
+pub struct OAuth2<C, F, U> {
+    inner: Arc<Inner<C, F, U>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Provider {
+    pub authorization_endpoint: String,
+    pub end_session_endpoint: Option<String>,
+    pub issuer: String,
+    pub token_endpoint: String,
+}
+
+/// Authenticates requests with the session cookie that `callback` sets.
+pub struct SessionStrategy<C, F, U> {
+    inner: Arc<Inner<C, F, U>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Tokens {
+    pub access_token: String,
+    pub id_token: Option<String>,
+    pub refresh_token: Option<String>,
+}
+
+struct Expiring<T> {
+    entries: HashMap<String, (Instant, T)>,
+    inserts: u32,
+    ttl: Duration,
+}
+
+struct Inner<C, F, U> {
+    client: C,
+    config: Config,
+    provider: Provider,
+    sessions: Mutex<Expiring<(Tokens, U)>>,
+    store: Box<dyn Store>,
+    verify: F,
+}
+
+fn cookie(context: &Context, name: &str) -> Option<String> {
+    context
+        .headers()
+        .iter()
+        .filter(|(key, _)| *key == http::header::COOKIE)
+        .filter_map(|(_, value)| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            Some(value.to_owned()).filter(|_| key == name)
+        })
+}
+
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, NON_ALPHANUMERIC).to_string()
+}
+
+fn random() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+fn redirect(location: String) -> Result {
+    "".status(302).header("location", location).respond()
+}
+
+fn string(value: &serde_json::Value, key: &str) -> Option<String> {
+    value.get(key)?.as_str().map(str::to_owned)
+}
+
+impl<C, F, T, U> OAuth2<C, F, U>
+where
+    C: Client,
+    F: Fn(String) -> T + Send + Sync + 'static,
+    T: Future<Output = Result<Option<U>>> + Send + 'static,
+    U: Clone + Send + Sync + 'static,
+{
+    pub fn new(provider: Provider, config: Config, client: C, verify: F) -> Self {
+        Self::with_store(provider, config, client, verify, MemoryStore::default())
+    }
+
+    pub fn with_store(
+        provider: Provider,
+        config: Config,
+        client: C,
+        verify: F,
+        store: impl Store,
+    ) -> Self {
+        OAuth2 {
+            inner: Arc::new(Inner {
+                client,
+                config,
+                provider,
+                sessions: Mutex::new(Expiring::new(SESSION_TTL)),
+                store: Box::new(store),
+                verify,
+            }),
+        }
+    }
+
+    /// Authenticates requests with the session that `callback` started when
+    /// the user signed in.
+    pub fn authenticate(&self) -> Authenticate<SessionStrategy<C, F, U>> {
+        Authenticate::new(SessionStrategy {
+            inner: Arc::clone(&self.inner),
+        })
+    }
+
+    /// Exchanges the authorization code for tokens and starts a session for
+    /// the user. The state must match the cookie that `login` set in the same
+    /// browser, so a login can't be forced on someone else.
+    pub fn callback(&self) -> impl Middleware {
+        let inner = Arc::clone(&self.inner);
+
+        move |mut context: Context, next: Next| {
+            let inner = Arc::clone(&inner);
+
+            async move {
+                let clear_state = inner.set_cookie(STATE_COOKIE, "", Duration::ZERO);
+                let grant = match (query(&context, "state"), cookie(&context, STATE_COOKIE)) {
+                    (Some(state), Some(expected))
+                        if bool::from(state.as_bytes().ct_eq(expected.as_bytes())) =>
+                    {
+                        inner.store.take(&state)
+                    }
+                    _ => None,
+                };
+                let (grant, code) = match (grant, query(&context, "code")) {
+                    (Some(grant), Some(code)) => (grant, code),
+                    _ => {
+                        return "Invalid authorization response"
+                            .status(400)
+                            .header("set-cookie", clear_state)
+                            .respond()
+                    }
+                };
+                let tokens = inner.exchange(code, grant).await.status(502)?;
+                let user = match tokens.id_token.clone() {
+                    Some(id_token) => (inner.verify)(id_token).await?,
+                    None => None,
+                };
+                let user = match user {
+                    Some(user) => user,
+                    None => return "Unauthorized".status(401).respond(),
+                };
+                let id = random();
+                let ttl = {
+                    let mut sessions = inner.sessions.lock().unwrap();
+
+                    sessions.insert(id.clone(), (tokens.clone(), user.clone()));
+                    sessions.ttl
+                };
+
+                context.insert(Session::new(user.clone()));
+                context.insert(tokens);
+                context.insert(user);
+
+                next.call(context)
+                    .await
+                    .header("set-cookie", clear_state)
+                    .header("set-cookie", inner.set_cookie(SESSION_COOKIE, &id, ttl))
+                    .respond()
+            }
+        }
+    }
+
+    /// Redirects to the provider to sign in. The state of the request is also
+    /// stored in a cookie that `callback` checks.
+    pub fn login(&self) -> impl Middleware {
+        let inner = Arc::clone(&self.inner);
+
+        move |_: Context, _: Next| {
+            let inner = Arc::clone(&inner);
+
+            async move {
+                let (state, location) = inner.authorization_url();
+
+                redirect(location)
+                    .header(
+                        "set-cookie",
+                        inner.set_cookie(STATE_COOKIE, &state, GRANT_TTL),
+                    )
+                    .respond()
+            }
+        }
+    }
+
+    /// Ends the session of the user and, if the provider supports it,
+    /// redirects to its end session endpoint.
+    pub fn logout(&self) -> impl Middleware {
+        let inner = Arc::clone(&self.inner);
+
+        move |context: Context, next: Next| {
+            let inner = Arc::clone(&inner);
+
+            async move {
+                if let Some(id) = cookie(&context, SESSION_COOKIE) {
+                    inner.sessions.lock().unwrap().remove(&id);
+                }
+
+                let response = next.call(context).await?;
+                let clear_session = inner.set_cookie(SESSION_COOKIE, "", Duration::ZERO);
+
+                match &inner.provider.end_session_endpoint {
+                    Some(endpoint) => redirect(format!(
+                        "{}?client_id={}",
+                        endpoint,
+                        encode(&inner.config.client_id)
+                    )),
+                    None => Ok(response),
+                }
+                .header("set-cookie", clear_session)
+                .respond()
+            }
+        }
+    }
+
+    /// Sets how long a login lasts before the user has to sign in again.
+    /// Defaults to 24 hours.
+    pub fn session_ttl(self, ttl: Duration) -> Self {
+        self.inner.sessions.lock().unwrap().ttl = ttl;
+        self
+    }
+}
+
+impl<T> Expiring<T> {
+    fn new(ttl: Duration) -> Self {
+        Expiring {
+            entries: HashMap::new(),
+            inserts: 0,
+            ttl,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&T> {
+        let (expires_at, value) = self.entries.get(key)?;
+        (*expires_at > Instant::now()).then_some(value)
+    }
+
+    fn insert(&mut self, key: String, value: T) {
+        let now = Instant::now();
+
+        self.inserts = (self.inserts + 1) % SWEEP_INTERVAL;
+
+        if self.inserts == 0 {
+            self.entries.retain(|_, (expires_at, _)| *expires_at > now);
+        }
+
+        self.entries.insert(key, (now + self.ttl, value));
+    }
+
+    fn remove(&mut self, key: &str) -> Option<T> {
+        let (expires_at, value) = self.entries.remove(key)?;
+        (expires_at > Instant::now()).then_some(value)
+    }
+}
+
+impl<C, F, U> Inner<C, F, U>
+where
+    C: Client,
+{
+    fn authorization_url(&self) -> (String, String) {
+        let state = random();
+        let verifier = random();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(&verifier));
+        let Config {
+            client_id,
+            redirect_uri,
+            scopes,
+            ..
+        } = &self.config;
+
+        self.store.insert(state.clone(), Grant { verifier });
+
+        let location = format!(
+            "{}?response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&code_challenge={}&code_challenge_method=S256",
+            self.provider.authorization_endpoint,
+            encode(client_id),
+            encode(redirect_uri),
+            encode(&scopes.join(" ")),
+            state,
+            challenge,
+        );
+
+        (state, location)
+    }
+
+    async fn exchange(&self, code: String, grant: Grant) -> Result<Tokens> {
+        let mut form = vec![
+            ("grant_type", "authorization_code".to_owned()),
+            ("code", code),
+            ("code_verifier", grant.verifier),
+            ("redirect_uri", self.config.redirect_uri.clone()),
+            ("client_id", self.config.client_id.clone()),
+        ];
+
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret.clone()));
+        }
+
+        let body = self
+            .client
+            .post_form(&self.provider.token_endpoint, form)
+            .await?;
+        let value: serde_json::Value = serde_json::from_slice(&body)?;
+
+        match string(&value, "access_token") {
+            Some(access_token) => Ok(Tokens {
+                access_token,
+                id_token: string(&value, "id_token"),
+                refresh_token: string(&value, "refresh_token"),
+            }),
+            None => error::bail!("token response is missing an access_token"),
+        }
+    }
+}
+
+impl<C, F, U> Inner<C, F, U> {
+    /// Cookies are only marked Secure when the app is served over https, so
+    /// that logins still work on localhost.
+    fn set_cookie(&self, name: &str, value: &str, max_age: Duration) -> String {
+        let secure = if self.config.redirect_uri.starts_with("https://") {
+            "; Secure"
+        } else {
+            ""
+        };
+
+        format!(
+            "{}={}; HttpOnly; Max-Age={}; Path=/; SameSite=Lax{}",
+            name,
+            value,
+            max_age.as_secs(),
+            secure
+        )
+    }
+}
+
+impl MemoryStore {
+    /// Creates a store whose grants expire after `ttl`.
+    pub fn new(ttl: Duration) -> Self {
+        MemoryStore {
+            grants: Mutex::new(Expiring::new(ttl)),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new(GRANT_TTL)
+    }
+}
+
+impl Provider {
+    pub async fn discover(issuer: &str, client: &impl Client) -> Result<Provider> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        );
+        let value: serde_json::Value = serde_json::from_slice(&client.get(&url).await?)?;
+
+        match (
+            string(&value, "authorization_endpoint"),
+            string(&value, "token_endpoint"),
+        ) {
+            (Some(authorization_endpoint), Some(token_endpoint)) => Ok(Provider {
+                authorization_endpoint,
+                end_session_endpoint: string(&value, "end_session_endpoint"),
+                issuer: string(&value, "issuer").unwrap_or_else(|| issuer.to_owned()),
+                token_endpoint,
+            }),
+            _ => error::bail!("provider metadata for {} is incomplete", issuer),
+        }
+    }
+}
+
+impl<C, F, T, U> Strategy for SessionStrategy<C, F, U>
+where
+    C: Client,
+    F: Fn(String) -> T + Send + Sync + 'static,
+    T: Future<Output = Result<Option<U>>> + Send + 'static,
+    U: Clone + Send + Sync + 'static,
+{
+    type Future = Ready<AuthResult<U>>;
+    type User = U;
+
+    fn authenticate(&self, context: &Context) -> Self::Future {
+        let user = cookie(context, SESSION_COOKIE).and_then(|id| {
+            let sessions = self.inner.sessions.lock().unwrap();
+            sessions.get(&id).map(|(_, user)| user.clone())
+        });
+
+        ready(Ok(user))
+    }
+}
+
+impl Store for MemoryStore {
+    fn insert(&self, state: String, grant: Grant) {
+        self.grants.lock().unwrap().insert(state, grant);
+    }
+
+    fn take(&self, state: &str) -> Option<Grant> {
+        self.grants.lock().unwrap().remove(state)
+    }
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use core::{Context, Next, Respond};
+use futures::executor::block_on;
+use via_auth::{
+    api_key::{api_keys, Keys},
+    prelude::*,
+    Authenticate, Strategy,
+};
+
+async fn whoami(context: Context, _: Next) -> core::Result {
+    let user = context.current_user::<&'static str>()?;
+    format!("hello, {}", user).respond()
+}
+
+async fn call<T: Strategy>(
+    authenticate: &Authenticate<T>,
+    request: http::request::Builder,
+) -> (u16, String) {
+    let reply = common::call(authenticate, request, whoami).await;
+    (reply.status, reply.body)
+}
+
+#[test]
+fn reads_the_key_from_a_header() {
+    let authenticate = api_keys([("s3cret", "ada")]);
+    let request = || http::Request::get("/");
+
+    assert_eq!(
+        block_on(call(&authenticate, request().header("x-api-key", "s3cret"))),
+        (200, "hello, ada".to_owned())
+    );
+    assert_eq!(
+        block_on(call(&authenticate, request().header("x-api-key", "wrong"))).0,
+        401
+    );
+    assert_eq!(block_on(call(&authenticate, request())).0, 401);
+
+    let authenticate = api_keys([("s3cret", "ada")]).header("x-token");
+
+    assert_eq!(
+        block_on(call(&authenticate, request().header("x-token", "s3cret"))).0,
+        200
+    );
+    assert_eq!(
+        block_on(call(&authenticate, request().header("x-api-key", "s3cret"))).0,
+        401
+    );
+}
+
+#[test]
+fn reads_the_key_from_the_query() {
+    let authenticate = api_keys([("a+b/c d", "ada")]).query("api_key");
+    let get = |uri| block_on(call(&authenticate, http::Request::get(uri))).0;
+
+    assert_eq!(get("/?page=2&api_key=a%2Bb%2Fc+d"), 200);
+    assert_eq!(get("/?api_key=a%2Bb%2Fc%20d"), 200);
+    assert_eq!(get("/?api_key=a+b/c+d"), 401);
+    assert_eq!(get("/?key=a%2Bb%2Fc+d"), 401);
+
+    // The query is only read without the header.
+    let request = http::Request::get("/?api_key=a%2Bb%2Fc+d").header("x-api-key", "wrong");
+    assert_eq!(block_on(call(&authenticate, request)).0, 401);
+
+    // Nor unless it's enabled.
+    let authenticate = api_keys([("a+b/c d", "ada")]);
+    let request = http::Request::get("/?api_key=a%2Bb%2Fc+d");
+    assert_eq!(block_on(call(&authenticate, request)).0, 401);
+}
+
+#[test]
+fn resolves_keys() {
+    let keys = Keys::new([("first", 1), ("second", 2)]);
+
+    assert_eq!(keys.resolve("first"), Some(1));
+    assert_eq!(keys.resolve("second"), Some(2));
+    assert_eq!(keys.resolve("firs"), None);
+    assert_eq!(keys.resolve("first "), None);
+    assert_eq!(keys.resolve(""), None);
+}
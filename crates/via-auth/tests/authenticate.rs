@@ -0,0 +1,71 @@
+mod common;
+
+use core::{BoxFuture, Context, Next, Respond};
+use futures::executor::block_on;
+use via_auth::{prelude::*, AuthResult, Authenticate, Strategy};
+
+#[derive(Debug, PartialEq)]
+struct User {
+    name: String,
+}
+
+/// Authenticates requests that include an `x-user` header.
+struct HeaderStrategy;
+
+impl Strategy for HeaderStrategy {
+    type Future = BoxFuture<AuthResult<User>>;
+    type User = User;
+
+    fn authenticate(&self, context: &Context) -> Self::Future {
+        let name = context
+            .headers()
+            .get("x-user")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        Box::pin(async { Ok(name.map(|name| User { name })) })
+    }
+}
+
+async fn greet(context: Context, _: Next) -> core::Result {
+    match context.session::<User>()?.user() {
+        Some(user) => format!("hello, {}", user.name).respond(),
+        None => "hello, stranger".to_owned().respond(),
+    }
+}
+
+async fn call(authenticate: Authenticate<HeaderStrategy>, user: Option<&str>) -> (u16, String) {
+    let mut request = http::Request::get("/");
+
+    if let Some(name) = user {
+        request = request.header("x-user", name);
+    }
+
+    let reply = common::call(&authenticate, request, greet).await;
+    (reply.status, reply.body)
+}
+
+#[test]
+fn rejects_anonymous_requests() {
+    let authenticate = || Authenticate::new(HeaderStrategy);
+
+    assert_eq!(
+        block_on(call(authenticate(), Some("ada"))),
+        (200, "hello, ada".to_owned())
+    );
+    assert_eq!(block_on(call(authenticate(), None)).0, 401);
+}
+
+#[test]
+fn optional_continues_with_an_empty_session() {
+    let authenticate = || Authenticate::new(HeaderStrategy).optional();
+
+    assert_eq!(
+        block_on(call(authenticate(), Some("ada"))),
+        (200, "hello, ada".to_owned())
+    );
+    assert_eq!(
+        block_on(call(authenticate(), None)),
+        (200, "hello, stranger".to_owned())
+    );
+}
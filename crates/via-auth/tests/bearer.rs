@@ -0,0 +1,109 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+mod common;
+
+use core::{Context, Next, Respond};
+use futures::executor::block_on;
+use via_auth::{basic, bearer, prelude::*, Authenticate, Strategy};
+
+async fn whoami(context: Context, _: Next) -> core::Result {
+    let user = context.current_user::<String>()?;
+    format!("hello, {}", user).respond()
+}
+
+/// Calls `authenticate` with `authorization`, returning the status, the
+/// WWW-Authenticate challenge, and the body of the response.
+async fn call<T: Strategy>(
+    authenticate: &Authenticate<T>,
+    authorization: Option<&str>,
+) -> (u16, Option<String>, String) {
+    let mut request = http::Request::get("/");
+
+    if let Some(value) = authorization {
+        request = request.header("authorization", value);
+    }
+
+    let reply = common::call(authenticate, request, whoami).await;
+    let challenge = reply.header("www-authenticate").pop();
+
+    (reply.status, challenge, reply.body)
+}
+
+fn credentials(username: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        STANDARD.encode(format!("{}:{}", username, password))
+    )
+}
+
+#[test]
+fn bearer_verifies_the_token() {
+    let authenticate =
+        bearer(|token: String| async move { Ok((token == "s3cret").then(|| "ada".to_owned())) });
+
+    assert_eq!(
+        block_on(call(&authenticate, Some("Bearer s3cret"))),
+        (200, None, "hello, ada".to_owned())
+    );
+    assert_eq!(
+        block_on(call(&authenticate, Some("bearer  s3cret "))).0,
+        200
+    );
+
+    for authorization in [Some("Bearer wrong"), None] {
+        let (status, challenge, _) = block_on(call(&authenticate, authorization));
+
+        assert_eq!(status, 401);
+        assert_eq!(challenge.as_deref(), Some("Bearer"));
+    }
+}
+
+#[test]
+fn basic_verifies_the_credentials() {
+    let authenticate = basic(|username: String, password: String| async move {
+        Ok((password == "hunter2").then_some(username))
+    });
+
+    assert_eq!(
+        block_on(call(&authenticate, Some(&credentials("ada", "hunter2")))),
+        (200, None, "hello, ada".to_owned())
+    );
+
+    // The password may contain a colon.
+    let authenticate = basic(|username: String, password: String| async move {
+        Ok((password == "a:b").then_some(username))
+    });
+
+    assert_eq!(
+        block_on(call(&authenticate, Some(&credentials("ada", "a:b")))).0,
+        200
+    );
+
+    let (status, challenge, _) =
+        block_on(call(&authenticate, Some(&credentials("ada", "hunter2"))));
+
+    assert_eq!(status, 401);
+    assert_eq!(challenge.as_deref(), Some("Basic"));
+}
+
+#[test]
+fn rejects_malformed_authorization_headers() {
+    let bearer = bearer(|token: String| async move { Ok(Some(token)) });
+    let basic = basic(|username: String, _: String| async move { Ok(Some(username)) });
+
+    for authorization in [
+        "Bearer",
+        "Bearer ",
+        "Token s3cret",
+        "s3cret",
+        "Basic s3cret",
+    ] {
+        assert_eq!(block_on(call(&bearer, Some(authorization))).0, 401);
+    }
+
+    let no_colon = format!("Basic {}", STANDARD.encode("ada"));
+    let not_utf8 = format!("Basic {}", STANDARD.encode([0xff, b':', b'a']));
+
+    for authorization in ["Basic", "Basic !!!", &no_colon, &not_utf8, "Bearer s3cret"] {
+        assert_eq!(block_on(call(&basic, Some(authorization))).0, 401);
+    }
+}
@@ -0,0 +1,47 @@
+// Each test binary uses a different part of this module.
+#![allow(dead_code)]
+
+use core::{middleware::context::Body, Context, Middleware, Next};
+use http_body_util::BodyExt;
+use std::sync::Arc;
+
+pub struct Reply {
+    pub body: String,
+    pub headers: http::HeaderMap,
+    pub status: u16,
+}
+
+impl Reply {
+    /// Returns every value of the header `name`.
+    pub fn header(&self, name: &str) -> Vec<String> {
+        self.headers
+            .get_all(name)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_owned())
+            .collect()
+    }
+}
+
+/// Calls `middleware` with `request`, followed by `endpoint`. Panics if the
+/// middleware fails rather than responding.
+pub async fn call(
+    middleware: &impl Middleware,
+    request: http::request::Builder,
+    endpoint: impl Middleware,
+) -> Reply {
+    let context = Context::from(request.body(Body::default()).unwrap());
+    let stack: [Arc<dyn Middleware>; 1] = [Arc::new(endpoint)];
+    let response = match middleware.call(context, Next::new(stack.iter())).await {
+        Ok(response) => http::Response::from(response),
+        Err(error) => panic!("{}", error),
+    };
+    let headers = response.headers().clone();
+    let status = response.status().as_u16();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    Reply {
+        body: String::from_utf8_lossy(&body).into_owned(),
+        headers,
+        status,
+    }
+}
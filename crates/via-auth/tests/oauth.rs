@@ -0,0 +1,266 @@
+mod common;
+
+use common::Reply;
+use core::{middleware::context::Body, BoxFuture, Context, Middleware, Next, Respond};
+use futures::executor::block_on;
+use std::{io, time::Duration};
+use via_auth::{
+    oauth::{Client, Config, Grant, MemoryStore, OAuth2, Provider, Store},
+    prelude::*,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+struct User {
+    name: String,
+}
+
+/// A provider that issues an id token naming whoever holds the code.
+struct FakeClient;
+
+impl Client for FakeClient {
+    fn get(&self, _: &str) -> BoxFuture<core::Result<Vec<u8>>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    fn post_form(
+        &self,
+        _: &str,
+        form: Vec<(&'static str, String)>,
+    ) -> BoxFuture<core::Result<Vec<u8>>> {
+        let code = form
+            .into_iter()
+            .find_map(|(name, value)| (name == "code").then_some(value))
+            .unwrap();
+        let body = format!(r#"{{"access_token":"token","id_token":"{}"}}"#, code);
+
+        Box::pin(async move {
+            if code == "unreachable" {
+                return Err(io::Error::other("connection refused").into());
+            }
+
+            Ok(body.into_bytes())
+        })
+    }
+}
+
+fn oauth() -> OAuth2<
+    FakeClient,
+    impl Fn(String) -> BoxFuture<core::Result<Option<User>>> + Send + Sync + 'static,
+    User,
+> {
+    let provider = Provider {
+        authorization_endpoint: "https://id.example.com/authorize".to_owned(),
+        end_session_endpoint: None,
+        issuer: "https://id.example.com".to_owned(),
+        token_endpoint: "https://id.example.com/token".to_owned(),
+    };
+    let config = Config {
+        client_id: "app".to_owned(),
+        client_secret: None,
+        redirect_uri: "https://app.example.com/callback".to_owned(),
+        scopes: vec!["openid".to_owned()],
+    };
+
+    OAuth2::new(provider, config, FakeClient, |name: String| {
+        Box::pin(async move {
+            if name == "broken" {
+                return Err(io::Error::other("the keys can't be fetched").into());
+            }
+
+            Ok(Some(User { name }))
+        }) as BoxFuture<_>
+    })
+}
+
+async fn whoami(context: Context, _: Next) -> core::Result {
+    match context
+        .session::<User>()
+        .ok()
+        .and_then(|session| session.user())
+    {
+        Some(user) => format!("hello, {}", user.name).respond(),
+        None => "hello, stranger".to_owned().respond(),
+    }
+}
+
+async fn call(middleware: &impl Middleware, uri: &str, cookie: Option<&str>) -> Reply {
+    let mut request = http::Request::get(uri);
+
+    if let Some(cookie) = cookie {
+        request = request.header("cookie", cookie);
+    }
+
+    common::call(middleware, request, whoami).await
+}
+
+/// Returns the `name=value` pair of the cookie that `reply` sets.
+fn cookie(reply: &Reply, name: &str) -> String {
+    let prefix = format!("{}=", name);
+    let cookie = reply
+        .header("set-cookie")
+        .into_iter()
+        .find(|c| c.starts_with(&prefix))
+        .unwrap();
+
+    cookie.split(';').next().unwrap().to_owned()
+}
+
+fn state(reply: &Reply) -> String {
+    let location = reply.header("location").pop().unwrap();
+    let (_, state) = location.split_once("state=").unwrap();
+
+    state.split('&').next().unwrap().to_owned()
+}
+
+#[test]
+fn login_binds_the_state_to_a_cookie() {
+    let oauth = oauth();
+    let reply = block_on(call(&oauth.login(), "/login", None));
+    let state_cookie = reply
+        .header("set-cookie")
+        .into_iter()
+        .find(|c| c.starts_with("via-oauth-state="))
+        .unwrap();
+    let location = reply.header("location").pop().unwrap();
+
+    assert_eq!(reply.status, 302);
+    assert!(location.starts_with("https://id.example.com/authorize?response_type=code"));
+    assert!(location.contains("code_challenge="));
+    assert!(state_cookie.starts_with(&format!("via-oauth-state={};", state(&reply))));
+    assert!(state_cookie.contains("HttpOnly"));
+    assert!(state_cookie.contains("Secure"));
+}
+
+#[test]
+fn callback_starts_a_session() {
+    let oauth = oauth();
+    let login = block_on(call(&oauth.login(), "/login", None));
+    let uri = format!("/callback?state={}&code=ada", state(&login));
+    let callback = block_on(call(
+        &oauth.callback(),
+        &uri,
+        Some(&cookie(&login, "via-oauth-state")),
+    ));
+    let session = cookie(&callback, "via-oauth-session");
+
+    assert_eq!(callback.status, 200);
+    assert_eq!(callback.body, "hello, ada");
+    assert_eq!(cookie(&callback, "via-oauth-state"), "via-oauth-state=");
+
+    let reply = block_on(call(&oauth.authenticate(), "/", Some(&session)));
+    assert_eq!((reply.status, reply.body.as_str()), (200, "hello, ada"));
+
+    // The state can only be used once.
+    let replay = block_on(call(
+        &oauth.callback(),
+        &uri,
+        Some(&cookie(&login, "via-oauth-state")),
+    ));
+    assert_eq!(replay.status, 400);
+}
+
+#[test]
+fn callback_propagates_errors() {
+    let oauth = oauth();
+
+    for (code, status, message) in [
+        ("unreachable", 502, "connection refused"),
+        ("broken", 500, "the keys can't be fetched"),
+    ] {
+        let login = block_on(call(&oauth.login(), "/login", None));
+        let uri = format!("/callback?state={}&code={}", state(&login), code);
+        let request = http::Request::get(uri)
+            .header("cookie", cookie(&login, "via-oauth-state"))
+            .body(Body::default())
+            .unwrap();
+        let callback = oauth.callback();
+        let error = block_on(callback.call(Context::from(request), Next::new([].iter())))
+            .err()
+            .unwrap();
+
+        assert_eq!(error.to_string(), message);
+        assert_eq!(
+            http::Response::from(core::Response::from(error)).status(),
+            status
+        );
+    }
+}
+
+#[test]
+fn callback_rejects_a_state_from_another_browser() {
+    let oauth = oauth();
+    let victim = block_on(call(&oauth.login(), "/login", None));
+    let attacker = block_on(call(&oauth.login(), "/login", None));
+    let uri = format!("/callback?state={}&code=mallory", state(&attacker));
+
+    let reply = block_on(call(&oauth.callback(), &uri, None));
+    assert_eq!(reply.status, 400);
+
+    let reply = block_on(call(
+        &oauth.callback(),
+        &uri,
+        Some(&cookie(&victim, "via-oauth-state")),
+    ));
+    assert_eq!(reply.status, 400);
+    assert!(reply
+        .header("set-cookie")
+        .into_iter()
+        .all(|c| !c.starts_with("via-oauth-session")));
+}
+
+#[test]
+fn logout_ends_the_session() {
+    let oauth = oauth();
+    let login = block_on(call(&oauth.login(), "/login", None));
+    let uri = format!("/callback?state={}&code=ada", state(&login));
+    let callback = block_on(call(
+        &oauth.callback(),
+        &uri,
+        Some(&cookie(&login, "via-oauth-state")),
+    ));
+    let session = cookie(&callback, "via-oauth-session");
+
+    let reply = block_on(call(&oauth.logout(), "/logout", Some(&session)));
+    assert_eq!(cookie(&reply, "via-oauth-session"), "via-oauth-session=");
+    assert!(reply.header("set-cookie")[0].contains("Max-Age=0"));
+
+    let reply = block_on(call(&oauth.authenticate(), "/", Some(&session)));
+    assert_eq!(reply.status, 401);
+}
+
+#[test]
+fn sessions_expire() {
+    let oauth = oauth().session_ttl(Duration::ZERO);
+    let login = block_on(call(&oauth.login(), "/login", None));
+    let uri = format!("/callback?state={}&code=ada", state(&login));
+    let callback = block_on(call(
+        &oauth.callback(),
+        &uri,
+        Some(&cookie(&login, "via-oauth-state")),
+    ));
+    let session = cookie(&callback, "via-oauth-session");
+
+    assert!(callback
+        .header("set-cookie")
+        .iter()
+        .any(|c| c.starts_with("via-oauth-session=") && c.contains("Max-Age=0")));
+
+    let reply = block_on(call(&oauth.authenticate(), "/", Some(&session)));
+    assert_eq!(reply.status, 401);
+}
+
+#[test]
+fn grants_expire() {
+    let grant = || Grant {
+        verifier: "verifier".to_owned(),
+    };
+
+    let store = MemoryStore::default();
+    store.insert("state".to_owned(), grant());
+    assert!(store.take("state").is_some());
+    assert!(store.take("state").is_none());
+
+    let store = MemoryStore::new(Duration::ZERO);
+    store.insert("state".to_owned(), grant());
+    assert!(store.take("state").is_none());
+}
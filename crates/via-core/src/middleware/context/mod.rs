@@ -9,7 +9,7 @@ use serde::de::DeserializeOwned;
 use std::io::Read;
 use std::{
     fmt::{self, Debug, Formatter},
-    mem::replace,
+    mem::take,
     str::FromStr,
     // task::{self, Poll},
 };
@@ -133,7 +133,7 @@ impl Context {
     }
 
     pub fn read(&mut self) -> Body {
-        replace(self.request.body_mut(), Body::empty())
+        take(self.request.body_mut())
     }
 
     pub fn uri(&self) -> &Uri {
@@ -156,6 +156,13 @@ impl Context {
     }
 }
 
+#[doc(hidden)]
+impl Default for Body {
+    fn default() -> Self {
+        Body::empty()
+    }
+}
+
 #[doc(hidden)]
 impl From<Request> for Context {
     fn from(request: Request) -> Self {
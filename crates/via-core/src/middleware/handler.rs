@@ -25,7 +25,8 @@ where
 }
 
 impl Next {
-    pub(crate) fn new<'a>(stack: impl Iterator<Item = &'a DynMiddleware>) -> Self {
+    #[doc(hidden)]
+    pub fn new<'a>(stack: impl Iterator<Item = &'a DynMiddleware>) -> Self {
         Next {
             stack: stack.cloned().collect(),
         }
@@ -0,0 +1,259 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::iter::Step;
+
+/// The most shards that a cache is split into. Each shard has its own lock,
+/// so concurrent requests for different paths rarely wait on each other.
+const MAX_SHARDS: usize = 16;
+
+/// The fewest paths that a shard holds. Small caches use a single shard so
+/// that eviction stays strictly least recently used.
+const MIN_SHARD_CAPACITY: usize = 64;
+
+/// Marks the end of the recency list of a shard.
+const NIL: usize = usize::MAX;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub evictions: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A handle to the counters of a route cache that stays valid after the
+/// router is moved, e.g. into a server.
+#[derive(Clone, Debug, Default)]
+pub struct CacheMonitor {
+    counters: Option<Arc<Counters>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Cache {
+    capacity: usize,
+    counters: Arc<Counters>,
+    hasher: RandomState,
+    shards: Box<[Mutex<Shard>]>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    evictions: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// A least recently used map from paths to steps. Entries are kept in a
+/// doubly linked list of slot indices, so both hits and evictions are O(1).
+#[derive(Debug)]
+struct Shard {
+    capacity: usize,
+    head: usize,
+    map: HashMap<Box<str>, usize>,
+    slots: Vec<Slot>,
+    tail: usize,
+}
+
+#[derive(Debug)]
+struct Slot {
+    key: Box<str>,
+    next: usize,
+    prev: usize,
+    steps: Arc<[Step]>,
+}
+
+impl Cache {
+    pub(crate) fn new(capacity: usize) -> Cache {
+        Cache::with_counters(capacity, Default::default())
+    }
+
+    /// Creates an empty cache that keeps counting with `counters`, so that
+    /// monitors of a replaced cache stay current.
+    pub(crate) fn with_counters(capacity: usize, counters: Arc<Counters>) -> Cache {
+        let count = (capacity / MIN_SHARD_CAPACITY).clamp(1, MAX_SHARDS);
+        let shards = (0..count).map(|index| {
+            // Spread the remainder so the total capacity is exact.
+            let capacity = capacity / count + usize::from(index < capacity % count);
+            Mutex::new(Shard::new(capacity))
+        });
+
+        Cache {
+            capacity,
+            counters,
+            hasher: RandomState::new(),
+            shards: shards.collect(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for shard in self.shards.iter_mut() {
+            shard.get_mut().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+
+    pub(crate) fn counters(&self) -> Arc<Counters> {
+        Arc::clone(&self.counters)
+    }
+
+    /// Returns the steps of `path`, resolving them without holding the lock
+    /// of its shard on a miss.
+    pub(crate) fn get_or_insert(
+        &self,
+        path: &str,
+        resolve: impl FnOnce() -> Arc<[Step]>,
+    ) -> Arc<[Step]> {
+        let index = self.hasher.hash_one(path) as usize % self.shards.len();
+        let shard = &self.shards[index];
+        let hit = shard.lock().unwrap_or_else(|e| e.into_inner()).get(path);
+
+        if let Some(steps) = hit {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+            return steps;
+        }
+
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+
+        let steps = resolve();
+        let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+
+        if shard.insert(path, Arc::clone(&steps)) {
+            self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        steps
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.counters.stats()
+    }
+}
+
+impl Clone for Cache {
+    fn clone(&self) -> Cache {
+        Cache::new(self.capacity)
+    }
+}
+
+impl CacheMonitor {
+    pub(crate) fn new(counters: Option<Arc<Counters>>) -> Self {
+        CacheMonitor { counters }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        match &self.counters {
+            Some(counters) => counters.stats(),
+            None => CacheStats::default(),
+        }
+    }
+}
+
+impl Counters {
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            evictions: self.evictions.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Shard {
+    fn new(capacity: usize) -> Self {
+        Shard {
+            capacity,
+            head: NIL,
+            map: HashMap::new(),
+            slots: Vec::new(),
+            tail: NIL,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.head = NIL;
+        self.map.clear();
+        self.slots.clear();
+        self.tail = NIL;
+    }
+
+    fn get(&mut self, path: &str) -> Option<Arc<[Step]>> {
+        let index = *self.map.get(path)?;
+
+        self.unlink(index);
+        self.push_front(index);
+        Some(Arc::clone(&self.slots[index].steps))
+    }
+
+    /// Inserts `steps` as the most recently used entry. Returns true if the
+    /// least recently used entry was evicted to make room.
+    fn insert(&mut self, path: &str, steps: Arc<[Step]>) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+
+        // Another request may have resolved the same path while the lock
+        // wasn't held.
+        if let Some(&index) = self.map.get(path) {
+            self.slots[index].steps = steps;
+            self.unlink(index);
+            self.push_front(index);
+            return false;
+        }
+
+        let slot = Slot {
+            key: path.into(),
+            next: NIL,
+            prev: NIL,
+            steps,
+        };
+
+        if self.slots.len() < self.capacity {
+            let index = self.slots.len();
+
+            self.map.insert(slot.key.clone(), index);
+            self.slots.push(slot);
+            self.push_front(index);
+            return false;
+        }
+
+        let index = self.tail;
+
+        self.unlink(index);
+        self.map.remove(&self.slots[index].key);
+        self.map.insert(slot.key.clone(), index);
+        self.slots[index] = slot;
+        self.push_front(index);
+        true
+    }
+
+    fn push_front(&mut self, index: usize) {
+        self.slots[index].prev = NIL;
+        self.slots[index].next = self.head;
+
+        match self.head {
+            NIL => self.tail = index,
+            head => self.slots[head].prev = index,
+        }
+
+        self.head = index;
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let Slot { next, prev, .. } = self.slots[index];
+
+        match prev {
+            NIL => self.head = next,
+            prev => self.slots[prev].next = next,
+        }
+
+        match next {
+            NIL => self.tail = prev,
+            next => self.slots[next].prev = prev,
+        }
+    }
+}
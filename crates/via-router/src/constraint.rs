@@ -0,0 +1,80 @@
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Clone, Debug)]
+pub enum Constraint {
+    I64,
+    U64,
+    Uuid,
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+fn is_uuid(value: &str) -> bool {
+    value.len() == 36
+        && value.char_indices().all(|(index, c)| match index {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+impl Constraint {
+    pub fn parse(source: &str) -> Constraint {
+        match source {
+            "i64" => Constraint::I64,
+            "u64" => Constraint::U64,
+            "uuid" => Constraint::Uuid,
+            #[cfg(feature = "regex")]
+            _ => match regex::Regex::new(&format!("^(?:{})$", source)) {
+                Ok(regex) => Constraint::Regex(regex),
+                Err(error) => panic!("invalid route constraint <{}>: {}", source, error),
+            },
+            #[cfg(not(feature = "regex"))]
+            _ => panic!(
+                "unknown route constraint <{}>. regex constraints require the `regex` feature",
+                source
+            ),
+        }
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            Constraint::I64 => value.parse::<i64>().is_ok(),
+            Constraint::U64 => value.parse::<u64>().is_ok(),
+            Constraint::Uuid => is_uuid(value),
+            #[cfg(feature = "regex")]
+            Constraint::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+impl Display for Constraint {
+    /// Writes the source of the constraint as it appears between the angle
+    /// brackets of a route.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Constraint::I64 => f.write_str("i64"),
+            Constraint::U64 => f.write_str("u64"),
+            Constraint::Uuid => f.write_str("uuid"),
+            #[cfg(feature = "regex")]
+            Constraint::Regex(regex) => {
+                let source = regex.as_str();
+                f.write_str(&source["^(?:".len()..source.len() - ")$".len()])
+            }
+        }
+    }
+}
+
+impl Eq for Constraint {}
+
+impl PartialEq for Constraint {
+    fn eq(&self, other: &Constraint) -> bool {
+        match (self, other) {
+            (Constraint::I64, Constraint::I64)
+            | (Constraint::U64, Constraint::U64)
+            | (Constraint::Uuid, Constraint::Uuid) => true,
+            #[cfg(feature = "regex")]
+            (Constraint::Regex(a), Constraint::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
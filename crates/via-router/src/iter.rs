@@ -1,9 +1,18 @@
+#[cfg(feature = "lru-cache")]
+use std::sync::Arc;
 use std::{iter::Peekable, ops::Deref, str::CharIndices};
 
-use crate::node::{Node, Pattern};
+#[cfg(feature = "lru-cache")]
+use crate::cache::Cache;
+
+use crate::{
+    constraint::Constraint,
+    node::{self, Node, Pattern},
+};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Component<'a, 'b, T> {
+    pub constraint: Option<&'a Constraint>,
     pub is_exact_match: bool,
     pub pattern: Pattern,
     pub param: Option<(&'static str, &'b str)>,
@@ -13,11 +22,28 @@ pub struct Component<'a, 'b, T> {
 #[derive(Debug)]
 pub struct Visit<'a, 'b, T> {
     node: &'a Node<T>,
-    path: Path<'b>,
     root: bool,
+    steps: Steps<'b>,
+    value: &'b str,
+}
+
+/// The position of a matched node among its siblings along with the byte
+/// range of its param in the visited path.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Step {
+    index: usize,
+    is_exact_match: bool,
+    param: Option<(usize, usize)>,
 }
 
 #[derive(Debug)]
+enum Steps<'a> {
+    Live(Path<'a>),
+    #[cfg(feature = "lru-cache")]
+    Cached(Arc<[Step]>, usize),
+}
+
+#[derive(Clone, Debug)]
 pub(crate) struct Path<'a> {
     iter: Peekable<CharIndices<'a>>,
     next: Option<(usize, &'a str)>,
@@ -27,6 +53,7 @@ pub(crate) struct Path<'a> {
 impl<'a, 'b, T> Component<'a, 'b, T> {
     pub(crate) fn root(route: &'a T, is_exact_match: bool) -> Component<'a, 'b, T> {
         Component {
+            constraint: None,
             pattern: Pattern::Root,
             param: None,
             is_exact_match,
@@ -62,8 +89,54 @@ impl<'a> Path<'a> {
         }
     }
 
-    pub fn slice(&self, from: usize) -> &'a str {
-        &self.value[from..]
+    /// Finds the longest suffix of the remaining segments that matches a path
+    /// below `node`, leaving at least one segment for the wildcard. Returns the
+    /// number of segments that precede the suffix and the offset it starts at.
+    fn split<T: Default>(&self, node: &Node<T>) -> Option<(usize, usize)> {
+        let rest: Vec<_> = self.clone().collect();
+
+        (1..=rest.len()).rev().find_map(|len| {
+            let skip = rest.len() - len;
+            let mut target = node;
+
+            for (_, value) in &rest[skip..] {
+                target = target.find(value)?;
+            }
+
+            Some((skip, rest[skip].0))
+        })
+    }
+
+    fn step<T: Default>(&mut self, node: &Node<T>) -> Option<Step> {
+        let (start, value) = self.next()?;
+        let index = node.position(value)?;
+        let next = &node.entries[index];
+
+        if let Pattern::CatchAll(_) = next.pattern {
+            // An interior wildcard stops short of the segments that match the
+            // rest of the pattern.
+            if let Some((skip, end)) = self.split(next) {
+                for _ in 0..skip {
+                    self.next();
+                }
+
+                return Some(Step {
+                    index,
+                    is_exact_match: false,
+                    param: Some((start, end)),
+                });
+            }
+        }
+
+        Some(Step {
+            index,
+            is_exact_match: self.peek().is_none(),
+            param: match next.pattern {
+                Pattern::CatchAll(_) => Some((start, self.value.len())),
+                Pattern::Dynamic(_) => Some((start + 1, start + 1 + value.len())),
+                _ => None,
+            },
+        })
     }
 
     fn advance(&mut self) -> Option<(usize, &'a str)> {
@@ -89,8 +162,8 @@ impl<'a> Path<'a> {
 }
 
 impl Path<'static> {
-    pub fn segments(source: &'static str) -> impl Iterator<Item = Pattern> {
-        Path::parse(source).map(|(_, segment)| segment.into())
+    pub fn segments(source: &'static str) -> impl Iterator<Item = (Pattern, Option<Constraint>)> {
+        Path::parse(source).map(|(_, segment)| node::parse(segment))
     }
 }
 
@@ -102,18 +175,38 @@ impl<'a> Iterator for Path<'a> {
     }
 }
 
-impl<'a> PartialEq<&'_ str> for Path<'a> {
-    fn eq(&self, other: &'_ &str) -> bool {
-        self.value == *other
-    }
-}
-
 impl<'a, 'b, T: Default> Visit<'a, 'b, T> {
     pub fn root(node: &'a Node<T>, path: &'b str) -> Self {
         Visit {
             node,
-            path: Path::parse(path),
             root: true,
+            steps: Steps::Live(Path::parse(path)),
+            value: path,
+        }
+    }
+
+    /// Replays the steps previously taken for `path`, resolving and storing
+    /// them in `cache` first if necessary.
+    #[cfg(feature = "lru-cache")]
+    pub(crate) fn cached(node: &'a Node<T>, path: &'b str, cache: &Cache) -> Self {
+        let steps = cache.get_or_insert(path, || {
+            let mut steps = Vec::new();
+            let mut target = node;
+            let mut segments = Path::parse(path);
+
+            while let Some(step) = segments.step(target) {
+                target = &target.entries[step.index];
+                steps.push(step);
+            }
+
+            steps.into()
+        });
+
+        Visit {
+            node,
+            root: true,
+            steps: Steps::Cached(steps, 0),
+            value: path,
         }
     }
 }
@@ -124,21 +217,30 @@ impl<'a, 'b, T: Default> Iterator for Visit<'a, 'b, T> {
     fn next(&mut self) -> Option<Self::Item> {
         if self.root {
             self.root = false;
-            return Some(Component::root(&self.node.route, self.path == "/"));
+            return Some(Component::root(&self.node.route, self.value == "/"));
         }
 
-        let Visit { node, path, .. } = self;
-        let (start, value) = path.next()?;
-        let next = node.find(value)?;
+        let step = match &mut self.steps {
+            Steps::Live(path) => path.step(self.node)?,
+            #[cfg(feature = "lru-cache")]
+            Steps::Cached(steps, offset) => {
+                let step = *steps.get(*offset)?;
+                *offset += 1;
+                step
+            }
+        };
+        let next = &self.node.entries[step.index];
 
-        *node = next;
+        self.node = next;
 
         Some(Component {
-            is_exact_match: path.peek().is_none(),
+            constraint: next.constraint.as_ref(),
+            is_exact_match: step.is_exact_match,
             pattern: next.pattern,
-            param: match next.pattern {
-                Pattern::CatchAll(name) => Some((name, path.slice(start))),
-                Pattern::Dynamic(name) => Some((name, value)),
+            param: match (next.pattern, step.param) {
+                (Pattern::CatchAll(name) | Pattern::Dynamic(name), Some((start, end))) => {
+                    Some((name, &self.value[start..end]))
+                }
                 _ => None,
             },
             route: &next.route,
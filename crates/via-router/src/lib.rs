@@ -1,3 +1,6 @@
+#[cfg(feature = "lru-cache")]
+mod cache;
+mod constraint;
 mod iter;
 mod node;
 mod verb;
@@ -6,21 +9,34 @@ use std::ops::{Deref, DerefMut};
 
 use crate::{iter::*, node::*};
 
+#[cfg(feature = "lru-cache")]
+pub use cache::{CacheMonitor, CacheStats};
+pub use constraint::Constraint;
 pub use iter::{Component, Visit};
-pub use node::Pattern;
+pub use node::{Pattern, Segment};
 pub use verb::Verb;
 
 #[derive(Debug)]
 pub struct Location<'a, T>(&'a mut Node<T>);
 
 #[derive(Clone, Debug, Default)]
-pub struct Router<T>(Node<T>);
+pub struct Router<T> {
+    #[cfg(feature = "lru-cache")]
+    cache: Option<cache::Cache>,
+    root: Node<T>,
+}
 
 impl<'a, T: Default> Location<'a, T> {
-    pub fn at(&mut self, path: &'static str) -> Location<T> {
+    pub fn at(&mut self, path: &'static str) -> Location<'_, T> {
         let mut segments = Path::segments(path);
         Location(self.0.insert(&mut segments))
     }
+
+    /// Grafts the route tree of `router` onto this location. Routes that exist
+    /// in both trees are combined with `merge`.
+    pub fn mount(&mut self, router: Router<T>, mut merge: impl FnMut(&mut T, T)) {
+        self.0.merge(router.root, &mut merge);
+    }
 }
 
 impl<'a, T: Default> Deref for Location<'a, T> {
@@ -42,13 +58,76 @@ impl<T: Default> Router<T> {
         Default::default()
     }
 
-    pub fn at(&mut self, path: &'static str) -> Location<T> {
+    /// Creates a router that remembers how the `capacity` most recently
+    /// visited paths were resolved.
+    #[cfg(feature = "lru-cache")]
+    pub fn with_cache_capacity(capacity: usize) -> Router<T> {
+        let mut router = Self::new();
+
+        router.set_cache_capacity(capacity);
+        router
+    }
+
+    pub fn at(&mut self, path: &'static str) -> Location<'_, T> {
         let mut segments = Path::segments(path);
-        Location(self.0.insert(&mut segments))
+
+        #[cfg(feature = "lru-cache")]
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+
+        Location(self.root.insert(&mut segments))
+    }
+
+    /// Returns a handle to the counters of the cache that keeps working after
+    /// the router is moved.
+    #[cfg(feature = "lru-cache")]
+    pub fn cache_monitor(&self) -> CacheMonitor {
+        CacheMonitor::new(self.cache.as_ref().map(cache::Cache::counters))
+    }
+
+    #[cfg(feature = "lru-cache")]
+    pub fn cache_stats(&self) -> CacheStats {
+        match &self.cache {
+            Some(cache) => cache.stats(),
+            None => CacheStats::default(),
+        }
+    }
+
+    /// Replaces the cache with an empty one that holds up to `capacity`
+    /// paths. A capacity of zero disables caching. The counters of the
+    /// previous cache carry over, so monitors stay current.
+    #[cfg(feature = "lru-cache")]
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        let counters = self.cache.take().map(|cache| cache.counters());
+
+        self.cache = (capacity > 0)
+            .then(|| cache::Cache::with_counters(capacity, counters.unwrap_or_default()));
+    }
+
+    /// Returns pairs of paths where the first can never be visited because the
+    /// second is always matched instead.
+    pub fn shadowed(&self) -> Vec<(Vec<Segment<'_>>, Vec<Segment<'_>>)> {
+        let mut output = Vec::new();
+
+        self.root.shadowed(&mut Vec::new(), &mut output);
+        output
+    }
+
+    pub fn routes(&self) -> Vec<(Vec<Segment<'_>>, &T)> {
+        let mut routes = Vec::new();
+
+        self.root.walk(&mut Vec::new(), &mut routes);
+        routes
     }
 
     pub fn visit<'a, 'b>(&'a self, path: &'b str) -> Visit<'a, 'b, T> {
-        Visit::root(&self.0, path)
+        #[cfg(feature = "lru-cache")]
+        if let Some(cache) = &self.cache {
+            return Visit::cached(&self.root, path, cache);
+        }
+
+        Visit::root(&self.root, path)
     }
 }
 
@@ -56,13 +135,13 @@ impl<T: Default> Deref for Router<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.0.route
+        &self.root.route
     }
 }
 
 impl<T: Default> DerefMut for Router<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0.route
+        &mut self.root.route
     }
 }
 
@@ -70,6 +149,8 @@ impl<T: Default> DerefMut for Router<T> {
 mod tests {
     use std::cmp::PartialEq;
 
+    use super::Pattern;
+
     type Router = super::Router<Path>;
 
     #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
@@ -111,4 +192,200 @@ mod tests {
         assert!(visit!(router, "/echo/hello/world") == "/echo/*path");
         assert!(visit!(router, "/articles/100/comments") == "/articles/:id/comments");
     }
+
+    #[test]
+    fn constraints() {
+        let mut router = Router::default();
+
+        at!(router, "/users/:name");
+        at!(router, "/users/:id<u64>");
+        at!(router, "/users/:id<u64>/posts");
+        at!(router, "/keys/:id<uuid>");
+        at!(router, "/keys/*rest");
+        at!(router, "/offsets/:n<i64>");
+
+        assert!(visit!(router, "/users/42") == "/users/:id<u64>");
+        assert!(visit!(router, "/users/avatar.png") == "/users/:name");
+        assert!(visit!(router, "/users/-1") == "/users/:name");
+        assert!(visit!(router, "/users/42/posts") == "/users/:id<u64>/posts");
+        assert!(visit!(router, "/keys/67e55044-10b1-426f-9247-bb680e5fe0c8") == "/keys/:id<uuid>");
+        assert!(visit!(router, "/keys/not-a-uuid") == "/keys/*rest");
+        assert!(visit!(router, "/offsets/-1") == "/offsets/:n<i64>");
+        assert_eq!(router.visit("/offsets/x").count(), 2);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex_constraints() {
+        let mut router = Router::default();
+
+        at!(router, "/files/:name<[a-z0-9_-]+>");
+        at!(router, "/files/*path");
+
+        assert!(visit!(router, "/files/read_me-1") == "/files/:name<[a-z0-9_-]+>");
+        assert!(visit!(router, "/files/README") == "/files/*path");
+
+        let (segments, _) = &router.routes()[2];
+        assert_eq!(segments[1].1.unwrap().to_string(), "[a-z0-9_-]+");
+    }
+
+    #[test]
+    fn shadowed() {
+        let mut router = Router::default();
+
+        at!(router, "/users/new");
+        at!(router, "/users/:id");
+        at!(router, "/users/:slug/posts");
+        at!(router, "/users/*rest");
+        at!(router, "/posts/:id<u64>");
+        at!(router, "/posts/:slug");
+
+        let shadowed: Vec<_> = router
+            .shadowed()
+            .into_iter()
+            .map(|(shadowed, by)| (shadowed[1].0, by[1].0))
+            .collect();
+
+        assert_eq!(
+            shadowed,
+            [
+                (Pattern::Dynamic("slug"), Pattern::Dynamic("id")),
+                (Pattern::CatchAll("rest"), Pattern::Dynamic("id")),
+            ]
+        );
+    }
+
+    #[test]
+    fn interior_wildcard() {
+        let mut router = Router::default();
+
+        at!(router, "/files/*path/preview");
+        at!(router, "/files/*path/raw/preview");
+        at!(router, "/files/*path");
+
+        let param = |path| {
+            router
+                .visit(path)
+                .find_map(|component| component.param)
+                .unwrap()
+        };
+
+        assert!(visit!(router, "/files/a/preview") == "/files/*path/preview");
+        assert!(visit!(router, "/files/a/b%20c/preview") == "/files/*path/preview");
+        assert!(visit!(router, "/files/a/raw/preview") == "/files/*path/raw/preview");
+        assert!(visit!(router, "/files/a/b") == "/files/*path");
+        assert!(visit!(router, "/files/preview") == "/files/*path");
+        assert!(visit!(router, "/files/raw/preview") == "/files/*path/preview");
+
+        assert_eq!(param("/files/a/preview"), ("path", "/a"));
+        assert_eq!(param("/files/a/b%20c/preview"), ("path", "/a/b%20c"));
+        assert_eq!(
+            param("/files/a/preview/b/preview"),
+            ("path", "/a/preview/b")
+        );
+        assert_eq!(param("/files/a/raw/preview"), ("path", "/a"));
+    }
+
+    #[test]
+    fn interior_wildcard_requires_middle() {
+        let mut router = Router::default();
+
+        at!(router, "/files/*path/preview");
+
+        assert!(visit!(router, "/files/preview") != "/files/*path/preview");
+        assert_eq!(router.visit("/files").count(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "only contain one wildcard")]
+    fn multiple_wildcards() {
+        Router::default().at("/files/*path/raw/*rest");
+    }
+
+    #[cfg(feature = "lru-cache")]
+    #[test]
+    fn cache_eviction() {
+        use super::CacheStats;
+
+        let mut router = Router::with_cache_capacity(2);
+
+        at!(router, "/users/:id");
+        at!(router, "/files/*path/preview");
+
+        assert!(visit!(router, "/users/1") == "/users/:id");
+        assert!(visit!(router, "/users/1") == "/users/:id");
+        assert!(visit!(router, "/files/a/b/preview") == "/files/*path/preview");
+        assert!(visit!(router, "/users/1") == "/users/:id");
+
+        // "/files/a/b/preview" is now the least recently used path.
+        assert!(visit!(router, "/users/2") == "/users/:id");
+        assert!(visit!(router, "/users/1") == "/users/:id");
+        assert!(visit!(router, "/files/a/b/preview") == "/files/*path/preview");
+
+        assert_eq!(
+            router
+                .visit("/files/a/b/preview")
+                .find_map(|component| component.param),
+            Some(("path", "/a/b"))
+        );
+        assert_eq!(
+            router.cache_stats(),
+            CacheStats {
+                evictions: 2,
+                hits: 4,
+                misses: 4,
+            }
+        );
+
+        at!(router, "/users/:id/posts");
+        assert!(visit!(router, "/users/1/posts") == "/users/:id/posts");
+        assert!(visit!(router, "/users/1") == "/users/:id");
+        assert_eq!(router.cache_stats().misses, 6);
+    }
+
+    #[cfg(feature = "lru-cache")]
+    #[test]
+    fn cache_monitor_outlives_the_router() {
+        use super::CacheStats;
+
+        let mut router = Router::with_cache_capacity(256);
+        let monitor = router.cache_monitor();
+
+        at!(router, "/users/:id");
+        router.set_cache_capacity(512);
+
+        let router = std::thread::spawn(move || {
+            for id in 0..1000 {
+                assert!(visit!(router, &format!("/users/{}", id)) == "/users/:id");
+            }
+
+            assert!(visit!(router, "/users/999") == "/users/:id");
+            router
+        })
+        .join()
+        .unwrap();
+
+        drop(router);
+        assert_eq!(
+            monitor.stats(),
+            CacheStats {
+                evictions: 488,
+                hits: 1,
+                misses: 1000,
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "unclosed constraint")]
+    fn unclosed_constraint() {
+        Router::default().at("/users/:id<u64");
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown route constraint")]
+    #[cfg(not(feature = "regex"))]
+    fn unknown_constraint() {
+        Router::default().at("/users/:id<slug>");
+    }
 }
@@ -1,15 +1,21 @@
 use smallvec::SmallVec;
-use std::cmp::{Ordering, PartialOrd};
+use std::cmp::{Ord, Ordering, PartialOrd};
+
+use crate::constraint::Constraint;
 
 #[derive(Clone, Debug)]
 pub struct Node<T> {
+    pub(crate) constraint: Option<Constraint>,
     pub(crate) entries: SmallVec<[Box<Self>; 4]>,
     pub(crate) pattern: Pattern,
     pub(crate) route: T,
 }
 
+/// A segment of a route along with the constraint on its param, if any.
+pub type Segment<'a> = (Pattern, Option<&'a Constraint>);
+
 #[non_exhaustive]
-#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Pattern {
     CatchAll(&'static str),
     Dynamic(&'static str),
@@ -19,44 +25,145 @@ pub enum Pattern {
 
 impl<T: Default> Node<T> {
     pub fn find(&self, path: &str) -> Option<&Self> {
-        self.entries.iter().find_map(|node| {
-            if node.pattern == *path {
-                Some(&**node)
-            } else {
-                None
-            }
+        self.position(path).map(|index| &*self.entries[index])
+    }
+
+    pub fn position(&self, path: &str) -> Option<usize> {
+        self.entries.iter().position(|node| {
+            let satisfied = match &node.constraint {
+                Some(constraint) => constraint.matches(path),
+                None => true,
+            };
+
+            satisfied && node.pattern == *path
         })
     }
 
-    pub fn index(&self, pattern: Pattern) -> Option<usize> {
-        self.entries.iter().position(|node| pattern == node.pattern)
+    pub fn merge<F>(&mut self, other: Node<T>, merge: &mut F)
+    where
+        F: FnMut(&mut T, T),
+    {
+        merge(&mut self.route, other.route);
+
+        for entry in other.entries {
+            match self.index(entry.pattern, &entry.constraint) {
+                Some(index) => self.entries[index].merge(*entry, merge),
+                None => {
+                    insert_node(self, entry);
+                }
+            }
+        }
+    }
+
+    pub fn shadowed<'a>(
+        &'a self,
+        path: &mut Vec<Segment<'a>>,
+        output: &mut Vec<(Vec<Segment<'a>>, Vec<Segment<'a>>)>,
+    ) {
+        let mut greedy: Option<Segment> = None;
+
+        if self.pattern != Pattern::Root {
+            path.push(self.segment());
+        }
+
+        for entry in &self.entries {
+            let catches_all = match entry.pattern {
+                Pattern::CatchAll(_) => true,
+                Pattern::Dynamic(_) => entry.constraint.is_none(),
+                _ => false,
+            };
+
+            // The visitor never backtracks, so once an unconstrained param or a
+            // wildcard matches a segment, later siblings are unreachable.
+            match greedy {
+                Some(segment) if catches_all || matches!(entry.pattern, Pattern::Dynamic(_)) => {
+                    let mut by = path.clone();
+                    let mut shadowed = path.clone();
+
+                    by.push(segment);
+                    shadowed.push(entry.segment());
+                    output.push((shadowed, by));
+                }
+                None if catches_all => greedy = Some(entry.segment()),
+                _ => {}
+            }
+
+            entry.shadowed(path, output);
+        }
+
+        if self.pattern != Pattern::Root {
+            path.pop();
+        }
+    }
+
+    pub fn walk<'a>(
+        &'a self,
+        path: &mut Vec<Segment<'a>>,
+        routes: &mut Vec<(Vec<Segment<'a>>, &'a T)>,
+    ) {
+        if self.pattern != Pattern::Root {
+            path.push(self.segment());
+        }
+
+        routes.push((path.clone(), &self.route));
+
+        for entry in &self.entries {
+            entry.walk(path, routes);
+        }
+
+        if self.pattern != Pattern::Root {
+            path.pop();
+        }
+    }
+
+    pub fn segment(&self) -> Segment<'_> {
+        (self.pattern, self.constraint.as_ref())
+    }
+
+    pub fn index(&self, pattern: Pattern, constraint: &Option<Constraint>) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|node| pattern == node.pattern && *constraint == node.constraint)
     }
 
     pub fn insert<I>(&mut self, segments: &mut I) -> &mut Self
     where
-        I: Iterator<Item = Pattern>,
+        I: Iterator<Item = (Pattern, Option<Constraint>)>,
     {
-        if let Pattern::CatchAll(_) = self.pattern {
-            return self;
-        }
+        let wildcard = matches!(self.pattern, Pattern::CatchAll(_));
+        self.insert_after(segments, wildcard)
+    }
 
-        let label = match segments.next() {
+    fn insert_after<I>(&mut self, segments: &mut I, wildcard: bool) -> &mut Self
+    where
+        I: Iterator<Item = (Pattern, Option<Constraint>)>,
+    {
+        let (label, constraint) = match segments.next() {
             Some(value) => value,
             None => return self,
         };
 
-        let index = match self.index(label) {
+        if let (true, Pattern::CatchAll(name)) = (wildcard, label) {
+            panic!(
+                r#"a route may only contain one wildcard, found a second "*{}""#,
+                name
+            );
+        }
+
+        let index = match self.index(label, &constraint) {
             Some(value) => value,
-            None => insert1(self, label),
+            None => insert_pattern(self, label, constraint),
         };
+        let wildcard = wildcard || matches!(label, Pattern::CatchAll(_));
 
-        self.entries[index].insert(segments)
+        self.entries[index].insert_after(segments, wildcard)
     }
 }
 
 impl<T: Default> Default for Node<T> {
     fn default() -> Node<T> {
         Node {
+            constraint: None,
             entries: SmallVec::new(),
             pattern: Pattern::Root,
             route: Default::default(),
@@ -64,6 +171,27 @@ impl<T: Default> Default for Node<T> {
     }
 }
 
+/// Parses a path segment such as `:id<u64>` into a pattern and an optional
+/// constraint. Panics if the constraint syntax is invalid.
+pub(crate) fn parse(segment: &'static str) -> (Pattern, Option<Constraint>) {
+    let start = match segment.find('<') {
+        Some(start) => start,
+        None => return (segment.into(), None),
+    };
+
+    if !segment.starts_with(':') {
+        panic!(
+            r#"constraints are only allowed on dynamic segments, found "{}""#,
+            segment
+        );
+    }
+
+    match segment[start + 1..].strip_suffix('>') {
+        Some(source) => (segment[..start].into(), Some(Constraint::parse(source))),
+        None => panic!(r#"unclosed constraint in segment "{}""#, segment),
+    }
+}
+
 impl From<&'static str> for Pattern {
     fn from(value: &'static str) -> Pattern {
         match value.chars().next() {
@@ -84,9 +212,9 @@ impl PartialEq<str> for Pattern {
     }
 }
 
-impl PartialOrd for Pattern {
-    fn partial_cmp(&self, other: &Pattern) -> Option<Ordering> {
-        Some(match self {
+impl Ord for Pattern {
+    fn cmp(&self, other: &Pattern) -> Ordering {
+        match self {
             Pattern::CatchAll(_) => match other {
                 Pattern::CatchAll(_) | Pattern::Root => Ordering::Equal,
                 _ => Ordering::Greater,
@@ -97,38 +225,58 @@ impl PartialOrd for Pattern {
                 Pattern::Static(_) => Ordering::Greater,
             },
             Pattern::Static(a) => match other {
-                Pattern::Static(b) => a.partial_cmp(b)?,
+                Pattern::Static(b) => a.cmp(b),
                 _ => Ordering::Less,
             },
             Pattern::Root => match other {
                 Pattern::CatchAll(_) | Pattern::Root => Ordering::Equal,
                 _ => Ordering::Greater,
             },
-        })
+        }
     }
 }
 
-fn insert1<T: Default>(node: &mut Node<T>, pattern: Pattern) -> usize {
-    let mut offset = 0;
-
-    for (index, entry) in node.entries.iter().enumerate() {
-        offset = match entry.pattern {
-            Pattern::Static(_) => index + 1,
-            _ => index,
-        };
+impl PartialOrd for Pattern {
+    fn partial_cmp(&self, other: &Pattern) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
-        if pattern < entry.pattern {
-            break;
-        }
+fn rank(pattern: Pattern, constraint: &Option<Constraint>) -> u8 {
+    match pattern {
+        Pattern::Static(_) => 0,
+        Pattern::Dynamic(_) if constraint.is_some() => 1,
+        Pattern::Dynamic(_) => 2,
+        Pattern::CatchAll(_) => 3,
+        Pattern::Root => 4,
     }
+}
 
-    node.entries.insert(
-        offset,
+fn insert_pattern<T: Default>(
+    node: &mut Node<T>,
+    pattern: Pattern,
+    constraint: Option<Constraint>,
+) -> usize {
+    insert_node(
+        node,
         Box::new(Node {
+            constraint,
             pattern,
             ..Default::default()
         }),
-    );
+    )
+}
+
+fn insert_node<T>(node: &mut Node<T>, entry: Box<Node<T>>) -> usize {
+    // Constrained params are tried before unconstrained ones so that a failed
+    // constraint can fall through to a sibling.
+    let rank = rank(entry.pattern, &entry.constraint);
+    let offset = node
+        .entries
+        .iter()
+        .position(|other| self::rank(other.pattern, &other.constraint) > rank)
+        .unwrap_or(node.entries.len());
 
+    node.entries.insert(offset, entry);
     offset
 }
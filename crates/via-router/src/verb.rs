@@ -1,7 +1,7 @@
 use http::method::Method;
-use std::ops::BitOr;
+use std::ops::{BitAnd, BitOr};
 
-#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Verb(u16);
 
 impl Verb {
@@ -28,6 +28,14 @@ impl Verb {
     }
 }
 
+impl BitAnd for Verb {
+    type Output = Verb;
+
+    fn bitand(self, other: Verb) -> Self::Output {
+        Verb(self.0 & other.0)
+    }
+}
+
 impl BitOr for Verb {
     type Output = Verb;
 
@@ -5,10 +5,11 @@ mod database;
 mod services;
 
 use services::ApiService;
+use std::process::ExitCode;
 use via::prelude::*;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     dotenvy::dotenv()?;
 
     let mut app = via::new();
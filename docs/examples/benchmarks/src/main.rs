@@ -1,7 +1,8 @@
+use std::process::ExitCode;
 use via::prelude::*;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let mut app = via::new();
 
     app.at("/text").get(|_, _| async { "Hello, world!" });
@@ -1,3 +1,4 @@
+use std::process::ExitCode;
 use via::prelude::*;
 
 struct Routes;
@@ -39,7 +40,7 @@ async fn logger(context: Context, next: Next) -> Result<impl Respond> {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let mut app = via::new();
 
     app.include(logger);
@@ -0,0 +1,173 @@
+use futures::{Future, Stream, StreamExt};
+use std::{
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    time::Duration,
+};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{Events, ServerEvent, TcpOptions};
+
+const MIN_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A source of connections. Implemented for `TcpListener` and by listeners
+/// that inject errors in tests.
+pub(crate) trait Listener: Send + Sync + 'static {
+    fn accept(&self) -> impl Future<Output = io::Result<(TcpStream, SocketAddr)>> + Send;
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> impl Future<Output = io::Result<(TcpStream, SocketAddr)>> + Send {
+        TcpListener::accept(self)
+    }
+}
+
+/// Accepts connections from each of `listeners` as they arrive. Transient
+/// errors, such as running out of file descriptors, are retried with an
+/// exponential backoff and reported to `events`. Any other error is yielded
+/// and should stop the server.
+pub(crate) fn incoming<L: Listener>(
+    listeners: Vec<L>,
+    options: TcpOptions,
+    events: Events,
+) -> impl Stream<Item = io::Result<(TcpStream, SocketAddr)>> + Unpin {
+    let accepted = futures::stream::select_all(listeners.into_iter().map(|listener| {
+        let events = events.clone();
+
+        Box::pin(futures::stream::unfold(listener, move |listener| {
+            let events = events.clone();
+
+            async move {
+                let accepted = accept(&listener, &events).await;
+                Some((accepted, listener))
+            }
+        }))
+    }));
+
+    accepted.inspect(move |accepted| {
+        if let Ok((stream, remote_addr)) = accepted {
+            if let Err(error) = options.accepted(stream) {
+                events.report(ServerEvent::ConnectionOptionsFailed {
+                    error,
+                    remote_addr: *remote_addr,
+                });
+            }
+        }
+    })
+}
+
+async fn accept(listener: &impl Listener, events: &Events) -> io::Result<(TcpStream, SocketAddr)> {
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        match listener.accept().await {
+            Err(error) if is_transient(&error) => {
+                events.report(ServerEvent::AcceptRetried { backoff, error });
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    if let Some(libc::EMFILE | libc::ENFILE) = error.raw_os_error() {
+        return true;
+    }
+
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use futures::StreamExt;
+    use std::{
+        collections::VecDeque,
+        io::{self, ErrorKind},
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{incoming, Listener};
+    use crate::{Events, ServerEvent, TcpOptions};
+
+    /// Fails with each of its errors in turn before accepting connections.
+    struct Failing {
+        errors: Mutex<VecDeque<io::Error>>,
+        listener: TcpListener,
+    }
+
+    impl Failing {
+        async fn new(errors: impl IntoIterator<Item = io::Error>) -> (Self, SocketAddr) {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let errors = Mutex::new(errors.into_iter().collect());
+
+            (Failing { errors, listener }, address)
+        }
+    }
+
+    impl Listener for Failing {
+        async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+            let error = self.errors.lock().unwrap().pop_front();
+
+            match error {
+                Some(error) => Err(error),
+                None => self.listener.accept().await,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors_with_a_backoff() {
+        let (listener, address) = Failing::new([
+            io::Error::from_raw_os_error(libc::EMFILE),
+            io::Error::from(ErrorKind::ConnectionAborted),
+            io::Error::from_raw_os_error(libc::ENFILE),
+        ])
+        .await;
+        let backoffs = Arc::new(Mutex::new(Vec::new()));
+        let events = Events::default();
+
+        events.set({
+            let backoffs = Arc::clone(&backoffs);
+
+            move |event| {
+                if let ServerEvent::AcceptRetried { backoff, .. } = event {
+                    backoffs.lock().unwrap().push(*backoff);
+                }
+            }
+        });
+
+        let mut incoming = incoming(vec![listener], TcpOptions::new(), events);
+        let started = Instant::now();
+        let _client = TcpStream::connect(address).await.unwrap();
+
+        assert!(incoming.next().await.unwrap().is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(70));
+        assert_eq!(
+            *backoffs.lock().unwrap(),
+            [10, 20, 40].map(Duration::from_millis)
+        );
+    }
+
+    #[tokio::test]
+    async fn yields_fatal_errors() {
+        let (listener, _) = Failing::new([io::Error::from_raw_os_error(libc::EBADF)]).await;
+        let mut incoming = incoming(vec![listener], TcpOptions::new(), Events::default());
+        let error = incoming.next().await.unwrap().unwrap_err();
+
+        assert_eq!(error.raw_os_error(), Some(libc::EBADF));
+    }
+}
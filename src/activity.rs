@@ -0,0 +1,342 @@
+use std::{
+    io::{self, IoSlice},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::Notify,
+    time::Instant,
+};
+
+/// Tracks what a connection is doing so that it can be closed when a client
+/// stalls. A connection is idle between requests, reading a head from the
+/// first byte of a request until it is handled, and busy while a request is
+/// handled.
+pub(crate) struct Activity {
+    changed: Notify,
+    state: Mutex<(State, Instant)>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Expired {
+    Head,
+    Idle,
+    Lifetime,
+}
+
+/// The timeouts applied to a connection. `None` disables a timeout.
+#[derive(Clone, Copy)]
+pub(crate) struct Timeouts {
+    pub(crate) header_read: Option<Duration>,
+    pub(crate) idle: Option<Duration>,
+    pub(crate) lifetime: Option<Duration>,
+    #[cfg(feature = "rustls")]
+    pub(crate) tls_handshake: Duration,
+}
+
+/// Marks the connection busy until it is dropped.
+pub(crate) struct Busy(Arc<Activity>);
+
+/// Wraps the IO of a connection to record when it is read from or written
+/// to.
+pub(crate) struct Tracked<T> {
+    activity: Arc<Activity>,
+    io: T,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Busy(usize),
+    Head,
+    Idle,
+}
+
+impl Activity {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Activity {
+            changed: Notify::new(),
+            state: Mutex::new((State::Idle, Instant::now())),
+        })
+    }
+
+    pub(crate) fn busy(self: &Arc<Self>) -> Busy {
+        self.update(|state| match state {
+            State::Busy(count) => State::Busy(count + 1),
+            State::Head | State::Idle => State::Busy(1),
+        });
+
+        Busy(Arc::clone(self))
+    }
+
+    pub(crate) fn track<T>(self: &Arc<Self>, io: T) -> Tracked<T> {
+        Tracked {
+            activity: Arc::clone(self),
+            io,
+        }
+    }
+
+    /// Resolves with the timeout that expired first. A connection opened at
+    /// `opened_at` is never closed while it is busy unless its lifetime is
+    /// over.
+    pub(crate) async fn expired(&self, timeouts: Timeouts, opened_at: Instant) -> Expired {
+        let lifetime = timeouts
+            .lifetime
+            .map(|lifetime| (opened_at + lifetime, Expired::Lifetime));
+
+        loop {
+            let changed = self.changed.notified();
+            let (state, since) = *self.state.lock().unwrap();
+            let timeout = match state {
+                State::Busy(_) => None,
+                State::Head => timeouts.header_read.map(|timeout| (timeout, Expired::Head)),
+                State::Idle => timeouts.idle.map(|timeout| (timeout, Expired::Idle)),
+            };
+            let deadline = timeout
+                .map(|(timeout, expired)| (since + timeout, expired))
+                .into_iter()
+                .chain(lifetime)
+                .min_by_key(|(deadline, _)| *deadline);
+
+            match deadline {
+                Some((deadline, expired)) if deadline <= Instant::now() => return expired,
+                Some((deadline, _)) => {
+                    tokio::select! {
+                        _ = changed => {}
+                        _ = tokio::time::sleep_until(deadline) => {}
+                    }
+                }
+                None => changed.await,
+            }
+        }
+    }
+
+    fn read(&self) {
+        self.update(|state| match state {
+            State::Idle => State::Head,
+            state => state,
+        });
+    }
+
+    fn wrote(&self) {
+        let mut guard = self.state.lock().unwrap();
+
+        // Writing a response keeps the connection from being idle until the
+        // client stops reading it.
+        if guard.0 == State::Idle {
+            guard.1 = Instant::now();
+        }
+    }
+
+    fn update(&self, update: impl FnOnce(State) -> State) {
+        let mut guard = self.state.lock().unwrap();
+        let state = update(guard.0);
+
+        if state != guard.0 {
+            *guard = (state, Instant::now());
+            self.changed.notify_one();
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            header_read: Some(Duration::from_secs(30)),
+            idle: Some(Duration::from_secs(75)),
+            lifetime: None,
+            #[cfg(feature = "rustls")]
+            tls_handshake: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.0.update(|state| match state {
+            State::Busy(count) if count > 1 => State::Busy(count - 1),
+            _ => State::Idle,
+        });
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Tracked<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        context: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.io).poll_read(context, buf);
+
+        if buf.filled().len() > filled {
+            self.activity.read();
+        }
+
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Tracked<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        context: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.io).poll_write(context, buf);
+
+        if let Poll::Ready(Ok(1..)) = poll {
+            self.activity.wrote();
+        }
+
+        poll
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        context: &mut Context,
+        bufs: &[IoSlice],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.io).poll_write_vectored(context, bufs);
+
+        if let Poll::Ready(Ok(1..)) = poll {
+            self.activity.wrote();
+        }
+
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(context)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        time::{sleep, timeout},
+    };
+
+    use crate::{Application, Context, Next};
+
+    async fn start(configure: impl FnOnce(&mut Application)) -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut app = crate::new();
+
+        app.at("/hello")
+            .get(|_: Context, _: Next| async { "hello" });
+        app.at("/slow").get(|_: Context, _: Next| async {
+            sleep(Duration::from_millis(300)).await;
+            "slow"
+        });
+        app.shutdown_signals(false);
+        configure(&mut app);
+        tokio::spawn(app.listen_on(listener));
+        address
+    }
+
+    /// Requests `path`, whose body is its name, on a kept-alive connection.
+    async fn get(stream: &mut TcpStream, path: &str) -> String {
+        let request = format!("GET {} HTTP/1.1\r\nhost: localhost\r\n\r\n", path);
+        let mut output = Vec::new();
+
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        while !output.ends_with(&path.as_bytes()[1..]) {
+            let mut buffer = [0; 1024];
+            let len = stream.read(&mut buffer).await.unwrap();
+
+            assert!(len > 0, "connection closed");
+            output.extend_from_slice(&buffer[..len]);
+        }
+
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Returns what is left to read once the server closes `stream`.
+    async fn closed(stream: &mut TcpStream) -> Vec<u8> {
+        let mut output = Vec::new();
+        let result = timeout(Duration::from_secs(5), stream.read_to_end(&mut output)).await;
+
+        assert!(result.is_ok(), "connection is still open");
+        output
+    }
+
+    #[tokio::test]
+    async fn closes_connections_that_stall_sending_a_head() {
+        let address = start(|app| {
+            app.header_read_timeout(Some(Duration::from_millis(100)));
+        })
+        .await;
+        let mut stream = TcpStream::connect(address).await.unwrap();
+
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nhost: ")
+            .await
+            .unwrap();
+        assert!(closed(&mut stream).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn closes_idle_connections() {
+        let address = start(|app| {
+            app.header_read_timeout(Some(Duration::from_millis(50)))
+                .idle_timeout(Some(Duration::from_millis(200)));
+        })
+        .await;
+        let mut stream = TcpStream::connect(address).await.unwrap();
+
+        // The header read timeout only starts with the first byte of a head.
+        sleep(Duration::from_millis(100)).await;
+        assert!(get(&mut stream, "/hello")
+            .await
+            .starts_with("HTTP/1.1 200 OK"));
+        sleep(Duration::from_millis(100)).await;
+        assert!(get(&mut stream, "/hello")
+            .await
+            .starts_with("HTTP/1.1 200 OK"));
+        assert!(closed(&mut stream).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_busy_connections_open() {
+        let address = start(|app| {
+            app.idle_timeout(Some(Duration::from_millis(100)));
+        })
+        .await;
+        let mut stream = TcpStream::connect(address).await.unwrap();
+
+        assert!(get(&mut stream, "/slow")
+            .await
+            .starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[tokio::test]
+    async fn aborts_connections_after_their_lifetime() {
+        let address = start(|app| {
+            app.max_connection_lifetime(Duration::from_millis(100));
+        })
+        .await;
+        let mut stream = TcpStream::connect(address).await.unwrap();
+
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(closed(&mut stream).await.is_empty());
+    }
+}
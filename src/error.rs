@@ -1,26 +1,72 @@
-use crate::{http::StatusCode, response::Response};
+use crate::{
+    http::{
+        header::{HeaderName, HeaderValue, InvalidHeaderName, InvalidHeaderValue, ACCEPT},
+        StatusCode,
+    },
+    middleware::context::Accepts,
+    response::Response,
+};
 use serde::ser::{Serialize, Serializer};
 use std::{
-    collections::HashSet,
+    any::Any,
+    borrow::Cow,
     error::Error as StdError,
     fmt::{self, Debug, Display, Formatter},
+    time::Duration,
+};
+#[cfg(feature = "backtrace")]
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub type Source = (dyn StdError + 'static);
 
+#[cfg(feature = "backtrace")]
+static FORCE_BACKTRACE: AtomicBool = AtomicBool::new(false);
+
 pub trait ResultExt<T> {
+    /// Wraps the error in a layer that describes what was being done when it
+    /// occurred, such as `"loading avatar for user 7"`. The context becomes the
+    /// message of the error and the original error becomes its source.
+    fn context<C>(self, context: C) -> Result<T>
+    where
+        C: Display + Send + 'static;
+
     fn json(self) -> Result<T>;
     fn status(self, code: u16) -> Result<T>;
+
+    /// Like `context`, but `context` is only called if there is an error.
+    fn with_context<C, F>(self, context: F) -> Result<T>
+    where
+        C: Display + Send + 'static,
+        F: FnOnce() -> C;
 }
 
 #[derive(Debug)]
 pub struct Error {
+    #[cfg(feature = "backtrace")]
+    backtrace: Option<Backtrace>,
+    code: Option<Cow<'static, str>>,
     format: Option<Format>,
+    headers: Vec<Result<(HeaderName, HeaderValue)>>,
     source: Box<dyn StdError + Send>,
     status: u16,
 }
 
+/// Collects the problems with each field of a form or JSON body so they can
+/// be returned together. Converting it to an `Error`, with `into_error` or
+/// `?`, responds with 422 and lists each problem in the order it was added.
+///
+/// As JSON, the `errors` array has a `{ "field", "message" }` object for
+/// each problem. The collector serializes as that array on its own, such as
+/// for the `invalid-params` member of a problem details body.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ValidationErrors {
+    errors: Vec<(String, String)>,
+}
+
 #[doc(hidden)]
 pub struct Bail {
     pub(crate) message: String,
@@ -31,22 +77,71 @@ struct Chain<'a> {
     source: Option<&'a (dyn StdError + 'static)>,
 }
 
+/// A problem with a field, with the code of the error that it's part of.
+struct FieldError<'a>(&'a str, &'a str, Option<&'a str>);
+
+/// A layer of context in the chain of an error.
+struct Contextual<C> {
+    context: C,
+    source: Box<dyn StdError + Send>,
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum Format {
     Json,
 }
 
-fn respond(error: Error) -> Result<Response> {
+/// Captures a backtrace of where an error is converted to an `Error` even if
+/// `RUST_BACKTRACE` and `RUST_LIB_BACKTRACE` are unset.
+#[cfg(feature = "backtrace")]
+pub fn force_backtraces(enabled: bool) {
+    FORCE_BACKTRACE.store(enabled, Ordering::Relaxed);
+}
+
+#[cfg(feature = "backtrace")]
+fn capture() -> Option<Backtrace> {
+    let backtrace = if FORCE_BACKTRACE.load(Ordering::Relaxed) {
+        Backtrace::force_capture()
+    } else {
+        Backtrace::capture()
+    };
+
+    match backtrace.status() {
+        BacktraceStatus::Captured => Some(backtrace),
+        _ => None,
+    }
+}
+
+fn prefers_json(accepts: &Accepts) -> bool {
+    let offered = [mime::TEXT_PLAIN, mime::APPLICATION_JSON];
+    accepts.best(&offered) == Some(&offered[1])
+}
+
+fn respond(mut error: Error) -> Result<Response> {
+    let headers = std::mem::take(&mut error.headers);
     let Error { format, status, .. } = error;
     let mut response = Response::new(match format {
         Some(Format::Json) => serde_json::to_vec(&error)?,
         None => error.to_string().into_bytes(),
     });
 
+    for header in headers {
+        let (name, value) = header?;
+        response.headers_mut().append(name, value);
+    }
+
     *response.status_mut() = StatusCode::from_u16(status)?;
     Ok(response)
 }
 
+impl Bail {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        Bail {
+            message: message.into(),
+        }
+    }
+}
+
 impl Debug for Bail {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         Debug::fmt(&self.message, f)
@@ -61,6 +156,24 @@ impl Display for Bail {
 
 impl StdError for Bail {}
 
+impl<C: Display> Debug for Contextual<C> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: {:?}", self.context, self.source)
+    }
+}
+
+impl<C: Display> Display for Contextual<C> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.context, f)
+    }
+}
+
+impl<C: Display> StdError for Contextual<C> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&*self.source)
+    }
+}
+
 impl<'a> Iterator for Chain<'a> {
     type Item = &'a (dyn StdError + 'static);
 
@@ -73,17 +186,124 @@ impl<'a> Iterator for Chain<'a> {
 }
 
 impl Error {
+    /// Returns the backtrace of where the error was converted to an `Error`,
+    /// if one was captured. It's never included in the response.
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_ref()
+    }
+
+    /// Returns the machine-readable code of the error. Unless one was set with
+    /// `with_code`, it's the reason of the status in snake case, such as
+    /// `internal_server_error`.
+    pub fn code(&self) -> Cow<'_, str> {
+        if let Some(code) = &self.code {
+            return Cow::Borrowed(code);
+        }
+
+        let status = self.status_code();
+        let reason = match status.canonical_reason() {
+            Some(reason) => reason,
+            None => return Cow::Owned(format!("status_{}", status.as_u16())),
+        };
+
+        let code = reason
+            .split([' ', '-'])
+            .fold(String::new(), |mut code, word| {
+                if !code.is_empty() {
+                    code.push('_');
+                }
+
+                code.extend(
+                    word.chars()
+                        .filter(char::is_ascii_alphanumeric)
+                        .map(|c| c.to_ascii_lowercase()),
+                );
+                code
+            });
+
+        Cow::Owned(code)
+    }
+
     pub fn chain(&self) -> impl Iterator<Item = &Source> {
         Chain {
             source: Some(&*self.source),
         }
     }
 
+    /// Wraps the source of the error in a layer of context, keeping the
+    /// status, format, and headers.
+    fn context<C>(mut self, context: C) -> Self
+    where
+        C: Display + Send + 'static,
+    {
+        self.source = Box::new(Contextual {
+            context,
+            source: self.source,
+        });
+
+        self
+    }
+
+    pub fn forbidden() -> Self {
+        Error::from(Bail::new("Forbidden")).status(403)
+    }
+
     pub fn json(mut self) -> Self {
         self.format = Some(Format::Json);
         self
     }
 
+    /// Renders the error as JSON when the client prefers it to plain text and
+    /// a format hasn't been chosen explicitly.
+    pub fn negotiate(mut self, accepts: &Accepts) -> Self {
+        if self.format.is_none() && prefers_json(accepts) {
+            self.format = Some(Format::Json);
+        }
+
+        self
+    }
+
+    /// Converts the error to a response in the format the client prefers.
+    /// Unless a format was chosen explicitly, the response varies by Accept.
+    pub(crate) fn respond_to(self, accepts: &Accepts) -> Response {
+        let negotiated = self.format.is_none();
+        let mut response = Response::from(self.negotiate(accepts));
+
+        if negotiated {
+            response.add_vary(ACCEPT);
+        }
+
+        response
+    }
+
+    pub fn not_found() -> Self {
+        Error::from(Bail::new("Not Found")).status(404)
+    }
+
+    pub fn precondition_failed() -> Self {
+        Error::from(Bail::new("Precondition Failed")).status(412)
+    }
+
+    pub fn precondition_required() -> Self {
+        Error::from(Bail::new("Precondition Required")).status(428)
+    }
+
+    /// Removes the code set with `with_code`, so the code is the default for
+    /// the status.
+    pub(crate) fn redact_code(self) -> Self {
+        Error { code: None, ..self }
+    }
+
+    /// Replaces the message with `message`, keeping the status, code, and
+    /// format.
+    pub(crate) fn redact(self, message: &str) -> Self {
+        Error {
+            source: Box::new(Bail::new(message)),
+            ..self
+        }
+    }
+
     pub fn source(&self) -> &Source {
         &*self.source
     }
@@ -92,6 +312,55 @@ impl Error {
         self.status = code;
         self
     }
+
+    /// Returns the status of the response that the error is converted to.
+    pub fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    /// Responds with 429 and a Retry-After of `retry_after`, rounded up to
+    /// the next second.
+    pub fn too_many_requests(retry_after: Duration) -> Self {
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+        Error::from(Bail::new("Too Many Requests"))
+            .status(429)
+            .with_header("retry-after", seconds.to_string())
+    }
+
+    /// Responds with 401. A challenge can be added with `with_header`, such
+    /// as `with_header("www-authenticate", "Bearer")`.
+    pub fn unauthorized() -> Self {
+        Error::from(Bail::new("Unauthorized")).status(401)
+    }
+
+    pub fn unprocessable(message: impl Into<String>) -> Self {
+        Error::from(Bail::new(message)).status(422)
+    }
+
+    /// Sets a stable code that clients can branch on instead of the message,
+    /// such as `"subscription_expired"`.
+    pub fn with_code(mut self, code: impl Into<Cow<'static, str>>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    /// Adds a header to the response that the error is converted to. A name
+    /// that is added more than once has each value. An invalid name or value
+    /// turns the response into a 500.
+    pub fn with_header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K, Error = InvalidHeaderName>,
+        HeaderValue: TryFrom<V, Error = InvalidHeaderValue>,
+    {
+        self.headers.push(
+            HeaderName::try_from(name)
+                .map_err(Error::from)
+                .and_then(|name| Ok((name, HeaderValue::try_from(value)?))),
+        );
+
+        self
+    }
 }
 
 impl Display for Error {
@@ -105,10 +374,21 @@ where
     T: StdError + Send + 'static,
 {
     fn from(value: T) -> Self {
+        // Validation errors are the client's fault, even when they're
+        // converted with `?`.
+        let status = match (&value as &dyn Any).is::<ValidationErrors>() {
+            true => 422,
+            false => 500,
+        };
+
         Error {
+            #[cfg(feature = "backtrace")]
+            backtrace: capture(),
+            code: None,
             format: None,
+            headers: Vec::new(),
             source: Box::new(value),
-            status: 500,
+            status,
         }
     }
 }
@@ -120,39 +400,163 @@ impl Serialize for Error {
     {
         use serde::ser::SerializeStruct;
 
-        #[derive(Eq, PartialEq, Hash)]
-        struct SerializedError {
-            message: String,
+        let code = self.code();
+        let mut state = serializer.serialize_struct("Errors", 1)?;
+
+        if let Some(errors) = self
+            .chain()
+            .find_map(|error| error.downcast_ref::<ValidationErrors>())
+        {
+            let errors: Vec<_> = errors
+                .iter()
+                .map(|(field, message)| FieldError(field, message, Some(&code)))
+                .collect();
+
+            state.serialize_field("errors", &errors)?;
+            return state.end();
         }
 
-        impl<'a> From<&'a Source> for SerializedError {
-            fn from(error: &'a Source) -> Self {
-                SerializedError {
-                    message: error.to_string(),
-                }
-            }
+        #[derive(Eq, PartialEq)]
+        struct SerializedError<'a> {
+            code: &'a str,
+            message: String,
         }
 
-        impl Serialize for SerializedError {
+        impl Serialize for SerializedError<'_> {
             fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
                 S: Serializer,
             {
-                let mut state = serializer.serialize_struct("Error", 1)?;
+                let mut state = serializer.serialize_struct("Error", 2)?;
 
                 state.serialize_field("message", &self.message)?;
+                state.serialize_field("code", self.code)?;
                 state.end()
             }
         }
 
-        let errors: HashSet<_> = self.chain().map(SerializedError::from).collect();
-        let mut state = serializer.serialize_struct("Errors", 1)?;
+        // Errors that repeat the message of their source are only listed once.
+        let errors = self.chain().fold(Vec::new(), |mut errors, error| {
+            let error = SerializedError {
+                code: &code,
+                message: error.to_string(),
+            };
+
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
+
+            errors
+        });
 
         state.serialize_field("errors", &errors)?;
         state.end()
     }
 }
 
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a problem with `field`. A field can have more than one.
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push((field.into(), message.into()));
+    }
+
+    /// Converts the collector to a 422 error. A collector without any errors
+    /// is a bug in the validation, so it's converted to a 500 instead. Use
+    /// `into_result` when the collector may be empty.
+    pub fn into_error(self) -> Error {
+        if self.is_empty() {
+            return Error::from(Bail::new(
+                "ValidationErrors::into_error called without errors",
+            ));
+        }
+
+        Error::from(self)
+    }
+
+    /// Returns `Ok(())` if no errors were added.
+    pub fn into_result(self) -> Result<()> {
+        match self.is_empty() {
+            true => Ok(()),
+            false => Err(self.into_error()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Returns the field and message of each problem in the order they were
+    /// added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.errors
+            .iter()
+            .map(|(field, message)| (field.as_str(), message.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+}
+
+impl Display for ValidationErrors {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (index, (field, message)) in self.iter().enumerate() {
+            if index > 0 {
+                f.write_str("\n")?;
+            }
+
+            write!(f, "{}: {}", field, message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StdError for ValidationErrors {}
+
+impl Serialize for ValidationErrors {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeSeq;
+
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+
+        for (field, message) in self.iter() {
+            seq.serialize_element(&FieldError(field, message, None))?;
+        }
+
+        seq.end()
+    }
+}
+
+impl Serialize for FieldError<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeStruct;
+
+        let FieldError(field, message, code) = *self;
+        let mut state = serializer.serialize_struct("FieldError", 3)?;
+
+        state.serialize_field("field", field)?;
+        state.serialize_field("message", message)?;
+
+        match code {
+            Some(code) => state.serialize_field("code", code)?,
+            None => state.skip_field("code")?,
+        }
+
+        state.end()
+    }
+}
+
 impl From<Error> for Box<dyn StdError + Send> {
     fn from(error: Error) -> Self {
         error.source
@@ -175,6 +579,13 @@ impl<T, E> ResultExt<T> for Result<T, E>
 where
     Error: From<E>,
 {
+    fn context<C>(self, context: C) -> Result<T, Error>
+    where
+        C: Display + Send + 'static,
+    {
+        self.map_err(|e| Error::from(e).context(context))
+    }
+
     fn json(self) -> Result<T, Error> {
         self.map_err(|e| Error::from(e).json())
     }
@@ -182,4 +593,317 @@ where
     fn status(self, code: u16) -> Result<T, Error> {
         self.map_err(|e| Error::from(e).status(code))
     }
+
+    fn with_context<C, F>(self, context: F) -> Result<T, Error>
+    where
+        C: Display + Send + 'static,
+        F: FnOnce() -> C,
+    {
+        self.map_err(|e| Error::from(e).context(context()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+    use std::{io, time::Duration};
+
+    use super::{Bail, Error, ResultExt, ValidationErrors};
+    use crate::{response::Response, Result};
+
+    fn read_avatar(id: u32) -> Result<()> {
+        std::fs::read("/nonexistent/avatar.png")
+            .map(drop)
+            .status(404)
+            .with_context(|| format!("reading avatar {}", id))
+    }
+
+    async fn respond(error: Error) -> http::Response<String> {
+        let (parts, body) = http::Response::from(Response::from(error)).into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+
+        http::Response::from_parts(parts, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn converts_constructors_to_responses() {
+        for (error, status, message) in [
+            (Error::forbidden(), 403, "Forbidden"),
+            (Error::not_found(), 404, "Not Found"),
+            (Error::unauthorized(), 401, "Unauthorized"),
+            (Error::unprocessable("invalid title"), 422, "invalid title"),
+            (Error::precondition_failed(), 412, "Precondition Failed"),
+        ] {
+            let response = respond(error).await;
+
+            assert_eq!(response.status(), status);
+            assert_eq!(response.body(), message);
+            assert!(response.headers().get("retry-after").is_none());
+        }
+
+        for (retry_after, seconds) in [
+            (Duration::from_secs(30), "30"),
+            (Duration::from_millis(1500), "2"),
+            (Duration::ZERO, "0"),
+        ] {
+            let response = respond(Error::too_many_requests(retry_after)).await;
+
+            assert_eq!(response.status(), 429);
+            assert_eq!(response.headers()["retry-after"], seconds);
+        }
+    }
+
+    #[tokio::test]
+    async fn merges_headers_into_responses() {
+        let error = Error::unauthorized()
+            .with_header("www-authenticate", r#"Bearer realm="api""#)
+            .with_header("WWW-Authenticate", "Basic")
+            .with_header("cache-control", "no-store")
+            .json();
+        let response = respond(error).await;
+        let challenges: Vec<_> = response
+            .headers()
+            .get_all("www-authenticate")
+            .iter()
+            .collect();
+
+        assert_eq!(response.status(), 401);
+        assert_eq!(challenges, [r#"Bearer realm="api""#, "Basic"]);
+        assert_eq!(response.headers()["cache-control"], "no-store");
+        assert_eq!(
+            response.body(),
+            r#"{"errors":[{"message":"Unauthorized","code":"unauthorized"}]}"#
+        );
+
+        // Headers survive redaction and a change of status.
+        let error = Error::from(Bail::new("secret"))
+            .with_header("x-reason", "upstream")
+            .status(503)
+            .redact("Service Unavailable");
+        let response = respond(error).await;
+
+        assert_eq!(response.status(), 503);
+        assert_eq!(response.headers()["x-reason"], "upstream");
+        assert_eq!(response.body(), "Service Unavailable");
+
+        let response = respond(Error::not_found().with_header("x-bad", "a\nb")).await;
+
+        assert_eq!(response.status(), 500);
+        assert_eq!(response.body(), "Internal Server Error");
+    }
+
+    #[tokio::test]
+    async fn chains_context_in_order() {
+        let error = read_avatar(7).context("loading profile").err().unwrap();
+        let chain: Vec<_> = error.chain().map(ToString::to_string).collect();
+
+        assert_eq!(error.to_string(), "loading profile");
+        assert_eq!(error.status_code(), 404);
+        assert_eq!(chain.len(), 3);
+        assert_eq!(chain[..2], ["loading profile", "reading avatar 7"]);
+
+        // The original error can still be downcast from the chain.
+        let io = error.chain().find_map(|e| e.downcast_ref::<io::Error>());
+        assert_eq!(io.unwrap().kind(), io::ErrorKind::NotFound);
+        assert!(error.source().downcast_ref::<io::Error>().is_none());
+
+        let response = respond(error.json()).await;
+        let body: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+        let messages: Vec<_> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| error["message"].as_str().unwrap())
+            .collect();
+
+        assert_eq!(response.status(), 404);
+        assert_eq!(messages, chain);
+    }
+
+    #[test]
+    fn calls_with_context_lazily() {
+        let mut called = false;
+        let ok: Result<u8, io::Error> = Ok(1);
+
+        assert_eq!(
+            ok.with_context(|| {
+                called = true;
+                "unused"
+            })
+            .unwrap(),
+            1
+        );
+        assert!(!called);
+
+        let error = Err::<(), _>(Error::unprocessable("invalid title").with_header("x-a", "1"))
+            .context("saving post")
+            .err()
+            .unwrap();
+
+        assert_eq!(error.status_code(), 422);
+        assert_eq!(
+            format!("{:?}", error.source()),
+            r#"saving post: "invalid title""#
+        );
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[tokio::test]
+    async fn never_responds_with_the_backtrace() {
+        super::force_backtraces(true);
+
+        let error = read_avatar(7).err().unwrap();
+        let backtrace = error.backtrace().unwrap().to_string();
+
+        assert!(backtrace.contains("read_avatar"));
+
+        for error in [read_avatar(7).err().unwrap(), error.json()] {
+            let body = respond(error).await.into_body();
+
+            assert!(!body.contains("read_avatar"), "{}", body);
+            assert!(!body.contains("backtrace"), "{}", body);
+        }
+    }
+
+    fn validate(title: &str, tags: &[&str]) -> Result<()> {
+        let mut errors = ValidationErrors::new();
+
+        if title.is_empty() {
+            errors.add("title", "is required");
+        }
+
+        for tag in tags.iter().filter(|tag| tag.len() > 3) {
+            errors.add("tags", format!("{} is too long", tag));
+        }
+
+        if title.len() > 5 {
+            errors.add("title", "is too long");
+        }
+
+        if !errors.is_empty() {
+            Err(errors)?;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lists_validation_errors_in_order() {
+        assert!(validate("hello", &["go"]).is_ok());
+
+        let error = validate("", &["rust", "go", "swift"]).err().unwrap();
+        let response = respond(error).await;
+
+        assert_eq!(response.status(), 422);
+        assert_eq!(
+            response.body(),
+            "title: is required\ntags: rust is too long\ntags: swift is too long"
+        );
+
+        let error = validate("a long title", &["rust"]).err().unwrap().json();
+        let response = respond(error).await;
+
+        assert_eq!(response.status(), 422);
+        assert_eq!(
+            response.body(),
+            r#"{"errors":[{"field":"tags","message":"rust is too long","code":"unprocessable_entity"},{"field":"title","message":"is too long","code":"unprocessable_entity"}]}"#
+        );
+
+        // Context doesn't hide the fields.
+        let error = validate("", &[]).context("creating post").err().unwrap();
+        let errors = error
+            .chain()
+            .find_map(|e| e.downcast_ref::<ValidationErrors>());
+
+        assert_eq!(error.status_code(), 422);
+        assert_eq!(
+            errors.unwrap().iter().collect::<Vec<_>>(),
+            [("title", "is required")]
+        );
+        assert_eq!(
+            respond(error.json()).await.body(),
+            r#"{"errors":[{"field":"title","message":"is required","code":"unprocessable_entity"}]}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_empty_validation_errors() {
+        assert!(ValidationErrors::new().into_result().is_ok());
+
+        let response = respond(ValidationErrors::new().into_error()).await;
+        assert_eq!(response.status(), 500);
+
+        let mut errors = ValidationErrors::new();
+
+        errors.add("email", "is invalid");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors.into_result().err().unwrap().status_code(), 422);
+    }
+
+    #[test]
+    fn defaults_codes_to_the_status() {
+        for (status, code) in [
+            (500, "internal_server_error"),
+            (404, "not_found"),
+            (203, "non_authoritative_information"),
+            (418, "im_a_teapot"),
+            (414, "uri_too_long"),
+            (599, "status_599"),
+        ] {
+            assert_eq!(Error::from(Bail::new("")).status(status).code(), code);
+        }
+
+        let error = Error::forbidden().with_code("subscription_expired");
+
+        assert_eq!(error.code(), "subscription_expired");
+        assert_eq!(error.status(402).code(), "subscription_expired");
+        assert_eq!(
+            Error::not_found()
+                .with_code(format!("{}_missing", "avatar"))
+                .code(),
+            "avatar_missing"
+        );
+    }
+
+    #[tokio::test]
+    async fn serializes_codes_next_to_messages() {
+        for (error, json) in [
+            (
+                Error::from(Bail::new("boom")),
+                r#"{"errors":[{"message":"boom","code":"internal_server_error"}]}"#,
+            ),
+            (
+                Error::forbidden().with_code("subscription_expired"),
+                r#"{"errors":[{"message":"Forbidden","code":"subscription_expired"}]}"#,
+            ),
+            (
+                read_avatar(7).context("loading profile").err().unwrap(),
+                r#"{"errors":[{"message":"loading profile","code":"not_found"},{"message":"reading avatar 7","code":"not_found"},{"message":"No such file or directory (os error 2)","code":"not_found"}]}"#,
+            ),
+            (
+                Error::from(Bail::new("secret"))
+                    .with_code("db_down")
+                    .redact("Internal Server Error"),
+                r#"{"errors":[{"message":"Internal Server Error","code":"db_down"}]}"#,
+            ),
+        ] {
+            assert_eq!(respond(error.json()).await.body(), json);
+        }
+
+        let mut errors = ValidationErrors::new();
+
+        errors.add("email", "is invalid");
+
+        // On its own, the collector is a list of fields without codes.
+        assert_eq!(
+            serde_json::to_string(&errors).unwrap(),
+            r#"[{"field":"email","message":"is invalid"}]"#
+        );
+        assert_eq!(
+            respond(errors.into_error().with_code("signup_invalid").json())
+                .await
+                .body(),
+            r#"{"errors":[{"field":"email","message":"is invalid","code":"signup_invalid"}]}"#
+        );
+    }
 }
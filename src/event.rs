@@ -0,0 +1,125 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::Error;
+
+type Callback = Arc<dyn Fn(&ServerEvent) + Send + Sync>;
+
+/// Something that went wrong while serving an application that isn't tied to
+/// the response of a request. Observe them with `Application::on_event`; by
+/// default they are written to stderr.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ServerEvent {
+    /// Accepting a connection failed with a transient error, such as running
+    /// out of file descriptors. It is retried after `backoff`.
+    AcceptRetried { backoff: Duration, error: io::Error },
+    /// A `ReloadingCertResolver` couldn't read its certificate again. It keeps
+    /// the previous one.
+    CertificateReloadFailed { error: Error },
+    /// A connection failed while it was served, such as when a client sent a
+    /// malformed request or closed the connection during a response.
+    ConnectionFailed { error: Error },
+    /// The options of `Application::tcp` couldn't be applied to an accepted
+    /// connection. It is served anyway.
+    ConnectionOptionsFailed {
+        error: io::Error,
+        remote_addr: SocketAddr,
+    },
+    /// Connections that didn't close within `shutdown_timeout` were aborted.
+    ConnectionsAborted { count: usize },
+    /// A hook added with `on_shutdown` failed.
+    ShutdownHookFailed { error: Error },
+    /// A hook added with `on_shutdown` didn't finish within
+    /// `shutdown_hook_timeout`.
+    ShutdownHookTimedOut,
+    /// The server couldn't listen for shutdown signals. It can still be shut
+    /// down with a `ShutdownHandle` or `with_shutdown`.
+    SignalListenFailed { error: io::Error },
+    /// A client didn't complete a TLS handshake. The connection is closed.
+    TlsHandshakeFailed {
+        error: io::Error,
+        remote_addr: SocketAddr,
+    },
+    /// A client didn't complete a TLS handshake within
+    /// `tls_handshake_timeout`. The connection is closed.
+    TlsHandshakeTimedOut { remote_addr: SocketAddr },
+}
+
+/// Reports server events to the callback of an application. Cloned from
+/// `Application::events`, it reports to the callback set with `on_event`
+/// whether it is set before or after the clone.
+#[derive(Clone)]
+pub struct Events {
+    callback: Arc<RwLock<Callback>>,
+}
+
+impl Display for ServerEvent {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ServerEvent::AcceptRetried { backoff, error } => write!(
+                f,
+                "Error accepting connection, retrying in {:?}: {}",
+                backoff, error
+            ),
+            ServerEvent::CertificateReloadFailed { error } => {
+                write!(f, "Error reloading certificate: {}", error)
+            }
+            ServerEvent::ConnectionFailed { error } => {
+                write!(f, "Error serving connection: {}", error)
+            }
+            ServerEvent::ConnectionOptionsFailed { error, remote_addr } => write!(
+                f,
+                "Error configuring connection from {}: {}",
+                remote_addr, error
+            ),
+            ServerEvent::ConnectionsAborted { count } => write!(
+                f,
+                "Aborting {} connection(s) that did not close in time",
+                count
+            ),
+            ServerEvent::ShutdownHookFailed { error } => {
+                write!(f, "Error running shutdown hook: {}", error)
+            }
+            ServerEvent::ShutdownHookTimedOut => write!(f, "Shutdown hook did not finish in time"),
+            ServerEvent::SignalListenFailed { error } => {
+                write!(f, "Error listening for shutdown signals: {}", error)
+            }
+            ServerEvent::TlsHandshakeFailed { error, remote_addr } => {
+                write!(f, "TLS handshake with {} failed: {}", remote_addr, error)
+            }
+            ServerEvent::TlsHandshakeTimedOut { remote_addr } => {
+                write!(f, "TLS handshake with {} timed out", remote_addr)
+            }
+        }
+    }
+}
+
+impl Events {
+    pub fn report(&self, event: ServerEvent) {
+        let callback = Arc::clone(&self.callback.read().unwrap());
+        callback(&event);
+    }
+
+    pub(crate) fn set<F>(&self, callback: F)
+    where
+        F: Fn(&ServerEvent) + Send + Sync + 'static,
+    {
+        *self.callback.write().unwrap() = Arc::new(callback);
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        let callback: Callback = Arc::new(|event| eprintln!("{}", event));
+
+        Events {
+            callback: Arc::new(RwLock::new(callback)),
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use hyper::server::conn::http2;
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use socket2::SockRef;
+use std::{future::Future, io, time::Duration};
+use tokio::{io::Interest, net::TcpStream};
+
+use crate::{activity::Timeouts, Protocol};
+
+/// The bytes that a client speaking HTTP/2 sends first.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Options for the HTTP/2 connections of a server. HTTP/2 is served to TLS
+/// clients that negotiate `h2` with ALPN, and to plaintext clients that send
+/// its preface when `Application::http2_cleartext` is enabled.
+#[derive(Clone, Debug, Default)]
+pub struct Http2Options {
+    initial_connection_window_size: Option<u32>,
+    initial_stream_window_size: Option<u32>,
+    keep_alive_interval: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+    max_concurrent_streams: Option<u32>,
+}
+
+impl Http2Options {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The flow control window of a connection, shared by its streams.
+    /// Defaults to 1 MiB.
+    pub fn initial_connection_window_size(mut self, size: u32) -> Self {
+        self.initial_connection_window_size = Some(size);
+        self
+    }
+
+    /// The flow control window of each stream. Defaults to 1 MiB.
+    pub fn initial_stream_window_size(mut self, size: u32) -> Self {
+        self.initial_stream_window_size = Some(size);
+        self
+    }
+
+    /// Pings clients every `interval` to keep connections open through
+    /// proxies and detect dead peers. Disabled by default.
+    pub fn keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.keep_alive_interval = Some(interval);
+        self
+    }
+
+    /// Closes connections that don't acknowledge a keep-alive ping within
+    /// `timeout`. Defaults to 20 seconds.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// The number of requests that a client can send at once on a
+    /// connection. Defaults to 200.
+    pub fn max_concurrent_streams(mut self, max: u32) -> Self {
+        self.max_concurrent_streams = Some(max);
+        self
+    }
+
+    pub(crate) fn builder(&self) -> http2::Builder<TokioExecutor> {
+        let mut builder = http2::Builder::new(TokioExecutor::new());
+
+        builder
+            .timer(TokioTimer::new())
+            .initial_connection_window_size(self.initial_connection_window_size)
+            .initial_stream_window_size(self.initial_stream_window_size)
+            .keep_alive_interval(self.keep_alive_interval);
+
+        // Unlike the others, `None` lifts the limit rather than keeping the
+        // default.
+        if let Some(max) = self.max_concurrent_streams {
+            builder.max_concurrent_streams(max);
+        }
+
+        if let Some(timeout) = self.keep_alive_timeout {
+            builder.keep_alive_timeout(timeout);
+        }
+
+        builder
+    }
+}
+
+/// Tells whether the client of `stream` speaks HTTP/2 with prior knowledge.
+/// Like the head of a request, the client has `idle` to send the first byte
+/// of the preface and `header_read` from then on to send the rest. Returns
+/// `None` if the connection closed, failed, or timed out first.
+pub(crate) async fn sniff(stream: &TcpStream, timeouts: Timeouts) -> Option<Protocol> {
+    within(timeouts.idle, stream.readable()).await?.ok()?;
+
+    match within(timeouts.header_read, is_prior_knowledge(stream)).await? {
+        Ok(true) => Some(Protocol::Http2),
+        Ok(false) => Some(Protocol::Http1),
+        Err(_) => None,
+    }
+}
+
+/// Waits for the first bytes of `stream`, without consuming them, to tell
+/// whether the client speaks HTTP/2 with prior knowledge.
+async fn is_prior_knowledge(stream: &TcpStream) -> io::Result<bool> {
+    // A duplicate of the socket can be peeked without blocking, which lets
+    // readiness be cleared while only part of the preface has arrived.
+    let socket = std::net::TcpStream::from(SockRef::from(stream).try_clone()?);
+    let mut buffer = [0; PREFACE.len()];
+
+    loop {
+        stream.readable().await?;
+
+        let peeked = stream.try_io(Interest::READABLE, || {
+            let len = socket.peek(&mut buffer)?;
+
+            if len > 0 && len < PREFACE.len() && buffer[..len] == PREFACE[..len] {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+
+            Ok(len)
+        });
+
+        match peeked {
+            Ok(len) => return Ok(len == PREFACE.len() && buffer == PREFACE),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+async fn within<F: Future>(timeout: Option<Duration>, future: F) -> Option<F::Output> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Empty};
+    use hyper::client::conn::http2;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use std::{process::ExitCode, time::Duration};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::{is_prior_knowledge, Http2Options, PREFACE};
+    use crate::{Context, Next};
+
+    #[tokio::test]
+    async fn serves_http2_with_prior_knowledge() {
+        let mut app = crate::new();
+
+        app.at("/version")
+            .get(|context: Context, _: Next| async move { format!("{:?}", context.version()) });
+        app.shutdown_signals(false)
+            .http2(Http2Options::new().max_concurrent_streams(10))
+            .http2_cleartext(true);
+
+        let server = app.bind(("127.0.0.1", 0)).unwrap();
+        let address = server.local_addr();
+
+        tokio::spawn(server.serve());
+
+        let stream = TcpStream::connect(address).await.unwrap();
+        let (mut sender, connection) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+
+        tokio::spawn(connection);
+
+        let request = http::Request::get("http://localhost/version")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+
+        assert_eq!(response.version(), http::Version::HTTP_2);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        assert_eq!(body, "HTTP/2.0");
+
+        // Clients without prior knowledge are still served HTTP/1.1.
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut output = String::new();
+
+        stream
+            .write_all(b"GET /version HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        stream.read_to_string(&mut output).await.unwrap();
+
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\nHTTP/1.1"));
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_rest_of_the_preface() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let sniffing = tokio::spawn(async move { is_prior_knowledge(&stream).await.unwrap() });
+
+        client.write_all(&PREFACE[..4]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!sniffing.is_finished());
+
+        client.write_all(&PREFACE[4..]).await.unwrap();
+        assert!(sniffing.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn shuts_down_with_idle_connections() {
+        let mut app = crate::new();
+
+        app.shutdown_signals(false)
+            .header_read_timeout(None)
+            .idle_timeout(None)
+            .http2_cleartext(true)
+            .shutdown_timeout(Duration::from_secs(5));
+
+        let server = app.bind(("127.0.0.1", 0)).unwrap();
+        let address = server.local_addr();
+        let handle = server.shutdown_handle();
+        let serving = tokio::spawn(server.serve());
+
+        // One connection sends nothing and the other part of the preface.
+        let _idle = TcpStream::connect(address).await.unwrap();
+        let mut partial = TcpStream::connect(address).await.unwrap();
+
+        partial.write_all(&PREFACE[..4]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        handle.shutdown();
+
+        let code = tokio::time::timeout(Duration::from_secs(1), serving).await;
+
+        assert_eq!(code.unwrap().unwrap().unwrap(), ExitCode::SUCCESS);
+    }
+}
@@ -7,38 +7,93 @@ macro_rules! bail {
     };
 }
 
+mod accept;
+mod activity;
+mod event;
+mod http2;
+mod runtime;
+mod server;
 mod service;
+mod shutdown;
+mod tcp;
+
+#[cfg(feature = "rustls")]
+mod tls;
 
 pub mod error;
 pub mod middleware;
 pub mod prelude;
 pub mod response;
+pub mod rooms;
 pub mod routing;
 pub mod view;
 
 #[doc(inline)]
 pub use self::{
     error::{Error, ResultExt},
-    middleware::{Context, Middleware, Next},
+    middleware::{
+        cache::Cache, catch_panic::CatchPanic, compress::Compress, concurrency::ConcurrencyLimit,
+        context::ProxyHeader, cookies::Cookies, decompress::Decompress, etag::AutoEtag,
+        guard::Guard, idempotency::Idempotency, limit::limit_body, maintenance::Maintenance,
+        method_override::MethodOverride, rate_limit::RateLimit, request_id::RequestId,
+        rescue::Rescue, session::Session, slow_log::SlowLog, timeout::Timeout, Context, Middleware,
+        Next,
+    },
     response::Respond,
 };
 pub use codegen::{endpoint, service};
+pub use cookie;
+pub use event::{Events, ServerEvent};
 pub use http;
+pub use http2::Http2Options;
 pub use router::Verb;
+pub use runtime::RuntimeOptions;
+pub use server::Server;
+pub use service::ConnectionInfo;
+pub use shutdown::ShutdownHandle;
+pub use tcp::TcpOptions;
+
+#[cfg(feature = "tracing")]
+pub use self::middleware::trace::Trace;
+#[cfg(feature = "rustls")]
+pub use self::tls::{ReloadingCertResolver, SniResolver, TlsInfo};
+#[cfg(feature = "rustls")]
+pub use tokio_rustls::rustls;
 
-use futures::future::{FutureExt, Map};
-use hyper::server::conn::http1;
-use hyper_util::rt::{TokioIo, TokioTimer};
+use futures::{
+    future::{Future, FutureExt, Map},
+    StreamExt,
+};
+use hyper::server::conn::{http1, http2::Connection as Http2Connection};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
 use std::{
     convert::Infallible,
-    net::{SocketAddr, ToSocketAddrs},
+    net::ToSocketAddrs,
+    pin::Pin,
+    process::ExitCode,
+    sync::Arc,
+    task::{self, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::watch,
 };
-use tokio::net::TcpListener;
 
-use self::{response::Response, routing::*};
+use self::{
+    accept::incoming,
+    activity::{Activity, Expired, Tracked},
+    middleware::context::TrustedProxies,
+    response::{Response, PRETTY_JSON},
+    routing::*,
+    service::Service as Connection,
+    shutdown::{Connections, Shutdown},
+};
 
 type CallFuture = Map<BoxFuture<Result>, fn(Result) -> Result<HttpResponse, Infallible>>;
 type HttpRequest = http::Request<hyper::body::Incoming>;
+type HttpIo<T> = TokioIo<Tracked<T>>;
 type HttpResponse = http::Response<response::Body>;
 
 pub type BoxFuture<T> = futures::future::BoxFuture<'static, T>;
@@ -60,76 +115,777 @@ macro_rules! only([$($method:ident),*] => {
 });
 
 pub struct Application {
+    auto_head: bool,
+    debug_routes: DebugRoutes,
+    events: Events,
+    health_checks: routing::health::HealthChecks,
+    hosts: routing::host::Hosts,
+    http2: Http2Options,
+    http2_cleartext: bool,
+    limits: Limits,
+    normalize_path: NormalizePath,
+    pretty_json: bool,
+    proxies: TrustedProxies,
+    rewrites: Rewrites,
     router: Router,
+    shutdown: Shutdown,
+    strict_routing: bool,
+    tcp: TcpOptions,
+    trailing_slash: TrailingSlash,
+}
+
+#[derive(Clone, Copy, Default, Eq, PartialEq)]
+enum DebugRoutes {
+    #[default]
+    Disabled,
+    Enabled,
+    Forced,
+}
+
+/// The protocol that a connection is served with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Protocol {
+    Http1,
+    Http2,
+}
+
+/// A connection that `serve` can poll and shut down gracefully regardless of
+/// its protocol.
+enum HttpConnection<T> {
+    Http1(Pin<Box<http1::Connection<HttpIo<T>, Connection>>>),
+    Http2(Pin<Box<Http2Connection<HttpIo<T>, Connection, TokioExecutor>>>),
+}
+
+#[derive(Default)]
+struct Limits {
+    timeouts: activity::Timeouts,
+    max_age: Option<Duration>,
+    max_body_size: Option<usize>,
+    max_requests: Option<u64>,
 }
 
 pub fn new() -> Application {
     Application {
+        auto_head: true,
+        debug_routes: Default::default(),
+        events: Default::default(),
+        health_checks: Default::default(),
+        hosts: Default::default(),
+        http2: Default::default(),
+        http2_cleartext: false,
+        limits: Default::default(),
+        normalize_path: Default::default(),
+        pretty_json: false,
+        proxies: Default::default(),
+        rewrites: Default::default(),
         router: Default::default(),
+        shutdown: Default::default(),
+        strict_routing: false,
+        tcp: Default::default(),
+        trailing_slash: Default::default(),
     }
 }
 
-fn get_addr(sources: impl ToSocketAddrs) -> Result<SocketAddr> {
-    match sources.to_socket_addrs()?.next() {
-        Some(value) => Ok(value),
-        None => todo!(),
+async fn serve<T>(
+    io: T,
+    protocol: Protocol,
+    service: Connection,
+    mut shutdown: watch::Receiver<bool>,
+) where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let activity = Activity::new();
+    let events = service.events();
+    let exhausted = service.exhausted();
+    let opened_at = tokio::time::Instant::now();
+    let mut timeouts = service.timeouts();
+    let mut service = service;
+
+    service.track(Arc::clone(&activity));
+
+    let io = TokioIo::new(activity.track(io));
+    let mut connection = match protocol {
+        // Header read and idle timeouts are enforced by `activity` rather
+        // than hyper, whose header read timer also runs while the connection
+        // is idle.
+        Protocol::Http1 => HttpConnection::Http1(Box::pin(
+            http1::Builder::new()
+                .timer(TokioTimer::new())
+                .header_read_timeout(None)
+                .serve_connection(io, service),
+        )),
+        // Frames such as pings and settings arrive while an HTTP/2 connection
+        // is idle, so they can't be told apart from the head of a request.
+        Protocol::Http2 => {
+            timeouts.header_read = None;
+            HttpConnection::Http2(Box::pin(
+                service.http2().builder().serve_connection(io, service),
+            ))
+        }
+    };
+    let mut closing = false;
+    let result = loop {
+        tokio::select! {
+            result = &mut connection => break result,
+            _ = shutdown.changed(), if !closing => {
+                closing = true;
+                connection.graceful_shutdown();
+            }
+            _ = exhausted.notified(), if !closing => {
+                closing = true;
+                connection.graceful_shutdown();
+            }
+            expired = activity.expired(timeouts, opened_at), if !closing => match expired {
+                Expired::Idle => {
+                    closing = true;
+                    connection.graceful_shutdown();
+                }
+                Expired::Head | Expired::Lifetime => return,
+            },
+        }
+    };
+
+    if let Err(error) = result {
+        events.report(ServerEvent::ConnectionFailed {
+            error: error.into(),
+        });
     }
 }
 
+/// Binds every address in `sources`. Fails without listening on any of them
+/// if one can't be bound.
+fn bind(sources: impl ToSocketAddrs, options: &TcpOptions) -> Result<Vec<TcpListener>> {
+    let mut addresses = Vec::new();
+    let mut listeners = Vec::new();
+
+    for address in sources.to_socket_addrs()? {
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+
+    if addresses.is_empty() {
+        bail!("no addresses to listen on");
+    }
+
+    for address in addresses {
+        listeners.push(options.bind(address)?);
+    }
+
+    Ok(listeners)
+}
+
 impl Application {
     pub fn at(&mut self, pattern: &'static str) -> Location {
         self.router.at(pattern)
     }
 
+    /// Answers HEAD requests to routes that register GET but not HEAD with
+    /// their GET handlers. Enabled by default.
+    pub fn auto_head(&mut self, enabled: bool) -> &mut Self {
+        self.auto_head = enabled;
+        self
+    }
+
+    /// Fails with every pair of routes that conflict or shadow one another.
+    pub fn check_routes(&self) -> Result<()> {
+        let conflicts: Vec<_> = self
+            .router
+            .conflicts()
+            .into_iter()
+            .chain(self.hosts.conflicts())
+            .chain(self.health_checks.conflicts(&self.router))
+            .collect();
+
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            bail!("conflicting routes:\n  {}", conflicts.join("\n  "))
+        }
+    }
+
+    /// Serves an index of the route table at `/_routes`. Only takes effect in
+    /// debug builds; see `force_debug_routes` to serve it from a release build.
+    pub fn debug_routes(&mut self, enabled: bool) -> &mut Self {
+        self.debug_routes = if enabled {
+            DebugRoutes::Enabled
+        } else {
+            DebugRoutes::Disabled
+        };
+        self
+    }
+
+    /// Returns a handle that reports to the callback set with `on_event`, such
+    /// as to pass to a `ReloadingCertResolver`.
+    pub fn events(&self) -> Events {
+        self.events.clone()
+    }
+
+    pub fn force_debug_routes(&mut self) -> &mut Self {
+        self.debug_routes = DebugRoutes::Forced;
+        self
+    }
+
+    /// Responds to requests for `path` with 200 OK before the router and the
+    /// middleware it calls are visited. Routes can't be added at `path`.
+    pub fn health_check(&mut self, path: &'static str) -> &mut Self {
+        self.health_checks.push(path);
+        self
+    }
+
+    /// Like `health_check`, but responds with 503 Service Unavailable when
+    /// `check` returns an error.
+    pub fn health_check_with<F, T>(&mut self, path: &'static str, check: F) -> &mut Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+        T: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.health_checks.push_with(path, check);
+        self
+    }
+
+    /// Routes registered on the returned location only match requests whose
+    /// Host is `pattern`. A leading `*.` matches any subdomain and captures it
+    /// as the `subdomain` param.
+    pub fn host(&mut self, pattern: &str) -> Location<'_> {
+        self.hosts.at(pattern)
+    }
+
     pub fn include(&mut self, middleware: impl Middleware) -> &mut Self {
         self.at("/").include(middleware);
         self
     }
 
-    pub async fn listen(self, address: impl ToSocketAddrs) -> Result<()> {
-        use crate::service::Service;
+    /// Grafts the routes and middleware of `application` under `prefix`. Only
+    /// its routes and middleware are kept. Everything else configured on
+    /// `application` is silently ignored, including its hosts, health checks,
+    /// rewrites, `normalize_path`, `trailing_slash`, `debug_routes`,
+    /// `auto_head`, `pretty_json`, `strict_routing`, trusted proxies,
+    /// `on_event` callback, shutdown hooks, signals, and timeouts, and its
+    /// connection, TCP, HTTP/2, and body size limits. Configure them on the
+    /// application that is served instead.
+    pub fn mount(&mut self, prefix: &'static str, application: Application) -> &mut Self {
+        self.router.mount(prefix, application.router);
+        self
+    }
+
+    pub fn not_found(&mut self, middleware: impl Middleware) -> &mut Self {
+        self.at("/").not_found(middleware);
+        self
+    }
+
+    /// Sets the default maximum size of request bodies that are aggregated.
+    /// Routes can override it with `limit_body`.
+    pub fn max_body_size(&mut self, max: usize) -> &mut Self {
+        self.limits.max_body_size = Some(max);
+        self
+    }
 
-        let address = get_addr(address)?;
-        let listener = TcpListener::bind(address).await?;
-        let service = Service::from(self);
-        // let ctrlc = async {
-        //     let message = "failed to install CTRL+C signal handler";
-        //     tokio::signal::ctrl_c().await.expect(message);
-        // };
+    /// Sets the options of HTTP/2 connections.
+    pub fn http2(&mut self, options: Http2Options) -> &mut Self {
+        self.http2 = options;
+        self
+    }
 
-        println!("Server listening at http://{}", address);
+    /// Serves HTTP/2 to plaintext clients that send its preface, known as h2c
+    /// with prior knowledge, and HTTP/1.1 to the others. Upgrading from
+    /// HTTP/1.1 with `Upgrade: h2c` isn't supported. Disabled by default.
+    pub fn http2_cleartext(&mut self, enabled: bool) -> &mut Self {
+        self.http2_cleartext = enabled;
+        self
+    }
+
+    /// Closes connections that take longer than `timeout` to send the head of
+    /// a request, measured from its first byte. Only applies to HTTP/1.1 and
+    /// to the preface of HTTP/2 with prior knowledge. Defaults to 30 seconds.
+    pub fn header_read_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.limits.timeouts.header_read = timeout;
+        self
+    }
+
+    /// Closes connections that are idle between requests for longer than
+    /// `timeout`. Writing a response counts as activity, so a streaming body
+    /// that sends nothing for `timeout` also closes the connection. Defaults
+    /// to 75 seconds.
+    pub fn idle_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.limits.timeouts.idle = timeout;
+        self
+    }
+
+    /// Closes connections that are older than `lifetime`, even if a response
+    /// is in flight. Unlike `max_connection_age`, which asks the client to
+    /// reconnect after its next request, this aborts the connection.
+    pub fn max_connection_lifetime(&mut self, lifetime: Duration) -> &mut Self {
+        self.limits.timeouts.lifetime = Some(lifetime);
+        self
+    }
+
+    /// Closes connections that don't complete a TLS handshake within
+    /// `timeout`, reporting each with `ServerEvent::TlsHandshakeTimedOut`. The
+    /// established connection isn't affected. Defaults to 10 seconds.
+    #[cfg(feature = "rustls")]
+    pub fn tls_handshake_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.limits.timeouts.tls_handshake = timeout;
+        self
+    }
+
+    /// Asks clients to reconnect, with `Connection: close` or an HTTP/2
+    /// GOAWAY frame, in the first response once a connection is `age` old. The age is only checked when a
+    /// request arrives, so an idle connection stays open until its next
+    /// request or the idle timeout. See `max_connection_lifetime` to close
+    /// connections regardless.
+    pub fn max_connection_age(&mut self, age: Duration) -> &mut Self {
+        self.limits.max_age = Some(age);
+        self
+    }
+
+    /// Asks clients to reconnect, with `Connection: close` or an HTTP/2
+    /// GOAWAY frame, in the response to the `count`th request of a
+    /// connection.
+    pub fn max_requests_per_connection(&mut self, count: u64) -> &mut Self {
+        self.limits.max_requests = Some(count);
+        self
+    }
+
+    /// Collapses repeated slashes and resolves dot segments in request paths
+    /// before they are rewritten or routed. Disabled by default.
+    pub fn normalize_path(&mut self, policy: NormalizePath) -> &mut Self {
+        self.normalize_path = policy;
+        self
+    }
+
+    /// Indents the body of responses created with `response::json` when
+    /// enabled, e.g. with `app.pretty_json(cfg!(debug_assertions))`.
+    pub fn pretty_json(&mut self, enabled: bool) -> &mut Self {
+        self.pretty_json = enabled;
+        self
+    }
+
+    /// Calls `callback` with each server event instead of writing it to
+    /// stderr.
+    pub fn on_event<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(&ServerEvent) + Send + Sync + 'static,
+    {
+        self.events.set(callback);
+        self
+    }
+
+    /// Shuts the server down gracefully on SIGINT or SIGTERM. Enabled by
+    /// default.
+    pub fn shutdown_signals(&mut self, enabled: bool) -> &mut Self {
+        self.shutdown.signals = enabled;
+        self
+    }
+
+    /// Runs `hook` once the server stops accepting connections and the open
+    /// ones are drained. Hooks run in the order they are added, each given up
+    /// to `shutdown_hook_timeout` to finish. A hook that fails or runs out of
+    /// time is reported as a server event, and makes the exit code of
+    /// `listen` a failure.
+    pub fn on_shutdown<F, T>(&mut self, hook: F) -> &mut Self
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.shutdown.push_hook(hook);
+        self
+    }
+
+    /// Sets how long each hook added with `on_shutdown` is given to finish.
+    /// Defaults to 10 seconds.
+    pub fn shutdown_hook_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown.hook_timeout = timeout;
+        self
+    }
+
+    /// Sets how long connections are given to finish their responses during
+    /// shutdown before they are aborted. Defaults to 30 seconds.
+    pub fn shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown.timeout = timeout;
+        self
+    }
+
+    /// Shuts the server down gracefully when `future` completes.
+    pub fn with_shutdown<F>(&mut self, future: F) -> &mut Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown.set_trigger(future);
+        self
+    }
+
+    /// Fails `listen` with the result of `check_routes` when enabled.
+    pub fn strict_routing(&mut self, enabled: bool) -> &mut Self {
+        self.strict_routing = enabled;
+        self
+    }
+
+    /// Caches how the `capacity` most recently requested paths were routed.
+    #[cfg(feature = "lru-cache")]
+    pub fn route_cache(&mut self, capacity: usize) -> &mut Self {
+        self.router.set_cache_capacity(capacity);
+        self
+    }
+
+    /// Returns a handle to the hit, miss, and eviction counts of the route
+    /// cache. Call it before `listen`; the handle keeps counting while the
+    /// application is served.
+    #[cfg(feature = "lru-cache")]
+    pub fn cache_stats(&self) -> routing::CacheMonitor {
+        self.router.cache_monitor()
+    }
+
+    /// Permanently redirects GET and HEAD requests from `from` to `to`.
+    pub fn redirect(&mut self, from: &'static str, to: &'static str) -> &mut Self {
+        self.at(from)
+            .redirect(http::StatusCode::MOVED_PERMANENTLY, to);
+        self
+    }
+
+    /// Rewrites the URI of requests before they are routed. When `rewrite`
+    /// returns a new URI, params are resolved against it and the original is
+    /// available with `Context::original_uri`.
+    pub fn rewrite<F>(&mut self, rewrite: F) -> &mut Self
+    where
+        F: Fn(&mut Context) -> Option<http::Uri> + Send + Sync + 'static,
+    {
+        self.rewrites.push(rewrite);
+        self
+    }
+
+    pub fn routes(&self) -> impl Iterator<Item = RouteEntry> {
+        self.router.routes()
+    }
+
+    /// Trusts the given header of requests from the given addresses or CIDR
+    /// networks when resolving `client_addr`. Pick the header that your
+    /// proxies write; the other is ignored since clients can spoof it.
+    pub fn trust_proxies(&mut self, header: ProxyHeader, networks: &[&str]) -> &mut Self {
+        self.proxies = TrustedProxies::new(header, networks);
+        self
+    }
+
+    /// Sets the options of the sockets that `listen` binds and the
+    /// connections it accepts.
+    pub fn tcp(&mut self, options: TcpOptions) -> &mut Self {
+        self.tcp = options;
+        self
+    }
+
+    pub fn trailing_slash(&mut self, policy: TrailingSlash) -> &mut Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Serves the application on every address that `address` resolves to
+    /// until it is shut down. Once shutdown is requested, new connections are
+    /// refused and open ones finish their responses in flight. The exit code
+    /// is a failure if any connection had to be aborted because it didn't
+    /// finish within `shutdown_timeout`.
+    pub async fn listen(self, address: impl ToSocketAddrs) -> Result<ExitCode> {
+        self.bind(address)?.serve().await
+    }
+
+    /// Builds a multi-threaded runtime with `options` and blocks the current
+    /// thread on `listen` until the application is shut down. Fails if it is
+    /// called from within a runtime, where `listen` should be awaited
+    /// instead.
+    pub fn run(self, address: impl ToSocketAddrs, options: RuntimeOptions) -> Result<ExitCode> {
+        if tokio::runtime::Handle::try_current().is_ok() {
+            bail!("run can't be called from within a tokio runtime, use listen instead");
+        }
+
+        options.build()?.block_on(self.listen(address))
+    }
+
+    /// Binds every address that `address` resolves to without accepting
+    /// connections, so that the bound addresses can be read before the
+    /// returned server is served.
+    pub fn bind(self, address: impl ToSocketAddrs) -> Result<Server> {
+        self.check_before_listening()?;
+
+        let listeners = bind(address, &self.tcp)?;
+        Server::new(self, listeners)
+    }
+
+    /// Like `listen`, but serves connections from a listener that is already
+    /// bound, such as one inherited with systemd socket activation or from
+    /// the process being replaced in a restart.
+    pub async fn listen_on(self, listener: std::net::TcpListener) -> Result<ExitCode> {
+        self.check_before_listening()?;
+
+        listener.set_nonblocking(true)?;
+        self.accept(vec![TcpListener::from_std(listener)?]).await
+    }
+
+    /// Like `listen`, but over TLS. HTTP/2 is served to clients that negotiate
+    /// it with ALPN. Unless `config` lists its own ALPN protocols, `h2` and
+    /// `http/1.1` are offered.
+    #[cfg(feature = "rustls")]
+    pub async fn listen_rustls(
+        self,
+        address: impl ToSocketAddrs,
+        config: Arc<rustls::ServerConfig>,
+    ) -> Result<ExitCode> {
+        self.bind(address)?.serve_rustls(config).await
+    }
+
+    /// Like `listen_on`, but over TLS. HTTP/2 is served to clients that
+    /// negotiate it with ALPN. Unless `config` lists its own ALPN protocols,
+    /// `h2` and `http/1.1` are offered.
+    #[cfg(feature = "rustls")]
+    pub async fn listen_rustls_on(
+        self,
+        listener: std::net::TcpListener,
+        config: Arc<rustls::ServerConfig>,
+    ) -> Result<ExitCode> {
+        self.check_before_listening()?;
+
+        listener.set_nonblocking(true)?;
+        self.accept_rustls(vec![TcpListener::from_std(listener)?], config)
+            .await
+    }
+
+    async fn accept(self, listeners: Vec<TcpListener>) -> Result<ExitCode> {
+        let events = self.events.clone();
+        let hooks = self.shutdown.hooks(events.clone());
+        let http2_cleartext = self.http2_cleartext;
+        let requested = self.shutdown.requested(events.clone());
+        let tcp = self.tcp.clone();
+        let timeout = self.shutdown.timeout;
+        let service = self.finish();
+        let timeouts = service.timeouts();
+        let mut connections = Connections::new(events.clone());
+        let mut requested = std::pin::pin!(requested);
+
+        for listener in &listeners {
+            println!("Server listening at http://{}", listener.local_addr()?);
+        }
+
+        let mut incoming = incoming(listeners, tcp, events.clone());
+        let mut failure = None;
 
         loop {
-            let (stream, _) = listener.accept().await?;
-            let instance = service.clone();
-
-            // Use an adapter to access something implementing `tokio::io` traits as if they implement
-            // `hyper::rt` IO traits.
-            let io = TokioIo::new(stream);
-
-            // Spawn a tokio task to serve multiple connections concurrently
-            tokio::task::spawn(async move {
-                // Finally, we bind the incoming connection to our `hello` service
-                if let Err(err) = http1::Builder::new()
-                    .timer(TokioTimer::new())
-                    // `service_fn` converts our function in a `Service`
-                    .serve_connection(io, instance)
-                    .await
-                {
-                    eprintln!("Error serving connection: {:?}", err);
-                }
+            let (stream, remote_addr) = tokio::select! {
+                Some(accepted) = incoming.next() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(error) => {
+                        failure = Some(error);
+                        break;
+                    }
+                },
+                _ = requested.as_mut() => break,
+            };
+            let service = service.connect(stream.local_addr().ok(), Some(remote_addr));
+
+            if !http2_cleartext {
+                connections.spawn(|shutdown| serve(stream, Protocol::Http1, service, shutdown));
+                continue;
+            }
+
+            connections.spawn(|mut shutdown| async move {
+                // An idle client would otherwise hold up shutdown until it
+                // times out.
+                let protocol = tokio::select! {
+                    protocol = http2::sniff(&stream, timeouts) => match protocol {
+                        Some(protocol) => protocol,
+                        None => return,
+                    },
+                    _ = shutdown.changed() => return,
+                };
+
+                serve(stream, protocol, service, shutdown).await;
             });
         }
 
-        // Ok(server.with_graceful_shutdown(ctrlc).await?)
+        drop(incoming);
+
+        // Open connections are drained and the shutdown hooks run even if the
+        // listener failed.
+        let drained = connections.drain(timeout).await;
+        let code = hooks.run(drained).await;
+
+        match failure {
+            Some(error) => Err(error.into()),
+            None => Ok(code),
+        }
+    }
+
+    #[cfg(feature = "rustls")]
+    async fn accept_rustls(
+        self,
+        listeners: Vec<TcpListener>,
+        config: Arc<rustls::ServerConfig>,
+    ) -> Result<ExitCode> {
+        // Offer HTTP/2 unless the config chose its own protocols.
+        let config = if config.alpn_protocols.is_empty() {
+            let mut config = Arc::unwrap_or_clone(config);
+
+            config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+            Arc::new(config)
+        } else {
+            config
+        };
+        let acceptor = tokio_rustls::TlsAcceptor::from(config);
+        let events = self.events.clone();
+        let hooks = self.shutdown.hooks(events.clone());
+        let requested = self.shutdown.requested(events.clone());
+        let tcp = self.tcp.clone();
+        let timeout = self.shutdown.timeout;
+        let service = self.finish();
+        let handshake_timeout = service.timeouts().tls_handshake;
+        let mut connections = Connections::new(events.clone());
+        let mut requested = std::pin::pin!(requested);
+
+        for listener in &listeners {
+            println!("Server listening at https://{}", listener.local_addr()?);
+        }
+
+        let mut incoming = incoming(listeners, tcp, events.clone());
+        let mut failure = None;
+
+        loop {
+            let (stream, remote_addr) = tokio::select! {
+                Some(accepted) = incoming.next() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(error) => {
+                        failure = Some(error);
+                        break;
+                    }
+                },
+                _ = requested.as_mut() => break,
+            };
+            let acceptor = acceptor.clone();
+            let events = events.clone();
+            let mut service = service.connect(stream.local_addr().ok(), Some(remote_addr));
+
+            connections.spawn(|shutdown| async move {
+                let accept = acceptor.accept(stream);
+                let stream = match tokio::time::timeout(handshake_timeout, accept).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(error)) => {
+                        events.report(ServerEvent::TlsHandshakeFailed { error, remote_addr });
+                        return;
+                    }
+                    Err(_) => {
+                        events.report(ServerEvent::TlsHandshakeTimedOut { remote_addr });
+                        return;
+                    }
+                };
+
+                let protocol = match stream.get_ref().1.alpn_protocol() {
+                    Some(b"h2") => Protocol::Http2,
+                    _ => Protocol::Http1,
+                };
+
+                service.insert(TlsInfo::from(stream.get_ref().1));
+                serve(stream, protocol, service, shutdown).await;
+            });
+        }
+
+        drop(incoming);
+
+        let drained = connections.drain(timeout).await;
+        let code = hooks.run(drained).await;
+
+        match failure {
+            Some(error) => Err(error.into()),
+            None => Ok(code),
+        }
+    }
+
+    /// Fails on conflicting routes with strict routing, and on route names
+    /// that are registered more than once or routes at a health check path
+    /// regardless.
+    fn check_before_listening(&self) -> Result<()> {
+        routing::names::Names::try_from(&self.router)?;
+
+        if self.strict_routing {
+            return self.check_routes();
+        }
+
+        let conflicts = self.health_checks.conflicts(&self.router);
+
+        if !conflicts.is_empty() {
+            bail!("conflicting routes:\n  {}", conflicts.join("\n  "));
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Connection {
+        // Duplicate names were rejected by `check_before_listening`.
+        let names = routing::names::Names::try_from(&self.router).unwrap_or_default();
+        let names = Arc::new(names);
+
+        match self.debug_routes {
+            DebugRoutes::Enabled if cfg!(debug_assertions) => {
+                routing::index::mount(&mut self.router, "/_routes");
+            }
+            DebugRoutes::Forced => {
+                routing::index::mount(&mut self.router, "/_routes");
+            }
+            _ => {}
+        }
+
+        let body_limit = self.limits.max_body_size;
+        let proxies = self.proxies.clone();
+        let mut service = Connection::from(self);
+
+        service.insert(names);
+        service.insert(proxies);
+
+        if let Some(max) = body_limit {
+            service.insert(middleware::limit::BodyLimit(max));
+        }
+
+        service
     }
 
     fn call(&self, request: HttpRequest) -> CallFuture {
+        if let Some(future) = self.health_checks.respond(request.uri().path()) {
+            return future.map(|result| Ok(result.unwrap_or_else(Response::from).into()));
+        }
+
+        let head = request.method() == http::Method::HEAD;
         let mut context = Context::from(request);
-        let next = self.router.visit(&mut context);
+        let accepts = context.accepts();
+
+        if head && self.auto_head {
+            context.insert(routing::AutoHead);
+        }
+
+        let normalized = self.normalize_path.apply(&mut context);
+        let rewritten = self.rewrites.apply(&mut context);
+        let next = match self.hosts.visit(&self.router, &mut context) {
+            Some(next) => next,
+            None => self.router.visit(&mut context),
+        };
+        let trailing_slash = self.trailing_slash.apply(&context);
+        let future: BoxFuture<Result> = match (normalized, rewritten, trailing_slash) {
+            (Some(result), _, _) => Box::pin(async { result }),
+            (_, Err(error), _) => Box::pin(async { Err(error) }),
+            (_, _, Some(result)) => Box::pin(async { result }),
+            (_, _, None) => next.call(context),
+        };
+        let future: BoxFuture<Result> = if self.pretty_json {
+            Box::pin(PRETTY_JSON.scope(true, future))
+        } else {
+            future
+        };
+        let future: BoxFuture<Result> = Box::pin(future.map(move |result| {
+            let mut response = result.unwrap_or_else(|error| error.respond_to(&accepts));
+
+            // The body of a response to a HEAD request is never sent.
+            if head {
+                response.discard_body();
+            }
+
+            Ok(response)
+        }));
 
-        next.call(context)
-            .map(|result| Ok(result.unwrap_or_else(Response::from).into()))
+        future.map(|result| Ok(result.unwrap_or_else(Response::from).into()))
     }
 }
 
@@ -138,3 +894,29 @@ impl Endpoint for Application {
         self.router.at("/").delegate(service);
     }
 }
+
+impl<T> HttpConnection<T>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    fn graceful_shutdown(&mut self) {
+        match self {
+            HttpConnection::Http1(connection) => connection.as_mut().graceful_shutdown(),
+            HttpConnection::Http2(connection) => connection.as_mut().graceful_shutdown(),
+        }
+    }
+}
+
+impl<T> Future for HttpConnection<T>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = hyper::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut task::Context) -> Poll<Self::Output> {
+        match self.get_mut() {
+            HttpConnection::Http1(connection) => connection.as_mut().poll(context),
+            HttpConnection::Http2(connection) => connection.as_mut().poll(context),
+        }
+    }
+}
@@ -0,0 +1,520 @@
+use bytes::Bytes;
+use http::{
+    header::{
+        HeaderMap, HeaderName, HeaderValue, AGE, AUTHORIZATION, CACHE_CONTROL, COOKIE, SET_COOKIE,
+        VARY,
+    },
+    Method, StatusCode,
+};
+use http_body_util::BodyExt;
+use std::{
+    collections::{BTreeMap, HashMap},
+    mem::take,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+use crate::{response::Response, BoxFuture, Context, Middleware, Next, Result};
+
+type Key = Arc<dyn Fn(&Context) -> Option<String> + Send + Sync>;
+
+/// The request headers named by the Vary header of a response along with
+/// their values in the request that it responded to.
+type Varied = Vec<(HeaderName, Option<HeaderValue>)>;
+
+/// Caches successful responses to GET and HEAD requests in memory for `ttl`
+/// and serves them without calling the middleware that follow. Responses are
+/// keyed by method, path, and query unless `key` is set.
+///
+/// Only bodies held in memory are cached. Responses with Set-Cookie or a
+/// Cache-Control of `no-store` or `private` aren't, nor are requests with
+/// Authorization or Cookie headers unless `allow_credentials` is set. A
+/// response with Vary is only served to requests with the same values of the
+/// headers that it names, and one with `Vary: *` isn't cached. When the cache
+/// grows past `max_bytes`, the least recently used responses are evicted.
+///
+/// Concurrent misses for the same key wait for the first of them to respond
+/// rather than each calling the middleware that follow.
+#[derive(Clone)]
+pub struct Cache {
+    allow_credentials: bool,
+    key: Key,
+    max_bytes: usize,
+    store: Arc<Mutex<Store>>,
+    ttl: Duration,
+}
+
+struct Entry {
+    body: Bytes,
+    headers: HeaderMap,
+    size: usize,
+    status: StatusCode,
+    stored_at: Instant,
+    used: u64,
+    varied: Varied,
+}
+
+/// Removes its key from the requests in flight when dropped, which wakes the
+/// requests waiting on it.
+struct Flight {
+    key: String,
+    store: Arc<Mutex<Store>>,
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<String, Entry>,
+    in_flight: HashMap<String, watch::Sender<()>>,
+    /// The keys of `entries` from least to most recently used.
+    recency: BTreeMap<u64, String>,
+    size: usize,
+    uses: u64,
+}
+
+enum Lookup {
+    Hit(Response),
+    Lead(Flight),
+    Wait(watch::Receiver<()>),
+}
+
+fn is_cacheable(response: &Response) -> bool {
+    let headers = response.headers();
+    let status = response.status_code();
+    let cache_control = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| {
+            let directive = directive.trim();
+            directive.eq_ignore_ascii_case("no-store") || directive.eq_ignore_ascii_case("private")
+        });
+
+    status.is_success()
+        && status != StatusCode::PARTIAL_CONTENT
+        && response.body().is_full()
+        && !cache_control
+        && !headers.contains_key(SET_COOKIE)
+}
+
+/// Returns the values in `request` of the headers that `response` varies by,
+/// or `None` if it varies by `*`.
+fn varied(response: &Response, request: &HeaderMap) -> Option<Varied> {
+    let mut varied = Varied::new();
+
+    for name in response
+        .headers()
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        if name == "*" {
+            return None;
+        }
+
+        if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+            let value = request.get(&name).cloned();
+            varied.push((name, value));
+        }
+    }
+
+    Some(varied)
+}
+
+impl Cache {
+    pub fn new(ttl: Duration) -> Self {
+        Cache {
+            allow_credentials: false,
+            key: Arc::new(|context| {
+                let uri = context.uri();
+                let path = uri
+                    .path_and_query()
+                    .map_or(uri.path(), |path| path.as_str());
+
+                Some(format!("{} {}", context.method(), path))
+            }),
+            max_bytes: 16 * 1024 * 1024,
+            store: Default::default(),
+            ttl,
+        }
+    }
+
+    /// Caches responses to requests with Authorization or Cookie headers.
+    /// Only enable this if the key includes whatever identifies the user, or
+    /// if the responses are the same for everyone.
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        self.allow_credentials = allow;
+        self
+    }
+
+    /// Keys responses by the return value of `key`, such as the path and a
+    /// selection of query params or headers. Requests for which it returns
+    /// `None` aren't cached. The method isn't added to the key.
+    pub fn key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Context) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    /// The most memory, in bytes, that cached bodies and headers can use.
+    /// Defaults to 16 MiB.
+    pub fn max_bytes(mut self, bytes: usize) -> Self {
+        self.max_bytes = bytes;
+        self
+    }
+
+    fn lookup(&self, key: &str, headers: &HeaderMap) -> Lookup {
+        let mut store = self.store.lock().unwrap();
+
+        if let Some(response) = store.hit(key, headers, self.ttl) {
+            return Lookup::Hit(response);
+        }
+
+        if let Some(sender) = store.in_flight.get(key) {
+            return Lookup::Wait(sender.subscribe());
+        }
+
+        store.in_flight.insert(key.to_owned(), watch::channel(()).0);
+
+        Lookup::Lead(Flight {
+            key: key.to_owned(),
+            store: Arc::clone(&self.store),
+        })
+    }
+
+    async fn store(&self, key: String, varied: Varied, mut response: Response) -> Result {
+        let body = take(response.body_mut()).collect().await?.to_bytes();
+        let headers = response.headers().clone();
+        let size = key.len()
+            + body.len()
+            + headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>()
+            + varied
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.as_ref().map_or(0, |v| v.len()))
+                .sum::<usize>();
+
+        if size <= self.max_bytes {
+            let mut store = self.store.lock().unwrap();
+
+            store.remove(&key);
+            store.uses += 1;
+
+            let used = store.uses;
+
+            store.recency.insert(used, key.clone());
+            store.size += size;
+            store.entries.insert(
+                key,
+                Entry {
+                    body: body.clone(),
+                    headers,
+                    size,
+                    status: response.status_code(),
+                    stored_at: Instant::now(),
+                    used,
+                    varied,
+                },
+            );
+
+            while store.size > self.max_bytes {
+                let Some((_, oldest)) = store.recency.pop_first() else {
+                    break;
+                };
+
+                store.remove(&oldest);
+            }
+        }
+
+        *response.body_mut() = body.into();
+        Ok(response)
+    }
+}
+
+impl Middleware for Cache {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        let method = context.method();
+        let headers = context.headers();
+        let credentialed = headers.get(AUTHORIZATION).is_some() || headers.get(COOKIE).is_some();
+
+        if *method != Method::GET && *method != Method::HEAD
+            || credentialed && !self.allow_credentials
+        {
+            return next.call(context);
+        }
+
+        let key = match (self.key)(&context) {
+            Some(key) => key,
+            None => return next.call(context),
+        };
+        let cache = self.clone();
+
+        Box::pin(async move {
+            let flight = match cache.lookup(&key, context.request.headers()) {
+                Lookup::Hit(response) => return Ok(response),
+                Lookup::Lead(flight) => Some(flight),
+                Lookup::Wait(mut receiver) => {
+                    // The sender is dropped, rather than sent to, once the
+                    // first request has responded.
+                    let _ = receiver.changed().await;
+
+                    let hit =
+                        cache
+                            .store
+                            .lock()
+                            .unwrap()
+                            .hit(&key, context.request.headers(), cache.ttl);
+
+                    if let Some(response) = hit {
+                        return Ok(response);
+                    }
+
+                    None
+                }
+            };
+            // Only the request that stores the response needs its headers
+            // once the context is passed on.
+            let headers = flight.as_ref().map(|_| context.request.headers().clone());
+            let response = next.call(context).await?;
+            let varied = match headers {
+                Some(headers) if is_cacheable(&response) => varied(&response, &headers),
+                _ => None,
+            };
+            let Some(varied) = varied else {
+                return Ok(response);
+            };
+            let response = cache.store(key, varied, response).await;
+
+            drop(flight);
+            response
+        })
+    }
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        if let Ok(mut store) = self.store.lock() {
+            store.in_flight.remove(&self.key);
+        }
+    }
+}
+
+impl Store {
+    fn hit(&mut self, key: &str, headers: &HeaderMap, ttl: Duration) -> Option<Response> {
+        let entry = self.entries.get(key)?;
+        let age = entry.stored_at.elapsed();
+
+        if age >= ttl {
+            self.remove(key);
+            return None;
+        }
+
+        // A request that differs in a header the response varies by is a
+        // miss. Its response replaces the entry.
+        if entry
+            .varied
+            .iter()
+            .any(|(name, value)| headers.get(name) != value.as_ref())
+        {
+            return None;
+        }
+
+        self.uses += 1;
+
+        let used = self.uses;
+        let entry = self.entries.get_mut(key)?;
+        let previous = std::mem::replace(&mut entry.used, used);
+        let mut response = Response::new(entry.body.clone());
+
+        *response.status_mut() = entry.status;
+        *response.headers_mut() = entry.headers.clone();
+        response
+            .headers_mut()
+            .insert(AGE, HeaderValue::from(age.as_secs()));
+
+        self.recency.remove(&previous);
+        self.recency.insert(used, key.to_owned());
+
+        Some(response)
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used);
+            self.size -= entry.size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::join_all;
+    use http_body_util::BodyExt;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::Cache;
+    use crate::{
+        middleware::{context::Body, DynMiddleware},
+        Context, Middleware, Next, Respond,
+    };
+
+    /// Returns an endpoint that counts its calls and responds with the path
+    /// and the number of calls so far.
+    fn counter(delay: Duration) -> (DynMiddleware, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&calls);
+        let endpoint = move |context: Context, _: Next| {
+            let calls = counted.fetch_add(1, Ordering::SeqCst) + 1;
+            let path = context.uri().path().to_owned();
+
+            async move {
+                tokio::time::sleep(delay).await;
+
+                match path.as_str() {
+                    "/private" => format!("{} {}", path, calls)
+                        .header("cache-control", "private")
+                        .respond(),
+                    "/error" => format!("{} {}", path, calls).status(500).respond(),
+                    "/language" => format!("{} {}", path, calls)
+                        .header("vary", "accept-language")
+                        .respond(),
+                    "/anything" => format!("{} {}", path, calls).header("vary", "*").respond(),
+                    _ => format!("{} {}", path, calls).respond(),
+                }
+            }
+        };
+
+        (Arc::new(endpoint), calls)
+    }
+
+    async fn get(
+        cache: &Cache,
+        endpoint: &DynMiddleware,
+        request: http::request::Builder,
+    ) -> (String, Option<String>) {
+        let request = request.body(Body::full("".into())).unwrap();
+        let response = cache
+            .call(Context::from(request), Next::new([endpoint].into_iter()))
+            .await
+            .unwrap();
+        let response = http::Response::from(response);
+        let age = response
+            .headers()
+            .get("age")
+            .map(|age| age.to_str().unwrap().to_owned());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (String::from_utf8(body.to_vec()).unwrap(), age)
+    }
+
+    #[tokio::test]
+    async fn serves_hits_until_they_expire() {
+        let cache = Cache::new(Duration::from_millis(100));
+        let (endpoint, _) = counter(Duration::ZERO);
+        let get = |uri| get(&cache, &endpoint, http::Request::get(uri));
+
+        assert_eq!(get("/posts").await, ("/posts 1".to_owned(), None));
+        assert_eq!(
+            get("/posts").await,
+            ("/posts 1".to_owned(), Some("0".to_owned()))
+        );
+        assert_eq!(get("/posts?page=2").await.0, "/posts 2");
+        assert_eq!(get("/posts?page=2").await.0, "/posts 2");
+
+        for uri in ["/private", "/private", "/error", "/error"] {
+            assert_eq!(get(uri).await.1, None);
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(get("/posts").await, ("/posts 7".to_owned(), None));
+    }
+
+    #[tokio::test]
+    async fn bypasses_requests_with_credentials() {
+        let (endpoint, _) = counter(Duration::ZERO);
+        let cache = Cache::new(Duration::from_secs(60));
+        let authorized = || http::Request::get("/posts").header("authorization", "Bearer a");
+
+        assert_eq!(get(&cache, &endpoint, authorized()).await.0, "/posts 1");
+        assert_eq!(get(&cache, &endpoint, authorized()).await.0, "/posts 2");
+
+        let cookie = || http::Request::get("/posts").header("cookie", "session=a");
+
+        assert_eq!(get(&cache, &endpoint, cookie()).await.0, "/posts 3");
+
+        let cache = cache.allow_credentials(true).key(|context| {
+            let token = context.headers().get("authorization")?;
+            Some(format!("{} {}", context.uri().path(), token.to_str().ok()?))
+        });
+
+        assert_eq!(get(&cache, &endpoint, authorized()).await.0, "/posts 4");
+        assert_eq!(get(&cache, &endpoint, authorized()).await.0, "/posts 4");
+        assert_eq!(get(&cache, &endpoint, cookie()).await.0, "/posts 5");
+    }
+
+    #[tokio::test]
+    async fn keys_by_the_headers_responses_vary_by() {
+        let (endpoint, _) = counter(Duration::ZERO);
+        let cache = Cache::new(Duration::from_secs(60));
+        let get = |request| get(&cache, &endpoint, request);
+        let language = |value| http::Request::get("/language").header("accept-language", value);
+
+        assert_eq!(get(language("en")).await.0, "/language 1");
+        assert_eq!(get(language("en")).await.0, "/language 1");
+        assert_eq!(get(language("fr")).await.0, "/language 2");
+        assert_eq!(get(language("fr")).await.0, "/language 2");
+        assert_eq!(get(http::Request::get("/language")).await.0, "/language 3");
+
+        assert_eq!(get(http::Request::get("/anything")).await.0, "/anything 4");
+        assert_eq!(get(http::Request::get("/anything")).await.0, "/anything 5");
+    }
+
+    #[tokio::test]
+    async fn evicts_the_least_recently_used() {
+        let (endpoint, _) = counter(Duration::ZERO);
+        let size = "GET /a".len() + "/a 1".len() + "content-type".len() + "text/plain".len();
+        let cache = Cache::new(Duration::from_secs(60)).max_bytes(size * 2);
+        let get = |uri| get(&cache, &endpoint, http::Request::get(uri));
+
+        assert_eq!(get("/a").await.0, "/a 1");
+        assert_eq!(get("/b").await.0, "/b 2");
+        assert_eq!(get("/a").await.0, "/a 1");
+        assert_eq!(get("/c").await.0, "/c 3");
+
+        assert_eq!(get("/a").await.0, "/a 1");
+        assert_eq!(get("/b").await.0, "/b 4");
+    }
+
+    #[tokio::test]
+    async fn coalesces_concurrent_misses() {
+        let (endpoint, calls) = counter(Duration::from_millis(50));
+        let cache = Cache::new(Duration::from_secs(60));
+        let requests = (0..10).map(|_| get(&cache, &endpoint, http::Request::get("/slow")));
+        let responses = join_all(requests).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(responses.iter().all(|(body, _)| body == "/slow 1"));
+
+        // Waiting requests call the endpoint themselves if the response
+        // can't be cached.
+        let requests = (0..3).map(|_| get(&cache, &endpoint, http::Request::get("/error")));
+        let responses = join_all(requests).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        assert_eq!(responses.len(), 3);
+    }
+}
@@ -0,0 +1,238 @@
+use futures::FutureExt;
+use std::{any::Any, panic::AssertUnwindSafe, sync::Arc};
+
+use crate::{error::Bail, BoxFuture, Context, Error, Middleware, Next, Result};
+
+type Callback = Arc<dyn Fn(&CaughtPanic) + Send + Sync>;
+
+/// Converts a panic in the middleware that follow into a 500 Internal Server
+/// Error, so that other requests on the connection aren't affected. The panic
+/// is reported with the route that panicked, by default to stderr, and its
+/// message is only included in the response in debug builds.
+///
+/// Once the status and headers are sent, a panic while streaming the body
+/// can't be turned into a response. The connection is closed instead and
+/// the client sees a truncated body.
+#[derive(Clone)]
+pub struct CatchPanic {
+    callback: Callback,
+}
+
+#[derive(Clone, Debug)]
+pub struct CaughtPanic {
+    pub message: String,
+    /// The method and pattern of the route that panicked, such as
+    /// `GET /posts/:id`, or the path if no route was matched.
+    pub route: String,
+}
+
+fn message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload
+            .downcast_ref::<String>()
+            .map_or("Box<dyn Any>", String::as_str),
+    }
+}
+
+impl CatchPanic {
+    pub fn new() -> Self {
+        CatchPanic {
+            callback: Arc::new(|panic| {
+                eprintln!("Panic while handling {}: {}", panic.route, panic.message);
+            }),
+        }
+    }
+
+    /// Calls `callback` with each panic instead of writing it to stderr.
+    pub fn on_panic<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&CaughtPanic) + Send + Sync + 'static,
+    {
+        self.callback = Arc::new(callback);
+        self
+    }
+}
+
+impl Default for CatchPanic {
+    fn default() -> Self {
+        CatchPanic::new()
+    }
+}
+
+impl Middleware for CatchPanic {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        let route = format!(
+            "{} {}",
+            context.method(),
+            context.route_pattern().unwrap_or(context.uri().path())
+        );
+
+        let callback = Arc::clone(&self.callback);
+
+        // A middleware can panic before it returns its future, too.
+        let future = std::panic::catch_unwind(AssertUnwindSafe(|| next.call(context)));
+
+        Box::pin(async move {
+            let payload = match future {
+                Ok(future) => match AssertUnwindSafe(future).catch_unwind().await {
+                    Ok(result) => return result,
+                    Err(payload) => payload,
+                },
+                Err(payload) => payload,
+            };
+            let panic = CaughtPanic {
+                message: message(&*payload).to_owned(),
+                route,
+            };
+
+            callback(&panic);
+
+            Err(Error::from(Bail::new(match cfg!(debug_assertions) {
+                true => format!("Internal Server Error: {}", panic.message),
+                false => "Internal Server Error".to_owned(),
+            }))
+            .status(500))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, StreamExt};
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::CatchPanic;
+    use crate::{response::json_stream, Context, Next, Respond};
+
+    async fn panics(_: Context, _: Next) -> &'static str {
+        panic!("out of widgets")
+    }
+
+    fn panics_early(_: Context, _: Next) -> std::future::Ready<&'static str> {
+        panic!("before the future")
+    }
+
+    async fn start(catch_panic: CatchPanic) -> SocketAddr {
+        let mut app = crate::new();
+
+        app.include(catch_panic);
+        app.at("/panic/:id").get(panics);
+        app.at("/early").get(panics_early);
+        app.at("/stream").get(|_: Context, _: Next| async {
+            // Wait before panicking so the first line is flushed.
+            let items = stream::iter(0..2).then(|item| async move {
+                if item > 0 {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    panic!("mid-stream");
+                }
+
+                Ok::<_, Infallible>(item)
+            });
+
+            json_stream(items).respond()
+        });
+        app.at("/ok").get(|_: Context, _: Next| async { "ok" });
+        app.shutdown_signals(false);
+
+        let server = app.bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr();
+
+        tokio::spawn(server.serve());
+        address
+    }
+
+    async fn get(stream: &mut TcpStream, path: &str, close: bool) -> String {
+        let connection = if close { "close" } else { "keep-alive" };
+        let request = format!(
+            "GET {} HTTP/1.1\r\nhost: localhost\r\nconnection: {}\r\n\r\n",
+            path, connection
+        );
+        let mut output = vec![0; 4096];
+
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        if close {
+            output.clear();
+            stream.read_to_end(&mut output).await.unwrap();
+        } else {
+            let read = stream.read(&mut output).await.unwrap();
+            output.truncate(read);
+        }
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn responds_with_500_and_keeps_the_connection() {
+        let address = start(CatchPanic::new()).await;
+        let mut stream = TcpStream::connect(address).await.unwrap();
+
+        for path in ["/panic/1", "/early"] {
+            let output = get(&mut stream, path, false).await;
+
+            assert!(output.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+            assert!(output.contains("Internal Server Error: "));
+        }
+
+        let output = get(&mut stream, "/ok", true).await;
+
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\nok"));
+    }
+
+    #[tokio::test]
+    async fn reports_panics_to_the_callback() {
+        let caught = Arc::new(Mutex::new(Vec::new()));
+        let address = start(CatchPanic::new().on_panic({
+            let caught = Arc::clone(&caught);
+            move |panic| caught.lock().unwrap().push(panic.clone())
+        }))
+        .await;
+        let mut stream = TcpStream::connect(address).await.unwrap();
+
+        get(&mut stream, "/panic/1", false).await;
+        get(&mut stream, "/early", true).await;
+
+        let caught = caught.lock().unwrap();
+        let caught: Vec<_> = caught
+            .iter()
+            .map(|panic| (panic.route.as_str(), panic.message.as_str()))
+            .collect();
+
+        assert_eq!(
+            caught,
+            [
+                ("GET /panic/:id", "out of widgets"),
+                ("GET /early", "before the future"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn aborts_the_connection_when_the_body_panics() {
+        let address = start(CatchPanic::new()).await;
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let output = get(&mut stream, "/stream", true).await;
+
+        // The headers and first line are sent, but the chunked body never
+        // ends with a zero-length chunk.
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.contains("0\n"));
+        assert!(!output.ends_with("0\r\n\r\n"));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        assert!(get(&mut stream, "/ok", true)
+            .await
+            .starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
@@ -0,0 +1,102 @@
+//! Streaming encoders for each supported `Content-Encoding`, writing to a
+//! buffer that is taken after each chunk.
+
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use std::{
+    io::{self, Write},
+    mem::take,
+};
+
+use super::Encoding;
+
+/// The size of the buffer that brotli encodes into before writing it out.
+#[cfg(feature = "brotli")]
+const BROTLI_BUFFER: usize = 4096;
+
+/// The base two logarithm of the brotli window, as recommended for HTTP.
+#[cfg(feature = "brotli")]
+const BROTLI_WINDOW: u32 = 22;
+
+pub(super) enum Encoder {
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+    Gzip(GzEncoder<Vec<u8>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    pub(super) fn new(encoding: Encoding, level: u32) -> io::Result<Self> {
+        Ok(match encoding {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER,
+                level,
+                BROTLI_WINDOW,
+            ))),
+            Encoding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::new(level)))
+            }
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::new(level))),
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => {
+                Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), level as i32)?)
+            }
+        })
+    }
+
+    /// Encodes all of `input` at once.
+    pub(super) fn encode(mut self, input: &[u8]) -> io::Result<Vec<u8>> {
+        self.writer().write_all(input)?;
+        self.finish()
+    }
+
+    /// Encodes `input` and flushes it, so that it can be sent as soon as it is
+    /// written at the cost of matches that span chunks.
+    pub(super) fn write(&mut self, input: &[u8]) -> io::Result<Vec<u8>> {
+        let writer = self.writer();
+
+        writer.write_all(input)?;
+        writer.flush()?;
+        Ok(take(self.output()))
+    }
+
+    /// Returns the rest of the output, ending with the trailer of the encoding.
+    pub(super) fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(writer) => Ok(writer.into_inner()),
+            Encoder::Deflate(writer) => writer.finish(),
+            Encoder::Gzip(writer) => writer.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(writer) => writer.finish(),
+        }
+    }
+
+    fn output(&mut self) -> &mut Vec<u8> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(writer) => writer.get_mut(),
+            Encoder::Deflate(writer) => writer.get_mut(),
+            Encoder::Gzip(writer) => writer.get_mut(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(writer) => writer.get_mut(),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(writer) => writer.as_mut(),
+            Encoder::Deflate(writer) => writer,
+            Encoder::Gzip(writer) => writer,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(writer) => writer,
+        }
+    }
+}
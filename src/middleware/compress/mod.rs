@@ -0,0 +1,554 @@
+mod encoder;
+
+use http::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTENT_RANGE, CONTENT_TYPE, ETAG,
+};
+use http_body_util::BodyExt;
+use hyper::body::{Body as _, Bytes};
+use std::mem::take;
+
+use self::encoder::Encoder;
+use crate::{
+    response::{Body, Response},
+    BoxFuture, Context, Middleware, Next, Result,
+};
+
+/// Compresses response bodies with the best encoding that the client lists in
+/// `Accept-Encoding`: `gzip` or `deflate`, or `br` and `zstd` with the
+/// `brotli` and `zstd` features. Responses that are already encoded, partial,
+/// smaller than `min_size`, or of a media type that is already compressed are
+/// sent as is. Streamed bodies are compressed chunk by chunk as they are produced.
+#[derive(Clone, Copy, Debug)]
+pub struct Compress {
+    #[cfg(feature = "brotli")]
+    brotli_level: u32,
+    deflate_level: u32,
+    gzip_level: u32,
+    min_size: u64,
+    #[cfg(feature = "zstd")]
+    zstd_level: u32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Encoding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    Deflate,
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// The supported encodings, from the most to the least preferred when a
+/// client accepts several with the same q-value.
+const ENCODINGS: &[(Encoding, &str)] = &[
+    #[cfg(feature = "brotli")]
+    (Encoding::Brotli, "br"),
+    #[cfg(feature = "zstd")]
+    (Encoding::Zstd, "zstd"),
+    (Encoding::Gzip, "gzip"),
+    (Encoding::Deflate, "deflate"),
+];
+
+/// Returns the supported encoding with the highest q-value. Ties are won by
+/// the encoding that comes first in `ENCODINGS`.
+fn negotiate(headers: &HeaderMap) -> Option<Encoding> {
+    let mut qualities = [None; ENCODINGS.len()];
+    let mut wildcard = None;
+
+    for coding in headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let quality = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .map_or(Some(1.0), |value| value.parse::<f32>().ok());
+        let quality = match quality {
+            Some(quality) if (0.0..=1.0).contains(&quality) => quality,
+            _ => continue,
+        };
+        let name = if name.eq_ignore_ascii_case("x-gzip") {
+            "gzip"
+        } else {
+            name
+        };
+
+        if name == "*" {
+            wildcard = Some(quality);
+        } else if let Some(index) = ENCODINGS
+            .iter()
+            .position(|(_, encoding)| name.eq_ignore_ascii_case(encoding))
+        {
+            qualities[index] = Some(quality);
+        }
+    }
+
+    let mut best = None;
+
+    for (index, (encoding, _)) in ENCODINGS.iter().enumerate() {
+        let quality = qualities[index].or(wildcard).unwrap_or(0.0);
+
+        if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+            best = Some((*encoding, quality));
+        }
+    }
+
+    best.map(|(encoding, _)| encoding)
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    if essence == "image/svg+xml" {
+        return true;
+    }
+
+    let (kind, _) = essence.split_once('/').unwrap_or((&essence, ""));
+
+    !matches!(kind, "image" | "audio" | "video")
+        && !matches!(
+            essence.as_str(),
+            "application/gzip"
+                | "application/octet-stream"
+                | "application/pdf"
+                | "application/x-gzip"
+                | "application/zip"
+                | "application/zstd"
+                | "font/woff"
+                | "font/woff2"
+                | "text/event-stream"
+        )
+}
+
+impl Compress {
+    pub fn new() -> Self {
+        Compress {
+            #[cfg(feature = "brotli")]
+            brotli_level: 4,
+            deflate_level: 6,
+            gzip_level: 6,
+            min_size: 1024,
+            #[cfg(feature = "zstd")]
+            zstd_level: 3,
+        }
+    }
+
+    /// The compression level, from 0 to 11, of the br encoding. Defaults to 4,
+    /// since higher levels are too slow for responses that are generated per
+    /// request.
+    #[cfg(feature = "brotli")]
+    pub fn brotli_level(mut self, level: u32) -> Self {
+        self.brotli_level = level.min(11);
+        self
+    }
+
+    /// The compression level, from 0 to 9, of the deflate encoding. Defaults
+    /// to 6.
+    pub fn deflate_level(mut self, level: u32) -> Self {
+        self.deflate_level = level.min(9);
+        self
+    }
+
+    /// The compression level, from 0 to 9, of the gzip encoding. Defaults to
+    /// 6.
+    pub fn gzip_level(mut self, level: u32) -> Self {
+        self.gzip_level = level.min(9);
+        self
+    }
+
+    /// Bodies that are smaller than `min_size` bytes are not compressed.
+    /// Defaults to 1024.
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// The compression level, from 1 to 22, of the zstd encoding. Defaults to
+    /// 3.
+    #[cfg(feature = "zstd")]
+    pub fn zstd_level(mut self, level: u32) -> Self {
+        self.zstd_level = level.clamp(1, 22);
+        self
+    }
+
+    fn level(&self, encoding: Encoding) -> u32 {
+        match encoding {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => self.brotli_level,
+            Encoding::Deflate => self.deflate_level,
+            Encoding::Gzip => self.gzip_level,
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => self.zstd_level,
+        }
+    }
+
+    fn should_compress(&self, response: &Response) -> bool {
+        let headers = response.headers();
+        let status = response.status_code();
+
+        if status.is_informational() || status == 204 || status == 206 || status == 304 {
+            return false;
+        }
+
+        // The ranges of a partial response are offsets into the identity
+        // encoding.
+        if headers.contains_key(CONTENT_ENCODING) || headers.contains_key(CONTENT_RANGE) {
+            return false;
+        }
+
+        let no_transform = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.to_ascii_lowercase().contains("no-transform"));
+
+        let compressible = match headers.get(CONTENT_TYPE).map(HeaderValue::to_str) {
+            Some(Ok(content_type)) => is_compressible(content_type),
+            _ => false,
+        };
+
+        // The size of a stream without a length isn't known until it ends.
+        let large_enough = match response.body().size_hint().exact() {
+            Some(size) => size >= self.min_size,
+            None => true,
+        };
+
+        !no_transform && compressible && large_enough
+    }
+
+    async fn encode(self, mut response: Response, encoding: Encoding) -> Result {
+        let name = ENCODINGS
+            .iter()
+            .find_map(|(supported, name)| (*supported == encoding).then_some(*name))
+            .unwrap_or_default();
+        let encoder = Encoder::new(encoding, self.level(encoding))?;
+
+        if response.body().is_full() {
+            let body = take(response.body_mut()).collect().await?.to_bytes();
+            *response.body_mut() = Bytes::from(encoder.encode(&body)?).into();
+        } else {
+            *response.body_mut() = encode_stream(take(response.body_mut()), encoder);
+        }
+
+        let headers = response.headers_mut();
+
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(name));
+        headers.remove(CONTENT_LENGTH);
+
+        // The encoded body isn't byte for byte the one that a strong tag
+        // was computed for.
+        if let Some(etag) = headers
+            .get(ETAG)
+            .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+        {
+            if let Ok(weak) = HeaderValue::from_bytes(&[b"W/", etag.as_bytes()].concat()) {
+                headers.insert(ETAG, weak);
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+/// Compresses each chunk of `body` as it is read, ending the stream with the
+/// trailer of the encoding.
+fn encode_stream(body: Body, encoder: Encoder) -> Body {
+    Body::stream(futures::stream::unfold(
+        Some((body, encoder)),
+        |state| async move {
+            let (mut body, mut encoder) = state?;
+
+            loop {
+                let data = match body.frame().await {
+                    Some(Ok(frame)) => match frame.into_data() {
+                        Ok(data) => data,
+                        Err(_) => continue,
+                    },
+                    Some(Err(error)) => return Some((Err(error), None)),
+                    None => return Some((encoder.finish().map(Bytes::from), None)),
+                };
+
+                match encoder.write(&data) {
+                    Ok(encoded) if encoded.is_empty() => {}
+                    Ok(encoded) => return Some((Ok(Bytes::from(encoded)), Some((body, encoder)))),
+                    Err(error) => return Some((Err(error), None)),
+                }
+            }
+        },
+    ))
+}
+
+impl Default for Compress {
+    fn default() -> Self {
+        Compress::new()
+    }
+}
+
+impl Middleware for Compress {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        let compress = *self;
+        let encoding = negotiate(context.request.headers());
+
+        Box::pin(async move {
+            let mut response = next.call(context).await?;
+
+            if !compress.should_compress(&response) {
+                return Ok(response);
+            }
+
+            response.add_vary(ACCEPT_ENCODING);
+
+            match encoding {
+                Some(encoding) => compress.encode(response, encoding).await,
+                None => Ok(response),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::{
+        ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_RANGE, CONTENT_TYPE, ETAG, VARY,
+    };
+    use http_body_util::BodyExt;
+    use hyper::body::Bytes;
+    use std::{io::Read, sync::Arc};
+
+    use super::{negotiate, Compress, Encoding, ENCODINGS};
+    use crate::{
+        middleware::{context, DynMiddleware},
+        response::Body,
+        Context, Middleware, Next, Respond, Response,
+    };
+
+    /// Decodes `input` with a decoder that doesn't share code with the
+    /// encoder: libflate for gzip and deflate, brotli-decompressor for br,
+    /// and the pure Rust ruzstd for zstd.
+    fn decode(encoding: &str, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut reader: Box<dyn Read + '_> = match encoding {
+            #[cfg(feature = "brotli")]
+            "br" => Box::new(brotli::Decompressor::new(input, 4096)),
+            "deflate" => Box::new(libflate::zlib::Decoder::new(input).unwrap()),
+            "gzip" => Box::new(libflate::gzip::Decoder::new(input).unwrap()),
+            "zstd" => Box::new(ruzstd::decoding::StreamingDecoder::new(input).unwrap()),
+            _ => panic!("unknown encoding {}", encoding),
+        };
+
+        reader.read_to_end(&mut output).unwrap();
+        output
+    }
+
+    /// The names and levels of each supported encoding, from the fastest to
+    /// the smallest.
+    fn levels() -> Vec<(&'static str, Compress)> {
+        let compress = Compress::new();
+        let mut levels = Vec::new();
+
+        for level in 0..10 {
+            levels.push(("deflate", compress.deflate_level(level)));
+            levels.push(("gzip", compress.gzip_level(level)));
+        }
+
+        #[cfg(feature = "brotli")]
+        for level in 0..12 {
+            levels.push(("br", compress.brotli_level(level)));
+        }
+
+        #[cfg(feature = "zstd")]
+        for level in [1, 3, 9, 19, 22] {
+            levels.push(("zstd", compress.zstd_level(level)));
+        }
+
+        levels
+    }
+
+    fn json() -> String {
+        let items: Vec<_> = (0..100)
+            .map(|id| format!(r#"{{"id":{},"title":"Post {}"}}"#, id, id))
+            .collect();
+
+        format!("[{}]", items.join(","))
+    }
+
+    async fn respond(_: Context, _: Next) -> crate::Result {
+        json().respond().map(|mut response| {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+            response
+        })
+    }
+
+    async fn stream(_: Context, _: Next) -> crate::Result {
+        let chunks: Vec<_> = json()
+            .into_bytes()
+            .chunks(100)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let mut response = Response::new(Body::stream(futures::stream::iter(chunks)));
+
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        response
+            .headers_mut()
+            .insert(ETAG, r#""strong""#.parse().unwrap());
+        Ok(response)
+    }
+
+    async fn partial(_: Context, _: Next) -> crate::Result {
+        let body = json();
+        let content_range = format!("bytes 0-{}/{}", body.len() - 1, body.len() * 2);
+        let mut response = Response::new(body);
+
+        *response.status_mut() = http::StatusCode::PARTIAL_CONTENT;
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        response
+            .headers_mut()
+            .insert(CONTENT_RANGE, content_range.parse().unwrap());
+        Ok(response)
+    }
+
+    async fn image(_: Context, _: Next) -> crate::Result {
+        json().respond().map(|mut response| {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, "image/png".parse().unwrap());
+            response
+        })
+    }
+
+    async fn call(
+        compress: Compress,
+        accept: Option<&str>,
+        stack: DynMiddleware,
+    ) -> (http::HeaderMap, Vec<u8>) {
+        let mut request = http::Request::get("/");
+
+        if let Some(accept) = accept {
+            request = request.header(ACCEPT_ENCODING, accept);
+        }
+
+        let context = Context::from(request.body(context::Body::full("".into())).unwrap());
+        let stack = [stack];
+        let response = compress
+            .call(context, Next::new(stack.iter()))
+            .await
+            .unwrap();
+        let (parts, body) = http::Response::from(response).into_parts();
+
+        (
+            parts.headers,
+            body.collect().await.unwrap().to_bytes().to_vec(),
+        )
+    }
+
+    #[tokio::test]
+    async fn compresses_with_the_negotiated_encoding() {
+        let body = json();
+
+        for (encoding, compress) in levels() {
+            let (headers, encoded) = call(compress, Some(encoding), Arc::new(respond)).await;
+
+            assert_eq!(headers[CONTENT_ENCODING], encoding);
+            assert_eq!(headers[VARY], "accept-encoding");
+            assert!(headers.get("content-length").is_none());
+            assert_eq!(decode(encoding, &encoded), body.as_bytes(), "{}", encoding);
+        }
+
+        let (_, gzip) = call(Compress::new(), Some("gzip"), Arc::new(respond)).await;
+
+        assert!(gzip.len() < body.len() / 4);
+    }
+
+    #[tokio::test]
+    async fn compresses_streams_as_they_are_read() {
+        let body = json();
+
+        for (encoding, compress) in levels() {
+            let (headers, encoded) = call(compress, Some(encoding), Arc::new(stream)).await;
+
+            assert_eq!(headers[CONTENT_ENCODING], encoding);
+            assert_eq!(headers[ETAG], r#"W/"strong""#);
+            assert_eq!(decode(encoding, &encoded), body.as_bytes(), "{}", encoding);
+        }
+
+        let (headers, identity) = call(Compress::new(), None, Arc::new(stream)).await;
+
+        assert_eq!(headers[ETAG], r#""strong""#);
+        assert_eq!(identity, body.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn skips_ineligible_responses() {
+        let body = json();
+        let (headers, identity) = call(Compress::new(), None, Arc::new(respond)).await;
+
+        assert!(headers.get(CONTENT_ENCODING).is_none());
+        assert_eq!(headers[VARY], "accept-encoding");
+        assert_eq!(identity, body.as_bytes());
+
+        let small = Compress::new().min_size(body.len() as u64 + 1);
+        let (headers, _) = call(small, Some("gzip"), Arc::new(respond)).await;
+
+        assert!(headers.get(CONTENT_ENCODING).is_none());
+
+        let (headers, png) = call(Compress::new(), Some("gzip"), Arc::new(image)).await;
+
+        assert!(headers.get(CONTENT_ENCODING).is_none());
+        assert!(headers.get(VARY).is_none());
+        assert_eq!(png, body.as_bytes());
+
+        let (headers, range) = call(Compress::new(), Some("gzip"), Arc::new(partial)).await;
+
+        assert!(headers.get(CONTENT_ENCODING).is_none());
+        assert_eq!(range, body.as_bytes());
+    }
+
+    #[test]
+    fn negotiates_by_quality() {
+        let negotiate = |value: &str| {
+            let mut headers = http::HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, value.parse().unwrap());
+            negotiate(&headers)
+        };
+
+        assert_eq!(negotiate("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0, deflate"), Some(Encoding::Deflate));
+        assert_eq!(
+            negotiate("gzip;q=0.5, deflate;q=0.8"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate("*"), Some(ENCODINGS[0].0));
+        assert_eq!(negotiate("*, deflate;q=0.9"), Some(ENCODINGS[0].0));
+        assert_eq!(
+            negotiate("x-gzip;q=0.5, deflate;q=0.4"),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate("compress, identity"), None);
+        assert_eq!(negotiate("*;q=0"), None);
+
+        #[cfg(feature = "brotli")]
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+
+        #[cfg(feature = "zstd")]
+        assert_eq!(
+            negotiate("br;q=0.5, gzip;q=0.9, zstd"),
+            Some(Encoding::Zstd)
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+
+use crate::{BoxFuture, Context, Middleware, Next, Respond, Result};
+
+/// Limits how many requests the middleware that follow handle at once.
+/// Requests over the limit wait in a bounded queue, in the order they
+/// arrived, and are answered with 503 Service Unavailable when the queue is
+/// full or they wait longer than `queue_timeout`.
+///
+/// Clones share the same limit, so one can be included on several routes to
+/// share a pool. Construct one per route to isolate them.
+#[derive(Clone, Debug)]
+pub struct ConcurrencyLimit {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    max: usize,
+    queue: usize,
+    queue_timeout: Option<Duration>,
+    queued: AtomicUsize,
+    retry_after: Duration,
+    semaphore: Semaphore,
+}
+
+/// Leaves the queue when dropped.
+struct Queued<'a>(&'a AtomicUsize);
+
+impl ConcurrencyLimit {
+    pub fn new(max: usize) -> Self {
+        ConcurrencyLimit {
+            shared: Arc::new(Shared {
+                max,
+                queue: 0,
+                queue_timeout: None,
+                queued: AtomicUsize::new(0),
+                retry_after: Duration::from_secs(1),
+                semaphore: Semaphore::new(max),
+            }),
+        }
+    }
+
+    /// The number of requests that can wait for a permit. Defaults to 0, so
+    /// requests over the limit are shed immediately.
+    pub fn queue(self, queue: usize) -> Self {
+        self.configure(|shared| shared.queue = queue)
+    }
+
+    /// How long a request can wait in the queue before it is shed.
+    pub fn queue_timeout(self, timeout: Duration) -> Self {
+        self.configure(|shared| shared.queue_timeout = Some(timeout))
+    }
+
+    /// The Retry-After of shed requests, rounded up to whole seconds.
+    /// Defaults to 1 second.
+    pub fn retry_after(self, retry_after: Duration) -> Self {
+        self.configure(|shared| shared.retry_after = retry_after)
+    }
+
+    /// The number of requests being handled.
+    pub fn in_flight(&self) -> usize {
+        self.shared.max - self.shared.semaphore.available_permits()
+    }
+
+    /// The number of requests waiting for a permit.
+    pub fn queued(&self) -> usize {
+        self.shared.queued.load(Ordering::Relaxed)
+    }
+
+    fn configure(mut self, configure: impl FnOnce(&mut Shared)) -> Self {
+        match Arc::get_mut(&mut self.shared) {
+            Some(shared) => configure(shared),
+            None => panic!("ConcurrencyLimit can't be configured once it is cloned"),
+        }
+
+        self
+    }
+}
+
+impl Middleware for ConcurrencyLimit {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        let shared = Arc::clone(&self.shared);
+
+        Box::pin(async move {
+            let _permit = match shared.semaphore.try_acquire() {
+                Ok(permit) => permit,
+                Err(_) => match shared.wait().await {
+                    Some(permit) => permit,
+                    None => return shared.shed(),
+                },
+            };
+
+            next.call(context).await
+        })
+    }
+}
+
+impl Shared {
+    async fn wait(&self) -> Option<tokio::sync::SemaphorePermit<'_>> {
+        let _queued = Queued::enter(&self.queued, self.queue)?;
+        let acquire = self.semaphore.acquire();
+        let acquired = match self.queue_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, acquire).await.ok()?,
+            None => acquire.await,
+        };
+
+        acquired.ok()
+    }
+
+    fn shed(&self) -> Result {
+        let seconds = self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0);
+
+        "Service Unavailable"
+            .status(503)
+            .header("retry-after", seconds.to_string())
+            .respond()
+    }
+}
+
+impl<'a> Queued<'a> {
+    fn enter(queued: &'a AtomicUsize, max: usize) -> Option<Self> {
+        queued
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |len| {
+                (len < max).then_some(len + 1)
+            })
+            .ok()?;
+
+        Some(Queued(queued))
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::Notify;
+
+    use super::ConcurrencyLimit;
+    use crate::{
+        middleware::{context::Body, DynMiddleware},
+        BoxFuture, Context, Middleware, Next, Respond,
+    };
+
+    fn context() -> Context {
+        Context::from(http::Request::get("/").body(Body::full("".into())).unwrap())
+    }
+
+    /// Calls `limit` with an endpoint that responds once `release` is
+    /// notified.
+    fn call(limit: &ConcurrencyLimit, release: &Arc<Notify>) -> BoxFuture<(u16, Option<String>)> {
+        let release = Arc::clone(release);
+        let endpoint: DynMiddleware = Arc::new(move |_: Context, _: Next| {
+            let release = Arc::clone(&release);
+
+            async move {
+                release.notified().await;
+                "done".respond()
+            }
+        });
+        let limit = limit.clone();
+
+        Box::pin(async move {
+            let response = limit
+                .call(context(), Next::new([&endpoint].into_iter()))
+                .await
+                .unwrap();
+            let response = http::Response::from(response);
+            let retry_after = response.headers().get("retry-after");
+
+            (
+                response.status().as_u16(),
+                retry_after.map(|value| value.to_str().unwrap().to_owned()),
+            )
+        })
+    }
+
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    #[tokio::test]
+    async fn queues_requests_over_the_limit() {
+        let release = Arc::new(Notify::new());
+        let limit = ConcurrencyLimit::new(2).queue(1);
+        let first = tokio::spawn(call(&limit, &release));
+        let second = tokio::spawn(call(&limit, &release));
+
+        settle().await;
+        assert_eq!((limit.in_flight(), limit.queued()), (2, 0));
+
+        let third = tokio::spawn(call(&limit, &release));
+
+        settle().await;
+        assert_eq!((limit.in_flight(), limit.queued()), (2, 1));
+        assert_eq!(call(&limit, &release).await, (503, Some("1".to_owned())));
+
+        release.notify_one();
+        settle().await;
+        assert_eq!((limit.in_flight(), limit.queued()), (2, 0));
+
+        release.notify_waiters();
+        settle().await;
+        release.notify_waiters();
+
+        for handle in [first, second, third] {
+            assert_eq!(handle.await.unwrap(), (200, None));
+        }
+
+        assert_eq!((limit.in_flight(), limit.queued()), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn sheds_requests_that_wait_too_long() {
+        let release = Arc::new(Notify::new());
+        let limit = ConcurrencyLimit::new(1)
+            .queue(8)
+            .queue_timeout(Duration::from_millis(50))
+            .retry_after(Duration::from_millis(1500));
+        let first = tokio::spawn(call(&limit, &release));
+
+        settle().await;
+        assert_eq!(call(&limit, &release).await, (503, Some("2".to_owned())));
+        assert_eq!(limit.queued(), 0);
+
+        release.notify_one();
+        assert_eq!(first.await.unwrap(), (200, None));
+    }
+}
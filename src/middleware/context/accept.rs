@@ -0,0 +1,173 @@
+use http::header::{HeaderMap, ACCEPT};
+use mime::{Mime, STAR};
+
+/// The media ranges listed in an `Accept` header, used to pick the best
+/// representation of a response.
+#[derive(Clone, Debug)]
+pub struct Accepts {
+    ranges: Vec<(Mime, u16)>,
+}
+
+/// Parses a q-value into thousandths. Values outside of `0..=1` are invalid.
+fn quality(range: &Mime) -> Option<u16> {
+    let value = match range.get_param("q") {
+        Some(value) => value.as_str().parse::<f32>().ok()?,
+        None => return Some(1000),
+    };
+
+    (0.0..=1.0)
+        .contains(&value)
+        .then(|| (value * 1000.0).round() as u16)
+}
+
+/// Returns how specifically `range` matches `offered`, if at all.
+fn specificity(range: &Mime, offered: &Mime) -> Option<usize> {
+    if range.type_() == STAR {
+        return Some(0);
+    }
+
+    if range.type_() != offered.type_() {
+        return None;
+    }
+
+    if range.subtype() == STAR {
+        return Some(1);
+    }
+
+    if range.subtype() != offered.subtype() || range.suffix() != offered.suffix() {
+        return None;
+    }
+
+    let mut params = 0;
+
+    for (name, value) in range.params().filter(|(name, _)| *name != "q") {
+        if offered.get_param(name) != Some(value) {
+            return None;
+        }
+
+        params += 1;
+    }
+
+    Some(2 + params)
+}
+
+impl Accepts {
+    pub(crate) fn new(headers: &HeaderMap) -> Self {
+        let ranges = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|segment| {
+                let range = segment.trim().parse().ok()?;
+                let quality = quality(&range)?;
+
+                Some((range, quality))
+            })
+            .collect();
+
+        Accepts { ranges }
+    }
+
+    /// Returns the offered media type with the highest quality. Ties are won
+    /// by the type that is offered first.
+    pub fn best<'a>(&self, offered: &'a [Mime]) -> Option<&'a Mime> {
+        let mut best = None;
+        let mut highest = 0;
+
+        for mime in offered {
+            let quality = self.quality(mime);
+
+            if quality > highest {
+                best = Some(mime);
+                highest = quality;
+            }
+        }
+
+        best
+    }
+
+    /// Returns true if `mime` is acceptable to the client.
+    pub fn includes(&self, mime: &Mime) -> bool {
+        self.quality(mime) > 0
+    }
+
+    /// Returns the quality of the most specific range that matches `mime`.
+    /// Without any ranges every type is acceptable.
+    fn quality(&self, mime: &Mime) -> u16 {
+        if self.ranges.is_empty() {
+            return 1000;
+        }
+
+        self.ranges
+            .iter()
+            .filter_map(|(range, quality)| Some((specificity(range, mime)?, *quality)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0, |(_, quality)| quality)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderMap;
+    use mime::{Mime, APPLICATION_JSON, TEXT_HTML, TEXT_PLAIN};
+
+    use super::Accepts;
+
+    fn best(accept: Option<&str>, offered: &[Mime]) -> Option<Mime> {
+        let mut headers = HeaderMap::new();
+
+        if let Some(value) = accept {
+            headers.insert("accept", value.parse().unwrap());
+        }
+
+        Accepts::new(&headers).best(offered).cloned()
+    }
+
+    #[test]
+    fn honors_quality_and_specificity() {
+        let offered = [APPLICATION_JSON, TEXT_HTML];
+        let browser = "text/html,application/xhtml+xml;q=0.9,*/*;q=0.8";
+
+        assert_eq!(best(Some(browser), &offered), Some(TEXT_HTML));
+        assert_eq!(
+            best(Some("application/*;q=0.5, text/*;q=0.4"), &offered),
+            Some(APPLICATION_JSON)
+        );
+        assert_eq!(
+            best(Some("text/*;q=0.4, text/html;q=0.1, */*;q=0.3"), &offered),
+            Some(APPLICATION_JSON)
+        );
+    }
+
+    #[test]
+    fn first_offered_wins_ties() {
+        let offered = [APPLICATION_JSON, TEXT_HTML];
+
+        assert_eq!(best(None, &offered), Some(APPLICATION_JSON));
+        assert_eq!(
+            best(Some("*/*"), &[TEXT_HTML, APPLICATION_JSON]),
+            Some(TEXT_HTML)
+        );
+        assert_eq!(
+            best(Some("text/html, application/json"), &offered),
+            Some(APPLICATION_JSON)
+        );
+    }
+
+    #[test]
+    fn excludes_zero_quality_and_skips_malformed_segments() {
+        assert_eq!(best(Some("*/*, text/html;q=0"), &[TEXT_HTML]), None);
+        assert_eq!(
+            best(Some("*/*, text/html;q=0"), &[TEXT_HTML, TEXT_PLAIN]),
+            Some(TEXT_PLAIN)
+        );
+        assert_eq!(
+            best(
+                Some("garbage, text/html;q=2, application/json"),
+                &[TEXT_HTML, APPLICATION_JSON]
+            ),
+            Some(APPLICATION_JSON)
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// The credentials sent in an `Authorization` header.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Authorization {
+    Basic { username: String, password: String },
+    Bearer(String),
+}
+
+impl Authorization {
+    /// Parses the value of an `Authorization` header. Returns `None` if the
+    /// scheme is not supported or the credentials are malformed.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (scheme, credentials) = value.trim().split_once(' ')?;
+        let credentials = credentials.trim();
+
+        if credentials.is_empty() {
+            return None;
+        }
+
+        if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = String::from_utf8(STANDARD.decode(credentials).ok()?).ok()?;
+            let (username, password) = decoded.split_once(':')?;
+
+            Some(Authorization::Basic {
+                username: username.to_owned(),
+                password: password.to_owned(),
+            })
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            Some(Authorization::Bearer(credentials.to_owned()))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Authorization;
+
+    #[test]
+    fn decodes_basic_credentials() {
+        // base64 of `aladdin:open:sesame`
+        assert_eq!(
+            Authorization::parse("Basic YWxhZGRpbjpvcGVuOnNlc2FtZQ=="),
+            Some(Authorization::Basic {
+                username: "aladdin".to_owned(),
+                password: "open:sesame".to_owned(),
+            })
+        );
+        assert_eq!(
+            Authorization::parse("bearer abc.def"),
+            Some(Authorization::Bearer("abc.def".to_owned()))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_credentials() {
+        for value in [
+            // base64 of `aladdin` without a colon
+            "Basic YWxhZGRpbg==",
+            "Basic not base64!",
+            "Basic ",
+            "Bearer",
+            "Digest username=aladdin",
+            "",
+        ] {
+            assert_eq!(Authorization::parse(value), None, "{}", value);
+        }
+    }
+}
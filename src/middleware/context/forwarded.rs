@@ -0,0 +1,256 @@
+use http::header::{HeaderMap, FORWARDED};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+/// The header that trusted proxies use to report the address of the client.
+/// Only the configured header is read, since a client can send the other one
+/// and the proxy will pass it through untouched.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ProxyHeader {
+    /// The standard `Forwarded` header of RFC 7239.
+    Forwarded,
+
+    /// The `X-Forwarded-For` header written by most load balancers, such as
+    /// AWS ALB and nginx.
+    #[default]
+    XForwardedFor,
+}
+
+/// The networks of the proxies that are trusted to report the address of the
+/// client in the `Forwarded` or `X-Forwarded-For` headers.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TrustedProxies {
+    header: ProxyHeader,
+    networks: Arc<Vec<Network>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Network {
+    address: IpAddr,
+    prefix: u32,
+}
+
+fn hop(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+
+    if let Some(rest) = value.strip_prefix('[') {
+        return rest[..rest.find(']')?].parse().ok();
+    }
+
+    value
+        .parse()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// Returns the hops listed in the given header of the request. Hops that can't
+/// be parsed are `None`.
+fn hops(header: ProxyHeader, headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    if header == ProxyHeader::Forwarded {
+        return headers
+            .get_all(FORWARDED)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.split_once('=')?;
+                    name.trim().eq_ignore_ascii_case("for").then(|| hop(value))
+                })
+            })
+            .collect();
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(hop)
+        .collect()
+}
+
+impl Network {
+    fn parse(source: &str) -> Option<Self> {
+        let (address, prefix) = match source.split_once('/') {
+            Some((address, prefix)) => (address.parse().ok()?, Some(prefix.parse().ok()?)),
+            None => (source.parse().ok()?, None),
+        };
+        let bits = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = prefix.unwrap_or(bits);
+
+        (prefix <= bits).then_some(Network { address, prefix })
+    }
+
+    fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl TrustedProxies {
+    pub(crate) fn new(header: ProxyHeader, networks: &[&str]) -> Self {
+        let networks = networks.iter().map(|source| match Network::parse(source) {
+            Some(network) => network,
+            None => panic!(r#"invalid trusted proxy "{}""#, source),
+        });
+
+        TrustedProxies {
+            header,
+            networks: Arc::new(networks.collect()),
+        }
+    }
+
+    fn contains(&self, address: IpAddr) -> bool {
+        self.networks
+            .iter()
+            .any(|network| network.contains(address))
+    }
+
+    /// Walks the forwarded hops right-to-left, starting at the peer, and
+    /// returns the first address that isn't a trusted proxy.
+    pub(crate) fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer;
+
+        if !self.contains(client) {
+            return client;
+        }
+
+        for hop in hops(self.header, headers).into_iter().rev() {
+            match hop {
+                Some(address) => client = address,
+                None => break,
+            }
+
+            if !self.contains(client) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderMap;
+    use std::net::IpAddr;
+
+    use super::{ProxyHeader, TrustedProxies};
+
+    fn resolve(proxies: &[&str], peer: &str, headers: &[(&'static str, &str)]) -> IpAddr {
+        resolve_with(ProxyHeader::XForwardedFor, proxies, peer, headers)
+    }
+
+    fn resolve_with(
+        header: ProxyHeader,
+        proxies: &[&str],
+        peer: &str,
+        headers: &[(&'static str, &str)],
+    ) -> IpAddr {
+        let mut map = HeaderMap::new();
+
+        for (name, value) in headers {
+            map.append(*name, value.parse().unwrap());
+        }
+
+        TrustedProxies::new(header, proxies).resolve(peer.parse().unwrap(), &map)
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let spoofed = [("x-forwarded-for", "1.2.3.4")];
+
+        assert_eq!(
+            resolve(&[], "203.0.113.9", &spoofed).to_string(),
+            "203.0.113.9"
+        );
+        assert_eq!(
+            resolve(&["10.0.0.0/8"], "203.0.113.9", &spoofed).to_string(),
+            "203.0.113.9"
+        );
+    }
+
+    #[test]
+    fn walks_trusted_hops_right_to_left() {
+        let proxies = ["10.0.0.0/8", "127.0.0.1"];
+        let forwarded_for = [("x-forwarded-for", "1.2.3.4, 198.51.100.7, 10.1.2.3")];
+
+        assert_eq!(
+            resolve(&proxies, "127.0.0.1", &forwarded_for).to_string(),
+            "198.51.100.7"
+        );
+        assert_eq!(
+            resolve(&proxies, "::ffff:10.0.0.1", &forwarded_for).to_string(),
+            "198.51.100.7"
+        );
+        assert_eq!(
+            resolve(&proxies, "10.0.0.1", &[("x-forwarded-for", "10.0.0.2")]).to_string(),
+            "10.0.0.2"
+        );
+    }
+
+    #[test]
+    fn reads_the_forwarded_header() {
+        let forwarded = ProxyHeader::Forwarded;
+        let headers = [
+            ("x-forwarded-for", "1.2.3.4"),
+            (
+                "forwarded",
+                r#"for="[2001:db8::1]:4711";proto=https, for=10.0.0.5"#,
+            ),
+        ];
+
+        assert_eq!(
+            resolve_with(forwarded, &["10.0.0.0/8"], "10.0.0.1", &headers).to_string(),
+            "2001:db8::1"
+        );
+        assert_eq!(
+            resolve_with(
+                forwarded,
+                &["10.0.0.0/8"],
+                "10.0.0.1",
+                &[("forwarded", "for=unknown")]
+            )
+            .to_string(),
+            "10.0.0.1"
+        );
+    }
+
+    #[test]
+    fn ignores_a_spoofed_forwarded_header() {
+        let headers = [
+            ("forwarded", "for=1.2.3.4"),
+            ("x-forwarded-for", "198.51.100.7"),
+        ];
+
+        assert_eq!(
+            resolve(&["10.0.0.0/8"], "10.0.0.1", &headers).to_string(),
+            "198.51.100.7"
+        );
+        assert_eq!(
+            resolve(&["10.0.0.0/8"], "10.0.0.1", &[("forwarded", "for=1.2.3.4")]).to_string(),
+            "10.0.0.1"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid trusted proxy")]
+    fn rejects_invalid_networks() {
+        TrustedProxies::new(ProxyHeader::XForwardedFor, &["10.0.0.0/33"]);
+    }
+}
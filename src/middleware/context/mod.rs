@@ -1,24 +1,63 @@
 // pub mod cookies;
-
-use crate::{Error, Result};
+mod accept;
+mod authorization;
+mod forwarded;
+mod multipart;
+mod precondition;
+pub(crate) mod query;
+mod range;
+
+pub use accept::Accepts;
+pub use authorization::Authorization;
+pub use forwarded::ProxyHeader;
+pub(crate) use forwarded::TrustedProxies;
+pub use multipart::{Multipart, Part};
+pub use precondition::Precondition;
+pub use query::QueryLimits;
+pub use range::{ByteRange, Range, Unsatisfiable};
+
+use crate::{
+    error::Bail,
+    middleware::{
+        cookies::Jar,
+        limit::{BodyLimit, DEFAULT_BODY_LIMIT},
+        request_id::Id,
+        session::SessionData,
+        timeout::Deadline,
+    },
+    response::{self, Response},
+    routing::{names::Names, OriginalUri, RoutePattern},
+    Error, Result,
+};
 use bytes::Buf;
+use futures::{stream::BoxStream, Stream, StreamExt};
 use http::header::{self, AsHeaderName, HeaderMap, HeaderName, HeaderValue};
 use http::{Method, Uri, Version};
-use http_body_util::{BodyExt, Empty};
-use hyper::body::{Bytes, Incoming};
+use http_body_util::{BodyExt, Empty, Full, LengthLimitError, Limited};
+use httpdate::HttpDate;
+use hyper::body::{Body as _, Bytes, Incoming, SizeHint};
 use indexmap::IndexMap;
+use mime::Mime;
+use percent_encoding::percent_decode_str;
 use serde::de::DeserializeOwned;
-use std::io::Read;
 use std::{
+    borrow::Cow,
     fmt::{self, Debug, Formatter},
+    io,
     mem::replace,
+    net::{IpAddr, SocketAddr},
     str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
     // task::{self, Poll},
 };
 
 type Request = http::Request<Body>;
 
-pub struct Body(BodyState);
+pub struct Body {
+    limit: Option<usize>,
+    state: BodyState,
+}
 
 #[derive(Debug)]
 pub struct Context {
@@ -31,6 +70,10 @@ pub struct Headers<'a> {
     entries: &'a HeaderMap,
 }
 
+/// The params captured from the path of the request. Values are stored as
+/// they appear in the still percent-encoded path, so an encoded `%2F` is
+/// never treated as a path separator when routes are matched. Use the
+/// `decode` methods to decode a value after routing.
 #[derive(Default, Clone)]
 pub struct Parameters {
     entries: IndexMap<&'static str, String>,
@@ -41,13 +84,37 @@ pub(super) struct State {
     pub(super) params: Parameters,
 }
 
-#[derive(Debug)]
 enum BodyState {
     Empty(Empty<Bytes>),
+    Full(Full<Bytes>),
     Incoming(Incoming),
+    // The stream is only polled through `&mut self`, so the mutex is never
+    // contended. It makes the body `Sync` like the other states.
+    Stream(Mutex<BoxStream<'static, Result<Bytes>>>),
 }
 
 impl Body {
+    /// Returns the next chunk of data in the body, skipping trailers.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        loop {
+            let frame = match &mut self.state {
+                BodyState::Empty(_) => return Ok(None),
+                BodyState::Full(full) => full.frame().await.transpose()?,
+                BodyState::Incoming(incoming) => incoming.frame().await.transpose()?,
+                BodyState::Stream(stream) => {
+                    let stream = stream.get_mut().unwrap_or_else(|e| e.into_inner());
+                    return stream.next().await.transpose();
+                }
+            };
+
+            match frame.map(|frame| frame.into_data()) {
+                Some(Ok(data)) => return Ok(Some(data)),
+                Some(Err(_)) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
     pub async fn json<T>(self) -> Result<T>
     where
         T: DeserializeOwned,
@@ -56,40 +123,151 @@ impl Body {
         serde_json::from_reader(reader).map_err(|e| Error::from(e).status(400).json())
     }
 
+    /// Parses a body of newline-delimited JSON, yielding each value as soon
+    /// as its line is received. Blank lines are skipped and a line that isn't
+    /// valid JSON yields a 400 Bad Request error.
+    pub fn json_lines<T>(self) -> impl Stream<Item = Result<T>> + Send
+    where
+        T: DeserializeOwned,
+    {
+        futures::stream::unfold(
+            (self, Vec::new(), false),
+            |(mut body, mut buffer, mut done)| async move {
+                loop {
+                    let line = match buffer.iter().position(|byte| *byte == b'\n') {
+                        Some(index) => buffer.drain(..=index).collect(),
+                        None if done => std::mem::take(&mut buffer),
+                        None => {
+                            match body.chunk().await {
+                                Ok(Some(chunk)) => buffer.extend_from_slice(&chunk),
+                                Ok(None) => done = true,
+                                Err(error) => {
+                                    return Some((Err(error), (body, Vec::new(), true)));
+                                }
+                            }
+
+                            continue;
+                        }
+                    };
+
+                    if line.trim_ascii().is_empty() {
+                        if done && buffer.is_empty() {
+                            return None;
+                        }
+
+                        continue;
+                    }
+
+                    let value = serde_json::from_slice(&line)
+                        .map_err(|e| Error::from(e).status(400).json());
+
+                    return Some((value, (body, buffer, done)));
+                }
+            },
+        )
+    }
+
+    /// Reads the body into memory, responding with 413 Payload Too Large as
+    /// soon as more than `max` bytes are announced or received.
+    pub async fn limited(self, max: usize) -> Result<Bytes> {
+        let too_large = || Err(Error::from(Bail::new("Payload Too Large")).status(413));
+        let result = match self.state {
+            BodyState::Empty(_) => return Ok(Bytes::new()),
+            BodyState::Full(full) => Limited::new(full, max).collect().await,
+            BodyState::Incoming(incoming) => {
+                // The size hint of an incoming body reflects its Content-Length.
+                if incoming.size_hint().lower() > max as u64 {
+                    return too_large();
+                }
+
+                Limited::new(incoming, max).collect().await
+            }
+            BodyState::Stream(stream) => {
+                let mut stream = stream.into_inner().unwrap_or_else(|e| e.into_inner());
+                let mut body = Vec::new();
+
+                while let Some(chunk) = stream.next().await {
+                    let chunk = chunk?;
+
+                    if body.len() + chunk.len() > max {
+                        return too_large();
+                    }
+
+                    body.extend_from_slice(&chunk);
+                }
+
+                return Ok(body.into());
+            }
+        };
+
+        match result {
+            Ok(collected) => Ok(collected.to_bytes()),
+            Err(error) if error.is::<LengthLimitError>() => too_large(),
+            Err(error) => Err(Bail::new(error.to_string()).into()),
+        }
+    }
+
     pub async fn text(self) -> Result<String> {
         let bytes = self.vec().await?;
         Ok(String::from_utf8(bytes)?)
     }
 
     pub async fn vec(self) -> Result<Vec<u8>> {
-        let buf = self.aggregate().await?;
-        let mut bytes = Vec::with_capacity(buf.remaining());
-
-        buf.reader().read_to_end(&mut bytes)?;
-        Ok(bytes)
+        Ok(self.aggregate().await?.into())
     }
 }
 
 impl Body {
+    fn new(state: BodyState) -> Self {
+        Body { limit: None, state }
+    }
+
     fn incoming(incoming: Incoming) -> Self {
-        Body(BodyState::Incoming(incoming))
+        Body::new(BodyState::Incoming(incoming))
     }
 
     fn empty() -> Self {
-        Body(BodyState::Empty(Empty::new()))
+        Body::new(BodyState::Empty(Empty::new()))
+    }
+
+    pub(crate) fn full(bytes: Bytes) -> Self {
+        Body::new(BodyState::Full(Full::new(bytes)))
     }
 
-    async fn aggregate(self) -> Result<impl Buf> {
-        Ok(match self.0 {
-            BodyState::Empty(empty) => empty.collect().await?.aggregate(),
-            BodyState::Incoming(incoming) => incoming.collect().await?.aggregate(),
-        })
+    /// A body of unknown length that yields the chunks of `stream`.
+    pub(crate) fn stream(stream: impl Stream<Item = Result<Bytes>> + Send + 'static) -> Self {
+        Body::new(BodyState::Stream(Mutex::new(stream.boxed())))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.state {
+            BodyState::Empty(empty) => empty.size_hint(),
+            BodyState::Full(full) => full.size_hint(),
+            BodyState::Incoming(incoming) => incoming.size_hint(),
+            BodyState::Stream(_) => SizeHint::default(),
+        }
+    }
+
+    async fn aggregate(self) -> Result<Bytes> {
+        let max = self.limit.unwrap_or(usize::MAX);
+        self.limited(max).await
     }
 }
 
 impl Debug for Body {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        Debug::fmt(&self.0, f)
+        Debug::fmt(&self.state, f)
+    }
+}
+
+impl Debug for BodyState {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            BodyState::Empty(empty) => f.debug_tuple("Empty").field(empty).finish(),
+            BodyState::Full(full) => f.debug_tuple("Full").field(full).finish(),
+            BodyState::Incoming(incoming) => f.debug_tuple("Incoming").field(incoming).finish(),
+            BodyState::Stream(_) => f.write_str("Stream"),
+        }
     }
 }
 
@@ -108,6 +286,56 @@ impl Debug for Body {
 // }
 
 impl Context {
+    /// Returns the media ranges of the Accept header. A missing header accepts
+    /// every media type.
+    pub fn accepts(&self) -> Accepts {
+        Accepts::new(self.request.headers())
+    }
+
+    /// Deserializes a CBOR body into `T`. Responds with 415 Unsupported Media
+    /// Type unless the Content-Type is `application/cbor`, and with 400 Bad
+    /// Request if the body isn't valid CBOR for `T`.
+    #[cfg(feature = "cbor")]
+    pub async fn cbor<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let reader = self
+            .read_as("application/cbor")?
+            .aggregate()
+            .await?
+            .reader();
+        ciborium::from_reader(reader).map_err(|e| Error::from(e).status(400))
+    }
+
+    /// Returns the address of the client. Forwarding headers are only used
+    /// when the peer is a proxy configured with `Application::trust_proxies`.
+    pub fn client_addr(&self) -> Option<IpAddr> {
+        let peer = self.connection_info()?.remote_addr()?.ip();
+
+        match self.request.extensions().get::<TrustedProxies>() {
+            Some(proxies) => Some(proxies.resolve(peer, self.request.headers())),
+            None => Some(peer),
+        }
+    }
+
+    pub fn connection_info(&self) -> Option<&crate::ConnectionInfo> {
+        self.request.extensions().get()
+    }
+
+    /// Returns the nearest deadline set by a `Timeout` middleware.
+    pub fn cookies(&self) -> Result<&Jar> {
+        match self.request.extensions().get() {
+            Some(jar) => Ok(jar),
+            None => crate::bail!("the Cookies middleware is not included"),
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        let Deadline(deadline) = self.request.extensions().get()?;
+        Some(*deadline)
+    }
+
     pub fn get<T>(&self) -> Result<&T>
     where
         T: Send + Sync + 'static,
@@ -135,18 +363,196 @@ impl Context {
         self.request.method()
     }
 
+    /// Returns the address the connection was accepted on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.connection_info()?.local_addr()
+    }
+
+    /// Returns the URI of the request before it was rewritten by a hook added
+    /// with `Application::rewrite`.
+    pub fn original_uri(&self) -> &Uri {
+        match self.request.extensions().get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri,
+            None => self.request.uri(),
+        }
+    }
+
     pub fn params(&self) -> &Parameters {
         &self.state.params
     }
 
+    /// The certificate chain the client authenticated with over mutual TLS.
+    /// Returns `None` for plain TCP connections and for clients that didn't
+    /// send a certificate.
+    #[cfg(feature = "rustls")]
+    pub fn peer_certificates(
+        &self,
+    ) -> Option<&[crate::rustls::pki_types::CertificateDer<'static>]> {
+        self.tls_info()?.peer_certificates()
+    }
+
+    /// Returns the address of the peer. Use `client_addr` to account for
+    /// trusted proxies.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.connection_info()?.remote_addr()
+    }
+
+    pub fn precondition(
+        &self,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+    ) -> Precondition {
+        Precondition::evaluate(self.request.headers(), etag, last_modified)
+    }
+
+    /// Deserializes a MessagePack body into `T`. Responds with 415 Unsupported
+    /// Media Type unless the Content-Type is `application/msgpack`, and with
+    /// 400 Bad Request if the body isn't valid MessagePack for `T`.
+    #[cfg(feature = "msgpack")]
+    pub async fn msgpack<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let reader = self
+            .read_as("application/msgpack")?
+            .aggregate()
+            .await?
+            .reader();
+        rmp_serde::from_read(reader).map_err(|e| Error::from(e).status(400))
+    }
+
+    /// Parses the body as `multipart/form-data` using the boundary from the
+    /// Content-Type header.
+    pub fn multipart(&mut self) -> Result<Multipart> {
+        let boundary = self
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| multipart::boundary(value.to_str().ok()?))
+            .map(str::to_owned);
+
+        match boundary {
+            Some(boundary) => Ok(Multipart::new(self.read(), &boundary)),
+            None => Err(Error::from(Bail::new("Missing multipart boundary")).status(400)),
+        }
+    }
+
+    /// Deserializes the query string into `T`. Repeated keys deserialize into
+    /// sequences and invalid values respond with 400 Bad Request.
+    pub fn query<T: DeserializeOwned>(&self) -> Result<T> {
+        query::deserialize(self.uri().query().unwrap_or_default())
+    }
+
+    /// Deserializes a query string with bracketed keys, such as
+    /// `filter[status]=open&sort[]=created_at`, into `T`.
+    pub fn query_nested<T: DeserializeOwned>(&self) -> Result<T> {
+        self.query_nested_with(QueryLimits::new())
+    }
+
+    pub fn query_nested_with<T: DeserializeOwned>(&self, limits: QueryLimits) -> Result<T> {
+        query::deserialize_nested(self.uri().query().unwrap_or_default(), limits)
+    }
+
+    /// Returns the first decoded value of the query parameter `name`.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        query::param(self.uri().query()?, name)
+    }
+
+    /// Takes the body of the request. Aggregating the body is limited to the
+    /// size configured with `limit_body` or `Application::max_body_size`.
     pub fn read(&mut self) -> Body {
-        replace(self.request.body_mut(), Body::empty())
+        let limit = match self.request.extensions().get::<BodyLimit>() {
+            Some(BodyLimit(max)) => *max,
+            None => DEFAULT_BODY_LIMIT,
+        };
+        let mut body = replace(self.request.body_mut(), Body::empty());
+
+        body.limit = Some(limit);
+        body
+    }
+
+    /// Streams the body of the request as the body of `response`, keeping its
+    /// Content-Length so that it isn't sent chunked. The Content-Type of the
+    /// request is used if `response` doesn't have one. If the body turns out
+    /// to be shorter or longer than its Content-Length, the response is
+    /// aborted.
+    pub fn finalize(&mut self, mut response: Response) -> Response {
+        let body = replace(self.request.body_mut(), Body::empty());
+        let len = body.size_hint().exact();
+        let chunks = futures::stream::unfold(Some(body), |body| async move {
+            let mut body = body?;
+
+            match body.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(body))),
+                Ok(None) => None,
+                Err(error) => Some((Err(io::Error::other(error.to_string())), None)),
+            }
+        });
+
+        *response.body_mut() = match len {
+            Some(len) => response::Body::sized(chunks, len),
+            None => response::Body::stream(chunks),
+        };
+
+        if let Some(content_type) = self.request.headers().get(header::CONTENT_TYPE) {
+            if !response.headers().contains_key(header::CONTENT_TYPE) {
+                let content_type = content_type.clone();
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_TYPE, content_type);
+            }
+        }
+
+        response
+    }
+
+    /// Returns the ID assigned to the request by the `RequestId` middleware.
+    pub fn request_id(&self) -> Option<&str> {
+        let Id(id) = self.request.extensions().get()?;
+        Some(id)
+    }
+
+    /// Returns the time left until the nearest `Timeout` deadline.
+    pub fn remaining_time(&self) -> Option<Duration> {
+        Some(self.deadline()?.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn route_pattern(&self) -> Option<&str> {
+        let pattern = self.request.extensions().get::<RoutePattern>()?;
+        Some(&pattern.0)
+    }
+
+    /// Returns the session loaded by the `Session` middleware.
+    pub fn session(&self) -> Result<&SessionData> {
+        match self.request.extensions().get() {
+            Some(session) => Ok(session),
+            None => crate::bail!("the Session middleware is not included"),
+        }
+    }
+
+    #[cfg(feature = "rustls")]
+    pub fn tls_info(&self) -> Option<&crate::TlsInfo> {
+        self.request.extensions().get()
+    }
+
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String> {
+        match self.request.extensions().get::<Arc<Names>>() {
+            Some(names) => names.url_for(name, params),
+            None => crate::bail!(r#"unknown route "{}""#, name),
+        }
     }
 
     pub fn uri(&self) -> &Uri {
         self.request.uri()
     }
 
+    pub(crate) fn rewrite(&mut self, uri: Uri) {
+        let original = replace(self.request.uri_mut(), uri);
+
+        if self.request.extensions().get::<OriginalUri>().is_none() {
+            self.request.extensions_mut().insert(OriginalUri(original));
+        }
+    }
+
     pub fn version(&self) -> Version {
         self.request.version()
     }
@@ -154,6 +560,10 @@ impl Context {
 
 #[doc(hidden)]
 impl Context {
+    pub fn extend(&mut self, extensions: http::Extensions) {
+        self.request.extensions_mut().extend(extensions);
+    }
+
     pub fn locate(&mut self) -> (&mut Parameters, &Method, &str) {
         (
             &mut self.state.params,
@@ -161,6 +571,15 @@ impl Context {
             self.request.uri().path(),
         )
     }
+
+    /// Takes the body of the request if its Content-Type is `essence`.
+    #[cfg(any(feature = "cbor", feature = "msgpack"))]
+    fn read_as(&mut self, essence: &str) -> Result<Body> {
+        match self.headers().content_type() {
+            Some(mime) if mime.essence_str() == essence => Ok(self.read()),
+            _ => Err(Error::from(Bail::new("Unsupported Media Type")).status(415)),
+        }
+    }
 }
 
 #[doc(hidden)]
@@ -184,10 +603,38 @@ impl From<crate::HttpRequest> for Context {
 }
 
 impl<'a> Headers<'a> {
+    pub fn authorization(&self) -> Option<Authorization> {
+        Authorization::parse(self.entries.get(header::AUTHORIZATION)?.to_str().ok()?)
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.entries
+            .get(header::CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    pub fn content_type(&self) -> Option<Mime> {
+        self.entries
+            .get(header::CONTENT_TYPE)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+    }
+
     pub fn get(&self, name: impl AsHeaderName) -> Option<&'a HeaderValue> {
         self.entries.get(name)
     }
 
+    pub fn if_modified_since(&self) -> Option<SystemTime> {
+        let value = self.entries.get(header::IF_MODIFIED_SINCE)?.to_str().ok()?;
+        value.trim().parse::<HttpDate>().ok().map(SystemTime::from)
+    }
+
     pub fn iter(&self) -> header::Iter<'a, HeaderValue> {
         self.into_iter()
     }
@@ -209,6 +656,29 @@ impl<'a> IntoIterator for Headers<'a> {
 }
 
 impl Parameters {
+    /// Percent-decodes the param `name`. Responds with 400 Bad Request if the
+    /// decoded value is not valid UTF-8.
+    pub fn decode(&self, name: &str) -> Result<Cow<'_, str>> {
+        match percent_decode_str(self.raw(name)?).decode_utf8() {
+            Ok(value) => Ok(value),
+            Err(_) => {
+                let message = format!(r#"parameter "{}" is not valid UTF-8"#, name);
+                Err(Error::from(Bail::new(message)).status(400))
+            }
+        }
+    }
+
+    /// Percent-decodes the param `name` into raw bytes.
+    pub fn decode_bytes(&self, name: &str) -> Result<Cow<'_, [u8]>> {
+        Ok(percent_decode_str(self.raw(name)?).into())
+    }
+
+    /// Percent-decodes the param `name`, replacing invalid UTF-8 with
+    /// `U+FFFD`.
+    pub fn decode_lossy(&self, name: &str) -> Result<Cow<'_, str>> {
+        Ok(percent_decode_str(self.raw(name)?).decode_utf8_lossy())
+    }
+
     pub fn get<T>(&self, name: &str) -> Result<T>
     where
         Error: From<T::Err>,
@@ -224,6 +694,13 @@ impl Parameters {
     pub(crate) fn insert(&mut self, name: &'static str, value: String) {
         self.entries.insert(name, value);
     }
+
+    fn raw(&self, name: &str) -> Result<&str> {
+        match self.entries.get(name) {
+            Some(value) => Ok(value),
+            None => crate::bail!(r#"unknown parameter "{}""#, name),
+        }
+    }
 }
 
 impl Debug for Parameters {
@@ -231,3 +708,145 @@ impl Debug for Parameters {
         Debug::fmt(&self.entries, f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::{server::conn::http1, service::service_fn};
+    use hyper_util::rt::TokioIo;
+    use std::io;
+    use tokio::{
+        io::{duplex, AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::Context;
+    use crate::{Next, Respond, Response};
+
+    /// Echoes the body of `request` with `finalize`, returning the bytes of
+    /// the response and whether the connection failed.
+    async fn echo(request: &'static str) -> (String, bool) {
+        let (mut client, server) = duplex(1024);
+        let service = service_fn(|request| async {
+            let mut context = Context::from(request);
+            let response = match context.uri().path() {
+                "/typed" => "".respond().unwrap(),
+                _ => Response::default(),
+            };
+
+            Ok::<_, io::Error>(http::Response::from(context.finalize(response)))
+        });
+        let connection = http1::Builder::new()
+            .half_close(true)
+            .serve_connection(TokioIo::new(server), service);
+        let connection = tokio::spawn(connection);
+        let mut output = String::new();
+
+        client.write_all(request.as_bytes()).await.unwrap();
+        client.shutdown().await.unwrap();
+        client.read_to_string(&mut output).await.unwrap();
+
+        (output, connection.await.unwrap().is_err())
+    }
+
+    fn split(output: &str) -> (Vec<String>, &str) {
+        let (head, body) = output.split_once("\r\n\r\n").unwrap();
+        let mut headers: Vec<_> = head.lines().skip(1).map(str::to_ascii_lowercase).collect();
+
+        headers.retain(|header| !header.starts_with("date:"));
+        headers.sort();
+        (headers, body)
+    }
+
+    #[tokio::test]
+    async fn finalize_keeps_a_content_length() {
+        let (output, failed) = echo(
+            "POST / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\
+             content-type: application/json\r\ncontent-length: 11\r\n\r\n{\"id\": 42}\n",
+        )
+        .await;
+
+        assert!(!failed);
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(
+            split(&output),
+            (
+                vec![
+                    "connection: close".to_owned(),
+                    "content-length: 11".to_owned(),
+                    "content-type: application/json".to_owned(),
+                ],
+                "{\"id\": 42}\n"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn finalize_streams_chunked_bodies() {
+        let (output, failed) = echo(
+            "POST /typed HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\
+             content-type: application/json\r\ntransfer-encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        )
+        .await;
+
+        assert!(!failed);
+        assert_eq!(
+            split(&output),
+            (
+                vec![
+                    "connection: close".to_owned(),
+                    "content-type: text/plain".to_owned(),
+                    "transfer-encoding: chunked".to_owned(),
+                ],
+                "5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn finalize_aborts_short_bodies() {
+        let (output, failed) = echo(
+            "POST / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\
+             content-length: 20\r\n\r\nhello",
+        )
+        .await;
+
+        // The response has already started, so the connection is failed
+        // rather than letting the client accept five bytes as the body.
+        assert!(failed);
+        assert!(output.contains("content-length: 20"));
+    }
+
+    #[tokio::test]
+    async fn exposes_the_socket_addresses() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut app = crate::new();
+
+        app.at("/addresses")
+            .get(|context: Context, _: Next| async move {
+                let local = context.local_addr().unwrap();
+                let remote = context.remote_addr().unwrap();
+
+                format!("{} {}", local, remote)
+            });
+        app.shutdown_signals(false);
+        tokio::spawn(app.listen_on(listener));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let expected = format!("{} {}", address, stream.local_addr().unwrap());
+        let mut output = String::new();
+
+        stream
+            .write_all(b"GET /addresses HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        stream.read_to_string(&mut output).await.unwrap();
+
+        assert!(
+            output.ends_with(&format!("\r\n\r\n{}", expected)),
+            "{}",
+            output
+        );
+    }
+}
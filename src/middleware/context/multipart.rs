@@ -0,0 +1,395 @@
+use bytes::{Bytes, BytesMut};
+use futures::Stream;
+use std::path::Path;
+use tokio::{fs::File, io::AsyncWriteExt};
+
+use super::Body;
+use crate::{error::Bail, middleware::limit::DEFAULT_BODY_LIMIT, Error, Result};
+
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// A `multipart/form-data` body that is parsed incrementally as parts are
+/// read. Only the part that is currently being read is buffered.
+#[derive(Debug)]
+pub struct Multipart {
+    body: Body,
+    buffer: BytesMut,
+    delimiter: Vec<u8>,
+    done: bool,
+    max_part_size: usize,
+    max_size: usize,
+    received: usize,
+}
+
+#[derive(Debug)]
+pub struct Part<'a> {
+    content_type: Option<String>,
+    done: bool,
+    file_name: Option<String>,
+    multipart: &'a mut Multipart,
+    name: Option<String>,
+    received: usize,
+}
+
+fn malformed(message: &str) -> Error {
+    Error::from(Bail::new(format!("Malformed multipart body: {}", message))).status(400)
+}
+
+fn too_large() -> Error {
+    Error::from(Bail::new("Payload Too Large")).status(413)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+pub(super) fn boundary(content_type: &str) -> Option<&str> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        let value = unquote(value);
+
+        (name.trim().eq_ignore_ascii_case("boundary") && !value.is_empty()).then_some(value)
+    })
+}
+
+impl Multipart {
+    /// Both limits default to the limit of `body`, so that an upload is
+    /// bounded like any other body.
+    pub(super) fn new(body: Body, boundary: &str) -> Self {
+        let limit = body.limit.unwrap_or(DEFAULT_BODY_LIMIT);
+        // The leading CRLF lets the first boundary be found with the same
+        // delimiter as the ones that follow the body of a part.
+        let mut buffer = BytesMut::from(&b"\r\n"[..]);
+        let delimiter = [b"\r\n--", boundary.as_bytes()].concat();
+
+        buffer.reserve(delimiter.len());
+
+        Multipart {
+            body,
+            buffer,
+            delimiter,
+            done: false,
+            max_part_size: limit,
+            max_size: limit,
+            received: 0,
+        }
+    }
+
+    /// Rejects a part with 413 Payload Too Large once its body exceeds `max`
+    /// bytes. Defaults to the body limit of the request.
+    pub fn max_part_size(mut self, max: usize) -> Self {
+        self.max_part_size = max;
+        self
+    }
+
+    /// Rejects the request with 413 Payload Too Large once more than `max`
+    /// bytes have been received. Defaults to the body limit of the request,
+    /// `DEFAULT_BODY_LIMIT` unless it is configured with `limit_body` or
+    /// `Application::max_body_size`.
+    pub fn max_size(mut self, max: usize) -> Self {
+        self.max_size = max;
+        self
+    }
+
+    /// Returns the next part of the body. Any unread data in the previous part
+    /// is discarded.
+    pub async fn next_part(&mut self) -> Result<Option<Part<'_>>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        self.skip_to_delimiter().await?;
+
+        while self.buffer.len() < 2 {
+            self.fill().await?;
+        }
+
+        if self.buffer.starts_with(b"--") {
+            self.done = true;
+            return Ok(None);
+        }
+
+        if !self.buffer.starts_with(b"\r\n") {
+            return Err(malformed("expected CRLF after boundary"));
+        }
+
+        let _ = self.buffer.split_to(2);
+
+        let end = loop {
+            if let Some(index) = find(&self.buffer, b"\r\n\r\n") {
+                break index;
+            }
+
+            if self.buffer.len() > MAX_HEADER_SIZE {
+                return Err(malformed("part headers are too large"));
+            }
+
+            self.fill().await?;
+        };
+        let headers = self.buffer.split_to(end + 4);
+        let headers = std::str::from_utf8(&headers[..end])
+            .map_err(|_| malformed("part headers are not valid UTF-8"))?;
+        let mut part = Part {
+            content_type: None,
+            done: false,
+            file_name: None,
+            multipart: self,
+            name: None,
+            received: 0,
+        };
+
+        for line in headers.split("\r\n").filter(|line| !line.is_empty()) {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| malformed("invalid part header"))?;
+
+            if name.trim().eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.trim().to_owned());
+            } else if name.trim().eq_ignore_ascii_case("content-disposition") {
+                for param in value.split(';').skip(1) {
+                    match param.split_once('=') {
+                        Some((key, value)) if key.trim() == "name" => {
+                            part.name = Some(unquote(value).to_owned());
+                        }
+                        Some((key, value)) if key.trim() == "filename" => {
+                            part.file_name = Some(unquote(value).to_owned());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        Ok(Some(part))
+    }
+
+    async fn fill(&mut self) -> Result<()> {
+        let chunk = match self.body.chunk().await? {
+            Some(chunk) => chunk,
+            None => return Err(malformed("missing terminal boundary")),
+        };
+
+        self.received += chunk.len();
+
+        if self.received > self.max_size {
+            return Err(too_large());
+        }
+
+        self.buffer.extend_from_slice(&chunk);
+        Ok(())
+    }
+
+    /// Reads the body of the current part up to the next delimiter. Returns
+    /// `None` once the delimiter is at the front of the buffer.
+    async fn read(&mut self) -> Result<Option<Bytes>> {
+        loop {
+            match find(&self.buffer, &self.delimiter) {
+                Some(0) => return Ok(None),
+                Some(index) => return Ok(Some(self.buffer.split_to(index).freeze())),
+                None => {
+                    // Keep enough bytes to recognize a delimiter that is split
+                    // across chunks.
+                    let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+
+                    if safe > 0 {
+                        return Ok(Some(self.buffer.split_to(safe).freeze()));
+                    }
+
+                    self.fill().await?;
+                }
+            }
+        }
+    }
+
+    async fn skip_to_delimiter(&mut self) -> Result<()> {
+        while self.read().await?.is_some() {}
+
+        let _ = self.buffer.split_to(self.delimiter.len());
+        Ok(())
+    }
+}
+
+impl<'a> Part<'a> {
+    pub async fn bytes(&mut self) -> Result<Bytes> {
+        let mut bytes = BytesMut::new();
+
+        while let Some(chunk) = self.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(bytes.freeze())
+    }
+
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        match self.multipart.read().await? {
+            Some(chunk) => {
+                self.received += chunk.len();
+
+                if self.received > self.multipart.max_part_size {
+                    return Err(too_large());
+                }
+
+                Ok(Some(chunk))
+            }
+            None => {
+                self.done = true;
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Writes the body of the part to `path`, returning the number of bytes
+    /// written.
+    pub async fn save_to(&mut self, path: impl AsRef<Path>) -> Result<u64> {
+        let mut file = File::create(path).await?;
+        let mut written = 0;
+
+        while let Some(chunk) = self.chunk().await? {
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+
+        file.flush().await?;
+        Ok(written)
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Result<Bytes>> + 'a {
+        futures::stream::unfold(self, |mut part| async move {
+            let next = part.chunk().await.transpose()?;
+            Some((next, part))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::StreamExt;
+
+    use super::Multipart;
+    use crate::{
+        middleware::{context::Body, limit::BodyLimit},
+        response::Response,
+        Context, Error,
+    };
+
+    const BODY: &[u8] = b"preamble\r\n\
+        --xyz\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        hello\r\n\
+        --xyz\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\
+        Content-Type: application/octet-stream\r\n\
+        \r\n\
+        \x00\x01\r\n--xy\xff\r\n\
+        --xyz--\r\n";
+
+    fn multipart(body: &'static [u8]) -> Multipart {
+        Multipart::new(Body::full(Bytes::from_static(body)), "xyz")
+    }
+
+    fn status(error: Error) -> u16 {
+        Response::from(error).status_code().as_u16()
+    }
+
+    #[tokio::test]
+    async fn reads_text_and_binary_parts() {
+        let mut multipart = multipart(BODY);
+        let mut title = multipart.next_part().await.unwrap().unwrap();
+
+        assert_eq!(title.name(), Some("title"));
+        assert_eq!(title.file_name(), None);
+        assert_eq!(title.bytes().await.unwrap(), "hello");
+
+        let file = multipart.next_part().await.unwrap().unwrap();
+
+        assert_eq!(file.name(), Some("file"));
+        assert_eq!(file.file_name(), Some("a.bin"));
+        assert_eq!(file.content_type(), Some("application/octet-stream"));
+
+        let chunks: Vec<_> = file.into_stream().map(Result::unwrap).collect().await;
+
+        assert_eq!(chunks.concat(), b"\x00\x01\r\n--xy\xff");
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_bodies() {
+        let mut multipart = multipart(&BODY[..BODY.len() - 20]);
+        let _ = multipart.next_part().await.unwrap();
+        let mut file = multipart.next_part().await.unwrap().unwrap();
+
+        assert_eq!(status(file.bytes().await.unwrap_err()), 400);
+        assert_eq!(
+            status(
+                Multipart::new(
+                    Body::full(Bytes::from_static(b"--xyz\r\nno-colon\r\n\r\n")),
+                    "xyz"
+                )
+                .next_part()
+                .await
+                .unwrap_err()
+            ),
+            400
+        );
+    }
+
+    #[tokio::test]
+    async fn enforces_size_limits() {
+        let mut parts = multipart(BODY).max_part_size(4);
+        let mut title = parts.next_part().await.unwrap().unwrap();
+
+        assert_eq!(status(title.bytes().await.unwrap_err()), 413);
+
+        let mut total = multipart(BODY).max_size(16);
+        assert_eq!(status(total.next_part().await.unwrap_err()), 413);
+    }
+
+    #[tokio::test]
+    async fn defaults_to_the_body_limit() {
+        let request = http::Request::post("/")
+            .header("content-type", "multipart/form-data; boundary=xyz")
+            .body(Body::full(Bytes::from_static(BODY)))
+            .unwrap();
+        let mut context = Context::from(request);
+
+        context.insert(BodyLimit(16));
+
+        let mut multipart = context.multipart().unwrap();
+        assert_eq!(status(multipart.next_part().await.unwrap_err()), 413);
+    }
+}
@@ -0,0 +1,128 @@
+use http::header::{HeaderMap, IF_MATCH, IF_UNMODIFIED_SINCE};
+use httpdate::HttpDate;
+use std::time::SystemTime;
+
+use crate::{Error, Result};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Precondition {
+    Failed,
+    Missing,
+    Passed,
+}
+
+fn is_strong_match(tag: &str, current: &str) -> bool {
+    !tag.starts_with("W/") && !current.starts_with("W/") && tag == current
+}
+
+impl Precondition {
+    pub(crate) fn evaluate(
+        headers: &HeaderMap,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+    ) -> Self {
+        if let Some(value) = headers.get(IF_MATCH) {
+            let value = value.to_str().unwrap_or_default().trim();
+            let passed = match etag {
+                Some(_) if value == "*" => true,
+                Some(current) => value
+                    .split(',')
+                    .any(|tag| is_strong_match(tag.trim(), current)),
+                None => false,
+            };
+
+            return if passed {
+                Precondition::Passed
+            } else {
+                Precondition::Failed
+            };
+        }
+
+        if let Some(value) = headers.get(IF_UNMODIFIED_SINCE) {
+            let since = value.to_str().ok().and_then(|s| s.parse::<HttpDate>().ok());
+
+            // HTTP-dates only have second precision, so compare them as such.
+            return match (since, last_modified.map(HttpDate::from)) {
+                (Some(since), Some(modified)) if modified > since => Precondition::Failed,
+                _ => Precondition::Passed,
+            };
+        }
+
+        Precondition::Missing
+    }
+
+    pub fn check(self) -> Result<()> {
+        match self {
+            Precondition::Failed => Err(Error::precondition_failed()),
+            Precondition::Missing | Precondition::Passed => Ok(()),
+        }
+    }
+
+    pub fn require(self) -> Result<()> {
+        match self {
+            Precondition::Missing => Err(Error::precondition_required()),
+            _ => self.check(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Precondition;
+    use http::header::{HeaderMap, HeaderValue, IF_MATCH, IF_UNMODIFIED_SINCE};
+    use std::time::{Duration, SystemTime};
+
+    fn status(result: crate::Result<()>) -> u16 {
+        let response = crate::response::Response::from(result.unwrap_err());
+        http::Response::from(response).status().as_u16()
+    }
+
+    fn if_match(value: &'static str, etag: Option<&str>) -> Precondition {
+        let mut headers = HeaderMap::new();
+
+        headers.insert(IF_MATCH, HeaderValue::from_static(value));
+        Precondition::evaluate(&headers, etag, None)
+    }
+
+    #[test]
+    fn if_match_uses_strong_comparison() {
+        let current = Some(r#""v2""#);
+
+        assert_eq!(if_match(r#""v2""#, current), Precondition::Passed);
+        assert_eq!(if_match(r#""v1", "v2""#, current), Precondition::Passed);
+        assert_eq!(if_match(r#""v1", "v3""#, current), Precondition::Failed);
+        assert_eq!(if_match(r#"W/"v2""#, current), Precondition::Failed);
+        assert_eq!(
+            if_match(r#"W/"v2""#, Some(r#"W/"v2""#)),
+            Precondition::Failed
+        );
+        assert_eq!(if_match("*", current), Precondition::Passed);
+        assert_eq!(if_match("*", None), Precondition::Failed);
+    }
+
+    #[test]
+    fn if_unmodified_since_and_missing() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(
+            Precondition::evaluate(&headers, None, Some(modified)),
+            Precondition::Missing
+        );
+        assert_eq!(status(Precondition::Missing.require()), 428);
+        assert_eq!(status(Precondition::Failed.require()), 412);
+
+        headers.insert(
+            IF_UNMODIFIED_SINCE,
+            HeaderValue::try_from(httpdate::fmt_http_date(modified)).unwrap(),
+        );
+        assert_eq!(
+            Precondition::evaluate(&headers, None, Some(modified)),
+            Precondition::Passed
+        );
+        assert_eq!(
+            Precondition::evaluate(&headers, None, Some(modified + Duration::from_secs(1))),
+            Precondition::Failed
+        );
+    }
+}
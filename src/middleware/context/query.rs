@@ -0,0 +1,622 @@
+use indexmap::IndexMap;
+use percent_encoding::percent_decode_str;
+use serde::de::{
+    self,
+    value::{SeqDeserializer, StringDeserializer},
+    DeserializeOwned, DeserializeSeed, Error as _, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use std::fmt::{self, Display, Formatter};
+
+use crate::{error::Bail, Error, Result};
+
+/// Limits the shape of the query strings that are accepted by
+/// `Context::query_nested_with`.
+#[derive(Clone, Copy, Debug)]
+pub struct QueryLimits {
+    max_depth: usize,
+    max_keys: usize,
+}
+
+#[derive(Debug)]
+struct QueryError {
+    message: String,
+    path: Option<String>,
+}
+
+struct Query {
+    entries: indexmap::map::IntoIter<String, Vec<String>>,
+    value: Option<(String, Vec<String>)>,
+}
+
+/// A query string with bracketed keys. `filter[status]=open` is a nested map
+/// and `sort[]=a&sort[]=b` appends to a list.
+enum Node {
+    Map(IndexMap<String, Node>),
+    Values(Vec<String>),
+}
+
+struct Nested {
+    entries: indexmap::map::IntoIter<String, Node>,
+    path: String,
+    value: Option<(String, Node)>,
+}
+
+struct Elements {
+    entries: std::vec::IntoIter<(usize, Node)>,
+    path: String,
+}
+
+/// The values of a single query parameter. Scalars are read from the first
+/// value while sequences consume every repetition of the key.
+struct Values(Vec<String>);
+
+fn decode(input: &str) -> String {
+    let input = input.replace('+', " ");
+    percent_decode_str(&input).decode_utf8_lossy().into_owned()
+}
+
+fn entries(query: &str) -> IndexMap<String, Vec<String>> {
+    let mut entries = IndexMap::<_, Vec<_>>::new();
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        entries.entry(decode(key)).or_default().push(decode(value));
+    }
+
+    entries
+}
+
+pub(super) fn deserialize<T: DeserializeOwned>(query: &str) -> Result<T> {
+    let query = Query {
+        entries: entries(query).into_iter(),
+        value: None,
+    };
+
+    T::deserialize(query).map_err(|error| Error::from(error).status(400))
+}
+
+fn child(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}[{}]", path, key)
+    }
+}
+
+/// Splits `a[b][]` into `a` and `["b"]`. A trailing `[]` appends to a list,
+/// which repeated values do already. Keys that aren't well formed are treated
+/// as plain keys.
+fn segments(key: &str) -> (&str, Vec<&str>) {
+    let (root, mut rest) = match key.find('[') {
+        Some(0) | None => return (key, Vec::new()),
+        Some(index) => key.split_at(index),
+    };
+    let mut segments = Vec::new();
+
+    while let Some(inner) = rest.strip_prefix('[') {
+        let end = match inner.find(']') {
+            Some(end) => end,
+            None => return (key, Vec::new()),
+        };
+
+        segments.push(&inner[..end]);
+        rest = &inner[end + 1..];
+    }
+
+    if !rest.is_empty() {
+        return (key, Vec::new());
+    }
+
+    if segments.last() == Some(&"") {
+        segments.pop();
+    }
+
+    (root, segments)
+}
+
+/// Inserts `values` at `path`, returning `None` if a value and a map are
+/// both assigned to the same key.
+fn insert(map: &mut IndexMap<String, Node>, path: &[&str], values: Vec<String>) -> Option<()> {
+    let (key, rest) = path.split_first()?;
+
+    if rest.is_empty() {
+        match map
+            .entry((*key).to_owned())
+            .or_insert(Node::Values(Vec::new()))
+        {
+            Node::Values(existing) => existing.extend(values),
+            Node::Map(_) => return None,
+        }
+
+        return Some(());
+    }
+
+    match map
+        .entry((*key).to_owned())
+        .or_insert_with(|| Node::Map(IndexMap::new()))
+    {
+        Node::Map(map) => insert(map, rest, values),
+        Node::Values(_) => None,
+    }
+}
+
+fn tree(query: &str, limits: QueryLimits) -> Result<IndexMap<String, Node>> {
+    let invalid = |message: String| Err(Error::from(Bail::new(message)).status(400));
+    let mut root = IndexMap::new();
+    let mut count = 0;
+
+    for (key, values) in entries(query) {
+        let (name, segments) = segments(&key);
+
+        count += values.len();
+
+        if count > limits.max_keys {
+            return invalid(format!(
+                "query string has more than {} parameters",
+                limits.max_keys
+            ));
+        }
+
+        if segments.len() > limits.max_depth {
+            return invalid(format!(
+                "query parameter `{}` is nested more than {} levels deep",
+                key, limits.max_depth
+            ));
+        }
+
+        if segments.contains(&"") {
+            return invalid(format!("query parameter `{}` has an empty key", key));
+        }
+
+        let path: Vec<_> = [name].into_iter().chain(segments).collect();
+
+        if insert(&mut root, &path, values).is_none() {
+            return invalid(format!(
+                "query parameter `{}` conflicts with another parameter",
+                key
+            ));
+        }
+    }
+
+    Ok(root)
+}
+
+pub(super) fn deserialize_nested<T: DeserializeOwned>(
+    query: &str,
+    limits: QueryLimits,
+) -> Result<T> {
+    let nested = Nested {
+        entries: tree(query, limits)?.into_iter(),
+        path: String::new(),
+        value: None,
+    };
+
+    T::deserialize(nested).map_err(|error| Error::from(error).status(400))
+}
+
+pub(crate) fn param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .find(|(key, _)| decode(key) == name)
+        .map(|(_, value)| decode(value))
+}
+
+impl QueryLimits {
+    pub fn new() -> Self {
+        QueryLimits {
+            max_depth: 5,
+            max_keys: 1000,
+        }
+    }
+
+    /// The maximum number of bracketed segments in a key. Defaults to 5.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// The maximum number of parameters in the query string. Defaults to 1000.
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        QueryLimits::new()
+    }
+}
+
+impl QueryError {
+    /// Attributes the error to the query parameter at `path` unless it was
+    /// already attributed to a parameter that is nested within it.
+    fn at(mut self, path: &str) -> Self {
+        if self.path.is_none() {
+            self.path = Some(path.to_owned());
+        }
+
+        self
+    }
+}
+
+impl de::Error for QueryError {
+    fn custom<T: Display>(message: T) -> Self {
+        QueryError {
+            message: message.to_string(),
+            path: None,
+        }
+    }
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(
+                f,
+                "invalid value for query parameter `{}`: {}",
+                path, self.message
+            ),
+            None => Display::fmt(&self.message, f),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl<'de> de::Deserializer<'de> for Query {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de> MapAccess<'de> for Query {
+    type Error = QueryError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        let (key, values) = match self.entries.next() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let deserializer: StringDeserializer<QueryError> = key.clone().into_deserializer();
+
+        self.value = Some((key, values));
+        seed.deserialize(deserializer).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let (key, values) = self.value.take().expect("value requested before key");
+        seed.deserialize(Values(values))
+            .map_err(|error| error.at(&key))
+    }
+}
+
+impl Node {
+    fn deserialize<'de, T>(self, seed: T, path: String) -> Result<T::Value, QueryError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let result = match self {
+            Node::Values(values) => seed.deserialize(Values(values)),
+            Node::Map(map) => seed.deserialize(Nested {
+                entries: map.into_iter(),
+                path: path.clone(),
+                value: None,
+            }),
+        };
+
+        result.map_err(|error| error.at(&path))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Nested {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    /// Maps with numeric keys, like `a[0]=x&a[1]=y`, are read as lists.
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let mut entries = Vec::new();
+
+        for (key, node) in self.entries {
+            match key.parse::<usize>() {
+                Ok(index) => entries.push((index, node)),
+                Err(_) => {
+                    let path = child(&self.path, &key);
+                    return Err(QueryError::custom("expected a numeric index").at(&path));
+                }
+            }
+        }
+
+        entries.sort_by_key(|(index, _)| *index);
+        visitor.visit_seq(Elements {
+            entries: entries.into_iter(),
+            path: self.path,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+impl<'de> MapAccess<'de> for Nested {
+    type Error = QueryError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        let (key, node) = match self.entries.next() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let deserializer: StringDeserializer<QueryError> = key.clone().into_deserializer();
+
+        self.value = Some((key, node));
+        seed.deserialize(deserializer).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let (key, node) = self.value.take().expect("value requested before key");
+        node.deserialize(seed, child(&self.path, &key))
+    }
+}
+
+impl<'de> SeqAccess<'de> for Elements {
+    type Error = QueryError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.entries.next() {
+            Some((index, node)) => {
+                let path = child(&self.path, &index.to_string());
+                node.deserialize(seed, path).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Values {
+    fn first(self) -> String {
+        self.0.into_iter().next().unwrap_or_default()
+    }
+}
+
+macro_rules! parse {
+    ($($method:ident => $visit:ident),* $(,)?) => {
+        $(fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+            match self.first().parse() {
+                Ok(value) => visitor.$visit(value),
+                Err(error) => Err(de::Error::custom(error)),
+            }
+        })*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Values {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.first())
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let deserializer: StringDeserializer<QueryError> = self.first().into_deserializer();
+        visitor.visit_enum(deserializer)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.0.iter().all(String::is_empty) {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let values = self.0.into_iter().map(|value| Values(vec![value]));
+        visitor.visit_seq(SeqDeserializer::new(values))
+    }
+
+    parse! {
+        deserialize_bool => visit_bool,
+        deserialize_char => visit_char,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+    }
+
+    serde::forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, QueryError> for Values {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    use super::{deserialize, deserialize_nested, param, QueryLimits};
+    use crate::response::Response;
+
+    #[derive(Debug, Default, Deserialize, PartialEq)]
+    struct Search {
+        page: Option<u32>,
+        #[serde(default)]
+        tag: Vec<String>,
+        q: Option<String>,
+    }
+
+    #[test]
+    fn deserializes_repeated_and_missing_keys() {
+        assert_eq!(
+            deserialize::<Search>("page=2&tag=a&tag=b%20c&q=x+y").unwrap(),
+            Search {
+                page: Some(2),
+                tag: vec!["a".to_owned(), "b c".to_owned()],
+                q: Some("x y".to_owned()),
+            }
+        );
+        assert_eq!(deserialize::<Search>("").unwrap(), Search::default());
+        assert_eq!(deserialize::<Search>("page=").unwrap(), Search::default());
+    }
+
+    #[test]
+    fn names_the_invalid_field() {
+        let error = deserialize::<Search>("page=abc").unwrap_err();
+
+        assert!(error
+            .to_string()
+            .starts_with("invalid value for query parameter `page`"));
+        assert_eq!(Response::from(error).status_code(), 400);
+    }
+
+    #[test]
+    fn param_returns_first_value() {
+        assert_eq!(param("tag=a&tag=b", "tag").as_deref(), Some("a"));
+        assert_eq!(param("flag", "flag").as_deref(), Some(""));
+        assert_eq!(param("tag=a", "page"), None);
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Filter {
+        assignee: String,
+        status: String,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Issues {
+        filter: Filter,
+        #[serde(default)]
+        sort: Vec<String>,
+        #[serde(default)]
+        ids: Vec<u32>,
+    }
+
+    #[test]
+    fn deserializes_nested_keys_and_arrays() {
+        let query =
+            "filter%5Bstatus%5D=open&filter[assignee]=me&sort[]=created_at&sort[]=-priority";
+
+        assert_eq!(
+            deserialize_nested::<Issues>(&format!("{}&ids=1&ids=2", query), QueryLimits::new())
+                .unwrap(),
+            Issues {
+                filter: Filter {
+                    assignee: "me".to_owned(),
+                    status: "open".to_owned(),
+                },
+                sort: vec!["created_at".to_owned(), "-priority".to_owned()],
+                ids: vec![1, 2],
+            }
+        );
+        assert_eq!(
+            deserialize_nested::<HashMap<String, Vec<u32>>>("a[1]=2&a[0]=1", QueryLimits::new())
+                .unwrap()["a"],
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn nested_errors_include_the_key_path() {
+        let limits = QueryLimits::new();
+        let error = |query| {
+            deserialize_nested::<Issues>(query, limits)
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            error("filter[status]=open"),
+            "invalid value for query parameter `filter`: missing field `assignee`"
+        );
+        assert!(error("filter[status]=a&filter[assignee]=b&ids[]=x")
+            .starts_with("invalid value for query parameter `ids`"));
+        assert!(error("filter[status][x]=a&filter[status]=b").contains("`filter[status]`"));
+    }
+
+    #[test]
+    fn enforces_depth_and_key_limits() {
+        let limits = QueryLimits::new().max_depth(2).max_keys(3);
+        let status = |query| {
+            let error = deserialize_nested::<HashMap<String, String>>(query, limits).unwrap_err();
+            (Response::from(error).status_code().as_u16(), query)
+        };
+
+        assert_eq!(status("a[b][c][d]=1"), (400, "a[b][c][d]=1"));
+        assert_eq!(status("a=1&b=2&c=3&d=4"), (400, "a=1&b=2&c=3&d=4"));
+        assert!(
+            deserialize_nested::<HashMap<String, HashMap<String, String>>>("a[b]=1", limits)
+                .is_ok()
+        );
+    }
+}
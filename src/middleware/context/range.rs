@@ -0,0 +1,176 @@
+use crate::{error::Bail, Error, Respond, Result};
+
+/// The byte ranges requested with a `Range` header.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Range {
+    specs: Vec<Spec>,
+}
+
+/// An inclusive range of bytes that is within the length of a resource.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// None of the requested ranges overlap the resource. Responds with 416 Range
+/// Not Satisfiable and a `Content-Range` of `bytes */len`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Unsatisfiable {
+    pub len: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Spec {
+    Bounded(u64, u64),
+    From(u64),
+    Suffix(u64),
+}
+
+fn number(input: &str) -> Option<u64> {
+    if input.is_empty() || !input.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    input.parse().ok()
+}
+
+fn spec(input: &str) -> Option<Spec> {
+    match input.split_once('-')? {
+        ("", suffix) => Some(Spec::Suffix(number(suffix)?)),
+        (start, "") => Some(Spec::From(number(start)?)),
+        (start, end) => {
+            let (start, end) = (number(start)?, number(end)?);
+            (start <= end).then_some(Spec::Bounded(start, end))
+        }
+    }
+}
+
+impl Range {
+    /// Parses the value of a `Range` header. Fails with 400 Bad Request if the
+    /// unit isn't bytes or any of the ranges are malformed.
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || Error::from(Bail::new("Invalid Range")).status(400);
+        let (unit, ranges) = value.trim().split_once('=').ok_or_else(invalid)?;
+
+        if !unit.trim().eq_ignore_ascii_case("bytes") {
+            return Err(invalid());
+        }
+
+        let specs = ranges
+            .split(',')
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .map(|range| spec(range).ok_or_else(invalid))
+            .collect::<Result<Vec<_>>>()?;
+
+        if specs.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Range { specs })
+    }
+
+    /// Clamps the requested ranges to a resource of `len` bytes. Ranges that
+    /// start beyond the end of the resource are dropped.
+    pub fn resolve(&self, len: u64) -> Result<Vec<ByteRange>, Unsatisfiable> {
+        let last = match len.checked_sub(1) {
+            Some(last) => last,
+            None => return Err(Unsatisfiable { len }),
+        };
+        let ranges: Vec<_> = self
+            .specs
+            .iter()
+            .filter_map(|spec| match *spec {
+                Spec::Bounded(start, end) if start <= last => Some((start, end.min(last))),
+                Spec::From(start) if start <= last => Some((start, last)),
+                Spec::Suffix(suffix) if suffix > 0 => Some((len.saturating_sub(suffix), last)),
+                _ => None,
+            })
+            .map(|(start, end)| ByteRange { start, end })
+            .collect();
+
+        if ranges.is_empty() {
+            Err(Unsatisfiable { len })
+        } else {
+            Ok(ranges)
+        }
+    }
+}
+
+impl ByteRange {
+    /// Returns the value of the `Content-Range` header for this range of a
+    /// resource that is `total` bytes long.
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+impl Respond for Unsatisfiable {
+    fn respond(self) -> Result {
+        let content_range = format!("bytes */{}", self.len);
+
+        "Range Not Satisfiable"
+            .status(416)
+            .header("content-range", content_range)
+            .respond()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ByteRange, Range, Unsatisfiable};
+    use crate::Respond;
+
+    fn resolve(value: &str, len: u64) -> Result<Vec<(u64, u64)>, Unsatisfiable> {
+        let ranges = Range::parse(value).unwrap().resolve(len)?;
+        Ok(ranges
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect())
+    }
+
+    #[test]
+    fn resolves_and_clamps_ranges() {
+        assert_eq!(resolve("bytes=0-1023", 100), Ok(vec![(0, 99)]));
+        assert_eq!(resolve("bytes=-500", 1000), Ok(vec![(500, 999)]));
+        assert_eq!(resolve("bytes=-500", 100), Ok(vec![(0, 99)]));
+        assert_eq!(resolve("bytes=500-", 1000), Ok(vec![(500, 999)]));
+        assert_eq!(
+            resolve("bytes=0-9, 2000-3000, 90-", 100),
+            Ok(vec![(0, 9), (90, 99)])
+        );
+        assert_eq!(
+            ByteRange { start: 0, end: 9 }.content_range(100),
+            "bytes 0-9/100"
+        );
+    }
+
+    #[test]
+    fn distinguishes_invalid_and_unsatisfiable_ranges() {
+        for value in [
+            "bytes=",
+            "bytes=5-1",
+            "bytes=a-b",
+            "items=0-1",
+            "0-1",
+            "bytes=1-2-3",
+        ] {
+            assert!(Range::parse(value).is_err(), "{}", value);
+        }
+
+        assert_eq!(resolve("bytes=100-", 100), Err(Unsatisfiable { len: 100 }));
+        assert_eq!(resolve("bytes=-0", 100), Err(Unsatisfiable { len: 100 }));
+        assert_eq!(resolve("bytes=0-", 0), Err(Unsatisfiable { len: 0 }));
+
+        let response = Unsatisfiable { len: 100 }.respond().unwrap();
+
+        assert_eq!(response.status_code(), 416);
+        assert_eq!(response.headers()["content-range"], "bytes */100");
+    }
+}
@@ -0,0 +1,756 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use cookie::{time::OffsetDateTime, Cookie, CookieBuilder, Key, ParseError};
+use http::{
+    header::{HeaderValue, COOKIE, SET_COOKIE},
+    HeaderMap,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex},
+};
+
+use crate::{error::Bail, BoxFuture, Context, Error, Middleware, Next, Result};
+
+/// The largest Set-Cookie value, in bytes, that every browser must accept.
+const MAX_SIZE: usize = 4096;
+
+type Callback = Arc<dyn Fn(&StrippedCookie) + Send + Sync>;
+
+/// Strips cookies that aren't allowed from requests and from the Set-Cookie
+/// headers of responses, so that the middleware that follow only see the
+/// cookies the application expects. Include it before any middleware that
+/// reads or sets cookies.
+///
+/// Patterns are either a cookie name or a prefix followed by `*`, such as
+/// `ab_test_*`. Names are matched case-sensitively. A name that matches a
+/// `deny` pattern is stripped even if it's allowed.
+///
+/// The allowed cookies of a request are available with `Context::cookies`,
+/// and the changes made to them are sent with the response. The attributes set
+/// with `defaults` are added to each Set-Cookie header that doesn't set them
+/// itself.
+#[derive(Clone)]
+pub struct Cookies {
+    allow: Vec<String>,
+    allow_all: bool,
+    callback: Callback,
+    defaults: Option<Cookie<'static>>,
+    deny: Vec<String>,
+    strict: bool,
+}
+
+/// The cookies of a request. Changes are sent as Set-Cookie headers once the
+/// response is returned to the `Cookies` middleware.
+#[derive(Clone, Debug, Default)]
+pub struct Jar {
+    jar: Arc<Mutex<cookie::CookieJar>>,
+}
+
+/// A Set-Cookie header that `strict` stripped from a response.
+#[derive(Clone, Debug)]
+pub struct StrippedCookie {
+    pub name: String,
+    pub reason: StripReason,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StripReason {
+    /// The cookie isn't Secure.
+    Insecure,
+    /// The header couldn't be parsed, so it can't be known to be Secure.
+    Invalid(ParseError),
+}
+
+/// Reads and writes cookies whose value is a `T` serialized as JSON, and
+/// signed or encrypted with a key.
+#[derive(Clone, Copy, Debug)]
+pub struct KeyedJar<'a> {
+    encrypt: bool,
+    jar: &'a Jar,
+    key: &'a Key,
+}
+
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}
+
+/// Returns the name of the cookie in a Cookie pair or Set-Cookie value.
+fn name(cookie: &str) -> &str {
+    let pair = cookie.split(';').next().unwrap_or_default();
+    pair.split('=').next().unwrap_or_default().trim()
+}
+
+pub(crate) fn parse(headers: &HeaderMap) -> cookie::CookieJar {
+    let mut jar = cookie::CookieJar::new();
+
+    for value in headers.get_all(COOKIE) {
+        let value = value.to_str().unwrap_or_default();
+
+        for cookie in value
+            .split(';')
+            .filter_map(|cookie| Cookie::parse_encoded(cookie.trim().to_owned()).ok())
+        {
+            jar.add_original(cookie);
+        }
+    }
+
+    jar
+}
+
+/// Copies each attribute of `defaults` that `cookie` doesn't set.
+fn merge(cookie: &mut Cookie<'static>, defaults: &Cookie<'static>) {
+    if cookie.http_only().is_none() {
+        cookie.set_http_only(defaults.http_only());
+    }
+
+    if cookie.secure().is_none() {
+        cookie.set_secure(defaults.secure());
+    }
+
+    if cookie.same_site().is_none() {
+        cookie.set_same_site(defaults.same_site());
+    }
+
+    if cookie.partitioned().is_none() {
+        cookie.set_partitioned(defaults.partitioned());
+    }
+
+    if let (None, Some(path)) = (cookie.path(), defaults.path()) {
+        cookie.set_path(path.to_owned());
+    }
+
+    if let (None, Some(domain)) = (cookie.domain(), defaults.domain()) {
+        cookie.set_domain(domain.to_owned());
+    }
+
+    // A cookie that removes another one must stay expired.
+    let removal = cookie.max_age().is_some_and(|max_age| max_age.is_zero())
+        || cookie
+            .expires_datetime()
+            .is_some_and(|expires| expires <= OffsetDateTime::now_utc());
+
+    if removal {
+        return;
+    }
+
+    if cookie.max_age().is_none() {
+        cookie.set_max_age(defaults.max_age());
+    }
+
+    if let (None, Some(expires)) = (cookie.expires(), defaults.expires()) {
+        cookie.set_expires(expires);
+    }
+}
+
+impl Cookies {
+    /// Strips every cookie until some are allowed.
+    pub fn new() -> Self {
+        Cookies {
+            allow: Vec::new(),
+            allow_all: false,
+            callback: Arc::new(|stripped| match stripped.reason {
+                StripReason::Insecure => {
+                    eprintln!("stripped cookie {} without Secure", stripped.name);
+                }
+                StripReason::Invalid(error) => {
+                    eprintln!("stripped invalid cookie {}: {}", stripped.name, error);
+                }
+            }),
+            defaults: None,
+            deny: Vec::new(),
+            strict: false,
+        }
+    }
+
+    pub fn allow(mut self, pattern: impl Into<String>) -> Self {
+        self.allow.push(pattern.into());
+        self
+    }
+
+    /// Allows every cookie that isn't denied.
+    pub fn allow_all(mut self) -> Self {
+        self.allow_all = true;
+        self
+    }
+
+    /// Sets the attributes that are added to response cookies that don't set
+    /// them, such as `|c| c.http_only(true).secure(true).path("/")`. The
+    /// name and value of the builder are ignored.
+    pub fn defaults<F>(mut self, defaults: F) -> Self
+    where
+        F: FnOnce(CookieBuilder<'static>) -> CookieBuilder<'static>,
+    {
+        self.defaults = Some(defaults(Cookie::build(("", ""))).build());
+        self
+    }
+
+    pub fn deny(mut self, pattern: impl Into<String>) -> Self {
+        self.deny.push(pattern.into());
+        self
+    }
+
+    /// Calls `callback` with each cookie that `strict` strips instead of
+    /// writing it to stderr.
+    pub fn on_strip<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&StrippedCookie) + Send + Sync + 'static,
+    {
+        self.callback = Arc::new(callback);
+        self
+    }
+
+    /// Strips response cookies that aren't Secure, after the defaults are
+    /// applied, from requests that were received over TLS.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Returns true if the cookie `name` is passed through. Middleware that
+    /// depend on a cookie can check this when they are configured.
+    pub fn is_allowed(&self, name: &str) -> bool {
+        let allowed = self.allow_all || self.allow.iter().any(|p| matches(p, name));
+        allowed && !self.deny.iter().any(|p| matches(p, name))
+    }
+
+    fn filter_request(&self, context: &mut Context) -> Result<()> {
+        let headers = context.request.headers_mut();
+        let mut allowed = Vec::new();
+
+        for value in headers.get_all(COOKIE) {
+            let value = value.to_str().unwrap_or_default();
+
+            for cookie in value.split(';').map(str::trim) {
+                if !cookie.is_empty() && self.is_allowed(name(cookie)) {
+                    allowed.push(cookie);
+                }
+            }
+        }
+
+        let allowed = allowed.join("; ");
+
+        if allowed.is_empty() {
+            headers.remove(COOKIE);
+        } else {
+            headers.insert(COOKIE, HeaderValue::try_from(allowed)?);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the Set-Cookie header `value` with the defaults applied, or
+    /// `None` if the cookie is stripped.
+    fn set_cookie(&self, value: HeaderValue, tls: bool) -> Option<HeaderValue> {
+        let text = value.to_str().unwrap_or_default();
+
+        if !self.is_allowed(name(text)) {
+            return None;
+        }
+
+        // Cookies that can't be parsed are passed through as they are, unless
+        // they have to be Secure.
+        let mut cookie = match Cookie::parse(text.to_owned()) {
+            Ok(cookie) => cookie,
+            Err(_) if !(self.strict && tls) => return Some(value),
+            Err(error) => {
+                self.strip(name(text), StripReason::Invalid(error));
+                return None;
+            }
+        };
+
+        if let Some(defaults) = &self.defaults {
+            merge(&mut cookie, defaults);
+        }
+
+        if self.strict && tls && cookie.secure() != Some(true) {
+            self.strip(cookie.name(), StripReason::Insecure);
+            return None;
+        }
+
+        HeaderValue::try_from(cookie.to_string()).ok()
+    }
+
+    fn strip(&self, name: &str, reason: StripReason) {
+        (self.callback)(&StrippedCookie {
+            name: name.to_owned(),
+            reason,
+        });
+    }
+}
+
+impl Debug for Cookies {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Cookies")
+            .field("allow", &self.allow)
+            .field("allow_all", &self.allow_all)
+            .field("defaults", &self.defaults)
+            .field("deny", &self.deny)
+            .field("strict", &self.strict)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Cookies {
+    fn default() -> Self {
+        Cookies::new()
+    }
+}
+
+impl Jar {
+    pub fn add(&self, cookie: impl Into<Cookie<'static>>) {
+        self.jar.lock().unwrap().add(cookie);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Cookie<'static>> {
+        self.jar.lock().unwrap().get(name).cloned()
+    }
+
+    /// Returns a jar that encrypts the values of cookies with `key`, so that
+    /// they can't be read or changed by the client.
+    pub fn private<'a>(&'a self, key: &'a Key) -> KeyedJar<'a> {
+        KeyedJar {
+            encrypt: true,
+            jar: self,
+            key,
+        }
+    }
+
+    /// Removes the cookie with the name and path of `cookie` from the client.
+    pub fn remove(&self, cookie: impl Into<Cookie<'static>>) {
+        self.jar.lock().unwrap().remove(cookie);
+    }
+
+    /// Returns a jar that signs the values of cookies with `key`, so that
+    /// they can be read but not changed by the client.
+    pub fn signed<'a>(&'a self, key: &'a Key) -> KeyedJar<'a> {
+        KeyedJar {
+            encrypt: false,
+            jar: self,
+            key,
+        }
+    }
+}
+
+impl KeyedJar<'_> {
+    /// Serializes `value` as the value of the cookie `name`. The builder can
+    /// set the attributes of the cookie, such as its `max_age`. Returns an
+    /// error if the cookie would be larger than the 4096 bytes that browsers
+    /// are required to store.
+    pub fn add_json<T, F>(&self, name: &str, value: &T, build: F) -> Result<()>
+    where
+        T: Serialize,
+        F: FnOnce(CookieBuilder<'static>) -> CookieBuilder<'static>,
+    {
+        let value = URL_SAFE_NO_PAD.encode(serde_json::to_vec(value)?);
+        let cookie = build(Cookie::build((name.to_owned(), value))).build();
+        let mut scratch = cookie::CookieJar::new();
+
+        // Sign or encrypt the cookie before it's measured.
+        if self.encrypt {
+            scratch.private_mut(self.key).add(cookie);
+        } else {
+            scratch.signed_mut(self.key).add(cookie);
+        }
+
+        let cookie = scratch.get(name).cloned().unwrap();
+        let size = cookie.encoded().to_string().len();
+
+        if size > MAX_SIZE {
+            let message = format!(
+                "The {} cookie is {} bytes, which is more than the {} allowed",
+                name, size, MAX_SIZE
+            );
+
+            return Err(Error::from(Bail::new(message)).status(500));
+        }
+
+        self.jar.add(cookie);
+        Ok(())
+    }
+
+    /// Returns the value of the cookie `name`, or `None` if it isn't set or
+    /// it was changed by the client. Returns an error if the value isn't a
+    /// `T`.
+    pub fn get_json<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>> {
+        let cookie = {
+            let jar = self.jar.jar.lock().unwrap();
+
+            if self.encrypt {
+                jar.private(self.key).get(name)
+            } else {
+                jar.signed(self.key).get(name)
+            }
+        };
+
+        let json = match cookie {
+            Some(cookie) => URL_SAFE_NO_PAD.decode(cookie.value()),
+            None => return Ok(None),
+        };
+
+        match json.map(|json| serde_json::from_slice(&json)) {
+            Ok(Ok(value)) => Ok(Some(value)),
+            Ok(Err(error)) => Err(Error::from(error).status(400)),
+            Err(error) => Err(Error::from(error).status(400)),
+        }
+    }
+}
+
+impl Middleware for Cookies {
+    fn call(&self, mut context: Context, next: Next) -> BoxFuture<Result> {
+        if let Err(error) = self.filter_request(&mut context) {
+            return Box::pin(async { Err(error) });
+        }
+
+        #[cfg(feature = "rustls")]
+        let tls = context.tls_info().is_some();
+        #[cfg(not(feature = "rustls"))]
+        let tls = false;
+
+        let cookies = self.clone();
+        let jar = Jar {
+            jar: Arc::new(Mutex::new(parse(context.request.headers()))),
+        };
+
+        context.insert(jar.clone());
+
+        let future = next.call(context);
+
+        Box::pin(async move {
+            let mut response = future.await?;
+            let headers = response.headers_mut();
+            let mut set_cookies: Vec<_> = headers.get_all(SET_COOKIE).iter().cloned().collect();
+
+            headers.remove(SET_COOKIE);
+
+            for cookie in jar.jar.lock().unwrap().delta() {
+                set_cookies.push(HeaderValue::try_from(cookie.encoded().to_string())?);
+            }
+
+            for value in set_cookies {
+                if let Some(value) = cookies.set_cookie(value, tls) {
+                    headers.append(SET_COOKIE, value);
+                }
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cookie::{time::Duration, Cookie, Key, ParseError, SameSite};
+    use http::HeaderValue;
+    use serde::{Deserialize, Serialize};
+    use std::sync::{Arc, Mutex};
+
+    use super::{merge, Cookies, Jar, StripReason};
+    use crate::{
+        middleware::{context::Body, DynMiddleware},
+        Context, Middleware, Next, Respond,
+    };
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Identity {
+        expires: u64,
+        user_id: u32,
+    }
+
+    fn cookies() -> Cookies {
+        Cookies::new()
+            .allow("counter")
+            .allow("ab_test_*")
+            .deny("ab_test_legacy")
+    }
+
+    #[test]
+    fn matches_patterns() {
+        let cookies = cookies();
+
+        for (name, allowed) in [
+            ("counter", true),
+            ("Counter", false),
+            ("counter2", false),
+            ("ab_test_", true),
+            ("ab_test_checkout", true),
+            ("AB_TEST_checkout", false),
+            ("ab_test_legacy", false),
+            ("ab_test_legacy2", true),
+            ("_ga", false),
+            ("", false),
+        ] {
+            assert_eq!(cookies.is_allowed(name), allowed, "{}", name);
+        }
+
+        let all = Cookies::new().allow_all().deny("_ga*");
+
+        assert!(all.is_allowed("anything"));
+        assert!(!all.is_allowed("_gat"));
+        assert!(!Cookies::new().is_allowed("counter"));
+    }
+
+    #[tokio::test]
+    async fn strips_request_and_response_cookies() {
+        let endpoint: DynMiddleware = Arc::new(|context: Context, _: Next| async move {
+            let cookie = context.headers().get("cookie");
+            let cookie = cookie.map(|value| value.to_str().unwrap().to_owned());
+
+            cookie
+                .unwrap_or_default()
+                .header("set-cookie", "counter=2; Path=/")
+                .header("set-cookie", "_ga=GA1; Path=/")
+                .header("set-cookie", "ab_test_legacy=b")
+                .header("set-cookie", "ab_test_home=a; HttpOnly")
+                .respond()
+        });
+        let call = |cookie: &'static str| {
+            let request = http::Request::get("/").header("cookie", cookie);
+            let context = Context::from(request.body(Body::full("".into())).unwrap());
+            let response = cookies().call(context, Next::new([&endpoint].into_iter()));
+
+            async move { http::Response::from(response.await.unwrap()) }
+        };
+
+        let response = call("_ga=GA1; counter=1;ab_test_home=a; ab_test_legacy=b").await;
+        let set_cookies: Vec<_> = response.headers().get_all("set-cookie").iter().collect();
+
+        assert_eq!(
+            set_cookies,
+            ["counter=2; Path=/", "ab_test_home=a; HttpOnly"]
+        );
+
+        let body = http_body_util::BodyExt::collect(response.into_body());
+        assert_eq!(body.await.unwrap().to_bytes(), "counter=1; ab_test_home=a");
+
+        let body = http_body_util::BodyExt::collect(call("_ga=GA1").await.into_body());
+        assert_eq!(body.await.unwrap().to_bytes(), "");
+    }
+
+    #[test]
+    fn merges_defaults_per_attribute() {
+        let defaults = Cookie::build(("", ""))
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::Lax)
+            .path("/")
+            .max_age(Duration::hours(1))
+            .build();
+        let epoch = "Expires=Thu, 01 Jan 1970 00:00:00 GMT";
+
+        for (set_cookie, expected) in [
+            (
+                "a=1".to_owned(),
+                "a=1; HttpOnly; SameSite=Lax; Secure; Path=/; Max-Age=3600".to_owned(),
+            ),
+            (
+                "a=1; SameSite=Strict".to_owned(),
+                "a=1; HttpOnly; SameSite=Strict; Secure; Path=/; Max-Age=3600".to_owned(),
+            ),
+            (
+                "a=1; SameSite=None".to_owned(),
+                "a=1; HttpOnly; SameSite=None; Secure; Path=/; Max-Age=3600".to_owned(),
+            ),
+            (
+                "a=1; Path=/admin".to_owned(),
+                "a=1; HttpOnly; SameSite=Lax; Secure; Path=/admin; Max-Age=3600".to_owned(),
+            ),
+            (
+                "a=1; Domain=example.com".to_owned(),
+                "a=1; HttpOnly; SameSite=Lax; Secure; Path=/; Domain=example.com; Max-Age=3600"
+                    .to_owned(),
+            ),
+            (
+                "a=1; Max-Age=60".to_owned(),
+                "a=1; HttpOnly; SameSite=Lax; Secure; Path=/; Max-Age=60".to_owned(),
+            ),
+            (
+                "a=; Max-Age=0".to_owned(),
+                "a=; HttpOnly; SameSite=Lax; Secure; Path=/; Max-Age=0".to_owned(),
+            ),
+            (
+                format!("a=; {}", epoch),
+                format!("a=; HttpOnly; SameSite=Lax; Secure; Path=/; {}", epoch),
+            ),
+        ] {
+            let mut cookie = Cookie::parse(set_cookie.clone()).unwrap();
+
+            merge(&mut cookie, &defaults);
+            assert_eq!(cookie.to_string(), expected, "{}", set_cookie);
+        }
+
+        let mut cookie = Cookie::new("a", "1");
+
+        cookie.set_http_only(false);
+        cookie.set_secure(false);
+        merge(&mut cookie, &defaults);
+        assert_eq!(
+            (cookie.http_only(), cookie.secure()),
+            (Some(false), Some(false))
+        );
+
+        let mut removal = Cookie::build(("a", "1")).path("/admin").build();
+
+        removal.make_removal();
+        merge(&mut removal, &defaults);
+        assert_eq!(removal.max_age(), Some(Duration::ZERO));
+        assert_eq!(removal.path(), Some("/admin"));
+        assert!(removal.expires_datetime().unwrap() < cookie::time::OffsetDateTime::now_utc());
+    }
+
+    #[test]
+    fn strips_insecure_cookies_over_tls() {
+        let stripped = Arc::new(Mutex::new(Vec::new()));
+        let cookies = Cookies::new().allow_all().strict().on_strip({
+            let stripped = Arc::clone(&stripped);
+            move |cookie| stripped.lock().unwrap().push(cookie.clone())
+        });
+        let set_cookie = |cookies: &Cookies, value, tls| {
+            let value = cookies.set_cookie(HeaderValue::from_static(value), tls);
+            value.map(|value| value.to_str().unwrap().to_owned())
+        };
+
+        assert_eq!(set_cookie(&cookies, "a=1", true), None);
+        assert_eq!(set_cookie(&cookies, "a=1", false).as_deref(), Some("a=1"));
+        assert_eq!(
+            set_cookie(&cookies, "a=1; Secure", true).as_deref(),
+            Some("a=1; Secure")
+        );
+
+        assert_eq!(set_cookie(&cookies, "b", true), None);
+
+        let cookies = cookies.defaults(|cookie| cookie.secure(true));
+        assert_eq!(
+            set_cookie(&cookies, "a=1", true).as_deref(),
+            Some("a=1; Secure")
+        );
+
+        let stripped = stripped.lock().unwrap();
+        let reasons: Vec<_> = stripped
+            .iter()
+            .map(|cookie| (cookie.name.as_str(), cookie.reason))
+            .collect();
+
+        assert_eq!(
+            reasons,
+            [
+                ("a", StripReason::Insecure),
+                ("b", StripReason::Invalid(ParseError::MissingPair)),
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trips_keyed_json() {
+        let key = Key::generate();
+        let identity = Identity {
+            expires: 1_700_000_000,
+            user_id: 7,
+        };
+
+        for encrypt in [false, true] {
+            let jar = Jar::default();
+            let keyed = |jar| match encrypt {
+                true => Jar::private(jar, &key),
+                false => Jar::signed(jar, &key),
+            };
+
+            keyed(&jar)
+                .add_json("identity", &identity, |cookie| cookie.path("/"))
+                .unwrap();
+
+            let cookie = jar.get("identity").unwrap();
+
+            assert_eq!(cookie.path(), Some("/"));
+            assert!(!cookie.value().contains("user_id"));
+            assert_eq!(
+                keyed(&jar)
+                    .get_json::<Identity>("identity")
+                    .unwrap()
+                    .as_ref(),
+                Some(&identity)
+            );
+            assert_eq!(keyed(&jar).get_json::<Identity>("missing").unwrap(), None);
+
+            // A value that was changed or keyed with another key is ignored.
+            let mut value = cookie.value().to_owned();
+            let last = if value.ends_with('A') { "B" } else { "A" };
+
+            value.replace_range(value.len() - 1.., last);
+            jar.add(Cookie::new("identity", value));
+            assert_eq!(keyed(&jar).get_json::<Identity>("identity").unwrap(), None);
+
+            keyed(&jar).add_json("identity", &identity, |c| c).unwrap();
+            assert!(Jar::signed(&jar, &Key::generate())
+                .get_json::<Identity>("identity")
+                .unwrap()
+                .is_none());
+
+            let error = keyed(&jar).get_json::<String>("identity").err().unwrap();
+            assert_eq!(error.status_code(), 400);
+
+            let error = keyed(&jar)
+                .add_json("large", &"a".repeat(4096), |c| c)
+                .err()
+                .unwrap();
+            assert_eq!(error.status_code(), 500);
+            assert!(error.to_string().contains("more than the 4096 allowed"));
+            assert!(jar.get("large").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_changes_to_the_jar() {
+        let key = Key::generate();
+        let endpoint: DynMiddleware = Arc::new({
+            let key = key.clone();
+
+            move |context: Context, _: Next| {
+                let key = key.clone();
+
+                async move {
+                    let jar = context.cookies()?;
+                    let count = jar.signed(&key).get_json::<u32>("count")?.unwrap_or(0);
+
+                    jar.signed(&key).add_json("count", &(count + 1), |c| c)?;
+                    jar.add(Cookie::new("_ga", "GA1"));
+                    jar.remove(Cookie::build("flash").path("/"));
+                    count.to_string().respond()
+                }
+            }
+        });
+        let mut count = String::new();
+
+        for expected in ["0", "1"] {
+            let request = http::Request::get("/").header("cookie", format!("flash=a; {}", count));
+            let context = Context::from(request.body(Body::full("".into())).unwrap());
+            let cookies = Cookies::new()
+                .allow("count")
+                .allow("flash")
+                .defaults(|cookie| cookie.secure(true));
+            let response = cookies.call(context, Next::new([&endpoint].into_iter()));
+            let response = http::Response::from(response.await.unwrap());
+            let mut set_cookies: Vec<_> = response
+                .headers()
+                .get_all("set-cookie")
+                .iter()
+                .map(|value| value.to_str().unwrap().to_owned())
+                .collect();
+
+            // The changes to a jar are unordered.
+            set_cookies.sort();
+            assert_eq!(set_cookies.len(), 2);
+            assert!(set_cookies[0].starts_with("count="));
+            assert!(set_cookies[0].ends_with("; Secure"));
+            assert!(set_cookies[1].starts_with("flash=; Secure; Path=/; Max-Age=0; Expires="));
+
+            let body = http_body_util::BodyExt::collect(response.into_body());
+            assert_eq!(body.await.unwrap().to_bytes(), expected);
+
+            count = set_cookies[0].split(';').next().unwrap().to_owned();
+        }
+    }
+}
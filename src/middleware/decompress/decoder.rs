@@ -0,0 +1,267 @@
+//! Streaming decoders for each supported `Content-Encoding`. Input is decoded
+//! as it is written, and decoding stops as soon as the output would exceed the
+//! configured maximum size, so a small body can't expand without bound.
+
+use flate2::{write::GzDecoder, Decompress, FlushDecompress, Status};
+use std::{
+    io::{self, Write},
+    mem::take,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum DecodeError {
+    /// The input is corrupt, truncated, or followed by trailing data.
+    Invalid,
+    TooLarge,
+}
+
+type Result<T> = std::result::Result<T, DecodeError>;
+
+/// The size of the buffer that each step of decoding writes to.
+const CHUNK: usize = 32 * 1024;
+
+pub(crate) struct Decoder(Codec);
+
+enum Codec {
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::DecompressorWriter<Output>>),
+    Deflate(Box<Inflate>),
+    Gzip(Box<GzDecoder<Output>>),
+    #[cfg(feature = "zstd")]
+    Zstd(Box<Zstd>),
+}
+
+/// Collects the decoded output until it is taken. Writing fails once more than
+/// `remaining` bytes would have been written in total.
+struct Output {
+    buffer: Vec<u8>,
+    exceeded: bool,
+    remaining: usize,
+}
+
+/// A zlib stream, or a raw DEFLATE stream as sent by some clients that
+/// misread RFC 9110. The two are told apart by the first two bytes.
+struct Inflate {
+    decompress: Option<Decompress>,
+    done: bool,
+    output: Output,
+    pending: Vec<u8>,
+}
+
+#[cfg(feature = "zstd")]
+struct Zstd {
+    context: zstd::zstd_safe::DCtx<'static>,
+    done: bool,
+    output: Output,
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid compressed body")
+}
+
+/// Returns true if `header` is a valid zlib header for a DEFLATE stream.
+fn is_zlib(header: [u8; 2]) -> bool {
+    header[0] & 0x0f == 8 && u16::from_be_bytes(header).is_multiple_of(31)
+}
+
+impl Decoder {
+    #[cfg(feature = "brotli")]
+    pub(crate) fn brotli(max_size: usize) -> Self {
+        let writer = brotli::DecompressorWriter::new(Output::new(max_size), CHUNK);
+        Decoder(Codec::Brotli(Box::new(writer)))
+    }
+
+    pub(crate) fn deflate(max_size: usize) -> Self {
+        Decoder(Codec::Deflate(Box::new(Inflate {
+            decompress: None,
+            done: false,
+            output: Output::new(max_size),
+            pending: Vec::new(),
+        })))
+    }
+
+    pub(crate) fn gzip(max_size: usize) -> Self {
+        let writer = GzDecoder::new(Output::new(max_size));
+        Decoder(Codec::Gzip(Box::new(writer)))
+    }
+
+    #[cfg(feature = "zstd")]
+    pub(crate) fn zstd(max_size: usize) -> Self {
+        Decoder(Codec::Zstd(Box::new(Zstd {
+            context: zstd::zstd_safe::DCtx::create(),
+            done: false,
+            output: Output::new(max_size),
+        })))
+    }
+
+    /// Decodes `input`, returning the output that it completes.
+    pub(crate) fn write(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        let result = match &mut self.0 {
+            #[cfg(feature = "brotli")]
+            Codec::Brotli(writer) => writer.write_all(input),
+            Codec::Deflate(inflate) => inflate.write(input),
+            // The writer holds on to output until it is flushed.
+            Codec::Gzip(writer) => writer.write_all(input).and_then(|_| writer.flush()),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(zstd) => zstd.write(input),
+        };
+
+        self.result(result)
+    }
+
+    /// Returns the rest of the output once all of the input has been written,
+    /// failing if the stream is incomplete.
+    pub(crate) fn finish(&mut self) -> Result<Vec<u8>> {
+        let result = match &mut self.0 {
+            #[cfg(feature = "brotli")]
+            Codec::Brotli(writer) => writer.close(),
+            Codec::Deflate(inflate) => inflate.finish(),
+            Codec::Gzip(writer) => writer.try_finish(),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(zstd) => zstd.finish(),
+        };
+
+        self.result(result)
+    }
+
+    fn output(&mut self) -> &mut Output {
+        match &mut self.0 {
+            #[cfg(feature = "brotli")]
+            Codec::Brotli(writer) => writer.get_mut(),
+            Codec::Deflate(inflate) => &mut inflate.output,
+            Codec::Gzip(writer) => writer.get_mut(),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(zstd) => &mut zstd.output,
+        }
+    }
+
+    fn result(&mut self, result: io::Result<()>) -> Result<Vec<u8>> {
+        let output = self.output();
+
+        match result {
+            Ok(()) => Ok(take(&mut output.buffer)),
+            Err(_) if output.exceeded => Err(DecodeError::TooLarge),
+            Err(_) => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+impl Output {
+    fn new(max_size: usize) -> Self {
+        Output {
+            buffer: Vec::new(),
+            exceeded: false,
+            remaining: max_size,
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, input: &[u8]) -> io::Result<usize> {
+        if input.len() > self.remaining {
+            self.exceeded = true;
+            return Err(io::Error::other("decoded body is too large"));
+        }
+
+        self.remaining -= input.len();
+        self.buffer.extend_from_slice(input);
+        Ok(input.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Inflate {
+    fn write(&mut self, mut input: &[u8]) -> io::Result<()> {
+        let decompress = match &mut self.decompress {
+            Some(decompress) => decompress,
+            None => {
+                self.pending.extend_from_slice(input);
+
+                let header = match self.pending[..] {
+                    [first, second, ..] => [first, second],
+                    _ => return Ok(()),
+                };
+                let pending = take(&mut self.pending);
+
+                self.decompress = Some(Decompress::new(is_zlib(header)));
+                return self.write(&pending);
+            }
+        };
+        let mut buffer = vec![0; CHUNK];
+
+        loop {
+            if self.done {
+                return if input.is_empty() {
+                    Ok(())
+                } else {
+                    Err(invalid())
+                };
+            }
+
+            let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+            let status = decompress.decompress(input, &mut buffer, FlushDecompress::None)?;
+            let read = (decompress.total_in() - total_in) as usize;
+            let written = (decompress.total_out() - total_out) as usize;
+
+            input = &input[read..];
+            self.done = status == Status::StreamEnd;
+            self.output.write_all(&buffer[..written])?;
+
+            // Stop once the input is used up, unless the buffer was filled and
+            // there may be more output to flush.
+            if input.is_empty() && written < buffer.len() && !self.done {
+                return Ok(());
+            }
+
+            if read == 0 && written == 0 && !self.done {
+                return Err(invalid());
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.done {
+            Ok(())
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Zstd {
+    fn write(&mut self, input: &[u8]) -> io::Result<()> {
+        use zstd::zstd_safe::{InBuffer, OutBuffer};
+
+        let mut input = InBuffer::around(input);
+        let mut buffer = vec![0; CHUNK];
+
+        loop {
+            let mut output = OutBuffer::around(&mut buffer[..]);
+            let hint = self
+                .context
+                .decompress_stream(&mut output, &mut input)
+                .map_err(|_| invalid())?;
+            let written = output.pos();
+
+            // A hint of 0 means that a frame just ended. More frames may follow.
+            self.done = hint == 0;
+            self.output.write_all(&buffer[..written])?;
+
+            if input.pos() == input.src.len() && written < buffer.len() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.done {
+            Ok(())
+        } else {
+            Err(invalid())
+        }
+    }
+}
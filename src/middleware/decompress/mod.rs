@@ -0,0 +1,400 @@
+mod decoder;
+
+use http::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH};
+
+use self::decoder::{DecodeError, Decoder};
+use super::{context::Body, limit::DEFAULT_BODY_LIMIT};
+use crate::{error::Bail, BoxFuture, Context, Error, Middleware, Next, Respond, Result};
+
+/// Decodes request bodies that are sent with a `Content-Encoding` of `gzip`
+/// or `deflate`, or of `br` and `zstd` with the `brotli` and `zstd` features,
+/// and removes the header so the middleware that follow read the original
+/// payload. The body is decoded as the next middleware reads it.
+/// Reading a body that is invalid fails with 400 and reading more than
+/// `max_size` bytes, compressed or decoded, fails with 413. Encodings that
+/// aren't supported are rejected with 415.
+#[derive(Clone, Copy, Debug)]
+pub struct Decompress {
+    max_size: usize,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Encoding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    Deflate,
+    Gzip,
+    Identity,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+fn encoding(value: Option<&HeaderValue>) -> Option<Encoding> {
+    let value = match value {
+        Some(value) => value.to_str().ok()?.trim(),
+        None => return Some(Encoding::Identity),
+    };
+
+    if value.eq_ignore_ascii_case("gzip") || value.eq_ignore_ascii_case("x-gzip") {
+        Some(Encoding::Gzip)
+    } else if value.eq_ignore_ascii_case("deflate") {
+        Some(Encoding::Deflate)
+    } else if value.is_empty() || value.eq_ignore_ascii_case("identity") {
+        Some(Encoding::Identity)
+    } else {
+        #[cfg(feature = "brotli")]
+        if value.eq_ignore_ascii_case("br") {
+            return Some(Encoding::Brotli);
+        }
+
+        #[cfg(feature = "zstd")]
+        if value.eq_ignore_ascii_case("zstd") {
+            return Some(Encoding::Zstd);
+        }
+
+        None
+    }
+}
+
+/// Decodes `body` as it is read. The compressed input is limited to
+/// `max_size` as well, since a stream of empty blocks decodes to nothing.
+fn decode(body: Body, decoder: Decoder, max_size: usize) -> Body {
+    let state = Some((body, decoder, 0));
+    let chunks = futures::stream::unfold(state, move |state| async move {
+        let (mut body, mut decoder, mut read) = state?;
+
+        loop {
+            let chunk = match body.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => {
+                    return match decoder.finish() {
+                        Ok(output) if output.is_empty() => None,
+                        Ok(output) => Some((Ok(output.into()), None)),
+                        Err(e) => Some((Err(error(e)), None)),
+                    };
+                }
+                Err(error) => return Some((Err(error), None)),
+            };
+
+            read += chunk.len();
+
+            let decoded = if read > max_size {
+                Err(DecodeError::TooLarge)
+            } else {
+                decoder.write(&chunk)
+            };
+
+            match decoded {
+                Ok(output) if output.is_empty() => {}
+                Ok(output) => return Some((Ok(output.into()), Some((body, decoder, read)))),
+                Err(e) => return Some((Err(error(e)), None)),
+            }
+        }
+    });
+
+    Body::stream(chunks)
+}
+
+fn error(error: DecodeError) -> Error {
+    match error {
+        DecodeError::TooLarge => Error::from(Bail::new("Payload Too Large")).status(413),
+        DecodeError::Invalid => Error::from(Bail::new("Invalid compressed body")).status(400),
+    }
+}
+
+impl Decompress {
+    pub fn new() -> Self {
+        Decompress {
+            max_size: DEFAULT_BODY_LIMIT,
+        }
+    }
+
+    /// The maximum number of bytes that a body may decode to. Defaults to
+    /// `DEFAULT_BODY_LIMIT`.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl Default for Decompress {
+    fn default() -> Self {
+        Decompress::new()
+    }
+}
+
+impl Middleware for Decompress {
+    fn call(&self, mut context: Context, next: Next) -> BoxFuture<Result> {
+        let decoder = match encoding(context.headers().get(CONTENT_ENCODING)) {
+            #[cfg(feature = "brotli")]
+            Some(Encoding::Brotli) => Decoder::brotli(self.max_size),
+            Some(Encoding::Deflate) => Decoder::deflate(self.max_size),
+            Some(Encoding::Gzip) => Decoder::gzip(self.max_size),
+            Some(Encoding::Identity) => return next.call(context),
+            #[cfg(feature = "zstd")]
+            Some(Encoding::Zstd) => Decoder::zstd(self.max_size),
+            None => {
+                return Box::pin(async { "Unsupported Content-Encoding".status(415).respond() });
+            }
+        };
+        let body = context.read();
+        let headers = context.request.headers_mut();
+
+        // The decoded length isn't known until the body has been read.
+        headers.remove(CONTENT_ENCODING);
+        headers.remove(CONTENT_LENGTH);
+        *context.request.body_mut() = decode(body, decoder, self.max_size);
+
+        next.call(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http_body_util::BodyExt;
+    use std::{sync::Arc, time::Duration};
+
+    use super::{Body, Decompress};
+    use crate::{
+        middleware::DynMiddleware, response::Response, Context, Middleware, Next, Respond,
+    };
+
+    // `{"id":1}` compressed with gzip and zlib.
+    const GZIP: &[u8] = &[
+        31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 171, 86, 202, 76, 81, 178, 50, 172, 5, 0, 197, 248, 93,
+        68, 8, 0, 0, 0,
+    ];
+    const ZLIB: &[u8] = &[
+        120, 156, 171, 86, 202, 76, 81, 178, 50, 172, 5, 0, 11, 77, 2, 117,
+    ];
+    // `hello hello hello hello` as raw deflate data.
+    const RAW: &[u8] = &[203, 72, 205, 201, 201, 87, 200, 64, 39, 1];
+    // `hello\n` compressed with brotli, from the tests of brotli-decompressor.
+    #[cfg(feature = "brotli")]
+    const BROTLI: &[u8] = b"\x8f\x02\x80\x68\x65\x6c\x6c\x6f\x0a\x03";
+
+    /// `text()` compressed by zlib at level 9 with a small memory level, which
+    /// splits it into several dynamic Huffman blocks, and by the zstd CLI.
+    const TEXT_GZIP: &[u8] = include_bytes!("fixtures/text.gz");
+    const TEXT_ZLIB: &[u8] = include_bytes!("fixtures/text.zlib");
+    #[cfg(feature = "zstd")]
+    const TEXT_ZSTD: &[u8] = include_bytes!("fixtures/text.zst");
+
+    /// Lines of words picked by an LCG, so that the fixtures are small but
+    /// not trivially compressible.
+    fn text() -> String {
+        let words = [
+            "alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india",
+            "juliet", "kilo", "lima",
+        ];
+        let mut seed = 1u32;
+
+        (0..1000)
+            .map(|line| {
+                let mut line = line.to_string();
+
+                for _ in 0..4 {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    line.push(' ');
+                    line.push_str(words[(seed >> 16) as usize % words.len()]);
+                }
+
+                line + "\n"
+            })
+            .collect()
+    }
+
+    fn fixtures() -> Vec<(&'static str, &'static [u8])> {
+        vec![
+            ("gzip", TEXT_GZIP),
+            ("deflate", TEXT_ZLIB),
+            #[cfg(feature = "zstd")]
+            ("zstd", TEXT_ZSTD),
+        ]
+    }
+
+    async fn echo(mut context: Context, _: Next) -> crate::Result {
+        let encoding = context.headers().get("content-encoding").is_some();
+        let body = context.read().text().await?;
+
+        format!("{}:{}", encoding, body).respond()
+    }
+
+    /// Sends `body` in chunks of 1000 bytes, so decoding steps span chunks.
+    async fn call(middleware: Decompress, encoding: &str, body: &[u8]) -> (u16, String) {
+        let chunks: Vec<_> = body
+            .chunks(1000)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let request = http::Request::post("/")
+            .header("content-encoding", encoding)
+            .body(Body::stream(futures::stream::iter(chunks)))
+            .unwrap();
+        let stack: [DynMiddleware; 1] = [Arc::new(echo)];
+        let response = middleware
+            .call(Context::from(request), Next::new(stack.iter()))
+            .await
+            .unwrap_or_else(Response::from);
+        let status = response.status_code().as_u16();
+        let body = http::Response::from(response).into_body().collect().await;
+
+        (
+            status,
+            String::from_utf8_lossy(&body.unwrap().to_bytes()).into_owned(),
+        )
+    }
+
+    #[tokio::test]
+    async fn decodes_gzip_and_deflate_bodies() {
+        let decompress = Decompress::new();
+
+        assert_eq!(
+            call(decompress, "gzip", GZIP).await,
+            (200, r#"false:{"id":1}"#.to_owned())
+        );
+        assert_eq!(
+            call(decompress, "deflate", ZLIB).await,
+            (200, r#"false:{"id":1}"#.to_owned())
+        );
+        assert_eq!(
+            call(decompress, "deflate", RAW).await,
+            (200, "false:hello hello hello hello".to_owned())
+        );
+        assert_eq!(
+            call(decompress, "identity", b"plain").await,
+            (200, "true:plain".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn decodes_external_encoder_output() {
+        let expected = format!("false:{}", text());
+
+        for (encoding, fixture) in fixtures() {
+            assert_eq!(
+                call(Decompress::new(), encoding, fixture).await,
+                (200, expected.clone()),
+                "{}",
+                encoding
+            );
+        }
+
+        #[cfg(feature = "brotli")]
+        {
+            use std::io::Write;
+
+            let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+
+            writer.write_all(text().as_bytes()).unwrap();
+            assert_eq!(
+                call(Decompress::new(), "br", &writer.into_inner()).await,
+                (200, expected.clone())
+            );
+            assert_eq!(
+                call(Decompress::new(), "br", BROTLI).await,
+                (200, "false:hello\n".to_owned())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_and_corrupt_streams() {
+        #[cfg(feature = "brotli")]
+        for len in 0..BROTLI.len() {
+            let (status, _) = call(Decompress::new(), "br", &BROTLI[..len]).await;
+            assert_eq!(status, 400, "br truncated to {}", len);
+        }
+
+        // Brotli has no checksum, so only the other encodings can tell when
+        // the data itself is corrupt.
+        for (encoding, fixture) in fixtures() {
+            for len in (0..fixture.len()).step_by(97) {
+                let (status, _) = call(Decompress::new(), encoding, &fixture[..len]).await;
+                assert_eq!(status, 400, "{} truncated to {}", encoding, len);
+            }
+
+            let mut corrupt = fixture.to_vec();
+            let middle = corrupt.len() / 2;
+
+            corrupt[middle] ^= 0x55;
+            assert_eq!(
+                call(Decompress::new(), encoding, &corrupt).await.0,
+                400,
+                "{} corrupt",
+                encoding
+            );
+
+            let trailing = [fixture, b"trailing"].concat();
+            assert_eq!(call(Decompress::new(), encoding, &trailing).await.0, 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_decoding_at_max_size() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        // 16 MiB of zeros compress to about 16 KiB.
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+
+        encoder.write_all(&vec![0; 16 << 20]).unwrap();
+
+        let bomb = encoder.finish().unwrap();
+        let max_size = text().len();
+
+        assert_eq!(call(Decompress::new(), "gzip", &bomb).await.0, 413);
+
+        for (encoding, fixture) in fixtures() {
+            let decompress = Decompress::new().max_size(max_size - 1);
+            assert_eq!(call(decompress, encoding, fixture).await.0, 413);
+
+            let decompress = Decompress::new().max_size(max_size);
+            assert_eq!(call(decompress, encoding, fixture).await.0, 200);
+        }
+    }
+
+    #[tokio::test]
+    async fn decodes_the_body_as_it_is_read() {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        let request = http::Request::post("/")
+            .header("content-encoding", "gzip")
+            .body(Body::stream(receiver))
+            .unwrap();
+        let first: DynMiddleware = Arc::new(|mut context: Context, _: Next| async move {
+            let chunk = context.read().chunk().await?.unwrap_or_default();
+            String::from_utf8_lossy(&chunk).into_owned().respond()
+        });
+        let stack = [first];
+
+        // The rest of the body is never sent, so a decoder that waited for
+        // the whole body would time out.
+        sender
+            .unbounded_send(Ok(Bytes::from_static(&GZIP[..20])))
+            .unwrap();
+
+        let call = Decompress::new().call(Context::from(request), Next::new(stack.iter()));
+        let response = tokio::time::timeout(Duration::from_secs(1), call)
+            .await
+            .unwrap()
+            .unwrap_or_else(Response::from);
+        let body = http::Response::from(response).into_body().collect().await;
+        let body = body.unwrap().to_bytes();
+
+        assert!(!body.is_empty());
+        assert!(br#"{"id":1}"#.starts_with(&body));
+        drop(sender);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_oversized_and_unsupported_bodies() {
+        assert_eq!(call(Decompress::new(), "gzip", ZLIB).await.0, 400);
+        assert_eq!(call(Decompress::new(), "gzip", &GZIP[..20]).await.0, 400);
+        assert_eq!(
+            call(Decompress::new().max_size(4), "gzip", GZIP).await.0,
+            413
+        );
+        assert_eq!(call(Decompress::new(), "compress", GZIP).await.0, 415);
+        assert_eq!(call(Decompress::new(), "gzip, br", GZIP).await.0, 415);
+    }
+}
@@ -0,0 +1,157 @@
+use http::header::{HeaderName, HeaderValue, LINK};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{BoxFuture, Context, Middleware, Next, Result};
+
+static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+static SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+#[derive(Clone, Debug, Default)]
+pub struct Deprecated {
+    hits: Arc<AtomicU64>,
+    link: Option<String>,
+    since: Option<SystemTime>,
+    successor: Option<String>,
+    sunset: Option<SystemTime>,
+}
+
+pub struct Deprecation {
+    log: bool,
+}
+
+pub fn deprecation() -> Deprecation {
+    Deprecation { log: false }
+}
+
+impl Deprecated {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn link(mut self, url: impl Into<String>) -> Self {
+        self.link = Some(url.into());
+        self
+    }
+
+    pub fn since(mut self, date: SystemTime) -> Self {
+        self.since = Some(date);
+        self
+    }
+
+    pub fn successor(mut self, url: impl Into<String>) -> Self {
+        self.successor = Some(url.into());
+        self
+    }
+
+    pub fn sunset(mut self, date: SystemTime) -> Self {
+        self.sunset = Some(date);
+        self
+    }
+
+    fn headers(&self) -> Vec<(&'static HeaderName, String)> {
+        let mut headers = vec![(
+            &DEPRECATION,
+            match self
+                .since
+                .and_then(|date| date.duration_since(UNIX_EPOCH).ok())
+            {
+                Some(elapsed) => format!("@{}", elapsed.as_secs()),
+                None => "true".to_owned(),
+            },
+        )];
+
+        if let Some(date) = self.sunset {
+            headers.push((&SUNSET, httpdate::fmt_http_date(date)));
+        }
+
+        if let Some(url) = &self.link {
+            headers.push((&LINK, format!(r#"<{}>; rel="deprecation""#, url)));
+        }
+
+        if let Some(url) = &self.successor {
+            headers.push((&LINK, format!(r#"<{}>; rel="successor-version""#, url)));
+        }
+
+        headers
+    }
+}
+
+impl Deprecation {
+    pub fn log(mut self) -> Self {
+        self.log = true;
+        self
+    }
+}
+
+impl Middleware for Deprecation {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        let deprecated = match context.get::<Deprecated>() {
+            Ok(deprecated) => deprecated.clone(),
+            Err(_) => return next.call(context),
+        };
+
+        deprecated.hits.fetch_add(1, Ordering::Relaxed);
+
+        if self.log {
+            eprintln!(
+                "Deprecated route requested: {} {}",
+                context.method(),
+                context.uri().path()
+            );
+        }
+
+        Box::pin(async move {
+            let mut response = next.call(context).await?;
+            let headers = response.headers_mut();
+
+            for (name, value) in deprecated.headers() {
+                if let Ok(value) = HeaderValue::try_from(value) {
+                    headers.append(name, value);
+                }
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Deprecated;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn formats_headers() {
+        let deprecated = Deprecated::new()
+            .since(UNIX_EPOCH + Duration::from_secs(1_688_169_599))
+            .sunset(UNIX_EPOCH + Duration::from_secs(1_735_689_600))
+            .link("https://example.com/docs/v1")
+            .successor("/v2/users");
+        let headers: Vec<_> = deprecated
+            .headers()
+            .into_iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+
+        assert_eq!(
+            headers,
+            [
+                "deprecation: @1688169599",
+                "sunset: Wed, 01 Jan 2025 00:00:00 GMT",
+                r#"link: <https://example.com/docs/v1>; rel="deprecation""#,
+                r#"link: </v2/users>; rel="successor-version""#,
+            ]
+        );
+        assert_eq!(Deprecated::new().headers()[0].1, "true");
+    }
+}
@@ -0,0 +1,211 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use http::header::HeaderName;
+use sha2::{Digest as _, Sha256, Sha512};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+use super::context::Body;
+use crate::{BoxFuture, Context, Middleware, Next, Respond, Result};
+
+static DIGEST: HeaderName = HeaderName::from_static("digest");
+
+pub trait Algorithm: Send + Sync + 'static {
+    fn digest(&self, body: &[u8]) -> Vec<u8>;
+}
+
+pub struct Digest {
+    algorithms: Vec<(&'static str, Arc<dyn Algorithm>)>,
+    hmac: Option<HeaderName>,
+    hmac_key: Option<HmacKey>,
+    max_size: usize,
+}
+
+/// A per-route HMAC key. Attach it with `route.meta(HmacKey(...))` to override
+/// the key configured on the middleware.
+#[derive(Clone)]
+pub struct HmacKey(pub Arc<[u8]>);
+
+pub struct Sha256Digest;
+
+pub struct Sha512Digest;
+
+enum Check {
+    Digest(Arc<dyn Algorithm>, Vec<u8>),
+    Hmac(HmacKey, Vec<u8>),
+}
+
+pub fn digest() -> Digest {
+    Digest {
+        algorithms: vec![
+            ("sha-256", Arc::new(Sha256Digest)),
+            ("sha-512", Arc::new(Sha512Digest)),
+        ],
+        hmac: None,
+        hmac_key: None,
+        max_size: 1024 * 1024,
+    }
+}
+
+fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    (0..input.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(input.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+impl Digest {
+    pub fn algorithm(mut self, name: &'static str, algorithm: impl Algorithm) -> Self {
+        self.algorithms
+            .retain(|(other, _)| !other.eq_ignore_ascii_case(name));
+        self.algorithms.push((name, Arc::new(algorithm)));
+        self
+    }
+
+    /// Verifies an HMAC-SHA256 signature of the body sent as a hex string in
+    /// `header`, optionally prefixed with `sha256=`.
+    pub fn hmac(mut self, header: &'static str) -> Self {
+        self.hmac = Some(HeaderName::from_static(header));
+        self
+    }
+
+    /// The key that HMAC signatures are verified with, unless a route sets
+    /// its own `HmacKey`. Can be set before or after `hmac`.
+    pub fn hmac_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.hmac_key = Some(HmacKey(key.into().into()));
+        self
+    }
+
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    fn check(&self, context: &Context) -> Option<Check> {
+        if let Some(header) = &self.hmac {
+            let key = match context.get::<HmacKey>() {
+                Ok(key) => key.clone(),
+                Err(_) => self.hmac_key.clone()?,
+            };
+            let value = context.headers().get(header)?.to_str().ok()?.trim();
+            let value = value.strip_prefix("sha256=").unwrap_or(value);
+
+            return Some(Check::Hmac(key, decode_hex(value)?));
+        }
+
+        let header = context.headers().get(&DIGEST)?.to_str().ok()?;
+
+        header.split(',').find_map(|entry| {
+            let (name, value) = entry.trim().split_once('=')?;
+            let (_, algorithm) = self
+                .algorithms
+                .iter()
+                .find(|(other, _)| other.eq_ignore_ascii_case(name))?;
+
+            Some(Check::Digest(
+                Arc::clone(algorithm),
+                STANDARD.decode(value).ok()?,
+            ))
+        })
+    }
+}
+
+impl Middleware for Digest {
+    fn call(&self, mut context: Context, next: Next) -> BoxFuture<Result> {
+        let check = match self.check(&context) {
+            Some(check) => check,
+            None => {
+                return Box::pin(async { "Missing or unsupported digest".status(400).respond() });
+            }
+        };
+        let max_size = self.max_size;
+
+        Box::pin(async move {
+            let body = context.read().limited(max_size).await?;
+            let (expected, actual) = match check {
+                Check::Digest(algorithm, expected) => (expected, algorithm.digest(&body)),
+                Check::Hmac(HmacKey(key), expected) => {
+                    let mut mac = Hmac::<Sha256>::new_from_slice(&key)?;
+
+                    mac.update(&body);
+                    (expected, mac.finalize().into_bytes().to_vec())
+                }
+            };
+
+            if !bool::from(expected[..].ct_eq(&actual[..])) {
+                return "Digest mismatch".status(400).respond();
+            }
+
+            *context.request.body_mut() = Body::full(body);
+            next.call(context).await
+        })
+    }
+}
+
+impl Algorithm for Sha256Digest {
+    fn digest(&self, body: &[u8]) -> Vec<u8> {
+        Sha256::digest(body).to_vec()
+    }
+}
+
+impl Algorithm for Sha512Digest {
+    fn digest(&self, body: &[u8]) -> Vec<u8> {
+        Sha512::digest(body).to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{digest, Body, Digest};
+    use crate::{middleware::DynMiddleware, Context, Middleware, Next, Respond};
+    use std::sync::Arc;
+
+    async fn echo(mut context: Context, _: Next) -> crate::Result {
+        context.read().text().await?.respond()
+    }
+
+    async fn status(middleware: &Digest, header: (&str, &str), body: &'static str) -> u16 {
+        let request = http::Request::builder()
+            .method("POST")
+            .header(header.0, header.1)
+            .body(Body::full(body.into()))
+            .unwrap();
+        let stack: [DynMiddleware; 1] = [Arc::new(echo)];
+        let response = match middleware
+            .call(Context::from(request), Next::new(stack.iter()))
+            .await
+        {
+            Ok(response) => response,
+            Err(error) => error.into(),
+        };
+
+        http::Response::from(response).status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_payloads() {
+        // sha-256 of `{"id":1}`
+        let digest_header = (
+            "digest",
+            "sha-256=A3ySFO73TMOIfzpPCFtOF9digNr9JzsO4WDAnEuhz9Q=",
+        );
+        let signature = (
+            "x-signature",
+            "sha256=03def589620c813f198fd03d7967e292b163ef0435ebf43071ce0e9519763cb7",
+        );
+        let hmac = digest().hmac("x-signature").hmac_key("secret");
+        let key_first = digest().hmac_key("secret").hmac("x-signature");
+
+        assert_eq!(status(&digest(), digest_header, r#"{"id":1}"#).await, 200);
+        assert_eq!(status(&digest(), digest_header, r#"{"id":2}"#).await, 400);
+        assert_eq!(
+            status(&digest().max_size(4), digest_header, r#"{"id":1}"#).await,
+            413
+        );
+        assert_eq!(status(&digest(), ("digest", "md5=abc"), "").await, 400);
+        assert_eq!(status(&hmac, signature, r#"{"id":1}"#).await, 200);
+        assert_eq!(status(&hmac, signature, r#"{"id":2}"#).await, 400);
+        assert_eq!(status(&key_first, signature, r#"{"id":1}"#).await, 200);
+        assert_eq!(status(&key_first, signature, r#"{"id":2}"#).await, 400);
+    }
+}
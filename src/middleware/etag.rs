@@ -0,0 +1,307 @@
+use http::{
+    header::{
+        HeaderValue, CACHE_CONTROL, CONTENT_LOCATION, DATE, ETAG, EXPIRES, IF_NONE_MATCH,
+        LAST_MODIFIED, VARY,
+    },
+    Method, StatusCode,
+};
+use http_body_util::BodyExt;
+use hyper::body::Body as _;
+use std::mem::take;
+
+use crate::{
+    response::{Body, Response},
+    BoxFuture, Context, Middleware, Next, Result,
+};
+
+/// The headers that are kept when a response is replaced with a 304.
+const NOT_MODIFIED_HEADERS: [http::HeaderName; 6] = [
+    CACHE_CONTROL,
+    CONTENT_LOCATION,
+    DATE,
+    EXPIRES,
+    LAST_MODIFIED,
+    VARY,
+];
+
+/// Adds a weak ETag to 200 responses to GET and HEAD requests, computed from
+/// the body, and responds with 304 Not Modified when it matches
+/// If-None-Match. Only bodies held in memory and no larger than `max_size`
+/// are hashed. Streamed bodies and responses that already have an ETag are
+/// sent as is.
+///
+/// The tag is computed from the body as this middleware sees it. Included
+/// before `Compress`, every encoding of a body shares a tag, which a weak tag
+/// allows. Included after it, each encoding has its own.
+#[derive(Clone, Copy, Debug)]
+pub struct AutoEtag {
+    max_size: u64,
+}
+
+/// Returns true if any tag in `value` is a weak match for `etag`.
+fn is_weak_match(value: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_owned();
+    let etag = opaque(etag);
+
+    value.trim() == "*" || value.split(',').any(|tag| opaque(tag.trim()) == etag)
+}
+
+/// Returns a weak tag from the length and 64-bit FNV-1a hash of `body`. The
+/// hash is stable across builds so that servers behind a load balancer agree.
+fn weak_etag(body: &[u8]) -> HeaderValue {
+    let hash = body.iter().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    });
+    let etag = format!("W/\"{:x}-{:016x}\"", body.len(), hash);
+
+    HeaderValue::try_from(etag).unwrap()
+}
+
+fn not_modified(response: Response, etag: HeaderValue) -> Response {
+    let mut not_modified = Response::new(Body::default());
+
+    *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+
+    let headers = not_modified.headers_mut();
+
+    for name in NOT_MODIFIED_HEADERS {
+        for value in response.headers().get_all(&name) {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+
+    headers.insert(ETAG, etag);
+    not_modified
+}
+
+impl AutoEtag {
+    pub fn new() -> Self {
+        AutoEtag {
+            max_size: 1024 * 1024,
+        }
+    }
+
+    /// Bodies that are larger than `max_size` bytes don't get an ETag.
+    /// Defaults to 1 MiB.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    fn should_tag(&self, response: &Response) -> bool {
+        let body = response.body();
+
+        response.status_code() == StatusCode::OK
+            && !response.headers().contains_key(ETAG)
+            && body.is_full()
+            && body
+                .size_hint()
+                .exact()
+                .is_some_and(|size| size <= self.max_size)
+    }
+}
+
+impl Default for AutoEtag {
+    fn default() -> Self {
+        AutoEtag::new()
+    }
+}
+
+impl Middleware for AutoEtag {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        if context.method() != Method::GET && context.method() != Method::HEAD {
+            return next.call(context);
+        }
+
+        let auto_etag = *self;
+        let if_none_match = context
+            .headers()
+            .get(IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        Box::pin(async move {
+            let mut response = next.call(context).await?;
+
+            if !auto_etag.should_tag(&response) {
+                return Ok(response);
+            }
+
+            let body = take(response.body_mut()).collect().await?.to_bytes();
+            let etag = weak_etag(&body);
+            let matches = if_none_match
+                .as_deref()
+                .zip(etag.to_str().ok())
+                .is_some_and(|(value, etag)| is_weak_match(value, etag));
+
+            if matches {
+                return Ok(not_modified(response, etag));
+            }
+
+            response.headers_mut().insert(ETAG, etag);
+            *response.body_mut() = body.into();
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+    use http::header::{CACHE_CONTROL, CONTENT_LENGTH, ETAG, IF_NONE_MATCH};
+    use http_body_util::BodyExt;
+    use std::{convert::Infallible, sync::Arc};
+
+    use super::{is_weak_match, weak_etag, AutoEtag};
+    use crate::{
+        middleware::{compress::Compress, context::Body, DynMiddleware},
+        response::json_stream,
+        Context, Next, Respond,
+    };
+
+    fn json() -> String {
+        let items: Vec<_> = (0..100).map(|id| format!(r#"{{"id":{}}}"#, id)).collect();
+        format!("[{}]", items.join(","))
+    }
+
+    async fn posts(_: Context, _: Next) -> crate::Result {
+        json()
+            .header("content-type", "application/json")
+            .header("cache-control", "max-age=60")
+            .respond()
+    }
+
+    async fn call(
+        stack: &[DynMiddleware],
+        request: http::request::Builder,
+    ) -> (u16, http::HeaderMap, Vec<u8>) {
+        let request = request.uri("/posts").body(Body::full("".into())).unwrap();
+        let response = stack[0]
+            .call(Context::from(request), Next::new(stack[1..].iter()))
+            .await
+            .unwrap();
+        let (parts, body) = http::Response::from(response).into_parts();
+
+        (
+            parts.status.as_u16(),
+            parts.headers,
+            body.collect().await.unwrap().to_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn compares_tags_weakly() {
+        let etag = r#"W/"5-abc""#;
+
+        for value in [r#"W/"5-abc""#, r#""5-abc""#, r#""x", W/"5-abc""#, "*"] {
+            assert!(is_weak_match(value, etag), "{}", value);
+        }
+
+        for value in [r#""5-abd""#, r#"W/"5-ab""#, "", r#"5-abc"#] {
+            assert!(!is_weak_match(value, etag), "{}", value);
+        }
+
+        assert_eq!(weak_etag(b"hello"), r#"W/"5-a430d84680aabd0b""#);
+    }
+
+    #[tokio::test]
+    async fn responds_not_modified_when_the_tag_matches() {
+        let stack: [DynMiddleware; 2] = [Arc::new(AutoEtag::new()), Arc::new(posts)];
+        let (status, headers, body) = call(&stack, http::Request::get("/")).await;
+        let etag = headers[ETAG].to_str().unwrap().to_owned();
+
+        assert_eq!(status, 200);
+        assert_eq!(body, json().as_bytes());
+        assert!(etag.starts_with("W/\""));
+
+        for value in [etag.clone(), etag[2..].to_owned()] {
+            let request = http::Request::get("/").header(IF_NONE_MATCH, value);
+            let (status, headers, body) = call(&stack, request).await;
+
+            assert_eq!(status, 304);
+            assert_eq!(headers[ETAG], etag);
+            assert_eq!(headers[CACHE_CONTROL], "max-age=60");
+            assert!(!headers.contains_key("content-type"));
+            assert!(!headers.contains_key(CONTENT_LENGTH));
+            assert!(body.is_empty());
+        }
+
+        let request = http::Request::get("/").header(IF_NONE_MATCH, r#"W/"stale""#);
+        assert_eq!(call(&stack, request).await.0, 200);
+
+        let request = http::Request::post("/").header(IF_NONE_MATCH, etag);
+        let (status, headers, _) = call(&stack, request).await;
+
+        assert_eq!(status, 200);
+        assert!(!headers.contains_key(ETAG));
+    }
+
+    #[tokio::test]
+    async fn skips_streams_and_existing_tags() {
+        let stream: DynMiddleware = Arc::new(|_: Context, _: Next| async {
+            json_stream(stream::iter([Ok::<_, Infallible>(1), Ok(2)])).respond()
+        });
+        let tagged: DynMiddleware =
+            Arc::new(|_: Context, _: Next| async { "tagged".header("etag", r#""v1""#).respond() });
+        let large: DynMiddleware = Arc::new(posts);
+
+        for (auto_etag, endpoint, etag) in [
+            (AutoEtag::new(), stream, None),
+            (AutoEtag::new(), tagged, Some(r#""v1""#)),
+            (AutoEtag::new().max_size(16), large, None),
+        ] {
+            let stack = [Arc::new(auto_etag) as DynMiddleware, endpoint];
+            let request = http::Request::get("/").header(IF_NONE_MATCH, "*");
+            let (status, headers, body) = call(&stack, request).await;
+
+            assert_eq!(status, 200);
+            assert_eq!(headers.get(ETAG).and_then(|v| v.to_str().ok()), etag);
+            assert!(!body.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn tags_the_body_it_sees_when_compressed() {
+        let gzip = |request: http::request::Builder| request.header("accept-encoding", "gzip");
+        let inner: [DynMiddleware; 3] = [
+            Arc::new(Compress::new().min_size(0)),
+            Arc::new(AutoEtag::new()),
+            Arc::new(posts),
+        ];
+        let outer: [DynMiddleware; 3] = [
+            Arc::new(AutoEtag::new()),
+            Arc::new(Compress::new().min_size(0)),
+            Arc::new(posts),
+        ];
+
+        // Before Compress, the tag is of the identity body and is shared by
+        // every encoding.
+        let (_, identity, _) = call(&inner, http::Request::get("/")).await;
+        let (_, gzipped, _) = call(&inner, gzip(http::Request::get("/"))).await;
+
+        assert_eq!(gzipped["content-encoding"], "gzip");
+        assert_eq!(identity[ETAG], gzipped[ETAG]);
+
+        let request = gzip(http::Request::get("/")).header(IF_NONE_MATCH, &identity[ETAG]);
+        let (status, headers, body) = call(&inner, request).await;
+
+        assert_eq!(status, 304);
+        assert!(!headers.contains_key("content-encoding"));
+        assert!(body.is_empty());
+
+        // After it, the tag is of the encoded body.
+        let (_, identity, _) = call(&outer, http::Request::get("/")).await;
+        let (_, gzipped, body) = call(&outer, gzip(http::Request::get("/"))).await;
+
+        assert_ne!(identity[ETAG], gzipped[ETAG]);
+        assert_eq!(gzipped[ETAG], weak_etag(&body));
+        assert_eq!(gzipped["vary"], "accept-encoding");
+
+        let request = gzip(http::Request::get("/")).header(IF_NONE_MATCH, &gzipped[ETAG]);
+        let (status, headers, _) = call(&outer, request).await;
+
+        assert_eq!(status, 304);
+        assert_eq!(headers["vary"], "accept-encoding");
+    }
+}
@@ -0,0 +1,153 @@
+use std::{future::Future, iter, sync::Arc};
+
+use super::DynMiddleware;
+use crate::{BoxFuture, Context, Middleware, Next, Result};
+
+type Check = Arc<dyn Fn(&Context) -> BoxFuture<Result<()>> + Send + Sync>;
+
+/// Calls the rest of the stack only if a check passes. A check receives the
+/// request by reference, so it can read the headers, params, and extensions
+/// but not the body, which is left for the handler.
+///
+/// When a check fails, the error it returns is the response, unless a
+/// handler is registered with `otherwise`.
+#[derive(Clone)]
+pub struct Guard {
+    check: Check,
+    otherwise: Option<DynMiddleware>,
+}
+
+impl Guard {
+    pub fn new<F>(check: F) -> Self
+    where
+        F: Fn(&Context) -> Result<()> + Send + Sync + 'static,
+    {
+        Guard {
+            check: Arc::new(move |context| {
+                let result = check(context);
+                Box::pin(async { result })
+            }),
+            otherwise: None,
+        }
+    }
+
+    /// Creates a guard from a check that returns a future, such as one that
+    /// queries a database. The future can't borrow the request, so copy what
+    /// it needs before the `async` block.
+    pub fn new_async<F, R>(check: F) -> Self
+    where
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: Future<Output = Result<()>> + Send + 'static,
+    {
+        Guard {
+            check: Arc::new(move |context| Box::pin(check(context))),
+            otherwise: None,
+        }
+    }
+
+    /// Responds to requests that fail the check with `middleware`, such as
+    /// one that redirects to a login page. The error from the check is
+    /// discarded. Calling `next` from it responds with 404 rather than
+    /// continuing past the guard.
+    pub fn otherwise(mut self, middleware: impl Middleware) -> Self {
+        self.otherwise = Some(Arc::new(middleware));
+        self
+    }
+}
+
+impl Middleware for Guard {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        let check = (self.check)(&context);
+        let otherwise = self.otherwise.clone();
+
+        Box::pin(async move {
+            match (check.await, otherwise) {
+                (Ok(()), _) => next.call(context).await,
+                (Err(_), Some(otherwise)) => {
+                    otherwise.call(context, Next::new(iter::empty())).await
+                }
+                (Err(error), None) => Err(error),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+    use std::{sync::Arc, time::Duration};
+
+    use super::Guard;
+    use crate::{
+        error::Bail,
+        middleware::{context::Body, DynMiddleware},
+        Context, Error, Middleware, Next, Respond,
+    };
+
+    fn authenticate(context: &Context) -> crate::Result<()> {
+        match context.headers().get("authorization") {
+            Some(_) => Ok(()),
+            None => Err(Error::from(Bail::new("Unauthorized")).status(401)),
+        }
+    }
+
+    async fn call(guard: &Guard, authorization: Option<&str>) -> (u16, http::HeaderMap, String) {
+        let endpoint: DynMiddleware =
+            Arc::new(|mut context: Context, _: Next| async move { context.read().text().await });
+        let mut request = http::Request::post("/reports?account=7");
+
+        if let Some(value) = authorization {
+            request = request.header("authorization", value);
+        }
+
+        let context = Context::from(request.body(Body::full("body".into())).unwrap());
+        let response = guard.call(context, Next::new([&endpoint].into_iter()));
+        let response = http::Response::from(response.await.unwrap_or_else(Into::into));
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+
+        (
+            parts.status.as_u16(),
+            parts.headers,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn responds_with_the_error_or_otherwise() {
+        let guard = Guard::new(authenticate);
+
+        assert_eq!(call(&guard, Some("token")).await.2, "body");
+        assert_eq!(call(&guard, None).await.0, 401);
+
+        let guard = guard.otherwise(|context: Context, _: Next| async move {
+            let location = format!("/login?to={}", context.uri().path());
+            "".status(303).header("location", location).respond()
+        });
+        let (status, headers, _) = call(&guard, None).await;
+
+        assert_eq!(status, 303);
+        assert_eq!(headers["location"], "/login?to=/reports");
+        assert_eq!(call(&guard, Some("token")).await.0, 200);
+    }
+
+    #[tokio::test]
+    async fn awaits_async_checks() {
+        let guard = Guard::new_async(|context| {
+            let account = context.query_param("account");
+
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+
+                match account {
+                    Some(account) if account == "7" => {
+                        Err(Error::from(Bail::new("Feature disabled")).status(403))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        });
+
+        assert_eq!(call(&guard, None).await.0, 403);
+    }
+}
@@ -39,3 +39,10 @@ impl Next {
         }
     }
 }
+
+/// Lets one instance be included at more than one point in the route tree.
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        M::call(self, context, next)
+    }
+}
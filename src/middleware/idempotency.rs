@@ -0,0 +1,627 @@
+use bytes::Bytes;
+use http::{
+    header::{HeaderMap, HeaderName, AUTHORIZATION, COOKIE},
+    StatusCode,
+};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Body as _;
+use router::Verb;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+use super::context::Body;
+use crate::{BoxFuture, Context, Middleware, Next, Respond, Response, Result};
+
+pub type Fingerprint = [u8; 32];
+
+/// The number of keys acquired from a `MemoryStore` between sweeps for
+/// expired entries.
+const SWEEP_INTERVAL: u32 = 256;
+
+type Scope = Arc<dyn Fn(&Context) -> Option<String> + Send + Sync>;
+
+/// Records the responses to idempotent requests. Implement this to share
+/// keys between servers, such as with Redis.
+pub trait Store: Send + Sync + 'static {
+    /// Returns the entry for `key`, or locks it for `ttl` if there isn't one.
+    fn acquire(
+        &self,
+        key: &str,
+        fingerprint: Fingerprint,
+        ttl: Duration,
+    ) -> BoxFuture<Result<Entry>>;
+
+    fn complete(&self, key: &str, record: Record, ttl: Duration) -> BoxFuture<Result<()>>;
+
+    fn release(&self, key: &str) -> BoxFuture<Result<()>>;
+
+    /// Resolves when `key` may no longer be in progress. By default this
+    /// sleeps briefly so that `acquire` is polled.
+    fn wait(&self, key: &str) -> BoxFuture<Result<()>> {
+        let _ = key;
+
+        Box::pin(async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(())
+        })
+    }
+}
+
+pub enum Entry {
+    Acquired,
+    InProgress(Fingerprint),
+    Complete(Record),
+}
+
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub fingerprint: Fingerprint,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// Replays the recorded response to a request with an Idempotency-Key that
+/// was already handled. Keys are scoped to the method, path, and credentials
+/// (a digest of the Authorization and Cookie headers) by default, so clients
+/// can't replay each other's responses, or to the return value of `scope`.
+///
+/// A request with the same key and body as one that is in progress waits for
+/// it to finish, up to `lock_ttl`, and then responds with 409 Conflict. A key
+/// that is reused with a different body responds with 422 Unprocessable
+/// Entity.
+///
+/// Streamed responses and those larger than `max_size` aren't recorded. The
+/// key is released instead, so a retry runs the handler again. So are errors,
+/// and requests that are cancelled before they respond.
+pub struct Idempotency<T: Store = MemoryStore> {
+    header: HeaderName,
+    lock_ttl: Duration,
+    max_size: u64,
+    methods: Verb,
+    required: bool,
+    scope: Scope,
+    store: Arc<T>,
+    ttl: Duration,
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    keys: Mutex<Keys>,
+}
+
+#[derive(Default)]
+struct Keys {
+    acquires: u32,
+    entries: HashMap<String, (State, Instant)>,
+}
+
+enum State {
+    /// The sender is dropped when the entry is replaced or removed, which
+    /// wakes the requests that are waiting for it.
+    InProgress(Fingerprint, watch::Sender<()>),
+    Complete(Record),
+}
+
+/// Releases the key when dropped before the response is recorded.
+struct Lock<T: Store> {
+    key: Option<String>,
+    store: Arc<T>,
+}
+
+pub fn idempotency() -> Idempotency {
+    Idempotency {
+        header: HeaderName::from_static("idempotency-key"),
+        lock_ttl: Duration::from_secs(60),
+        max_size: 1024 * 1024,
+        methods: Verb::POST | Verb::PATCH,
+        required: false,
+        scope: Arc::new(default_scope),
+        store: Arc::new(MemoryStore::default()),
+        ttl: Duration::from_secs(60 * 60 * 24),
+    }
+}
+
+fn default_scope(context: &Context) -> Option<String> {
+    let mut hasher = Sha256::new();
+
+    for name in [AUTHORIZATION, COOKIE] {
+        for value in context.request.headers().get_all(name) {
+            hasher.update(value.as_bytes());
+            hasher.update(b"\n");
+        }
+    }
+
+    let credentials: Fingerprint = hasher.finalize().into();
+    let credentials: String = credentials
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    Some(format!(
+        "{} {} {}",
+        context.method(),
+        context.uri().path(),
+        credentials
+    ))
+}
+
+fn fingerprint(context: &Context, body: &[u8]) -> Fingerprint {
+    let mut hasher = Sha256::new();
+
+    hasher.update(context.method().as_str());
+    hasher.update(b" ");
+    hasher.update(context.uri().path());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hasher.finalize().into()
+}
+
+fn replay(record: Record) -> Response {
+    let mut response = Response::new(Full::new(record.body));
+
+    *response.status_mut() = record.status;
+    *response.headers_mut() = record.headers;
+    response
+}
+
+async fn record(response: Response, fingerprint: Fingerprint) -> Result<(Response, Record)> {
+    let (parts, body) = http::Response::from(response).into_parts();
+    let body = body.collect().await?.to_bytes();
+    let record = Record {
+        fingerprint,
+        status: parts.status,
+        headers: parts.headers.clone(),
+        body: body.clone(),
+    };
+
+    Ok((
+        http::Response::from_parts(parts, crate::response::Body::from(body)).into(),
+        record,
+    ))
+}
+
+impl Idempotency {
+    pub fn new() -> Self {
+        idempotency()
+    }
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        idempotency()
+    }
+}
+
+impl<T: Store> Idempotency<T> {
+    pub fn header(mut self, name: &'static str) -> Self {
+        self.header = HeaderName::from_static(name);
+        self
+    }
+
+    pub fn lock_ttl(mut self, ttl: Duration) -> Self {
+        self.lock_ttl = ttl;
+        self
+    }
+
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    pub fn methods(mut self, methods: Verb) -> Self {
+        self.methods = methods;
+        self
+    }
+
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    /// Scopes keys by the return value of `scope`, such as the ID of the
+    /// authenticated user and the route. Requests for which it returns `None`
+    /// aren't idempotent.
+    pub fn scope<F>(mut self, scope: F) -> Self
+    where
+        F: Fn(&Context) -> Option<String> + Send + Sync + 'static,
+    {
+        self.scope = Arc::new(scope);
+        self
+    }
+
+    pub fn store<S: Store>(self, store: S) -> Idempotency<S> {
+        Idempotency {
+            header: self.header,
+            lock_ttl: self.lock_ttl,
+            max_size: self.max_size,
+            methods: self.methods,
+            required: self.required,
+            scope: self.scope,
+            store: Arc::new(store),
+            ttl: self.ttl,
+        }
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+}
+
+impl<T: Store> Middleware for Idempotency<T> {
+    fn call(&self, mut context: Context, next: Next) -> BoxFuture<Result> {
+        if !self.methods.intersects(context.method().into()) {
+            return next.call(context);
+        }
+
+        let key = match context.headers().get(&self.header) {
+            Some(value) => value.to_str().map(str::to_owned),
+            None if self.required => {
+                return Box::pin(async { "Missing Idempotency-Key header".status(400).respond() });
+            }
+            None => return next.call(context),
+        };
+        let scope = match (self.scope)(&context) {
+            Some(scope) => scope,
+            None => return next.call(context),
+        };
+
+        let store = Arc::clone(&self.store);
+        let (lock_ttl, max_size, ttl) = (self.lock_ttl, self.max_size, self.ttl);
+
+        Box::pin(async move {
+            let key = match key {
+                Ok(value) if !value.is_empty() => format!("{}\n{}", scope, value),
+                _ => return "Invalid Idempotency-Key header".status(400).respond(),
+            };
+            let body = Bytes::from(context.read().vec().await?);
+            let fingerprint = fingerprint(&context, &body);
+
+            *context.request.body_mut() = Body::full(body);
+
+            let deadline = tokio::time::Instant::now() + lock_ttl;
+
+            loop {
+                match store.acquire(&key, fingerprint, lock_ttl).await? {
+                    Entry::Complete(record) if record.fingerprint == fingerprint => {
+                        return Ok(replay(record));
+                    }
+                    Entry::InProgress(other) if other == fingerprint => {
+                        let wait = store.wait(&key);
+
+                        if tokio::time::timeout_at(deadline, wait).await.is_err() {
+                            return "A request with this Idempotency-Key is in progress"
+                                .status(409)
+                                .respond();
+                        }
+                    }
+                    Entry::Complete(_) | Entry::InProgress(_) => {
+                        return "Idempotency-Key was reused with a different request"
+                            .status(422)
+                            .respond();
+                    }
+                    Entry::Acquired => break,
+                }
+            }
+
+            let mut lock = Lock {
+                key: Some(key),
+                store: Arc::clone(&store),
+            };
+            let response = next.call(context).await?;
+            let body = response.body();
+
+            let recordable = body.is_full()
+                && body
+                    .size_hint()
+                    .exact()
+                    .is_some_and(|size| size <= max_size);
+
+            if !recordable {
+                return Ok(response);
+            }
+
+            let (response, record) = record(response, fingerprint).await?;
+
+            if let Some(key) = lock.key.take() {
+                store.complete(&key, record, ttl).await?;
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn acquire(
+        &self,
+        key: &str,
+        fingerprint: Fingerprint,
+        ttl: Duration,
+    ) -> BoxFuture<Result<Entry>> {
+        let now = Instant::now();
+        let mut keys = self.keys.lock().unwrap();
+
+        keys.acquires += 1;
+
+        if keys.acquires >= SWEEP_INTERVAL {
+            keys.acquires = 0;
+            keys.entries.retain(|_, (_, expires)| *expires > now);
+        }
+
+        let entry = match keys.entries.get(key).filter(|(_, expires)| *expires > now) {
+            Some((State::Complete(record), _)) => Entry::Complete(record.clone()),
+            Some((State::InProgress(other, _), _)) => Entry::InProgress(*other),
+            None => {
+                let state = State::InProgress(fingerprint, watch::channel(()).0);
+
+                keys.entries.insert(key.to_owned(), (state, now + ttl));
+                Entry::Acquired
+            }
+        };
+
+        Box::pin(async { Ok(entry) })
+    }
+
+    fn complete(&self, key: &str, record: Record, ttl: Duration) -> BoxFuture<Result<()>> {
+        let state = State::Complete(record);
+
+        self.keys
+            .lock()
+            .unwrap()
+            .entries
+            .insert(key.to_owned(), (state, Instant::now() + ttl));
+
+        Box::pin(async { Ok(()) })
+    }
+
+    fn release(&self, key: &str) -> BoxFuture<Result<()>> {
+        self.keys.lock().unwrap().entries.remove(key);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn wait(&self, key: &str) -> BoxFuture<Result<()>> {
+        let receiver = match self.keys.lock().unwrap().entries.get(key) {
+            Some((State::InProgress(_, sender), expires)) if *expires > Instant::now() => {
+                Some(sender.subscribe())
+            }
+            _ => None,
+        };
+
+        Box::pin(async move {
+            if let Some(mut receiver) = receiver {
+                let _ = receiver.changed().await;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl<T: Store> Drop for Lock<T> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            tokio::spawn(self.store.release(&key));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::DynMiddleware;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn request(key: &str, body: &'static str) -> Context {
+        let request = http::Request::post("/charges")
+            .header("idempotency-key", key)
+            .body(Body::full(Bytes::from(body)))
+            .unwrap();
+
+        Context::from(request)
+    }
+
+    #[tokio::test]
+    async fn replays_and_rejects_reuse() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let middleware = idempotency();
+        let handler: DynMiddleware = {
+            let calls = Arc::clone(&calls);
+            Arc::new(move |mut context: Context, _: Next| {
+                let calls = Arc::clone(&calls);
+                async move {
+                    let body = context.read().text().await?;
+                    let count = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                    Ok::<_, crate::Error>(format!("{} #{}", body, count).status(201))
+                }
+            })
+        };
+        let stack = [handler];
+
+        let first = middleware.call(request("a", "charge"), Next::new(stack.iter()));
+        let second = middleware.call(request("a", "charge"), Next::new(stack.iter()));
+        let reused = middleware.call(request("a", "refund"), Next::new(stack.iter()));
+
+        let first = http::Response::from(first.await.unwrap());
+        let second = http::Response::from(second.await.unwrap());
+        let reused = http::Response::from(reused.await.unwrap());
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(first.status(), 201);
+        assert_eq!(second.status(), 201);
+        assert_eq!(reused.status(), 422);
+
+        let first = first.into_body().collect().await.unwrap().to_bytes();
+        let second = second.into_body().collect().await.unwrap().to_bytes();
+
+        assert_eq!(first, "charge #1");
+        assert_eq!(second, first);
+    }
+
+    fn counter(calls: &Arc<AtomicUsize>, millis: u64) -> DynMiddleware {
+        let calls = Arc::clone(calls);
+
+        Arc::new(move |mut context: Context, _: Next| {
+            let calls = Arc::clone(&calls);
+            async move {
+                let body = context.read().text().await?;
+                let count = calls.fetch_add(1, Ordering::SeqCst) + 1;
+
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                Ok::<_, crate::Error>(format!("{} #{}", body, count).status(201))
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn waits_for_concurrent_duplicates() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let middleware = Idempotency::new();
+        let stack = [counter(&calls, 50)];
+
+        let (first, second, reused) = tokio::join!(
+            middleware.call(request("a", "charge"), Next::new(stack.iter())),
+            middleware.call(request("a", "charge"), Next::new(stack.iter())),
+            middleware.call(request("a", "refund"), Next::new(stack.iter())),
+        );
+
+        let first = http::Response::from(first.unwrap());
+        let second = http::Response::from(second.unwrap());
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(http::Response::from(reused.unwrap()).status(), 422);
+        assert_eq!(second.status(), 201);
+        assert_eq!(
+            first.into_body().collect().await.unwrap().to_bytes(),
+            second.into_body().collect().await.unwrap().to_bytes()
+        );
+
+        // The duplicate gives up once the lock expires.
+        let middleware = Idempotency::new().lock_ttl(Duration::from_millis(20));
+        let stack = [counter(&calls, 100)];
+        let (_, second) = tokio::join!(
+            middleware.call(request("b", "charge"), Next::new(stack.iter())),
+            middleware.call(request("b", "charge"), Next::new(stack.iter())),
+        );
+
+        assert_eq!(http::Response::from(second.unwrap()).status(), 409);
+    }
+
+    #[tokio::test]
+    async fn scopes_keys() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let stack = [counter(&calls, 0)];
+        let middleware = Idempotency::new();
+        let refunds = http::Request::post("/refunds")
+            .header("idempotency-key", "a")
+            .body(Body::full(Bytes::from("charge")))
+            .unwrap();
+
+        for context in [request("a", "charge"), Context::from(refunds)] {
+            let response = middleware.call(context, Next::new(stack.iter()));
+            assert_eq!(http::Response::from(response.await.unwrap()).status(), 201);
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Clients with different credentials don't share keys.
+        for token in ["Bearer alice", "Bearer mallory"] {
+            let mut context = request("a", "charge");
+
+            context
+                .request
+                .headers_mut()
+                .insert(AUTHORIZATION, token.parse().unwrap());
+
+            let response = middleware.call(context, Next::new(stack.iter()));
+            let body = http::Response::from(response.await.unwrap()).into_body();
+
+            assert_ne!(body.collect().await.unwrap().to_bytes(), "charge #1");
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        let middleware = Idempotency::new().scope(|_| None);
+
+        for _ in 0..2 {
+            let response = middleware.call(request("a", "charge"), Next::new(stack.iter()));
+            response.await.unwrap();
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn sweeps_expired_keys() {
+        let store = MemoryStore::new();
+
+        store
+            .acquire("expired", [0; 32], Duration::ZERO)
+            .await
+            .unwrap();
+
+        for index in 1..SWEEP_INTERVAL {
+            let key = format!("key-{}", index);
+            store
+                .acquire(&key, [0; 32], Duration::from_secs(60))
+                .await
+                .unwrap();
+        }
+
+        let keys = store.keys.lock().unwrap();
+
+        assert!(!keys.entries.contains_key("expired"));
+        assert_eq!(keys.entries.len(), SWEEP_INTERVAL as usize - 1);
+    }
+
+    #[tokio::test]
+    async fn releases_unrecorded_keys() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let middleware = Idempotency::new();
+        let stream: DynMiddleware = {
+            let calls = Arc::clone(&calls);
+            Arc::new(move |_: Context, _: Next| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async {
+                    let items = futures::stream::iter([Ok::<_, std::convert::Infallible>(1)]);
+                    crate::response::json_stream(items).respond()
+                }
+            })
+        };
+
+        for _ in 0..2 {
+            let response =
+                middleware.call(request("a", "charge"), Next::new([&stream].into_iter()));
+            response.await.unwrap();
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // A request that is cancelled before it responds releases its key.
+        let slow = [counter(&calls, 10_000)];
+        let cancelled = middleware.call(request("b", "charge"), Next::new(slow.iter()));
+
+        assert!(tokio::time::timeout(Duration::from_millis(20), cancelled)
+            .await
+            .is_err());
+        tokio::task::yield_now().await;
+
+        let fast = [counter(&calls, 0)];
+        let response = middleware.call(request("b", "charge"), Next::new(fast.iter()));
+        let body = http::Response::from(response.await.unwrap()).into_body();
+
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "charge #4");
+    }
+}
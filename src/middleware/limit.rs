@@ -0,0 +1,62 @@
+use crate::{Context, Middleware, Next};
+
+/// The maximum number of bytes read when a body is aggregated unless a limit
+/// is configured with `Application::max_body_size` or `limit_body`.
+pub const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BodyLimit(pub(crate) usize);
+
+/// Overrides the maximum size of request bodies that are aggregated by the
+/// middleware that follow. Streaming consumers are not limited.
+pub fn limit_body(max: usize) -> impl Middleware {
+    move |mut context: Context, next: Next| {
+        context.insert(BodyLimit(max));
+        next.call(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{limit_body, BodyLimit, DEFAULT_BODY_LIMIT};
+    use crate::{
+        middleware::{context::Body, DynMiddleware},
+        response::Response,
+        Context, Error, Middleware, Next,
+    };
+
+    fn context(body: impl Into<String>) -> Context {
+        let request = http::Request::post("/").body(Body::full(body.into().into()));
+        Context::from(request.unwrap())
+    }
+
+    fn status(error: Error) -> u16 {
+        Response::from(error).status_code().as_u16()
+    }
+
+    #[tokio::test]
+    async fn rejects_bodies_over_the_limit() {
+        let echo: DynMiddleware =
+            Arc::new(|mut context: Context, _: Next| async move { context.read().text().await });
+        let limit = limit_body(5);
+
+        assert!(limit
+            .call(context("hello"), Next::new([&echo].into_iter()))
+            .await
+            .is_ok());
+
+        let result = limit
+            .call(context("hello!"), Next::new([&echo].into_iter()))
+            .await;
+        assert_eq!(result.err().map(status), Some(413));
+
+        let mut large = context("x".repeat(DEFAULT_BODY_LIMIT + 1));
+        assert_eq!(status(large.read().vec().await.unwrap_err()), 413);
+
+        let mut custom = context("hello");
+        custom.insert(BodyLimit(4));
+        assert_eq!(status(custom.read().text().await.unwrap_err()), 413);
+    }
+}
@@ -0,0 +1,282 @@
+use http::header::{HeaderValue, ACCEPT, CACHE_CONTROL, CONTENT_TYPE, RETRY_AFTER};
+use mime::Mime;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{response::Response, BoxFuture, Context, Middleware, Next, Respond, Result};
+
+/// Responds with 503 Service Unavailable to every request while enabled,
+/// except those to an allowed path. Enable and disable it at runtime with a
+/// `MaintenanceHandle`. Requests that are already in flight when it's
+/// enabled finish normally.
+///
+/// `/healthz` is allowed by default. Health checks added with
+/// `Application::health_check` are answered before any middleware, so they
+/// aren't affected either way.
+#[derive(Clone)]
+pub struct Maintenance {
+    allow: Vec<String>,
+    enabled: Arc<AtomicBool>,
+    html: Option<String>,
+    json: Option<String>,
+    retry_after: Duration,
+}
+
+/// Enables and disables a `Maintenance` middleware, such as from an admin
+/// endpoint or a signal handler.
+#[derive(Clone, Debug)]
+pub struct MaintenanceHandle {
+    enabled: Arc<AtomicBool>,
+}
+
+impl Maintenance {
+    pub fn new() -> Self {
+        Maintenance {
+            allow: vec!["/healthz".to_owned()],
+            enabled: Arc::new(AtomicBool::new(false)),
+            html: None,
+            json: None,
+            retry_after: Duration::from_secs(120),
+        }
+    }
+
+    /// Lets requests to `path` through while enabled. A path that ends with
+    /// `/*` allows every path that starts with it.
+    pub fn allow(mut self, path: impl Into<String>) -> Self {
+        self.allow.push(path.into());
+        self
+    }
+
+    pub fn handle(&self) -> MaintenanceHandle {
+        MaintenanceHandle {
+            enabled: Arc::clone(&self.enabled),
+        }
+    }
+
+    /// Responds with `body` to clients that prefer HTML.
+    pub fn html(mut self, body: impl Into<String>) -> Self {
+        self.html = Some(body.into());
+        self
+    }
+
+    /// Responds with `body` to clients that prefer JSON. The body is
+    /// serialized once, when this is called, and fails if it can't be.
+    pub fn json(mut self, body: &impl serde::Serialize) -> Result<Self> {
+        self.json = Some(serde_json::to_string(body)?);
+        Ok(self)
+    }
+
+    /// The value of the Retry-After header. Defaults to 2 minutes.
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    fn is_allowed(&self, path: &str) -> bool {
+        self.allow
+            .iter()
+            .any(|allowed| match allowed.strip_suffix('*') {
+                Some(prefix) if prefix.ends_with('/') => path.starts_with(prefix),
+                _ => path == allowed,
+            })
+    }
+
+    fn respond(&self, context: &Context) -> Result {
+        let offered: Vec<(Mime, &str)> = [
+            (mime::TEXT_HTML_UTF_8, self.html.as_deref()),
+            (mime::APPLICATION_JSON, self.json.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(mime, body)| Some((mime, body?)))
+        .chain([(mime::TEXT_PLAIN_UTF_8, "Service Unavailable")])
+        .collect();
+        let mimes: Vec<Mime> = offered.iter().map(|(mime, _)| mime.clone()).collect();
+        let index = context
+            .accepts()
+            .best(&mimes)
+            .and_then(|best| mimes.iter().position(|mime| mime == best))
+            .unwrap_or(offered.len() - 1);
+        let (mime, body) = &offered[index];
+        let mut response = Response::new(body.to_string());
+        let headers = response.headers_mut();
+
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(mime.as_ref())?);
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        headers.insert(RETRY_AFTER, HeaderValue::from(self.retry_after.as_secs()));
+        *response.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+
+        if offered.len() > 1 {
+            response.add_vary(ACCEPT);
+        }
+
+        response.respond()
+    }
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Maintenance::new()
+    }
+}
+
+impl Middleware for Maintenance {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        if !self.enabled.load(Ordering::Relaxed) || self.is_allowed(context.uri().path()) {
+            return next.call(context);
+        }
+
+        let response = self.respond(&context);
+        Box::pin(async { response })
+    }
+}
+
+impl MaintenanceHandle {
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::oneshot;
+
+    use super::Maintenance;
+    use crate::{
+        middleware::{context::Body, DynMiddleware},
+        Context, Middleware, Next,
+    };
+
+    async fn call(
+        maintenance: &Maintenance,
+        endpoint: &DynMiddleware,
+        path: &str,
+        accept: &str,
+    ) -> (u16, http::HeaderMap, String) {
+        let request = http::Request::get(path).header("accept", accept);
+        let context = Context::from(request.body(Body::full("".into())).unwrap());
+        let response = maintenance.call(context, Next::new([endpoint].into_iter()));
+        let (parts, body) = http::Response::from(response.await.unwrap()).into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+
+        (
+            parts.status.as_u16(),
+            parts.headers,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn toggles_at_runtime() {
+        let (started, wait) = oneshot::channel::<()>();
+        let (finish, finished) = oneshot::channel::<()>();
+        let gates = Arc::new(std::sync::Mutex::new(Some((started, finished))));
+        let endpoint: DynMiddleware = Arc::new(move |_: Context, _: Next| {
+            let gate = gates.lock().unwrap().take();
+
+            async move {
+                if let Some((started, finished)) = gate {
+                    let _ = started.send(());
+                    let _ = finished.await;
+                }
+
+                "ok"
+            }
+        });
+        let maintenance = Maintenance::new()
+            .allow("/admin/*")
+            .retry_after(Duration::from_secs(300));
+        let handle = maintenance.handle();
+
+        // Start a request, then enable maintenance while it's in flight.
+        let in_flight = tokio::spawn({
+            let maintenance = maintenance.clone();
+            let endpoint = Arc::clone(&endpoint);
+            async move { call(&maintenance, &endpoint, "/posts", "*/*").await }
+        });
+
+        wait.await.unwrap();
+        handle.enable();
+        finish.send(()).unwrap();
+
+        assert!(handle.is_enabled());
+        assert_eq!(in_flight.await.unwrap().0, 200);
+
+        let (status, headers, body) = call(&maintenance, &endpoint, "/posts", "*/*").await;
+
+        assert_eq!(status, 503);
+        assert_eq!(headers["retry-after"], "300");
+        assert_eq!(body, "Service Unavailable");
+
+        for path in ["/healthz", "/admin/migrations", "/admin/"] {
+            assert_eq!(call(&maintenance, &endpoint, path, "*/*").await.0, 200);
+        }
+
+        assert_eq!(call(&maintenance, &endpoint, "/admin", "*/*").await.0, 503);
+
+        handle.disable();
+        assert_eq!(call(&maintenance, &endpoint, "/posts", "*/*").await.0, 200);
+    }
+
+    #[tokio::test]
+    async fn negotiates_the_body() {
+        let endpoint: DynMiddleware = Arc::new(|_: Context, _: Next| async { "ok" });
+        let maintenance = Maintenance::new()
+            .html("<h1>Back soon</h1>")
+            .json(&serde_json::json!({ "error": "maintenance" }))
+            .unwrap();
+
+        maintenance.handle().enable();
+
+        for (accept, content_type, body) in [
+            (
+                "text/html",
+                "text/html; charset=utf-8",
+                "<h1>Back soon</h1>",
+            ),
+            (
+                "application/json",
+                "application/json",
+                r#"{"error":"maintenance"}"#,
+            ),
+            (
+                "text/plain",
+                "text/plain; charset=utf-8",
+                "Service Unavailable",
+            ),
+            (
+                "image/png",
+                "text/plain; charset=utf-8",
+                "Service Unavailable",
+            ),
+        ] {
+            let (status, headers, actual) = call(&maintenance, &endpoint, "/", accept).await;
+
+            assert_eq!(status, 503);
+            assert_eq!(headers["content-type"], content_type);
+            assert_eq!(headers["vary"], "accept");
+            assert_eq!(actual, body);
+        }
+    }
+
+    #[test]
+    fn fails_to_serialize_a_json_body() {
+        let body = std::collections::HashMap::from([((1, 2), "maintenance")]);
+
+        assert!(Maintenance::new().json(&body).is_err());
+    }
+}
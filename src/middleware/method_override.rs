@@ -0,0 +1,206 @@
+use bytes::Bytes;
+use http::{header::HeaderName, Method};
+use router::Verb;
+
+use super::context::{query, Body};
+use crate::{BoxFuture, Context, Middleware, Next, Result};
+
+/// The largest urlencoded body that is read to find the override field.
+const MAX_FORM_SIZE: u64 = 16 * 1024;
+
+/// Lets clients that can only send GET and POST, such as HTML forms, make a
+/// POST request with another method. The method is read from the
+/// X-HTTP-Method-Override header, or a field of a urlencoded body if one is
+/// configured with `field`. Overrides that aren't allowed are ignored.
+///
+/// Routes check the method when they are called rather than when they are
+/// matched, so this must be included before the routes it applies to, such
+/// as on the application before any route is added.
+#[derive(Clone, Debug)]
+pub struct MethodOverride {
+    field: Option<&'static str>,
+    header: HeaderName,
+    methods: Verb,
+}
+
+impl MethodOverride {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Also reads the method from `name` in urlencoded bodies, such as
+    /// `_method`. The body is left for the middleware that follow. Bodies
+    /// over 16 KiB or without a Content-Length aren't read.
+    pub fn field(mut self, name: &'static str) -> Self {
+        self.field = Some(name);
+        self
+    }
+
+    pub fn header(mut self, name: &'static str) -> Self {
+        self.header = HeaderName::from_static(name);
+        self
+    }
+
+    /// The methods that a request can be overridden to. Defaults to PUT,
+    /// PATCH, and DELETE.
+    pub fn methods(mut self, methods: Verb) -> Self {
+        self.methods = methods;
+        self
+    }
+
+    fn allowed(&self, value: &str) -> Option<Method> {
+        let method = Method::from_bytes(value.trim().to_ascii_uppercase().as_bytes()).ok()?;
+        self.methods.intersects((&method).into()).then_some(method)
+    }
+
+    fn is_form(&self, context: &Context) -> bool {
+        let headers = context.headers();
+        let is_urlencoded = headers
+            .content_type()
+            .is_some_and(|mime| mime.essence_str() == "application/x-www-form-urlencoded");
+
+        self.field.is_some()
+            && is_urlencoded
+            && headers
+                .content_length()
+                .is_some_and(|len| len <= MAX_FORM_SIZE)
+    }
+}
+
+impl Default for MethodOverride {
+    fn default() -> Self {
+        MethodOverride {
+            field: None,
+            header: HeaderName::from_static("x-http-method-override"),
+            methods: Verb::PUT | Verb::PATCH | Verb::DELETE,
+        }
+    }
+}
+
+impl Middleware for MethodOverride {
+    fn call(&self, mut context: Context, next: Next) -> BoxFuture<Result> {
+        if context.method() != Method::POST {
+            return next.call(context);
+        }
+
+        let header = context.headers().get(&self.header);
+        let method = header
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| self.allowed(value));
+
+        if let Some(method) = method {
+            *context.request.method_mut() = method;
+            return next.call(context);
+        }
+
+        if !self.is_form(&context) {
+            return next.call(context);
+        }
+
+        let field = self.field.unwrap_or_default();
+        let this = self.clone();
+
+        Box::pin(async move {
+            let body = Bytes::from(context.read().vec().await?);
+            let method = std::str::from_utf8(&body)
+                .ok()
+                .and_then(|form| query::param(form, field))
+                .and_then(|value| this.allowed(&value));
+
+            *context.request.body_mut() = Body::full(body);
+
+            if let Some(method) = method {
+                *context.request.method_mut() = method;
+            }
+
+            next.call(context).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MethodOverride;
+    use crate::{middleware::context::Body, routing::Router, Context, Next};
+
+    async fn destroy(mut context: Context, _: Next) -> crate::Result<String> {
+        Ok(format!("deleted {}", context.read().text().await?))
+    }
+
+    async fn call(router: &Router, request: http::request::Builder, body: &str) -> (u16, String) {
+        let request = request
+            .uri("/posts/1")
+            .body(Body::full(body.to_owned().into()));
+        let mut context = Context::from(request.unwrap());
+        let next = router.visit(&mut context);
+        let response = next.call(context).await.unwrap_or_else(Into::into);
+        let response = http::Response::from(response);
+        let status = response.status().as_u16();
+        let body = http_body_util::BodyExt::collect(response.into_body());
+
+        (
+            status,
+            String::from_utf8_lossy(&body.await.unwrap().to_bytes()).into_owned(),
+        )
+    }
+
+    fn post(content_type: &str) -> http::request::Builder {
+        http::Request::post("/").header("content-type", content_type)
+    }
+
+    #[tokio::test]
+    async fn overrides_post_requests() {
+        let mut router = Router::default();
+
+        router
+            .at("/")
+            .include(MethodOverride::new().field("_method"));
+        router.at("/posts/:id").delete(destroy);
+
+        let header = post("text/plain").header("x-http-method-override", "delete");
+        let form = "title=Hello&_method=DELETE";
+        let form = post("application/x-www-form-urlencoded").header("content-length", form.len());
+
+        assert_eq!(
+            call(&router, header, "").await,
+            (200, "deleted ".to_owned())
+        );
+        assert_eq!(
+            call(&router, form, "title=Hello&_method=DELETE").await,
+            (200, "deleted title=Hello&_method=DELETE".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_invalid_overrides() {
+        let mut router = Router::default();
+
+        router.at("/").include(MethodOverride::new());
+        router.at("/posts/:id").delete(destroy);
+
+        for value in ["TRACE", "GET", "not a method"] {
+            let request = post("text/plain").header("x-http-method-override", value);
+            assert_eq!(call(&router, request, "").await.0, 404);
+        }
+
+        // The field isn't read unless it is configured.
+        let form = "_method=DELETE";
+        let request =
+            post("application/x-www-form-urlencoded").header("content-length", form.len());
+        assert_eq!(call(&router, request, form).await.0, 404);
+
+        let request = http::Request::get("/").header("x-http-method-override", "DELETE");
+        assert_eq!(call(&router, request, "").await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn must_run_before_the_route() {
+        let mut router = Router::default();
+
+        router.at("/posts/:id").delete(destroy);
+        router.at("/posts/:id").include(MethodOverride::new());
+
+        let request = post("text/plain").header("x-http-method-override", "DELETE");
+        assert_eq!(call(&router, request, "").await.0, 404);
+    }
+}
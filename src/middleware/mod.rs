@@ -1,8 +1,28 @@
 mod handler;
-mod session;
 
+pub mod cache;
+pub mod catch_panic;
+pub mod compress;
+pub mod concurrency;
 pub mod context;
+pub mod cookies;
+pub mod decompress;
+pub mod deprecation;
+pub mod digest;
+pub mod etag;
 pub mod filter;
+pub mod guard;
+pub mod idempotency;
+pub mod limit;
+pub mod maintenance;
+pub mod method_override;
+pub mod rate_limit;
+pub mod request_id;
+pub mod rescue;
+pub mod session;
+pub mod slow_log;
+pub mod timeout;
+pub mod trace;
 
 pub(crate) use handler::DynMiddleware;
 
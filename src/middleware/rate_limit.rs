@@ -0,0 +1,333 @@
+use http::header::{HeaderName, HeaderValue};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{BoxFuture, Context, Middleware, Next, Respond, Result};
+
+/// The number of shards in a `MemoryStore`. Each has its own lock.
+const SHARDS: usize = 16;
+
+/// The number of tokens taken from a shard between sweeps for idle keys.
+const SWEEP_INTERVAL: u32 = 256;
+
+type Key = Arc<dyn Fn(&Context) -> Option<String> + Send + Sync>;
+
+/// Stores a token bucket for each key. Implement this to share limits
+/// between servers, such as with Redis.
+pub trait Store: Send + Sync + 'static {
+    /// Takes a token from the bucket of `key`, creating a full one if it
+    /// doesn't exist.
+    fn take(&self, key: &str, quota: Quota) -> BoxFuture<Result<Decision>>;
+}
+
+/// How many requests a key can make per period. Tokens are refilled
+/// continuously, so a key that has used its quota can make another request
+/// after `period / limit`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Quota {
+    limit: u32,
+    period: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    /// The number of tokens left in the bucket.
+    pub remaining: u32,
+    /// How long until the bucket is full again.
+    pub reset: Duration,
+    /// How long until a token is available when the request isn't allowed.
+    pub retry_after: Duration,
+}
+
+/// Responds with 429 Too Many Requests when a client exceeds its quota. By
+/// default clients are keyed by `Context::client_addr`, and requests without
+/// a key aren't limited. Every response has the RateLimit-Limit,
+/// RateLimit-Remaining, and RateLimit-Reset headers.
+pub struct RateLimit<T: Store = MemoryStore> {
+    key: Key,
+    quota: Quota,
+    store: Arc<T>,
+}
+
+/// Stores buckets in memory, split between shards so that concurrent
+/// requests rarely contend for a lock. Buckets that have refilled are
+/// evicted periodically to bound memory.
+pub struct MemoryStore {
+    hasher: RandomState,
+    shards: Vec<Mutex<Shard>>,
+}
+
+#[derive(Default)]
+struct Shard {
+    buckets: HashMap<String, Bucket>,
+    takes: u32,
+}
+
+struct Bucket {
+    full_at: Instant,
+    tokens: f64,
+    updated: Instant,
+}
+
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+impl Quota {
+    pub fn new(limit: u32, period: Duration) -> Self {
+        assert!(limit > 0, "a quota must allow at least one request");
+        Quota { limit, period }
+    }
+
+    pub fn per_second(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(60))
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// The number of tokens added to a bucket per second.
+    fn rate(&self) -> f64 {
+        f64::from(self.limit) / self.period.as_secs_f64()
+    }
+}
+
+impl RateLimit {
+    pub fn new(quota: Quota) -> Self {
+        RateLimit {
+            key: Arc::new(|context| Some(context.client_addr()?.to_string())),
+            quota,
+            store: Arc::new(MemoryStore::new()),
+        }
+    }
+}
+
+impl<T: Store> RateLimit<T> {
+    /// Keys requests by the return value of `key`, such as an API token.
+    /// Requests for which it returns `None` aren't limited.
+    pub fn key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Context) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    pub fn store<S: Store>(self, store: S) -> RateLimit<S> {
+        RateLimit {
+            key: self.key,
+            quota: self.quota,
+            store: Arc::new(store),
+        }
+    }
+}
+
+impl<T: Store> Middleware for RateLimit<T> {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        let key = match (self.key)(&context) {
+            Some(key) => key,
+            None => return next.call(context),
+        };
+        let quota = self.quota;
+        let store = Arc::clone(&self.store);
+
+        Box::pin(async move {
+            let decision = store.take(&key, quota).await?;
+            let mut response = if decision.allowed {
+                next.call(context).await?
+            } else {
+                "Too Many Requests"
+                    .status(429)
+                    .header("retry-after", seconds(decision.retry_after).to_string())
+                    .respond()?
+            };
+            let headers = response.headers_mut();
+
+            for (name, value) in [
+                ("ratelimit-limit", u64::from(quota.limit)),
+                ("ratelimit-remaining", u64::from(decision.remaining)),
+                ("ratelimit-reset", seconds(decision.reset)),
+            ] {
+                headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn take_at(&self, key: &str, quota: Quota, now: Instant) -> Decision {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        let mut shard = self.shards[index].lock().unwrap();
+
+        shard.takes += 1;
+
+        if shard.takes >= SWEEP_INTERVAL {
+            shard.takes = 0;
+            shard.buckets.retain(|_, bucket| bucket.full_at > now);
+        }
+
+        let limit = f64::from(quota.limit);
+        let rate = quota.rate();
+        let bucket = shard
+            .buckets
+            .entry(key.to_owned())
+            .or_insert_with(|| Bucket {
+                full_at: now,
+                tokens: limit,
+                updated: now,
+            });
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        let mut tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(limit);
+        let allowed = tokens >= 1.0;
+
+        if allowed {
+            tokens -= 1.0;
+        }
+
+        let reset = Duration::from_secs_f64((limit - tokens) / rate);
+
+        *bucket = Bucket {
+            full_at: now + reset,
+            tokens,
+            updated: now,
+        };
+
+        Decision {
+            allowed,
+            remaining: tokens as u32,
+            reset,
+            retry_after: match allowed {
+                true => Duration::ZERO,
+                false => Duration::from_secs_f64((1.0 - tokens) / rate),
+            },
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore {
+            hasher: RandomState::new(),
+            shards: (0..SHARDS).map(|_| Default::default()).collect(),
+        }
+    }
+}
+
+impl Store for MemoryStore {
+    fn take(&self, key: &str, quota: Quota) -> BoxFuture<Result<Decision>> {
+        let decision = self.take_at(key, quota, Instant::now());
+        Box::pin(async move { Ok(decision) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        hash::BuildHasher,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::{MemoryStore, Quota, RateLimit, SHARDS, SWEEP_INTERVAL};
+    use crate::{
+        middleware::{context::Body, DynMiddleware},
+        Context, Middleware, Next,
+    };
+
+    fn context(token: &str) -> Context {
+        let request = http::Request::get("/reports")
+            .header("x-api-token", token)
+            .body(Body::full("".into()))
+            .unwrap();
+
+        Context::from(request)
+    }
+
+    #[tokio::test]
+    async fn limits_each_key_separately() {
+        let endpoint: DynMiddleware = Arc::new(|_: Context, _: Next| async { "report" });
+        let limit = RateLimit::new(Quota::per_minute(3)).key(|context| {
+            let token = context.headers().get("x-api-token")?;
+            Some(token.to_str().ok()?.to_owned())
+        });
+        let call = |token| {
+            let response = limit.call(context(token), Next::new([&endpoint].into_iter()));
+
+            async move {
+                let response = http::Response::from(response.await.unwrap());
+                let header = |name| {
+                    let value = response.headers().get(name)?;
+                    Some(value.to_str().unwrap().to_owned())
+                };
+
+                (
+                    response.status().as_u16(),
+                    header("ratelimit-remaining"),
+                    header("retry-after"),
+                )
+            }
+        };
+
+        for remaining in ["2", "1", "0"] {
+            assert_eq!(call("a").await, (200, Some(remaining.to_owned()), None));
+        }
+
+        assert_eq!(
+            call("a").await,
+            (429, Some("0".to_owned()), Some("20".to_owned()))
+        );
+        assert_eq!(call("b").await, (200, Some("2".to_owned()), None));
+    }
+
+    #[test]
+    fn refills_and_evicts_idle_buckets() {
+        let quota = Quota::new(2, Duration::from_secs(2));
+        let store = MemoryStore::new();
+        let start = Instant::now();
+
+        assert!(store.take_at("a", quota, start).allowed);
+        assert!(store.take_at("a", quota, start).allowed);
+        assert!(!store.take_at("a", quota, start).allowed);
+
+        let decision = store.take_at("a", quota, start + Duration::from_secs(1));
+
+        assert!(decision.allowed);
+        assert_eq!(decision.reset, Duration::from_secs(2));
+
+        for index in 0..SWEEP_INTERVAL {
+            store.take_at(&index.to_string(), quota, start);
+        }
+
+        let index = store.hasher.hash_one("b") as usize % SHARDS;
+        let buckets = || store.shards[index].lock().unwrap().buckets.len();
+
+        assert!(buckets() > 1);
+
+        // Every bucket but the one taken from has refilled by now.
+        for _ in 0..SWEEP_INTERVAL {
+            store.take_at("b", quota, start + Duration::from_secs(60));
+        }
+
+        assert_eq!(buckets(), 1);
+    }
+}
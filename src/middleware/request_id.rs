@@ -0,0 +1,148 @@
+use http::header::{HeaderName, HeaderValue};
+use std::{
+    fmt::Write,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{BoxFuture, Context, Middleware, Next, Result};
+
+/// Assigns an ID to every request, reusing the one sent by the client when it
+/// is valid, and echoes it in the response headers. Include it before any
+/// middleware that logs so they can read it with `Context::request_id`.
+#[derive(Clone, Debug)]
+pub struct RequestId {
+    header: HeaderName,
+    max_length: usize,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Id(pub(crate) Arc<str>);
+
+/// Generates a UUIDv7: a millisecond timestamp followed by random bits.
+fn generate() -> String {
+    use rand::Rng;
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64);
+    let mut bytes: [u8; 16] = rand::thread_rng().gen();
+    let mut output = String::with_capacity(36);
+
+    bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x70;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    for (index, byte) in bytes.iter().enumerate() {
+        if matches!(index, 4 | 6 | 8 | 10) {
+            output.push('-');
+        }
+
+        let _ = write!(output, "{:02x}", byte);
+    }
+
+    output
+}
+
+fn is_valid(value: &str, max_length: usize) -> bool {
+    !value.is_empty()
+        && value.len() <= max_length
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b':'))
+}
+
+impl RequestId {
+    pub fn new() -> Self {
+        RequestId {
+            header: HeaderName::from_static("x-request-id"),
+            max_length: 128,
+        }
+    }
+
+    pub fn header(mut self, name: HeaderName) -> Self {
+        self.header = name;
+        self
+    }
+
+    /// Inbound IDs that are longer than `max_length` are replaced with a
+    /// generated one. Defaults to 128.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        RequestId::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn call(&self, mut context: Context, next: Next) -> BoxFuture<Result> {
+        let header = self.header.clone();
+        let id = match context.headers().get(&header).map(HeaderValue::to_str) {
+            Some(Ok(value)) if is_valid(value, self.max_length) => value.to_owned(),
+            _ => generate(),
+        };
+        let value = HeaderValue::from_str(&id);
+
+        context.insert(Id(id.into()));
+
+        Box::pin(async move {
+            let mut response = next.call(context).await?;
+
+            if let Ok(value) = value {
+                response.headers_mut().insert(header, value);
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, is_valid, RequestId};
+    use crate::{middleware::context::Body, Context, Middleware, Next};
+
+    #[test]
+    fn generates_uuid_v7() {
+        let id = generate();
+
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "7");
+        assert!(matches!(&id[19..20], "8" | "9" | "a" | "b"));
+        assert_ne!(id, generate());
+    }
+
+    #[test]
+    fn validates_inbound_ids() {
+        assert!(is_valid("abc-123_x.y:z", 16));
+        assert!(!is_valid("", 16));
+        assert!(!is_valid("has space", 16));
+        assert!(!is_valid(&"a".repeat(17), 16));
+    }
+
+    #[tokio::test]
+    async fn reuses_and_echoes_ids() {
+        let request = http::Request::get("/").header("x-request-id", "abc-123");
+        let context = Context::from(request.body(Body::full("".into())).unwrap());
+        let response = RequestId::new()
+            .call(context, Next::new([].into_iter()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+
+        let request = http::Request::get("/").header("x-request-id", "a b");
+        let context = Context::from(request.body(Body::full("".into())).unwrap());
+        let response = RequestId::new()
+            .call(context, Next::new([].into_iter()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["x-request-id"].len(), 36);
+    }
+}
@@ -0,0 +1,513 @@
+use futures::FutureExt;
+use http::{Method, StatusCode};
+use std::{
+    error::Error as StdError,
+    future::Future,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{error::Source, BoxFuture, Context, Error, Middleware, Next, Result};
+
+type Inspect = Arc<dyn Fn(&Error, &ErrorMeta) + Send + Sync>;
+type InspectAsync = Arc<dyn Fn(&Error, &ErrorMeta) -> BoxFuture<()> + Send + Sync>;
+type Rule = Arc<dyn Fn(&Source) -> Option<StatusCode> + Send + Sync>;
+
+/// Sets the status of errors returned by the middleware that follow from a
+/// table of rules, one per error type. Rules are tried in the order they're
+/// added, and the first one whose type is in the error's chain decides the
+/// status. Errors that don't match a rule keep their status.
+///
+/// Each error is passed to the `inspect` hooks, with its original message,
+/// before it's redacted. Without a hook, errors with a 5xx status are written
+/// to stderr.
+#[derive(Clone)]
+pub struct Rescue {
+    hooks: Arc<Vec<Hook>>,
+    json: bool,
+    redact_5xx: Option<Arc<str>>,
+    redact_codes: bool,
+    rules: Arc<Vec<Rule>>,
+}
+
+/// The request that an error was returned for.
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorMeta<'a> {
+    elapsed: Duration,
+    request: &'a Request,
+}
+
+#[derive(Clone)]
+enum Hook {
+    Async(InspectAsync),
+    Sync(Inspect),
+}
+
+#[derive(Debug)]
+struct Request {
+    method: Method,
+    path: String,
+    request_id: Option<String>,
+    route: Option<String>,
+    started: Instant,
+}
+
+impl<'a> ErrorMeta<'a> {
+    /// The time from when the request reached `Rescue` until the error did.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn method(&self) -> &'a Method {
+        &self.request.method
+    }
+
+    pub fn path(&self) -> &'a str {
+        &self.request.path
+    }
+
+    /// The ID assigned by the `RequestId` middleware, if it was included
+    /// first.
+    pub fn request_id(&self) -> Option<&'a str> {
+        self.request.request_id.as_deref()
+    }
+
+    /// The pattern of the route that was matched, such as `/posts/:id`, if
+    /// `Rescue` was included on a route.
+    pub fn route(&self) -> Option<&'a str> {
+        self.request.route.as_deref()
+    }
+}
+
+impl Rescue {
+    pub fn new() -> Self {
+        Rescue {
+            hooks: Arc::new(Vec::new()),
+            json: false,
+            redact_5xx: None,
+            redact_codes: false,
+            rules: Arc::new(Vec::new()),
+        }
+    }
+
+    /// Calls `inspect` with each error and the request it was returned for,
+    /// such as to report it, instead of writing 5xx errors to stderr. Hooks
+    /// are called in the order they're added. A hook that panics doesn't
+    /// change the error.
+    pub fn inspect<F>(mut self, inspect: F) -> Self
+    where
+        F: Fn(&Error, &ErrorMeta) + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.hooks).push(Hook::Sync(Arc::new(inspect)));
+        self
+    }
+
+    /// Like `inspect`, but the response waits for the future that `inspect`
+    /// returns, such as one that sends the error to a channel. The future
+    /// can't borrow the error, so copy what it needs before the `async` block.
+    pub fn inspect_async<F, R>(mut self, inspect: F) -> Self
+    where
+        F: Fn(&Error, &ErrorMeta) -> R + Send + Sync + 'static,
+        R: Future<Output = ()> + Send + 'static,
+    {
+        let hook = Hook::Async(Arc::new(move |error, meta| Box::pin(inspect(error, meta))));
+
+        Arc::make_mut(&mut self.hooks).push(hook);
+        self
+    }
+
+    /// Renders every error as JSON.
+    pub fn json(mut self) -> Self {
+        self.json = true;
+        self
+    }
+
+    /// Sets the status of errors with an `E` in their chain to the return
+    /// value of `status`.
+    pub fn map<E, F>(mut self, status: F) -> Self
+    where
+        E: StdError + 'static,
+        F: Fn(&E) -> StatusCode + Send + Sync + 'static,
+    {
+        let rule: Rule = Arc::new(move |source: &Source| {
+            let mut chain = Some(source);
+
+            while let Some(error) = chain {
+                if let Some(error) = error.downcast_ref::<E>() {
+                    return Some(status(error));
+                }
+
+                chain = error.source();
+            }
+
+            None
+        });
+
+        Arc::make_mut(&mut self.rules).push(rule);
+        self
+    }
+
+    /// Replaces the message of errors with a 5xx status with `message`, so
+    /// that internal details aren't sent to the client. Their codes are kept
+    /// unless `redact_codes` is called.
+    pub fn redact_5xx(mut self, message: impl Into<Arc<str>>) -> Self {
+        self.redact_5xx = Some(message.into());
+        self
+    }
+
+    /// Replaces the codes of redacted errors with the default code for their
+    /// status, such as `internal_server_error`.
+    pub fn redact_codes(mut self) -> Self {
+        self.redact_codes = true;
+        self
+    }
+
+    async fn rescue(&self, mut error: Error, request: &Request) -> Error {
+        let status = self.rules.iter().find_map(|rule| rule(error.source()));
+
+        if let Some(status) = status {
+            error = error.status(status.as_u16());
+        }
+
+        if self.json {
+            error = error.json();
+        }
+
+        let meta = ErrorMeta {
+            elapsed: request.started.elapsed(),
+            request,
+        };
+
+        if self.hooks.is_empty() && error.status_code().is_server_error() {
+            eprintln!("{}", error);
+        }
+
+        for hook in self.hooks.iter() {
+            let panicked = match hook {
+                Hook::Sync(inspect) => {
+                    catch_unwind(AssertUnwindSafe(|| inspect(&error, &meta))).is_err()
+                }
+                Hook::Async(inspect) => {
+                    let future = catch_unwind(AssertUnwindSafe(|| inspect(&error, &meta)));
+
+                    match future {
+                        Ok(future) => AssertUnwindSafe(future).catch_unwind().await.is_err(),
+                        Err(_) => true,
+                    }
+                }
+            };
+
+            if panicked {
+                eprintln!("a Rescue inspect hook panicked while inspecting: {}", error);
+            }
+        }
+
+        match &self.redact_5xx {
+            Some(message) if error.status_code().is_server_error() => match self.redact_codes {
+                true => error.redact(message).redact_code(),
+                false => error.redact(message),
+            },
+            _ => error,
+        }
+    }
+}
+
+impl Default for Rescue {
+    fn default() -> Self {
+        Rescue::new()
+    }
+}
+
+impl Middleware for Rescue {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        let request = Request {
+            method: context.method().clone(),
+            path: context.uri().path().to_owned(),
+            request_id: context.request_id().map(str::to_owned),
+            route: context.route_pattern().map(str::to_owned),
+            started: Instant::now(),
+        };
+        let future = next.call(context);
+        let rescue = self.clone();
+
+        Box::pin(async move {
+            match future.await {
+                Ok(response) => Ok(response),
+                Err(error) => Err(rescue.rescue(error, &request).await),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+    use http_body_util::BodyExt;
+    use std::{
+        fmt::{self, Display, Formatter},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::Rescue;
+    use crate::{
+        error::Bail,
+        middleware::{context::Body, request_id::Id, DynMiddleware},
+        routing::RoutePattern,
+        Context, Error, Middleware, Next, ResultExt,
+    };
+
+    #[derive(Debug)]
+    enum DbError {
+        NotFound,
+        Timeout,
+    }
+
+    /// Wraps another error, which is its source.
+    #[derive(Debug)]
+    struct Validation(Option<DbError>);
+
+    impl Display for DbError {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            write!(f, "database: {:?}", self)
+        }
+    }
+
+    impl std::error::Error for DbError {}
+
+    impl Display for Validation {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            f.write_str("invalid title")
+        }
+    }
+
+    impl std::error::Error for Validation {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            self.0.as_ref().map(|error| error as _)
+        }
+    }
+
+    async fn call(
+        rescue: &Rescue,
+        error: impl Fn() -> Error + Send + Sync + 'static,
+    ) -> (u16, String) {
+        let endpoint: DynMiddleware = Arc::new(move |_: Context, _: Next| {
+            let error = error();
+            async { Err::<&str, _>(error) }
+        });
+        let context = Context::from(http::Request::get("/").body(Body::full("".into())).unwrap());
+        let error = rescue
+            .call(context, Next::new([&endpoint].into_iter()))
+            .await
+            .err()
+            .unwrap();
+        let response = http::Response::from(crate::response::Response::from(error));
+        let status = response.status().as_u16();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn db(status: &DbError) -> StatusCode {
+        match status {
+            DbError::NotFound => StatusCode::NOT_FOUND,
+            DbError::Timeout => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    #[tokio::test]
+    async fn maps_statuses_by_type() {
+        let rescue = Rescue::new()
+            .map::<DbError, _>(db)
+            .map::<Validation, _>(|_| StatusCode::UNPROCESSABLE_ENTITY)
+            .inspect(|_, _| {});
+
+        assert_eq!(
+            call(&rescue, || DbError::NotFound.into()).await,
+            (404, "database: NotFound".to_owned())
+        );
+        assert_eq!(call(&rescue, || DbError::Timeout.into()).await.0, 503);
+        assert_eq!(
+            call(&rescue, || Validation(None).into()).await,
+            (422, "invalid title".to_owned())
+        );
+
+        // Context doesn't hide the type of the error it wraps.
+        let with_context = || {
+            Err::<(), _>(DbError::NotFound)
+                .context("loading post")
+                .err()
+                .unwrap()
+        };
+        assert_eq!(
+            call(&rescue, with_context).await,
+            (404, "loading post".to_owned())
+        );
+
+        // Unmatched errors keep their status.
+        let bail = || Error::from(Bail::new("gone")).status(410);
+        assert_eq!(call(&rescue, bail).await.0, 410);
+    }
+
+    #[tokio::test]
+    async fn tries_rules_in_order() {
+        let wrapped = || Validation(Some(DbError::NotFound)).into();
+        let db_first = Rescue::new()
+            .map::<DbError, _>(db)
+            .map::<Validation, _>(|_| StatusCode::UNPROCESSABLE_ENTITY);
+        let validation_first = Rescue::new()
+            .map::<Validation, _>(|_| StatusCode::UNPROCESSABLE_ENTITY)
+            .map::<DbError, _>(db);
+
+        // A rule matches errors anywhere in the chain.
+        assert_eq!(call(&db_first, wrapped).await.0, 404);
+        assert_eq!(call(&validation_first, wrapped).await.0, 422);
+    }
+
+    #[tokio::test]
+    async fn redacts_server_errors() {
+        let inspected = Arc::new(Mutex::new(Vec::new()));
+        let rescue = Rescue::new()
+            .map::<DbError, _>(db)
+            .redact_5xx("Internal Server Error")
+            .json()
+            .inspect({
+                let inspected = Arc::clone(&inspected);
+                move |error, _| inspected.lock().unwrap().push(error.to_string())
+            });
+
+        assert_eq!(
+            call(&rescue, || DbError::Timeout.into()).await,
+            (
+                503,
+                r#"{"errors":[{"message":"Internal Server Error","code":"service_unavailable"}]}"#
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            call(&rescue, || DbError::NotFound.into()).await,
+            (
+                404,
+                r#"{"errors":[{"message":"database: NotFound","code":"not_found"}]}"#.to_owned()
+            )
+        );
+        assert_eq!(
+            *inspected.lock().unwrap(),
+            ["database: Timeout", "database: NotFound"]
+        );
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[tokio::test]
+    async fn inspects_backtraces() {
+        crate::error::force_backtraces(true);
+
+        let backtraces = Arc::new(Mutex::new(Vec::new()));
+        let rescue = Rescue::new().redact_5xx("Internal Server Error").inspect({
+            let backtraces = Arc::clone(&backtraces);
+            move |error, _| {
+                let backtrace = error.backtrace().map(ToString::to_string);
+                backtraces
+                    .lock()
+                    .unwrap()
+                    .push(backtrace.unwrap_or_default());
+            }
+        });
+        let (status, body) = call(&rescue, || DbError::Timeout.into()).await;
+
+        assert_eq!((status, body.as_str()), (500, "Internal Server Error"));
+        assert!(backtraces.lock().unwrap()[0].contains("rescue"));
+    }
+
+    #[tokio::test]
+    async fn calls_hooks_in_order_with_meta() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let push = |calls: &Arc<Mutex<Vec<String>>>| {
+            let calls = Arc::clone(calls);
+            move |call: String| calls.lock().unwrap().push(call)
+        };
+        let rescue = Rescue::new()
+            .redact_5xx("Internal Server Error")
+            .inspect({
+                let push = push(&calls);
+                move |error, meta| {
+                    push(format!(
+                        "{} {} {:?} {:?} {}",
+                        meta.method(),
+                        meta.path(),
+                        meta.route(),
+                        meta.request_id(),
+                        error
+                    ));
+                    assert!(meta.elapsed() >= Duration::from_millis(10));
+                }
+            })
+            .inspect(|_, _| panic!("the hook is broken"))
+            .inspect_async({
+                let push = push(&calls);
+                move |error, _| {
+                    let push = push.clone();
+                    let status = error.status_code().as_u16();
+
+                    async move {
+                        tokio::task::yield_now().await;
+                        push(format!("async {}", status));
+                    }
+                }
+            })
+            .inspect_async(|_, _| async { panic!("the async hook is broken") })
+            .inspect({
+                let push = push(&calls);
+                move |_, _| push("last".to_owned())
+            });
+        let endpoint: DynMiddleware = Arc::new(|_: Context, _: Next| async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Err::<&str, _>(Error::from(DbError::Timeout))
+        });
+        let mut context = Context::from(
+            http::Request::post("/posts/7")
+                .body(Body::full("".into()))
+                .unwrap(),
+        );
+
+        context.insert(Id("abc".into()));
+        context.insert(RoutePattern("/posts/:id".to_owned()));
+
+        let error = rescue
+            .call(context, Next::new([&endpoint].into_iter()))
+            .await
+            .err()
+            .unwrap();
+
+        // Panicking hooks don't replace the error.
+        assert_eq!(error.status_code(), 500);
+        assert_eq!(error.to_string(), "Internal Server Error");
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                r#"POST /posts/7 Some("/posts/:id") Some("abc") database: Timeout"#,
+                "async 500",
+                "last",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_codes_unless_redacted() {
+        let timeout = || Error::from(DbError::Timeout).with_code("database_timeout");
+        let rescue = Rescue::new()
+            .redact_5xx("Internal Server Error")
+            .json()
+            .inspect(|_, _| {});
+
+        assert_eq!(
+            call(&rescue, timeout).await.1,
+            r#"{"errors":[{"message":"Internal Server Error","code":"database_timeout"}]}"#
+        );
+        assert_eq!(
+            call(&rescue.redact_codes(), timeout).await.1,
+            r#"{"errors":[{"message":"Internal Server Error","code":"internal_server_error"}]}"#
+        );
+    }
+}
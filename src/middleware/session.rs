@@ -1 +1,703 @@
+use cookie::{time, Cookie, CookieJar, Key, SameSite};
+use http::header::{self, HeaderValue};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    mem::take,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use super::cookies::parse;
+use crate::{error::Bail, BoxFuture, Context, Error, Middleware, Next, Result};
+
+/// The number of saves to a `MemoryStore` between sweeps for expired
+/// sessions.
+const SWEEP_INTERVAL: u32 = 256;
+
+pub type Values = serde_json::Map<String, serde_json::Value>;
+
+/// Stores sessions by ID. Implement this to keep sessions in a database,
+/// such as Redis or Postgres.
+pub trait SessionStore: Send + Sync + 'static {
+    /// Returns the values of the session with `id`, or `None` if it doesn't
+    /// exist or has expired.
+    fn load(&self, id: &str) -> BoxFuture<Result<Option<Values>>>;
+
+    /// Saves the values of the session with `id`, which expires `ttl` after
+    /// it was last saved.
+    fn save(&self, id: &str, values: &Values, ttl: Duration) -> BoxFuture<Result<()>>;
+
+    fn destroy(&self, id: &str) -> BoxFuture<Result<()>>;
+}
+
+/// Loads the session of each request from a cookie and saves the changes made
+/// to it with `Context::session` once the middleware that follow respond.
+/// Changes aren't saved if they respond with an error or a 4xx or 5xx status.
+///
+/// By default sessions are kept in a `MemoryStore` and the cookie holds a
+/// signed, random 128-bit ID. With `cookie_store`, the whole session is
+/// encrypted into the cookie instead. Sessions expire after `ttl`, which
+/// restarts on every request unless `rolling` is disabled.
+#[derive(Clone)]
+pub struct Session {
+    cookie_name: &'static str,
+    key: Key,
+    previous_keys: Arc<[Key]>,
+    rolling: bool,
+    secure: bool,
+    storage: Storage,
+    ttl: Duration,
+}
+
+/// Keeps the whole session in an encrypted cookie, so nothing is stored on
+/// the server. Browsers limit the size of a cookie, so a session that grows
+/// past `max_size` fails the response instead of being silently dropped.
+#[derive(Clone, Copy, Debug)]
+pub struct CookieStore {
+    max_size: usize,
+}
+
+/// Keeps sessions in memory. Sessions are lost when the server restarts and
+/// aren't shared between servers, so this is meant for development.
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: Mutex<Sessions>,
+}
+
+/// The session of a request. Changes are deferred until the response is
+/// sent, when the `Session` middleware saves them.
+#[derive(Clone, Debug, Default)]
+pub struct SessionData {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct Sessions {
+    entries: HashMap<String, (Values, Instant)>,
+    saves: u32,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    changed: bool,
+    destroyed: bool,
+    /// The ID of the session in a `SessionStore`.
+    id: Option<String>,
+    loaded: bool,
+    regenerate: bool,
+    /// The cookie was verified by a previous secret, so it's re-issued with
+    /// the current one.
+    stale: bool,
+    values: Values,
+}
+
+/// The contents of a cookie saved by a `CookieStore`.
+struct Payload {
+    expires: u64,
+    values: Values,
+}
+
+#[derive(Clone)]
+enum Storage {
+    Cookie(CookieStore),
+    Store(Arc<dyn SessionStore>),
+}
+
+fn generate_id() -> String {
+    use rand::Rng;
+
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+
+    bytes
+        .iter()
+        .fold(String::with_capacity(32), |mut id, byte| {
+            let _ = write!(id, "{:02x}", byte);
+            id
+        })
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+fn key(secret: &[u8]) -> Key {
+    match Key::try_from(secret) {
+        Ok(key) => key,
+        Err(_) => panic!("a session secret must be at least 64 bytes"),
+    }
+}
+
+impl Session {
+    /// Signs and encrypts cookies with `secret`, which must be at least 64
+    /// bytes of random data.
+    pub fn new(secret: &[u8]) -> Self {
+        Session {
+            cookie_name: "session",
+            key: key(secret),
+            previous_keys: Arc::new([]),
+            rolling: true,
+            secure: true,
+            storage: Storage::Store(Arc::new(MemoryStore::new())),
+            ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+
+    pub fn cookie_name(mut self, name: &'static str) -> Self {
+        self.cookie_name = name;
+        self
+    }
+
+    pub fn cookie_store(mut self, store: CookieStore) -> Self {
+        self.storage = Storage::Cookie(store);
+        self
+    }
+
+    /// Accepts cookies signed or encrypted with `secrets`, which are tried in
+    /// order after the current secret, so that it can be rotated without
+    /// ending every session. A cookie that is accepted by one of them is
+    /// re-issued with the current secret.
+    pub fn previous_secrets(mut self, secrets: &[&[u8]]) -> Self {
+        self.previous_keys = secrets.iter().map(|secret| key(secret)).collect();
+        self
+    }
+
+    /// Whether a session is saved on every request to restart its ttl, or
+    /// only when it changes. Defaults to true.
+    pub fn rolling(mut self, rolling: bool) -> Self {
+        self.rolling = rolling;
+        self
+    }
+
+    /// Whether the cookie is only sent over HTTPS. Defaults to true.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn store(mut self, store: impl SessionStore) -> Self {
+        self.storage = Storage::Store(Arc::new(store));
+        self
+    }
+
+    /// How long a session lasts after it was last saved. Defaults to a day.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn cookie(&self, value: String) -> Cookie<'static> {
+        let max_age = time::Duration::try_from(self.ttl).unwrap_or(time::Duration::MAX);
+
+        Cookie::build((self.cookie_name, value))
+            .http_only(true)
+            .max_age(max_age)
+            .path("/")
+            .same_site(SameSite::Lax)
+            .secure(self.secure)
+            .build()
+    }
+
+    /// Returns the ID and values of the session in `jar`, or a new session if
+    /// it doesn't have a valid one.
+    async fn load(&self, jar: &CookieJar) -> Result<State> {
+        let (cookie, stale) = match self.verify(jar) {
+            Some(verified) => verified,
+            None => return Ok(Default::default()),
+        };
+        let state = match &self.storage {
+            Storage::Cookie(_) => match Payload::parse(cookie.value()) {
+                Some(payload) if payload.expires > unix_millis(SystemTime::now()) => State {
+                    loaded: true,
+                    stale,
+                    values: payload.values,
+                    ..Default::default()
+                },
+                _ => Default::default(),
+            },
+            Storage::Store(store) => match store.load(cookie.value()).await? {
+                Some(values) => State {
+                    id: Some(cookie.value().to_owned()),
+                    loaded: true,
+                    stale,
+                    values,
+                    ..Default::default()
+                },
+                None => Default::default(),
+            },
+        };
+
+        Ok(state)
+    }
+
+    /// Returns the session cookie in `jar` as verified by the first secret
+    /// that accepts it, and whether that is a previous secret.
+    fn verify(&self, jar: &CookieJar) -> Option<(Cookie<'static>, bool)> {
+        let keys = std::iter::once(&self.key).chain(self.previous_keys.iter());
+
+        keys.enumerate().find_map(|(index, key)| {
+            let cookie = match &self.storage {
+                Storage::Cookie(_) => jar.private(key).get(self.cookie_name),
+                Storage::Store(_) => jar.signed(key).get(self.cookie_name),
+            };
+
+            Some((cookie?, index > 0))
+        })
+    }
+
+    /// Saves `state` and returns the Set-Cookie header to send, if any.
+    async fn save(&self, state: State) -> Result<Option<HeaderValue>> {
+        let State {
+            changed,
+            destroyed,
+            id,
+            loaded,
+            regenerate,
+            stale,
+            values,
+        } = state;
+
+        if let (Some(id), Storage::Store(store)) = (&id, &self.storage) {
+            if destroyed || regenerate {
+                store.destroy(id).await?;
+            }
+        }
+
+        if destroyed {
+            if !loaded {
+                return Ok(None);
+            }
+
+            let mut cookie = self.cookie(String::new());
+
+            cookie.make_removal();
+            return Ok(Some(HeaderValue::try_from(cookie.encoded().to_string())?));
+        }
+
+        if !(changed || regenerate || loaded && (self.rolling || stale)) {
+            return Ok(None);
+        }
+
+        let mut jar = CookieJar::new();
+
+        match &self.storage {
+            Storage::Cookie(_) => {
+                let expires = unix_millis(SystemTime::now() + self.ttl);
+                let payload = Payload { expires, values }.into_json();
+
+                jar.private_mut(&self.key).add(self.cookie(payload));
+            }
+            Storage::Store(store) => {
+                let id = match id {
+                    Some(id) if !regenerate => id,
+                    _ => generate_id(),
+                };
+
+                store.save(&id, &values, self.ttl).await?;
+                jar.signed_mut(&self.key).add(self.cookie(id));
+            }
+        }
+
+        let cookie = jar.get(self.cookie_name).unwrap().encoded().to_string();
+
+        if let Storage::Cookie(store) = &self.storage {
+            if cookie.len() > store.max_size {
+                let message = format!(
+                    "The session cookie is {} bytes, which is more than the {} allowed",
+                    cookie.len(),
+                    store.max_size
+                );
+
+                return Err(Error::from(Bail::new(message)).status(500));
+            }
+        }
+
+        Ok(Some(HeaderValue::try_from(cookie)?))
+    }
+}
+
+impl Middleware for Session {
+    fn call(&self, mut context: Context, next: Next) -> BoxFuture<Result> {
+        let jar = parse(context.request.headers());
+        let session = self.clone();
+
+        Box::pin(async move {
+            let data = SessionData {
+                state: Arc::new(Mutex::new(session.load(&jar).await?)),
+            };
+
+            context.insert(data.clone());
+
+            let mut response = next.call(context).await?;
+            let status = response.status_code();
+
+            if status.is_client_error() || status.is_server_error() {
+                return Ok(response);
+            }
+
+            let state = take(&mut *data.state.lock().unwrap());
+
+            if let Some(cookie) = session.save(state).await? {
+                response.headers_mut().append(header::SET_COOKIE, cookie);
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+impl Payload {
+    fn parse(input: &str) -> Option<Self> {
+        let mut payload = match serde_json::from_str(input).ok()? {
+            Value::Object(payload) => payload,
+            _ => return None,
+        };
+        let expires = payload.get("expires")?.as_u64()?;
+
+        match payload.remove("values")? {
+            Value::Object(values) => Some(Payload { expires, values }),
+            _ => None,
+        }
+    }
+
+    fn into_json(self) -> String {
+        let Payload { expires, values } = self;
+        serde_json::json!({ "expires": expires, "values": values }).to_string()
+    }
+}
+
+impl CookieStore {
+    pub fn new() -> Self {
+        CookieStore { max_size: 4096 }
+    }
+
+    /// The largest Set-Cookie value, in bytes, that a session can be saved
+    /// as. Defaults to 4096, the smallest limit that browsers must support.
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self
+    }
+}
+
+impl Default for CookieStore {
+    fn default() -> Self {
+        CookieStore::new()
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> BoxFuture<Result<Option<Values>>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let values = match sessions.entries.get(id) {
+            Some((values, expires)) if *expires > Instant::now() => Some(values.clone()),
+            Some(_) => {
+                sessions.entries.remove(id);
+                None
+            }
+            None => None,
+        };
+
+        Box::pin(async move { Ok(values) })
+    }
+
+    fn save(&self, id: &str, values: &Values, ttl: Duration) -> BoxFuture<Result<()>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+
+        sessions.saves += 1;
+
+        if sessions.saves >= SWEEP_INTERVAL {
+            sessions.saves = 0;
+            sessions.entries.retain(|_, (_, expires)| *expires > now);
+        }
+
+        sessions
+            .entries
+            .insert(id.to_owned(), (values.clone(), now + ttl));
+
+        Box::pin(async { Ok(()) })
+    }
+
+    fn destroy(&self, id: &str) -> BoxFuture<Result<()>> {
+        self.sessions.lock().unwrap().entries.remove(id);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl SessionData {
+    /// Returns the value of `key`, or `None` if it isn't set or isn't a `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let state = self.state.lock().unwrap();
+        serde_json::from_value(state.values.get(key)?.clone()).ok()
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.values.remove(key).is_some() {
+            state.changed = true;
+        }
+    }
+
+    pub fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        let value = serde_json::to_value(value)?;
+        let mut state = self.state.lock().unwrap();
+
+        state.values.insert(key.to_owned(), value);
+        state.changed = true;
+        Ok(())
+    }
+
+    /// Removes the session and its cookie once the response is sent.
+    pub fn destroy(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        state.destroyed = true;
+        state.values.clear();
+    }
+
+    /// Moves the session to a new ID once the response is sent, keeping its
+    /// values. Call this when a user signs in so that an ID planted before
+    /// then, such as by session fixation, can't be used to act as them.
+    pub fn regenerate(&self) {
+        self.state.lock().unwrap().regenerate = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+    use std::{sync::Arc, time::Duration};
+
+    use super::{CookieStore, Session};
+    use crate::{
+        middleware::{context::Body, DynMiddleware},
+        Context, Middleware, Next, Respond,
+    };
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    async fn endpoint(context: Context, _: Next) -> crate::Result {
+        let session = context.session()?;
+        let count = session.get::<u32>("count").unwrap_or(0);
+
+        match context.uri().path() {
+            "/count" => session.set("count", &(count + 1))?,
+            "/fail" => {
+                session.set("count", &100)?;
+                return "Bad Request".status(400).respond();
+            }
+            "/large" => session.set("large", &"a".repeat(4096))?,
+            "/login" => session.regenerate(),
+            "/logout" => session.destroy(),
+            _ => {}
+        }
+
+        session
+            .get::<u32>("count")
+            .unwrap_or(0)
+            .to_string()
+            .respond()
+    }
+
+    /// Returns the body of the response and the cookie it sets, if any.
+    async fn call(session: &Session, path: &str, cookie: Option<&str>) -> (String, Option<String>) {
+        let endpoint: DynMiddleware = Arc::new(endpoint);
+        let mut request = http::Request::get(path);
+
+        if let Some(cookie) = cookie {
+            request = request.header("cookie", format!("other=1; {}", cookie));
+        }
+
+        let context = Context::from(request.body(Body::full("".into())).unwrap());
+        let response = session.call(context, Next::new([&endpoint].into_iter()));
+        let response = http::Response::from(response.await.unwrap_or_else(Into::into));
+        let cookie = response
+            .headers()
+            .get("set-cookie")
+            .map(|value| value.to_str().unwrap().to_owned());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (String::from_utf8(body.to_vec()).unwrap(), cookie)
+    }
+
+    /// Returns the name and value of a Set-Cookie header.
+    fn pair(set_cookie: &str) -> &str {
+        set_cookie.split(';').next().unwrap()
+    }
+
+    fn tamper(cookie: &str) -> String {
+        let mut cookie = cookie.to_owned();
+        let last = cookie.pop().unwrap();
+
+        cookie.push(if last == 'A' { 'B' } else { 'A' });
+        cookie
+    }
+
+    #[tokio::test]
+    async fn saves_changes_by_a_signed_id() {
+        let session = Session::new(SECRET);
+        let (count, set_cookie) = call(&session, "/count", None).await;
+        let set_cookie = set_cookie.unwrap();
+        let cookie = pair(&set_cookie);
+
+        assert_eq!(count, "1");
+        assert!(set_cookie.contains("HttpOnly"));
+        assert!(set_cookie.contains("SameSite=Lax"));
+        assert!(set_cookie.contains("Secure"));
+        assert!(set_cookie.contains("Max-Age=86400"));
+
+        assert_eq!(call(&session, "/count", Some(cookie)).await.0, "2");
+        assert_eq!(call(&session, "/fail", Some(cookie)).await.0, "Bad Request");
+        assert_eq!(call(&session, "/read", Some(cookie)).await.0, "2");
+
+        // The ID is 128 bits of hex after a signature.
+        let id = &cookie[cookie.len() - 32..];
+
+        assert!(id.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_eq!(call(&session, "/read", Some(&tamper(cookie))).await.0, "0");
+        assert_eq!(
+            call(&session, "/read", Some(&format!("session={}", id)))
+                .await
+                .0,
+            "0"
+        );
+
+        // A request that doesn't change a new session doesn't create one.
+        assert_eq!(call(&session, "/read", None).await, ("0".to_owned(), None));
+    }
+
+    #[tokio::test]
+    async fn regenerates_and_destroys_sessions() {
+        let session = Session::new(SECRET);
+        let (_, first) = call(&session, "/count", None).await;
+        let first = first.unwrap();
+        let (count, second) = call(&session, "/login", Some(pair(&first))).await;
+        let second = second.unwrap();
+
+        assert_eq!(count, "1");
+        assert_ne!(pair(&first), pair(&second));
+        assert_eq!(call(&session, "/read", Some(pair(&first))).await.0, "0");
+        assert_eq!(call(&session, "/read", Some(pair(&second))).await.0, "1");
+
+        let (_, removal) = call(&session, "/logout", Some(pair(&second))).await;
+        let removal = removal.unwrap();
+
+        assert!(removal.starts_with("session=;"));
+        assert!(removal.contains("Max-Age=0"));
+        assert_eq!(call(&session, "/read", Some(pair(&second))).await.0, "0");
+    }
+
+    #[tokio::test]
+    async fn expires_sessions_that_are_not_rolled() {
+        let ttl = Duration::from_millis(200);
+        let rolling = Session::new(SECRET).ttl(ttl);
+        let fixed = Session::new(SECRET).ttl(ttl).rolling(false);
+        let (_, rolling_cookie) = call(&rolling, "/count", None).await;
+        let (_, fixed_cookie) = call(&fixed, "/count", None).await;
+        let rolling_cookie = rolling_cookie.unwrap();
+        let fixed_cookie = fixed_cookie.unwrap();
+
+        for count in ["1", "1", "0"] {
+            tokio::time::sleep(Duration::from_millis(80)).await;
+
+            let (rolled, set_cookie) = call(&rolling, "/read", Some(pair(&rolling_cookie))).await;
+
+            assert_eq!(rolled, "1");
+            assert!(set_cookie.is_some());
+            assert_eq!(
+                call(&fixed, "/read", Some(pair(&fixed_cookie))).await,
+                (count.to_owned(), None)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_the_session_in_an_encrypted_cookie() {
+        let session = Session::new(SECRET)
+            .cookie_name("app")
+            .cookie_store(CookieStore::new())
+            .secure(false);
+        let (_, set_cookie) = call(&session, "/count", None).await;
+        let set_cookie = set_cookie.unwrap();
+        let cookie = pair(&set_cookie);
+
+        assert!(cookie.starts_with("app="));
+        assert!(!cookie.contains("count"));
+        assert!(!set_cookie.contains("Secure"));
+
+        let (count, set_cookie) = call(&session, "/count", Some(cookie)).await;
+
+        assert_eq!(count, "2");
+        assert_eq!(call(&session, "/read", Some(&tamper(cookie))).await.0, "0");
+
+        let cookie = pair(set_cookie.as_deref().unwrap());
+        let (body, set_cookie) = call(&session, "/large", Some(cookie)).await;
+
+        assert!(body.contains("which is more than the 4096 allowed"));
+        assert_eq!(set_cookie, None);
+
+        let session = session.ttl(Duration::ZERO);
+        let (_, set_cookie) = call(&session, "/count", None).await;
+
+        assert_eq!(
+            call(&session, "/read", Some(pair(&set_cookie.unwrap())))
+                .await
+                .0,
+            "0"
+        );
+    }
+
+    #[tokio::test]
+    async fn rotates_secrets() {
+        const OLD: &[u8] = b"fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210";
+
+        for old in [
+            Session::new(OLD).rolling(false),
+            Session::new(OLD)
+                .rolling(false)
+                .cookie_store(CookieStore::new()),
+        ] {
+            // The store is shared so that only the secret changes.
+            let current = Session {
+                key: super::key(SECRET),
+                ..old.clone()
+            };
+            let rotated = current.clone().previous_secrets(&[
+                b"unused secret that is at least sixty-four bytes long for the key",
+                OLD,
+            ]);
+            let (_, set_cookie) = call(&old, "/count", None).await;
+            let old_cookie = set_cookie.unwrap();
+
+            assert_eq!(
+                call(&current, "/read", Some(pair(&old_cookie))).await.0,
+                "0"
+            );
+
+            // A session that isn't rolled or changed is still re-issued.
+            let (count, set_cookie) = call(&rotated, "/read", Some(pair(&old_cookie))).await;
+            let new_cookie = set_cookie.unwrap();
+
+            assert_eq!(count, "1");
+            assert_ne!(pair(&new_cookie), pair(&old_cookie));
+            assert_eq!(
+                call(&current, "/read", Some(pair(&new_cookie))).await,
+                ("1".to_owned(), None)
+            );
+            assert_eq!(
+                call(&rotated, "/read", Some(pair(&new_cookie))).await,
+                ("1".to_owned(), None)
+            );
+        }
+    }
+}
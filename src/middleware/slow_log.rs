@@ -0,0 +1,317 @@
+use http::{Method, StatusCode};
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+
+use crate::{BoxFuture, Context, Middleware, Next, Result};
+
+type Callback = Arc<dyn Fn(&SlowRequest) + Send + Sync>;
+
+/// Reports requests that take longer than a threshold to respond, by default
+/// to stderr. Include it before the middleware it should time.
+///
+/// With `still_running`, requests that haven't responded are also reported
+/// each time that interval passes, which catches requests that hang and never
+/// respond. Requests that are cancelled, such as when the client disconnects,
+/// are reported if they were slow.
+#[derive(Clone)]
+pub struct SlowLog {
+    callback: Callback,
+    still_running: Option<Duration>,
+    threshold: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct SlowRequest {
+    pub elapsed: Duration,
+    pub method: Method,
+    pub outcome: Outcome,
+    /// The ID assigned by the `RequestId` middleware, if it was included
+    /// first.
+    pub request_id: Option<String>,
+    /// The pattern of the route that was matched, such as `/posts/:id`.
+    pub route: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// The request was dropped before it responded.
+    Cancelled,
+    /// The request failed with an error that is converted to a response with
+    /// this status.
+    Failed(StatusCode),
+    Responded(StatusCode),
+    /// The request hasn't responded yet.
+    Running,
+}
+
+/// Reports a cancelled request when dropped before it's finished, and stops
+/// the watchdog either way.
+struct Watch {
+    finished: bool,
+    log: SlowLog,
+    request: SlowRequest,
+    started: Instant,
+    watchdog: Option<JoinHandle<()>>,
+}
+
+impl SlowLog {
+    pub fn threshold(threshold: Duration) -> Self {
+        SlowLog {
+            callback: Arc::new(|request| eprintln!("Slow request: {}", request)),
+            still_running: None,
+            threshold,
+        }
+    }
+
+    /// Calls `callback` with each slow request instead of writing it to
+    /// stderr.
+    pub fn on_slow<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&SlowRequest) + Send + Sync + 'static,
+    {
+        self.callback = Arc::new(callback);
+        self
+    }
+
+    /// Reports requests that are still running each time `interval` passes.
+    pub fn still_running(mut self, interval: Duration) -> Self {
+        self.still_running = Some(interval);
+        self
+    }
+
+    fn watchdog(&self, request: &SlowRequest, started: Instant) -> Option<JoinHandle<()>> {
+        let interval = self.still_running?;
+        let callback = Arc::clone(&self.callback);
+        let mut request = request.clone();
+
+        Some(tokio::spawn(async move {
+            let mut ticks = tokio::time::interval_at((started + interval).into(), interval);
+
+            loop {
+                ticks.tick().await;
+                request.elapsed = started.elapsed();
+                callback(&request);
+            }
+        }))
+    }
+}
+
+impl Middleware for SlowLog {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        let started = Instant::now();
+        let request = SlowRequest {
+            elapsed: Duration::ZERO,
+            method: context.method().clone(),
+            outcome: Outcome::Running,
+            request_id: context.request_id().map(str::to_owned),
+            route: context.route_pattern().map(str::to_owned),
+        };
+        let mut watch = Watch {
+            finished: false,
+            log: self.clone(),
+            watchdog: self.watchdog(&request, started),
+            request,
+            started,
+        };
+        let future = next.call(context);
+
+        Box::pin(async move {
+            let result = future.await;
+
+            watch.finish(match &result {
+                Ok(response) => Outcome::Responded(response.status_code()),
+                Err(error) => Outcome::Failed(error.status_code()),
+            });
+
+            result
+        })
+    }
+}
+
+impl Display for SlowRequest {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} in {:?}",
+            self.method,
+            self.route.as_deref().unwrap_or("(no route)"),
+            self.outcome,
+            self.elapsed
+        )?;
+
+        match &self.request_id {
+            Some(id) => write!(f, " (request {})", id),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Outcome::Cancelled => f.write_str("was cancelled"),
+            Outcome::Failed(status) => write!(f, "failed with {}", status.as_u16()),
+            Outcome::Responded(status) => write!(f, "responded {}", status.as_u16()),
+            Outcome::Running => f.write_str("is still running"),
+        }
+    }
+}
+
+impl Watch {
+    fn finish(&mut self, outcome: Outcome) {
+        let elapsed = self.started.elapsed();
+
+        self.finished = true;
+
+        if elapsed >= self.log.threshold {
+            self.request.elapsed = elapsed;
+            self.request.outcome = outcome;
+            (self.log.callback)(&self.request);
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        if let Some(watchdog) = self.watchdog.take() {
+            watchdog.abort();
+        }
+
+        if !self.finished {
+            self.finish(Outcome::Cancelled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+    use http::StatusCode;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{Outcome, SlowLog, SlowRequest};
+    use crate::{
+        error::Bail,
+        middleware::{context::Body, request_id::Id, DynMiddleware},
+        routing::RoutePattern,
+        Context, Error, Middleware, Next,
+    };
+
+    fn context() -> Context {
+        let mut context =
+            Context::from(http::Request::get("/").body(Body::full("".into())).unwrap());
+
+        context.insert(Id("abc".into()));
+        context.insert(RoutePattern("/reports/:id".to_owned()));
+        context
+    }
+
+    fn sleep(millis: u64, fail: bool) -> DynMiddleware {
+        Arc::new(move |_: Context, _: Next| async move {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+
+            if fail {
+                return Err(Error::from(Bail::new("unavailable")).status(503));
+            }
+
+            Ok("report")
+        })
+    }
+
+    fn recorder() -> (SlowLog, Arc<Mutex<Vec<SlowRequest>>>) {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let log = SlowLog::threshold(Duration::from_millis(50)).on_slow({
+            let reported = Arc::clone(&reported);
+            move |request| reported.lock().unwrap().push(request.clone())
+        });
+
+        (log, reported)
+    }
+
+    fn outcomes(reported: &Mutex<Vec<SlowRequest>>) -> Vec<Outcome> {
+        let reported = reported.lock().unwrap();
+        reported.iter().map(|request| request.outcome).collect()
+    }
+
+    #[tokio::test]
+    async fn reports_requests_over_the_threshold() {
+        let (log, reported) = recorder();
+
+        for (millis, fail) in [(0, false), (80, false), (0, true), (80, true)] {
+            let endpoint = sleep(millis, fail);
+            let _ = log
+                .call(context(), Next::new([&endpoint].into_iter()))
+                .await;
+        }
+
+        assert_eq!(
+            outcomes(&reported),
+            [
+                Outcome::Responded(StatusCode::OK),
+                Outcome::Failed(StatusCode::SERVICE_UNAVAILABLE)
+            ]
+        );
+
+        let reported = reported.lock().unwrap();
+
+        assert!(reported[0].elapsed >= Duration::from_millis(80));
+        assert_eq!(reported[0].request_id.as_deref(), Some("abc"));
+        assert_eq!(reported[0].route.as_deref(), Some("/reports/:id"));
+        assert_eq!(
+            reported[0].to_string()[..31],
+            *"GET /reports/:id responded 200 "
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_hangs_and_cancellations() {
+        let (log, reported) = recorder();
+        let log = log.still_running(Duration::from_millis(40));
+        let endpoint = sleep(10_000, false);
+        let future = log.call(context(), Next::new([&endpoint].into_iter()));
+
+        assert!(tokio::time::timeout(Duration::from_millis(100), future)
+            .await
+            .is_err());
+        assert_eq!(
+            outcomes(&reported),
+            [Outcome::Running, Outcome::Running, Outcome::Cancelled]
+        );
+
+        // A fast request that is cancelled isn't reported.
+        let future = log.call(context(), Next::new([&endpoint].into_iter()));
+
+        assert!(future.now_or_never().is_none());
+        assert_eq!(reported.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn stops_the_watchdog_when_the_request_finishes() {
+        let (log, reported) = recorder();
+        let log = log.still_running(Duration::from_millis(30));
+        let endpoint = sleep(0, false);
+        let callbacks = || Arc::strong_count(&log.callback);
+        let before = callbacks();
+
+        for _ in 0..10 {
+            log.call(context(), Next::new([&endpoint].into_iter()))
+                .await
+                .unwrap();
+        }
+
+        // Aborted tasks are dropped the next time the runtime polls them.
+        tokio::task::yield_now().await;
+
+        assert_eq!(callbacks(), before);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(reported.lock().unwrap().is_empty());
+    }
+}
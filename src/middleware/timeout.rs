@@ -0,0 +1,141 @@
+use http::StatusCode;
+use std::time::{Duration, Instant};
+
+use crate::{BoxFuture, Context, Middleware, Next, Respond, Result};
+
+/// Responds with 503 Service Unavailable if the middleware that follow don't
+/// respond within `duration`. The deadline is available to them with
+/// `Context::deadline` so outbound calls can be given the remaining budget.
+#[derive(Clone, Copy, Debug)]
+pub struct Timeout {
+    duration: Duration,
+    status: StatusCode,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Deadline(pub(crate) Instant);
+
+impl Timeout {
+    pub fn new(duration: Duration) -> Self {
+        Timeout {
+            duration,
+            status: StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// The status of the response when the deadline passes, such as 504
+    /// Gateway Timeout for a proxy. 408 Request Timeout is meant for clients
+    /// that are too slow to send a request, and may be retried automatically.
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+impl Middleware for Timeout {
+    fn call(&self, mut context: Context, next: Next) -> BoxFuture<Result> {
+        let deadline = Instant::now() + self.duration;
+
+        // A nested timeout can't extend the deadline of the one it's in.
+        if context.deadline().is_none_or(|current| deadline < current) {
+            context.insert(Deadline(deadline));
+        }
+
+        let future = tokio::time::timeout_at(deadline.into(), next.call(context));
+        let status = self.status;
+
+        Box::pin(async move {
+            match future.await {
+                Ok(result) => result,
+                Err(_) => status
+                    .canonical_reason()
+                    .unwrap_or_default()
+                    .status(status.as_u16())
+                    .respond(),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::Timeout;
+    use crate::{
+        middleware::{context::Body, DynMiddleware},
+        Context, Next, Respond,
+    };
+
+    fn context() -> Context {
+        Context::from(http::Request::get("/").body(Body::full("".into())).unwrap())
+    }
+
+    async fn remaining(context: Context, _: Next) -> crate::Result {
+        match context.remaining_time() {
+            Some(remaining) => remaining.as_secs().to_string().respond(),
+            None => "none".respond(),
+        }
+    }
+
+    async fn sleep(_: Context, _: Next) -> crate::Result {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        "".respond()
+    }
+
+    async fn call(stack: [DynMiddleware; 3]) -> (u16, String) {
+        let response = stack[0]
+            .call(context(), Next::new(stack[1..].iter()))
+            .await
+            .unwrap();
+        let status = response.status_code().as_u16();
+        let body = http::Response::from(response).into_body();
+        let body = http_body_util::BodyExt::collect(body).await.unwrap();
+
+        (
+            status,
+            String::from_utf8_lossy(&body.to_bytes()).into_owned(),
+        )
+    }
+
+    #[tokio::test]
+    async fn exposes_the_nearest_deadline() {
+        let outer = Arc::new(Timeout::new(Duration::from_secs(30)));
+        let inner = Arc::new(Timeout::new(Duration::from_secs(10)));
+
+        assert_eq!(
+            call([outer.clone(), inner.clone(), Arc::new(remaining)]).await,
+            (200, "9".to_owned())
+        );
+        assert_eq!(
+            call([inner, outer, Arc::new(remaining)]).await,
+            (200, "9".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn responds_when_the_deadline_passes() {
+        let timeout = Arc::new(Timeout::new(Duration::from_millis(10)));
+        let started = Instant::now();
+
+        assert_eq!(
+            call([timeout.clone(), timeout, Arc::new(sleep)]).await,
+            (503, "Service Unavailable".to_owned())
+        );
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let gateway = Arc::new(
+            Timeout::new(Duration::from_millis(10)).status(http::StatusCode::GATEWAY_TIMEOUT),
+        );
+
+        assert_eq!(
+            call([gateway.clone(), gateway, Arc::new(sleep)]).await.0,
+            504
+        );
+        assert!(context().deadline().is_none());
+        assert!(context().remaining_time().is_none());
+    }
+}
@@ -0,0 +1,264 @@
+#[cfg(feature = "tracing")]
+mod span;
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use std::fmt::{self, Display, Formatter};
+
+use crate::{Context, Next, Result};
+
+#[cfg(feature = "tracing")]
+pub use self::span::Trace;
+
+const SAMPLED: u8 = 0b0000_0001;
+
+static TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+static TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceContext {
+    flags: u8,
+    parent_id: Option<[u8; 8]>,
+    span_id: [u8; 8],
+    state: Option<String>,
+    trace_id: [u8; 16],
+}
+
+struct Hex<'a>(&'a [u8]);
+
+/// Continues the trace of a `traceparent` header, or starts a new one if it
+/// is missing or malformed. The `TraceContext` is inserted into the context
+/// for handlers, and `Trace`, and injected into the headers of the response.
+pub async fn propagate(mut context: Context, next: Next) -> Result {
+    let headers = context.headers();
+    let trace = match headers
+        .get(&TRACEPARENT)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(traceparent) => TraceContext::parse(traceparent, headers.get(&TRACESTATE)),
+        None => None,
+    }
+    .unwrap_or_else(TraceContext::new);
+
+    context.insert(trace.clone());
+
+    let mut response = next.call(context).await?;
+
+    trace.inject(response.headers_mut());
+    Ok(response)
+}
+
+fn random_id<const N: usize>() -> [u8; N] {
+    use rand::Rng;
+
+    let mut id = [0; N];
+
+    while nonzero(id).is_none() {
+        rand::thread_rng().fill(&mut id[..]);
+    }
+
+    id
+}
+
+fn decode<const N: usize>(input: &str) -> Option<[u8; N]> {
+    let mut output = [0; N];
+
+    if input.len() != N * 2
+        || !input
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        return None;
+    }
+
+    for (index, byte) in output.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&input[index * 2..index * 2 + 2], 16).ok()?;
+    }
+
+    Some(output)
+}
+
+fn nonzero<const N: usize>(id: [u8; N]) -> Option<[u8; N]> {
+    Some(id).filter(|id| id.iter().any(|byte| *byte != 0))
+}
+
+impl TraceContext {
+    pub fn new() -> Self {
+        TraceContext {
+            flags: SAMPLED,
+            parent_id: None,
+            span_id: random_id(),
+            state: None,
+            trace_id: random_id(),
+        }
+    }
+
+    pub fn parse(traceparent: &str, tracestate: Option<&HeaderValue>) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let [version] = decode::<1>(parts.next()?)?;
+
+        if version == 0xff {
+            return None;
+        }
+
+        let trace_id = nonzero(decode(parts.next()?)?)?;
+        let parent_id = nonzero(decode(parts.next()?)?)?;
+        let [flags] = decode::<1>(parts.next()?)?;
+
+        if version == 0 && parts.next().is_some() {
+            return None;
+        }
+
+        Some(TraceContext {
+            flags,
+            parent_id: Some(parent_id),
+            span_id: random_id(),
+            state: tracestate
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+            trace_id,
+        })
+    }
+
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::try_from(self.traceparent()) {
+            headers.insert(&TRACEPARENT, value);
+        }
+
+        if let Some(value) = self
+            .tracestate()
+            .and_then(|s| HeaderValue::try_from(s).ok())
+        {
+            headers.insert(&TRACESTATE, value);
+        }
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.flags & SAMPLED == SAMPLED
+    }
+
+    pub fn parent_id(&self) -> Option<String> {
+        self.parent_id.map(|id| Hex(&id).to_string())
+    }
+
+    pub fn span_id(&self) -> String {
+        Hex(&self.span_id).to_string()
+    }
+
+    pub fn trace_id(&self) -> String {
+        Hex(&self.trace_id).to_string()
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            Hex(&self.trace_id),
+            Hex(&self.span_id),
+            self.flags & SAMPLED
+        )
+    }
+
+    pub fn tracestate(&self) -> Option<&str> {
+        self.state.as_deref()
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        TraceContext::new()
+    }
+}
+
+impl<'a> Display for Hex<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::{propagate, TraceContext};
+    use crate::{Context, Next};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    fn parse(value: &str) -> Option<TraceContext> {
+        TraceContext::parse(value, None)
+    }
+
+    #[test]
+    fn continues_valid_traceparent() {
+        let trace = parse(&format!("00-{}-00f067aa0ba902b7-01", TRACE_ID)).unwrap();
+
+        assert_eq!(trace.trace_id(), TRACE_ID);
+        assert_eq!(trace.parent_id().as_deref(), Some("00f067aa0ba902b7"));
+        assert_ne!(trace.span_id(), "00f067aa0ba902b7");
+        assert!(trace.is_sampled());
+        assert!(trace
+            .traceparent()
+            .starts_with(&format!("00-{}-", TRACE_ID)));
+        assert!(!parse(&format!("00-{}-00f067aa0ba902b7-00", TRACE_ID))
+            .unwrap()
+            .is_sampled());
+    }
+
+    #[test]
+    fn ignores_malformed_traceparent() {
+        for value in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert_eq!(parse(value), None, "{}", value);
+        }
+
+        assert!(parse(&format!("01-{}-00f067aa0ba902b7-01-extra", TRACE_ID)).is_some());
+    }
+
+    #[tokio::test]
+    async fn propagates_to_the_context_and_response() {
+        let mut app = crate::new();
+
+        app.include(propagate);
+        app.at("/trace")
+            .get(|context: Context, _: Next| async move {
+                let trace = context.get::<TraceContext>()?;
+                Ok::<_, crate::Error>(format!("{} {}", trace.trace_id(), trace.span_id()))
+            });
+        app.shutdown_signals(false);
+
+        let server = app.bind(("127.0.0.1", 0)).unwrap();
+        let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+        let mut output = String::new();
+
+        tokio::spawn(server.serve());
+        stream
+            .write_all(
+                format!(
+                    "GET /trace HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\
+                     traceparent: 00-{}-00f067aa0ba902b7-01\r\ntracestate: vendor=1\r\n\r\n",
+                    TRACE_ID
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        stream.read_to_string(&mut output).await.unwrap();
+
+        let (head, body) = output.split_once("\r\n\r\n").unwrap();
+        let (trace_id, span_id) = body.split_once(' ').unwrap();
+
+        assert_eq!(trace_id, TRACE_ID);
+        assert_ne!(span_id, "00f067aa0ba902b7");
+        assert!(head.contains(&format!("traceparent: 00-{}-{}-01\r\n", TRACE_ID, span_id)));
+        assert!(head.contains("tracestate: vendor=1"));
+    }
+}
@@ -0,0 +1,293 @@
+use http::Method;
+use std::time::Instant;
+use tracing::{field::Empty, Instrument, Level, Span};
+
+use super::TraceContext;
+use crate::{BoxFuture, Context, Middleware, Next, Result};
+
+/// Opens a `tracing` span named `request` around the middleware that follow.
+/// It records the method, the path, and the pattern of the matched route
+/// when the request arrives, and the status, latency in milliseconds, and
+/// the size of a buffered response body when it responds. The ID assigned by
+/// `RequestId` is recorded too if it was included first.
+///
+/// When `propagate` is included first, the span continues its trace: the
+/// trace, span, and parent IDs of the `TraceContext` are recorded as the
+/// `trace_id`, `span_id`, and `parent_id` fields, and requests whose
+/// `traceparent` isn't sampled aren't traced.
+///
+/// Spans opened while the request is handled, such as by a database client,
+/// are children of the request span. An error is also reported as an event
+/// at the error level.
+#[derive(Clone, Debug)]
+pub struct Trace {
+    level: Level,
+}
+
+fn span(level: Level, method: &Method, path: &str, route: &str) -> Span {
+    macro_rules! span {
+        ($level:expr) => {
+            tracing::span!(
+                $level,
+                "request",
+                method = %method,
+                path,
+                route,
+                request_id = Empty,
+                trace_id = Empty,
+                span_id = Empty,
+                parent_id = Empty,
+                status = Empty,
+                latency_ms = Empty,
+                response_size = Empty,
+            )
+        };
+    }
+
+    match level {
+        Level::ERROR => span!(Level::ERROR),
+        Level::WARN => span!(Level::WARN),
+        Level::INFO => span!(Level::INFO),
+        Level::DEBUG => span!(Level::DEBUG),
+        Level::TRACE => span!(Level::TRACE),
+    }
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Trace { level: Level::INFO }
+    }
+
+    /// The level of the request span. Defaults to `INFO`.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Trace::new()
+    }
+}
+
+impl Middleware for Trace {
+    fn call(&self, context: Context, next: Next) -> BoxFuture<Result> {
+        let path = context.uri().path();
+        let route = context.route_pattern().unwrap_or(path);
+        let trace = context.get::<TraceContext>().ok();
+
+        if trace.is_some_and(|trace| !trace.is_sampled()) {
+            return next.call(context);
+        }
+
+        let span = span(self.level, context.method(), path, route);
+        let started = Instant::now();
+
+        if let Some(id) = context.request_id() {
+            span.record("request_id", id);
+        }
+
+        if let Some(trace) = trace {
+            span.record("trace_id", trace.trace_id());
+            span.record("span_id", trace.span_id());
+
+            if let Some(id) = trace.parent_id() {
+                span.record("parent_id", id);
+            }
+        }
+
+        let future = span
+            .in_scope(|| next.call(context))
+            .instrument(span.clone());
+
+        Box::pin(async move {
+            let result = future.await;
+
+            span.record("latency_ms", started.elapsed().as_millis() as u64);
+
+            match &result {
+                Ok(response) => {
+                    span.record("status", response.status_code().as_u16());
+
+                    if let Some(size) = hyper::body::Body::size_hint(response.body()).exact() {
+                        span.record("response_size", size);
+                    }
+                }
+                Err(error) => {
+                    span.record("status", error.status_code().as_u16());
+                    tracing::error!(parent: &span, error = %error, "request failed");
+                }
+            }
+
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        fmt::Debug,
+        sync::{Arc, Mutex},
+    };
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Event, Subscriber,
+    };
+    use tracing_subscriber::{
+        layer::{Context as LayerContext, SubscriberExt},
+        registry::LookupSpan,
+        Layer,
+    };
+
+    use super::super::propagate;
+    use super::Trace;
+    use crate::{
+        error::Bail,
+        middleware::{context::Body, DynMiddleware},
+        Context, Error, Middleware, Next,
+    };
+
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    type Fields = BTreeMap<String, String>;
+
+    /// A span, or an event without an id, with its name and fields.
+    type Entry = (Option<Id>, &'static str, Fields);
+
+    /// Collects the fields of every span, and of the events in them.
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<Vec<Entry>>>);
+
+    struct Visitor<'a>(&'a mut Fields);
+
+    impl Visit for Visitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0
+                .insert(field.name().to_owned(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_owned(), value.to_owned());
+        }
+    }
+
+    impl<S> Layer<S> for Spans
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(&self, attributes: &Attributes, id: &Id, _: LayerContext<S>) {
+            let mut fields = Fields::new();
+            let name = attributes.metadata().name();
+
+            attributes.record(&mut Visitor(&mut fields));
+            self.0
+                .lock()
+                .unwrap()
+                .push((Some(id.clone()), name, fields));
+        }
+
+        fn on_record(&self, id: &Id, record: &Record, _: LayerContext<S>) {
+            let mut spans = self.0.lock().unwrap();
+            let span = spans.iter_mut().find(|span| span.0.as_ref() == Some(id));
+
+            if let Some((_, _, fields)) = span {
+                record.record(&mut Visitor(fields));
+            }
+        }
+
+        fn on_event(&self, event: &Event, _: LayerContext<S>) {
+            let mut fields = Fields::new();
+
+            event.record(&mut Visitor(&mut fields));
+            self.0.lock().unwrap().push((None, "event", fields));
+        }
+    }
+
+    async fn call(endpoint: DynMiddleware) -> Vec<(&'static str, Fields)> {
+        call_with(http::Request::post("/posts?page=2"), endpoint).await
+    }
+
+    async fn call_with(
+        request: http::request::Builder,
+        endpoint: DynMiddleware,
+    ) -> Vec<(&'static str, Fields)> {
+        let spans = Spans::default();
+        let subscriber = tracing_subscriber::registry().with(spans.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let context = Context::from(request.body(Body::full("".into())).unwrap());
+        let trace: DynMiddleware = Arc::new(Trace::new());
+        let propagate: DynMiddleware = Arc::new(propagate);
+        let next = Next::new([&trace, &endpoint].into_iter());
+        let _ = propagate.call(context, next).await;
+        let spans = spans.0.lock().unwrap();
+
+        spans
+            .iter()
+            .map(|(_, name, fields)| (*name, fields.clone()))
+            .collect()
+    }
+
+    fn field<'a>(fields: &'a Fields, name: &str) -> Option<&'a str> {
+        fields.get(name).map(String::as_str)
+    }
+
+    #[tokio::test]
+    async fn records_the_request_and_response() {
+        let endpoint: DynMiddleware = Arc::new(|_: Context, _: Next| async {
+            tracing::info_span!("query").in_scope(|| {});
+            "created"
+        });
+        let spans = call(endpoint).await;
+        let (name, request) = &spans[0];
+
+        assert_eq!(*name, "request");
+        assert_eq!(field(request, "method"), Some("POST"));
+        assert_eq!(field(request, "path"), Some("/posts"));
+        assert_eq!(field(request, "route"), Some("/posts"));
+        assert_eq!(field(request, "status"), Some("200"));
+        assert_eq!(field(request, "response_size"), Some("7"));
+        assert!(field(request, "latency_ms").is_some());
+        assert_eq!(spans[1].0, "query");
+    }
+
+    #[tokio::test]
+    async fn reports_errors() {
+        let endpoint: DynMiddleware = Arc::new(|_: Context, _: Next| async {
+            Err::<&str, _>(Error::from(Bail::new("no such post")).status(404))
+        });
+        let spans = call(endpoint).await;
+
+        assert_eq!(field(&spans[0].1, "status"), Some("404"));
+        assert_eq!(spans[1].0, "event");
+        assert_eq!(field(&spans[1].1, "error"), Some("no such post"));
+    }
+
+    #[tokio::test]
+    async fn continues_the_incoming_trace() {
+        let endpoint: DynMiddleware = Arc::new(|_: Context, _: Next| async { "" });
+        let request = http::Request::get("/posts")
+            .header("traceparent", format!("00-{}-{}-01", TRACE_ID, PARENT_ID));
+        let spans = call_with(request, endpoint).await;
+        let (name, request) = &spans[0];
+
+        assert_eq!(*name, "request");
+        assert_eq!(field(request, "trace_id"), Some(TRACE_ID));
+        assert_eq!(field(request, "parent_id"), Some(PARENT_ID));
+        assert!(field(request, "span_id").is_some_and(|id| id != PARENT_ID));
+        assert_eq!(field(request, "status"), Some("200"));
+    }
+
+    #[tokio::test]
+    async fn skips_unsampled_traces() {
+        let endpoint: DynMiddleware = Arc::new(|_: Context, _: Next| async { "" });
+        let request = http::Request::get("/posts")
+            .header("traceparent", format!("00-{}-{}-00", TRACE_ID, PARENT_ID));
+
+        assert!(call_with(request, endpoint).await.is_empty());
+    }
+}
@@ -0,0 +1,298 @@
+use bytes::BytesMut;
+use futures::stream::{BoxStream, Stream, StreamExt};
+use http_body_util::Full;
+use hyper::body::{Body as HttpBody, Bytes, Frame, SizeHint};
+use std::{
+    fmt::{self, Debug, Formatter},
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The body of a response. Bodies are either held in memory or produced by a
+/// stream, which is polled as the client reads so that data is only
+/// generated as fast as it can be sent.
+pub struct Body {
+    state: BodyState,
+}
+
+enum BodyState {
+    Full(Full<Bytes>),
+    Stream(BoxStream<'static, io::Result<Bytes>>, Option<u64>),
+}
+
+impl Body {
+    /// Reads `reader` in chunks of up to `chunk_size` bytes. The body ends
+    /// when the reader reaches EOF. A read error aborts the response so that
+    /// the client can't mistake what was sent for the whole body.
+    pub(crate) fn reader<R>(reader: R, chunk_size: usize) -> Self
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        let chunk_size = chunk_size.max(1);
+
+        Body::stream(futures::stream::unfold(
+            Some(Box::pin(reader)),
+            move |reader| async move {
+                let mut reader = reader?;
+                let mut buffer = BytesMut::with_capacity(chunk_size);
+
+                match reader.read_buf(&mut buffer).await {
+                    Ok(0) => None,
+                    Ok(_) => Some((Ok(buffer.freeze()), Some(reader))),
+                    Err(error) => Some((Err(error), None)),
+                }
+            },
+        ))
+    }
+
+    /// A stream that is known to be `len` bytes long, which lets it be sent
+    /// with a Content-Length. The body fails if the stream turns out to be
+    /// shorter or longer so the response is aborted rather than corrupt.
+    pub(crate) fn sized<S>(stream: S, len: u64) -> Self
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+    {
+        let mismatch = |message| Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        let checked = futures::stream::unfold(
+            (stream.boxed(), Some(len)),
+            move |(mut stream, remaining)| async move {
+                let remaining = remaining?;
+                let item = match stream.next().await {
+                    Some(Ok(chunk)) if chunk.len() as u64 > remaining => {
+                        return Some((mismatch("body is longer than its length"), (stream, None)));
+                    }
+                    Some(Ok(chunk)) => {
+                        let remaining = remaining - chunk.len() as u64;
+                        return Some((Ok(chunk), (stream, Some(remaining))));
+                    }
+                    Some(Err(error)) => Err(error),
+                    None if remaining > 0 => mismatch("body is shorter than its length"),
+                    None => return None,
+                };
+
+                Some((item, (stream, None)))
+            },
+        );
+
+        Body {
+            state: BodyState::Stream(checked.boxed(), Some(len)),
+        }
+    }
+
+    /// Returns true if the body is held in memory rather than streamed.
+    pub(crate) fn is_full(&self) -> bool {
+        matches!(self.state, BodyState::Full(_))
+    }
+
+    pub(crate) fn stream<S>(stream: S) -> Self
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+    {
+        Body {
+            state: BodyState::Stream(stream.boxed(), None),
+        }
+    }
+}
+
+impl Debug for Body {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.state {
+            BodyState::Full(full) => Debug::fmt(full, f),
+            BodyState::Stream(..) => f.write_str("Stream"),
+        }
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::from(Full::default())
+    }
+}
+
+impl From<Full<Bytes>> for Body {
+    fn from(full: Full<Bytes>) -> Self {
+        Body {
+            state: BodyState::Full(full),
+        }
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Body::from(Full::new(bytes))
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::from(Bytes::from(bytes))
+    }
+}
+
+impl From<String> for Body {
+    fn from(string: String) -> Self {
+        Body::from(Bytes::from(string))
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(string: &'static str) -> Self {
+        Body::from(Bytes::from_static(string.as_bytes()))
+    }
+}
+
+impl HttpBody for Body {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        context: &mut Context,
+    ) -> Poll<Option<io::Result<Frame<Bytes>>>> {
+        match &mut self.state {
+            BodyState::Full(full) => Pin::new(full)
+                .poll_frame(context)
+                .map(|frame| frame.map(|frame| frame.map_err(|never| match never {}))),
+            BodyState::Stream(stream, _) => stream
+                .poll_next_unpin(context)
+                .map(|data| data.map(|data| data.map(Frame::data))),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.state {
+            BodyState::Full(full) => full.is_end_stream(),
+            BodyState::Stream(_, len) => *len == Some(0),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.state {
+            BodyState::Full(full) => full.size_hint(),
+            BodyState::Stream(_, Some(len)) => SizeHint::with_exact(*len),
+            BodyState::Stream(_, None) => SizeHint::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+    use hyper::{server::conn::http1, service::service_fn};
+    use hyper_util::rt::TokioIo;
+    use std::{
+        io,
+        pin::Pin,
+        sync::Mutex,
+        task::{Context, Poll},
+    };
+    use tokio::io::{duplex, AsyncRead, AsyncReadExt, AsyncWriteExt, ReadBuf};
+
+    use crate::{Respond, Response};
+
+    struct Broken;
+
+    impl AsyncRead for Broken {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context,
+            _: &mut ReadBuf,
+        ) -> Poll<io::Result<()>> {
+            Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+        }
+    }
+
+    async fn reader(contents: &'static [u8]) -> tokio::io::DuplexStream {
+        let (mut writer, reader) = duplex(64);
+
+        tokio::spawn(async move {
+            writer.write_all(contents).await.unwrap();
+        });
+
+        reader
+    }
+
+    /// Writes `response` to a connection, returning what a client receives
+    /// and whether the connection failed.
+    async fn send(response: Response) -> (String, bool) {
+        let (mut client, server) = duplex(1024);
+        let response = Mutex::new(Some(response));
+        let service = service_fn(move |_| {
+            let response = response.lock().unwrap().take().unwrap();
+            async move { Ok::<_, io::Error>(http::Response::from(response)) }
+        });
+
+        let connection = http1::Builder::new().serve_connection(TokioIo::new(server), service);
+        let connection = tokio::spawn(connection);
+
+        client
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut output = String::new();
+
+        client.read_to_string(&mut output).await.unwrap();
+        (
+            output.to_ascii_lowercase(),
+            connection.await.unwrap().is_err(),
+        )
+    }
+
+    #[tokio::test]
+    async fn reads_chunks_until_eof() {
+        let response = Response::from_reader(reader(b"hello world").await, 4);
+        let mut body = http::Response::from(response).into_body();
+        let mut chunks = Vec::new();
+
+        while let Some(frame) = body.frame().await {
+            chunks.push(frame.unwrap().into_data().unwrap());
+        }
+
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 4));
+        assert_eq!(chunks.concat(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn aborts_on_read_errors() {
+        let reader = reader(b"partial").await.chain(Broken);
+        let body = http::Response::from(Response::from_reader(reader, 1024)).into_body();
+        let error = body.collect().await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[tokio::test]
+    async fn uses_a_known_content_length() {
+        let response = Response::from_reader(reader(b"hello world").await, 4);
+        let (output, failed) = send(response).await;
+
+        assert!(!failed);
+        assert!(output.contains("transfer-encoding: chunked"));
+        assert!(output.ends_with("0\r\n\r\n"));
+
+        let response = Response::from_reader(reader(b"hello world").await, 4)
+            .header("content-length", "11")
+            .respond()
+            .unwrap();
+        let (output, failed) = send(response).await;
+
+        assert!(!failed);
+        assert!(output.contains("content-length: 11"));
+        assert!(!output.contains("transfer-encoding"));
+        assert!(output.ends_with("\r\n\r\nhello world"));
+
+        // An early EOF truncates the body, which fails the connection rather
+        // than completing a response shorter than its Content-Length.
+        let response = Response::from_reader(reader(b"hello").await, 4)
+            .header("content-length", "11")
+            .respond()
+            .unwrap();
+        let (output, failed) = send(response).await;
+
+        assert!(failed);
+        assert!(!output.ends_with("hello world"));
+    }
+}
@@ -0,0 +1,297 @@
+use http::header::{HeaderValue, CACHE_CONTROL};
+use std::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+
+use super::{Respond, Response};
+use crate::{error::Bail, Error, Result};
+
+/// The directives of a `Cache-Control` header. Directives are always written
+/// in the same order, and combinations that contradict each other are
+/// rejected when the response is built.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CacheControl {
+    extensions: Vec<String>,
+    immutable: bool,
+    max_age: Option<Duration>,
+    must_revalidate: bool,
+    no_cache: bool,
+    no_store: bool,
+    no_transform: bool,
+    private: bool,
+    public: bool,
+    s_maxage: Option<Duration>,
+    stale_if_error: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+}
+
+pub struct WithCacheControl<T: Respond> {
+    cache_control: CacheControl,
+    value: T,
+}
+
+impl CacheControl {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Parses the value of a `Cache-Control` header. Directives that aren't
+    /// known are kept as they are.
+    pub fn parse(value: &str) -> Self {
+        let mut cache_control = CacheControl::new();
+
+        for directive in value.split(',').map(str::trim) {
+            let (name, argument) = match directive.split_once('=') {
+                Some((name, argument)) => (name.trim(), Some(argument.trim())),
+                None => (directive, None),
+            };
+            let seconds = argument
+                .and_then(|argument| argument.trim_matches('"').parse().ok())
+                .map(Duration::from_secs);
+
+            match (name.to_ascii_lowercase().as_str(), seconds) {
+                ("", _) => {}
+                ("immutable", _) => cache_control.immutable = true,
+                ("max-age", Some(seconds)) => cache_control.max_age = Some(seconds),
+                ("must-revalidate", _) => cache_control.must_revalidate = true,
+                ("no-cache", _) => cache_control.no_cache = true,
+                ("no-store", _) => cache_control.no_store = true,
+                ("no-transform", _) => cache_control.no_transform = true,
+                ("private", _) => cache_control.private = true,
+                ("public", _) => cache_control.public = true,
+                ("s-maxage", Some(seconds)) => cache_control.s_maxage = Some(seconds),
+                ("stale-if-error", Some(seconds)) => cache_control.stale_if_error = Some(seconds),
+                ("stale-while-revalidate", Some(seconds)) => {
+                    cache_control.stale_while_revalidate = Some(seconds);
+                }
+                _ => cache_control.extensions.push(directive.to_owned()),
+            }
+        }
+
+        cache_control
+    }
+
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    pub fn no_store(mut self) -> Self {
+        self.no_store = true;
+        self
+    }
+
+    pub fn no_transform(mut self) -> Self {
+        self.no_transform = true;
+        self
+    }
+
+    pub fn private(mut self) -> Self {
+        self.private = true;
+        self
+    }
+
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    pub fn s_maxage(mut self, s_maxage: Duration) -> Self {
+        self.s_maxage = Some(s_maxage);
+        self
+    }
+
+    pub fn stale_if_error(mut self, stale_if_error: Duration) -> Self {
+        self.stale_if_error = Some(stale_if_error);
+        self
+    }
+
+    pub fn stale_while_revalidate(mut self, stale_while_revalidate: Duration) -> Self {
+        self.stale_while_revalidate = Some(stale_while_revalidate);
+        self
+    }
+
+    /// Adds the directives of `other`. Durations in `other` replace the ones
+    /// that are already set.
+    pub fn merge(mut self, other: CacheControl) -> Self {
+        for extension in other.extensions {
+            if !self.extensions.contains(&extension) {
+                self.extensions.push(extension);
+            }
+        }
+
+        self.immutable |= other.immutable;
+        self.max_age = other.max_age.or(self.max_age);
+        self.must_revalidate |= other.must_revalidate;
+        self.no_cache |= other.no_cache;
+        self.no_store |= other.no_store;
+        self.no_transform |= other.no_transform;
+        self.private |= other.private;
+        self.public |= other.public;
+        self.s_maxage = other.s_maxage.or(self.s_maxage);
+        self.stale_if_error = other.stale_if_error.or(self.stale_if_error);
+        self.stale_while_revalidate = other.stale_while_revalidate.or(self.stale_while_revalidate);
+        self
+    }
+
+    /// Returns an error if the directives contradict each other.
+    pub fn validate(&self) -> Result<()> {
+        let contradiction = if self.public && self.private {
+            Some("public with private")
+        } else if self.no_store && self.max_age.is_some() {
+            Some("no-store with max-age")
+        } else if self.no_store && self.s_maxage.is_some() {
+            Some("no-store with s-maxage")
+        } else if self.no_store && self.immutable {
+            Some("no-store with immutable")
+        } else if self.no_store && self.public {
+            Some("no-store with public")
+        } else {
+            None
+        };
+
+        match contradiction {
+            Some(message) => Err(Error::from(Bail::new(format!(
+                "Cache-Control cannot combine {}",
+                message
+            )))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Display for CacheControl {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let seconds = |name, duration: Option<Duration>| {
+            duration.map(|duration| format!("{}={}", name, duration.as_secs()))
+        };
+        let flag = |name: &str, enabled: bool| enabled.then(|| name.to_owned());
+        let directives = [
+            flag("public", self.public),
+            flag("private", self.private),
+            flag("no-cache", self.no_cache),
+            flag("no-store", self.no_store),
+            seconds("max-age", self.max_age),
+            seconds("s-maxage", self.s_maxage),
+            flag("must-revalidate", self.must_revalidate),
+            flag("no-transform", self.no_transform),
+            flag("immutable", self.immutable),
+            seconds("stale-while-revalidate", self.stale_while_revalidate),
+            seconds("stale-if-error", self.stale_if_error),
+        ];
+        let directives: Vec<_> = directives
+            .into_iter()
+            .flatten()
+            .chain(self.extensions.iter().cloned())
+            .collect();
+
+        f.write_str(&directives.join(", "))
+    }
+}
+
+impl<T: Respond> WithCacheControl<T> {
+    pub(super) fn new(value: T, cache_control: CacheControl) -> Self {
+        WithCacheControl {
+            cache_control,
+            value,
+        }
+    }
+}
+
+impl<T: Respond> Respond for WithCacheControl<T> {
+    fn respond(self) -> Result<Response> {
+        let mut response = self.value.respond()?;
+        let headers = response.headers_mut();
+        let cache_control = match headers.get(CACHE_CONTROL).map(HeaderValue::to_str) {
+            Some(Ok(current)) => CacheControl::parse(current).merge(self.cache_control),
+            _ => self.cache_control,
+        };
+
+        cache_control.validate()?;
+        headers.insert(
+            CACHE_CONTROL,
+            HeaderValue::try_from(cache_control.to_string())?,
+        );
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::CacheControl;
+    use crate::Respond;
+
+    #[test]
+    fn writes_directives_in_order() {
+        let cache_control = CacheControl::new()
+            .stale_while_revalidate(Duration::from_secs(300))
+            .max_age(Duration::from_secs(3600))
+            .public();
+
+        assert_eq!(
+            cache_control.to_string(),
+            "public, max-age=3600, stale-while-revalidate=300"
+        );
+        assert_eq!(
+            CacheControl::parse(&cache_control.to_string()),
+            cache_control
+        );
+    }
+
+    #[test]
+    fn merges_repeated_calls() {
+        let response = "hello"
+            .cache_control(
+                CacheControl::new()
+                    .public()
+                    .max_age(Duration::from_secs(60)),
+            )
+            .cache_control(CacheControl::new().max_age(Duration::from_secs(120)))
+            .immutable()
+            .respond()
+            .unwrap();
+
+        assert_eq!(
+            response.headers().get_all("cache-control").iter().count(),
+            1
+        );
+        assert_eq!(
+            response.headers()["cache-control"],
+            "public, max-age=31536000, immutable"
+        );
+    }
+
+    #[test]
+    fn rejects_contradictions() {
+        let result = "hello"
+            .cache_control(CacheControl::new().max_age(Duration::from_secs(60)))
+            .no_store()
+            .respond();
+
+        assert!(result.is_err());
+        assert!(CacheControl::new().public().private().validate().is_err());
+        assert_eq!(
+            "hello".no_store().respond().unwrap().headers()["cache-control"],
+            "no-store"
+        );
+    }
+}
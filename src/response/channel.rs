@@ -0,0 +1,95 @@
+use hyper::body::Bytes;
+use std::{error::Error as StdError, io};
+use tokio::sync::mpsc;
+
+use super::Body;
+use crate::{error::Bail, Error, Result};
+
+/// The sending half of a body that chunks are pushed into. At most
+/// `capacity` chunks are buffered, so `send` waits while the client is slow
+/// to read.
+pub struct Channel {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl Channel {
+    /// Returns a channel and the body that receives the chunks sent to it.
+    /// The body ends when the channel is dropped.
+    pub fn new(capacity: usize) -> (Self, Body) {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let body = Body::stream(futures::stream::unfold(
+            receiver,
+            |mut receiver| async move {
+                let next = receiver.recv().await?;
+                Some((next, receiver))
+            },
+        ));
+
+        (Channel { sender }, body)
+    }
+
+    /// Sends a chunk of the body. Fails once the body has been dropped,
+    /// which happens when the client disconnects.
+    pub async fn send(&mut self, chunk: impl Into<Bytes>) -> Result<()> {
+        match self.sender.send(Ok(chunk.into())).await {
+            Ok(()) => Ok(()),
+            Err(_) => Err(Error::from(Bail::new("The client disconnected"))),
+        }
+    }
+
+    /// Ends the body with `error`, which aborts the response instead of
+    /// letting the client mistake what was sent for the whole body.
+    pub async fn abort<E>(self, error: E)
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let _ = self.sender.send(Err(io::Error::other(error))).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+    use http_body_util::BodyExt;
+
+    use super::Channel;
+    use crate::Response;
+
+    #[tokio::test]
+    async fn applies_backpressure() {
+        let (mut channel, mut body) = Channel::new(1);
+
+        channel.send("a").await.unwrap();
+        assert!(channel.send("b").now_or_never().is_none());
+
+        let frame = body.frame().await.unwrap().unwrap();
+
+        assert_eq!(frame.into_data().unwrap(), "a");
+        channel.send("b").await.unwrap();
+        drop(channel);
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "b");
+    }
+
+    #[tokio::test]
+    async fn fails_once_the_response_is_dropped() {
+        let (mut channel, body) = Channel::new(4);
+        let response = Response::new(body);
+
+        channel.send("a").await.unwrap();
+        drop(response);
+        assert!(channel.send("b").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn aborts_the_body() {
+        let (mut channel, body) = Channel::new(4);
+        let producer = tokio::spawn(async move {
+            channel.send("partial").await.unwrap();
+            channel.abort("cursor closed").await;
+        });
+        let error = body.collect().await.unwrap_err();
+
+        producer.await.unwrap();
+        assert_eq!(error.to_string(), "cursor closed");
+    }
+}
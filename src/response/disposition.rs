@@ -0,0 +1,169 @@
+use http::header::{HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use super::{Respond, Response};
+use crate::Result;
+
+/// The characters that RFC 5987 allows unencoded in an `ext-value`.
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+pub struct WithDisposition<T: Respond> {
+    filename: String,
+    kind: &'static str,
+    value: T,
+}
+
+pub(super) fn content_type(filename: &str) -> &'static str {
+    let extension = match filename.rsplit_once('.') {
+        Some((_, extension)) => extension.to_ascii_lowercase(),
+        None => return "application/octet-stream",
+    };
+
+    match extension.as_str() {
+        "csv" => "text/csv; charset=utf-8",
+        "gif" => "image/gif",
+        "gz" => "application/gzip",
+        "htm" | "html" => "text/html; charset=utf-8",
+        "ics" => "text/calendar; charset=utf-8",
+        "jpeg" | "jpg" => "image/jpeg",
+        "json" => "application/json",
+        "md" => "text/markdown; charset=utf-8",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "svg" => "image/svg+xml",
+        "txt" => "text/plain; charset=utf-8",
+        "webp" => "image/webp",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "xml" => "application/xml",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Formats the value of a Content-Disposition header with a quoted ASCII
+/// `filename` for older clients and the exact name as an RFC 5987
+/// `filename*`.
+fn disposition(kind: &str, filename: &str) -> String {
+    let filename: String = filename
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if c == '/' || c == '\\' { '_' } else { c })
+        .collect();
+    let filename = filename.trim();
+
+    if filename.is_empty() {
+        return kind.to_owned();
+    }
+
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            '"' => "\\\"".to_owned(),
+            // Some clients decode percent-escapes in the plain parameter.
+            '%' => "_".to_owned(),
+            ' '..='~' => c.to_string(),
+            _ => "_".to_owned(),
+        })
+        .collect();
+
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        kind,
+        fallback,
+        utf8_percent_encode(filename, ATTR_CHAR)
+    )
+}
+
+impl<T: Respond> WithDisposition<T> {
+    pub(super) fn new(value: T, kind: &'static str, filename: String) -> Self {
+        WithDisposition {
+            filename,
+            kind,
+            value,
+        }
+    }
+}
+
+impl<T: Respond> Respond for WithDisposition<T> {
+    fn respond(self) -> Result<Response> {
+        let mut response = self.value.respond()?;
+        let value = disposition(self.kind, &self.filename);
+        let headers = response.headers_mut();
+
+        headers.insert(CONTENT_DISPOSITION, HeaderValue::try_from(value)?);
+
+        // String bodies default to text/plain, which is replaced as well.
+        if headers
+            .get(CONTENT_TYPE)
+            .is_none_or(|value| value == "text/plain")
+        {
+            let content_type = content_type(&self.filename);
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disposition;
+    use crate::Respond;
+
+    #[test]
+    fn quotes_the_ascii_fallback() {
+        assert_eq!(
+            disposition("attachment", r#"say "hi" 100%.txt"#),
+            r#"attachment; filename="say \"hi\" 100_.txt"; filename*=UTF-8''say%20%22hi%22%20100%25.txt"#
+        );
+        assert_eq!(
+            disposition("inline", "réport 2024.csv"),
+            "inline; filename=\"r_port 2024.csv\"; filename*=UTF-8''r%C3%A9port%202024.csv"
+        );
+    }
+
+    #[test]
+    fn strips_control_characters() {
+        assert_eq!(
+            disposition("attachment", "a\r\nb\t../c.pdf"),
+            "attachment; filename=\"ab.._c.pdf\"; filename*=UTF-8''ab.._c.pdf"
+        );
+        assert_eq!(disposition("attachment", "\n\u{7f}"), "attachment");
+    }
+
+    #[test]
+    fn encodes_cjk_filenames() {
+        let response = "a,b\n".attachment("報告書.csv").respond().unwrap();
+
+        assert_eq!(
+            response.headers()["content-disposition"],
+            "attachment; filename=\"___.csv\"; \
+             filename*=UTF-8''%E5%A0%B1%E5%91%8A%E6%9B%B8.csv"
+        );
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/csv; charset=utf-8"
+        );
+
+        let response = crate::response::json(&[1])
+            .inline("data.bin")
+            .respond()
+            .unwrap();
+
+        assert_eq!(response.headers()["content-type"], "application/json");
+    }
+}
@@ -0,0 +1,536 @@
+use http::header::{
+    HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+    CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use httpdate::HttpDate;
+use std::{
+    io::{self, ErrorKind, SeekFrom},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::{disposition::content_type, Body, Response};
+use crate::{
+    middleware::context::{ByteRange, Range},
+    Context, Error, Respond, Result,
+};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Serves files from disk. The body is read in chunks as the client reads
+/// the response rather than loaded into memory.
+pub struct File {
+    encoding: Option<Encoding>,
+    etag: String,
+    file: tokio::fs::File,
+    len: u64,
+    modified: Option<SystemTime>,
+    name: String,
+}
+
+/// An encoding that a file can be precompressed with. The compressed copy of
+/// `app.js` is expected next to it, such as `app.js.br`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Returns true if the client lists the encoding, or `*`, in
+    /// `Accept-Encoding` with a q-value above 0.
+    fn is_accepted(self, context: &Context) -> bool {
+        let mut accepted = None;
+        let mut wildcard = None;
+
+        for coding in context
+            .headers()
+            .iter()
+            .filter(|(name, _)| **name == ACCEPT_ENCODING)
+            .filter_map(|(_, value)| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+        {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let quality = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |value| value.parse::<f32>().ok())
+                .unwrap_or(0.0);
+
+            if name.eq_ignore_ascii_case(self.name())
+                || (self == Encoding::Gzip && name.eq_ignore_ascii_case("x-gzip"))
+            {
+                accepted = Some(quality);
+            } else if name == "*" {
+                wildcard = Some(quality);
+            }
+        }
+
+        accepted.or(wildcard).is_some_and(|quality| quality > 0.0)
+    }
+}
+
+impl File {
+    /// Responds with the file at `path`. A single range requested with a
+    /// `Range` header is served as 206 Partial Content unless an `If-Range`
+    /// header no longer matches the file. Multiple ranges are answered with
+    /// the whole file.
+    pub async fn serve(context: &Context, path: impl AsRef<Path>) -> Result {
+        match File::open(path.as_ref(), None).await {
+            Ok(file) => file.respond_to(context).await,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                Err(Error::from(error).status(404))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Like `serve`, but responds with a precompressed copy of the file when
+    /// the client accepts its encoding. The first of `encodings` that is
+    /// accepted and has a copy next to the file is served, with the Content
+    /// Type of the file and the ETag and Last-Modified of the copy. When none
+    /// is, the file itself is served.
+    ///
+    /// The copies are found by appending an extension to `path`, so a `path`
+    /// that is safe to serve only leads to copies that are.
+    pub async fn serve_precompressed(
+        context: &Context,
+        path: impl AsRef<Path>,
+        encodings: &[Encoding],
+    ) -> Result {
+        let path = path.as_ref();
+
+        for encoding in encodings
+            .iter()
+            .filter(|encoding| encoding.is_accepted(context))
+        {
+            let mut sidecar = path.as_os_str().to_owned();
+
+            sidecar.push(".");
+            sidecar.push(encoding.extension());
+
+            match File::open(Path::new(&sidecar), Some(*encoding)).await {
+                Ok(mut file) => {
+                    file.name = file_name(path);
+                    return file.respond_to(context).await;
+                }
+                Err(error) if error.kind() == ErrorKind::NotFound => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        let mut response = File::serve(context, path).await?;
+
+        response.add_vary(ACCEPT_ENCODING);
+        Ok(response)
+    }
+
+    async fn open(path: &Path, encoding: Option<Encoding>) -> io::Result<Self> {
+        let file = tokio::fs::File::open(path).await?;
+        let metadata = file.metadata().await?;
+
+        if !metadata.is_file() {
+            return Err(ErrorKind::NotFound.into());
+        }
+
+        let modified = metadata.modified().ok();
+        let suffix = encoding.map_or(String::new(), |encoding| format!("-{}", encoding.name()));
+        let etag = match modified.and_then(|time| time.duration_since(UNIX_EPOCH).ok()) {
+            Some(since) => format!("\"{:x}-{:x}{}\"", metadata.len(), since.as_secs(), suffix),
+            None => format!("\"{:x}{}\"", metadata.len(), suffix),
+        };
+
+        Ok(File {
+            encoding,
+            etag,
+            file,
+            len: metadata.len(),
+            modified,
+            name: file_name(path),
+        })
+    }
+
+    /// Returns true if the `If-Range` header is missing or still describes
+    /// the file. Only strong validators match.
+    fn if_range(&self, context: &Context) -> bool {
+        let value = match context.headers().get(IF_RANGE) {
+            Some(value) => value.to_str().unwrap_or_default().trim(),
+            None => return true,
+        };
+
+        if value.starts_with('"') {
+            return value == self.etag;
+        }
+
+        match (value.parse::<HttpDate>(), self.modified) {
+            (Ok(date), Some(modified)) => date == HttpDate::from(modified),
+            _ => false,
+        }
+    }
+
+    fn range(&self, context: &Context) -> Option<Result<ByteRange, Response>> {
+        let value = context.headers().get(RANGE)?.to_str().ok()?;
+
+        // A stale If-Range asks for the whole file, even if the range can't
+        // be satisfied.
+        if !self.if_range(context) {
+            return None;
+        }
+
+        // An invalid Range header is ignored rather than rejected.
+        let ranges = match Range::parse(value).ok()?.resolve(self.len) {
+            Ok(ranges) => ranges,
+            Err(unsatisfiable) => return Some(Err(unsatisfiable.respond().ok()?)),
+        };
+
+        match ranges.as_slice() {
+            [range] => Some(Ok(*range)),
+            _ => None,
+        }
+    }
+
+    async fn respond_to(mut self, context: &Context) -> Result {
+        let range = self.range(context);
+        let mut response = match range {
+            Some(Err(response)) => return Ok(response),
+            Some(Ok(range)) => {
+                self.file.seek(SeekFrom::Start(range.start)).await?;
+
+                let mut response =
+                    Response::new(Body::reader(self.file.take(range.len()), CHUNK_SIZE));
+                let content_range = range.content_range(self.len);
+
+                *response.status_mut() = http::StatusCode::PARTIAL_CONTENT;
+                response
+                    .headers_mut()
+                    .insert(CONTENT_RANGE, HeaderValue::try_from(content_range)?);
+                response
+                    .headers_mut()
+                    .insert(CONTENT_LENGTH, HeaderValue::from(range.len()));
+                response
+            }
+            None => {
+                let mut response = Response::new(Body::reader(self.file, CHUNK_SIZE));
+
+                response
+                    .headers_mut()
+                    .insert(CONTENT_LENGTH, HeaderValue::from(self.len));
+                response
+            }
+        };
+        let headers = response.headers_mut();
+
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(content_type(&self.name)),
+        );
+        headers.insert(ETAG, HeaderValue::try_from(self.etag)?);
+
+        if let Some(modified) = self.modified {
+            let modified = httpdate::fmt_http_date(modified);
+            headers.insert(LAST_MODIFIED, HeaderValue::try_from(modified)?);
+        }
+
+        if let Some(encoding) = self.encoding {
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+            response.add_vary(ACCEPT_ENCODING);
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::{
+        CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE,
+        LAST_MODIFIED, RANGE, VARY,
+    };
+    use http_body_util::BodyExt;
+    use std::path::PathBuf;
+
+    use super::{Encoding, File};
+    use crate::{middleware::context::Body, Context};
+
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            Fixture::with(name, &(0..100).collect::<Vec<u8>>())
+        }
+
+        fn with(name: &str, contents: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("via-{}-{}", std::process::id(), name));
+
+            std::fs::write(&path, contents).unwrap();
+            Fixture(path)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    async fn serve(
+        fixture: &Fixture,
+        headers: &[(http::HeaderName, &str)],
+    ) -> (u16, http::HeaderMap, Vec<u8>) {
+        let mut request = http::Request::get("/");
+
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+
+        let context = Context::from(request.body(Body::full("".into())).unwrap());
+        let response = match File::serve(&context, &fixture.0).await {
+            Ok(response) => response,
+            Err(error) => crate::Response::from(error),
+        };
+        let (parts, body) = http::Response::from(response).into_parts();
+        let body = body.collect().await.unwrap().to_bytes().to_vec();
+
+        (parts.status.as_u16(), parts.headers, body)
+    }
+
+    async fn range(fixture: &Fixture, value: &str) -> (u16, Option<String>, Vec<u8>) {
+        let (status, headers, body) = serve(fixture, &[(RANGE, value)]).await;
+        let content_range = headers
+            .get(CONTENT_RANGE)
+            .map(|value| value.to_str().unwrap().to_owned());
+
+        if status != 416 {
+            assert_eq!(headers[CONTENT_LENGTH], body.len().to_string());
+        }
+
+        (status, content_range, body)
+    }
+
+    #[tokio::test]
+    async fn serves_single_ranges() {
+        let fixture = Fixture::new("ranges.bin");
+        let bytes = |start: u8, end: u8| (start..=end).collect::<Vec<_>>();
+
+        let (status, headers, body) = serve(&fixture, &[]).await;
+
+        assert_eq!(status, 200);
+        assert_eq!(headers["accept-ranges"], "bytes");
+        assert_eq!(headers["content-type"], "application/octet-stream");
+        assert_eq!(body, bytes(0, 99));
+
+        for (value, start, end) in [
+            ("bytes=0-0", 0, 0),
+            ("bytes=0-9", 0, 9),
+            ("bytes=10-19", 10, 19),
+            ("bytes=90-99", 90, 99),
+            ("bytes=90-1000", 90, 99),
+            ("bytes=99-", 99, 99),
+            ("bytes=50-", 50, 99),
+            ("bytes=-1", 99, 99),
+            ("bytes=-10", 90, 99),
+            ("bytes=-1000", 0, 99),
+            ("bytes=0-99", 0, 99),
+        ] {
+            let content_range = format!("bytes {}-{}/100", start, end);
+
+            assert_eq!(
+                range(&fixture, value).await,
+                (206, Some(content_range), bytes(start, end)),
+                "{}",
+                value
+            );
+        }
+
+        for value in ["bytes=100-", "bytes=100-200", "bytes=-0"] {
+            let (status, content_range, _) = range(&fixture, value).await;
+
+            assert_eq!(status, 416, "{}", value);
+            assert_eq!(content_range.as_deref(), Some("bytes */100"));
+        }
+
+        for value in ["bytes=0-1,5-6", "bytes=5-1", "items=0-1"] {
+            assert_eq!(
+                range(&fixture, value).await,
+                (200, None, bytes(0, 99)),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_whole_file_when_if_range_differs() {
+        let fixture = Fixture::new("if-range.bin");
+        let (_, headers, _) = serve(&fixture, &[]).await;
+        let etag = headers[ETAG].to_str().unwrap();
+        let modified = headers[LAST_MODIFIED].to_str().unwrap();
+
+        for (if_range, status) in [
+            (etag, 206),
+            (modified, 206),
+            ("\"stale\"", 200),
+            ("W/\"stale\"", 200),
+            ("Thu, 01 Jan 1970 00:00:00 GMT", 200),
+        ] {
+            let headers = [(RANGE, "bytes=0-9"), (IF_RANGE, if_range)];
+            let (actual, _, body) = serve(&fixture, &headers).await;
+
+            assert_eq!(actual, status, "{}", if_range);
+            assert_eq!(body.len(), if status == 206 { 10 } else { 100 });
+        }
+
+        // A range past the end only matters if If-Range still matches.
+        let headers = [(RANGE, "bytes=100-"), (IF_RANGE, etag)];
+
+        assert_eq!(serve(&fixture, &headers).await.0, 416);
+
+        let headers = [(RANGE, "bytes=100-"), (IF_RANGE, "\"stale\"")];
+        let (status, _, body) = serve(&fixture, &headers).await;
+
+        assert_eq!(status, 200);
+        assert_eq!(body.len(), 100);
+
+        let missing = Fixture(fixture.0.with_extension("missing"));
+
+        assert_eq!(serve(&missing, &[]).await.0, 404);
+    }
+
+    async fn serve_precompressed(
+        fixture: &Fixture,
+        accept_encoding: Option<&str>,
+        headers: &[(http::HeaderName, &str)],
+    ) -> (u16, http::HeaderMap, Vec<u8>) {
+        let mut request = http::Request::get("/");
+
+        if let Some(value) = accept_encoding {
+            request = request.header("accept-encoding", value);
+        }
+
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+
+        let context = Context::from(request.body(Body::full("".into())).unwrap());
+        let encodings = [Encoding::Brotli, Encoding::Gzip];
+        let response = File::serve_precompressed(&context, &fixture.0, &encodings).await;
+        let (parts, body) = http::Response::from(response.unwrap()).into_parts();
+        let body = body.collect().await.unwrap().to_bytes().to_vec();
+
+        (parts.status.as_u16(), parts.headers, body)
+    }
+
+    #[tokio::test]
+    async fn serves_precompressed_copies() {
+        let plain = Fixture::with("all.txt", b"plain text");
+        let _brotli = Fixture::with("all.txt.br", b"brotli");
+        let _gzip = Fixture::with("all.txt.gz", b"gzip!");
+        let mut etags = Vec::new();
+
+        for (accept_encoding, encoding, body) in [
+            (Some("gzip, br"), Some("br"), "brotli"),
+            (Some("br;q=0.1, gzip"), Some("br"), "brotli"),
+            (Some("gzip"), Some("gzip"), "gzip!"),
+            (Some("x-gzip"), Some("gzip"), "gzip!"),
+            (Some("br;q=0, gzip"), Some("gzip"), "gzip!"),
+            (Some("*"), Some("br"), "brotli"),
+            (Some("*, br;q=0"), Some("gzip"), "gzip!"),
+            (Some("deflate"), None, "plain text"),
+            (Some("*;q=0"), None, "plain text"),
+            (None, None, "plain text"),
+        ] {
+            let (status, headers, actual) = serve_precompressed(&plain, accept_encoding, &[]).await;
+            let message = format!("{:?}", accept_encoding);
+
+            assert_eq!(status, 200, "{}", message);
+            assert_eq!(
+                headers.get(CONTENT_ENCODING).map(|v| v.to_str().unwrap()),
+                encoding,
+                "{}",
+                message
+            );
+            assert_eq!(actual, body.as_bytes(), "{}", message);
+            assert_eq!(headers[CONTENT_LENGTH], body.len().to_string());
+            assert_eq!(headers[CONTENT_TYPE], "text/plain; charset=utf-8");
+            assert_eq!(headers[VARY], "accept-encoding");
+            etags.push((encoding, headers[ETAG].clone()));
+        }
+
+        // Each copy has its own validator, so If-Range only matches the copy
+        // it came from.
+        for (encoding, etag) in &etags {
+            for (other, other_etag) in &etags {
+                assert_eq!(encoding == other, etag == other_etag);
+            }
+        }
+
+        let brotli_etag = etags[0].1.to_str().unwrap();
+        let headers = [(RANGE, "bytes=0-2"), (IF_RANGE, brotli_etag)];
+
+        assert_eq!(
+            serve_precompressed(&plain, Some("br"), &headers).await.2,
+            b"bro"
+        );
+        assert_eq!(
+            serve_precompressed(&plain, Some("gzip"), &headers).await.0,
+            200
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_when_copies_are_missing() {
+        let plain = Fixture::with("gzip-only.txt", b"plain text");
+        let _gzip = Fixture::with("gzip-only.txt.gz", b"gzip!");
+        let (_, headers, body) = serve_precompressed(&plain, Some("br, gzip"), &[]).await;
+
+        assert_eq!(headers[CONTENT_ENCODING], "gzip");
+        assert_eq!(body, b"gzip!");
+
+        let (_, headers, body) = serve_precompressed(&plain, Some("br"), &[]).await;
+
+        assert!(headers.get(CONTENT_ENCODING).is_none());
+        assert_eq!(body, b"plain text");
+
+        let plain = Fixture::with("none.txt", b"plain text");
+        let (status, headers, body) = serve_precompressed(&plain, Some("br, gzip"), &[]).await;
+
+        assert_eq!(status, 200);
+        assert!(headers.get(CONTENT_ENCODING).is_none());
+        assert_eq!(headers[VARY], "accept-encoding");
+        assert_eq!(body, b"plain text");
+
+        // A directory with the name of a copy isn't served.
+        let directory = std::env::temp_dir().join(format!("via-{}-dir.txt.br", std::process::id()));
+        let plain = Fixture::with("dir.txt", b"plain text");
+
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let (_, headers, body) = serve_precompressed(&plain, Some("br"), &[]).await;
+
+        std::fs::remove_dir(&directory).unwrap();
+        assert!(headers.get(CONTENT_ENCODING).is_none());
+        assert_eq!(body, b"plain text");
+    }
+}
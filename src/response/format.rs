@@ -1,11 +1,111 @@
+use futures::stream::{Stream, StreamExt};
+use hyper::body::Bytes;
+
 use super::{Body, Respond, Response};
-use crate::Result;
+use crate::{Error, Result};
+
+#[cfg(feature = "cbor")]
+struct Cbor(Result<Bytes>);
+
+struct Html(String);
+
+struct Json {
+    body: Result<Bytes>,
+    pretty: bool,
+}
+
+tokio::task_local! {
+    /// Set around the middleware stack of each request when
+    /// `Application::pretty_json` is enabled, so that `json` indents its body
+    /// before any middleware sees the response.
+    pub(crate) static PRETTY_JSON: bool;
+}
 
-struct Json(Result<Body>);
+struct JsonStream(Body);
+
+#[cfg(feature = "msgpack")]
+struct Msgpack(Result<Bytes>);
+
+#[cfg(feature = "xml")]
+struct Xml(Result<Body>);
+
+/// Responds with `body` serialized as CBOR (`application/cbor`).
+#[cfg(feature = "cbor")]
+pub fn cbor(body: &impl serde::Serialize) -> impl Respond {
+    let mut buffer = Vec::new();
+
+    Cbor(match ciborium::into_writer(body, &mut buffer) {
+        Ok(_) => Ok(buffer.into()),
+        Err(error) => Err(error.into()),
+    })
+}
+
+pub fn html(body: impl Into<String>) -> impl Respond {
+    Html(body.into())
+}
 
 pub fn json(body: &impl serde::Serialize) -> impl Respond {
-    Json(match serde_json::to_vec(body) {
-        Ok(bytes) => Ok(bytes.into()),
+    Json::new(serde_json::to_vec(body), false)
+}
+
+pub fn json_pretty(body: &impl serde::Serialize) -> impl Respond {
+    Json::new(serde_json::to_vec_pretty(body), true)
+}
+
+/// Responds with each item of `stream` serialized as a line of JSON
+/// (`application/x-ndjson`). Items are serialized as the client reads the
+/// body, so the stream only advances as fast as the response is sent.
+///
+/// The status and headers are sent before the first item, so an error from
+/// the stream, or one serializing an item, can't change them. Instead the
+/// body ends early and the error is written to stderr.
+pub fn json_stream<S, T, E>(stream: S) -> impl Respond
+where
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    T: serde::Serialize,
+    Error: From<E>,
+{
+    let lines = stream.scan((), |_, item| {
+        let line = item.map_err(Error::from).and_then(|item| {
+            let mut line = serde_json::to_vec(&item)?;
+
+            line.push(b'\n');
+            Ok(Bytes::from(line))
+        });
+
+        futures::future::ready(match line {
+            Ok(line) => Some(Ok(line)),
+            Err(error) => {
+                eprintln!("Error streaming response: {}", error);
+                None
+            }
+        })
+    });
+
+    JsonStream(Body::stream(lines))
+}
+
+/// Responds with `body` serialized as MessagePack (`application/msgpack`).
+/// Structs are encoded as maps keyed by field name, so clients don't depend
+/// on the order of their fields.
+#[cfg(feature = "msgpack")]
+pub fn msgpack(body: &impl serde::Serialize) -> impl Respond {
+    Msgpack(match rmp_serde::to_vec_named(body) {
+        Ok(buffer) => Ok(buffer.into()),
+        Err(error) => Err(error.into()),
+    })
+}
+
+#[cfg(feature = "xml")]
+pub fn xml(root: &str, body: &impl serde::Serialize) -> impl Respond {
+    use quick_xml::se::Serializer;
+
+    let mut buffer = r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_owned();
+    let result = Serializer::with_root(&mut buffer, Some(root))
+        .and_then(|serializer| body.serialize(serializer));
+
+    Xml(match result {
+        Ok(_) => Ok(buffer.into_bytes().into()),
         Err(error) => Err(error.into()),
     })
 }
@@ -20,8 +120,314 @@ macro_rules! media(($body:expr, $type:expr) => {{
     response
 }});
 
+#[cfg(feature = "cbor")]
+impl Respond for Cbor {
+    fn respond(self) -> Result<Response> {
+        Ok(media!(Body::from(self.0?), "application/cbor"))
+    }
+}
+
+impl Respond for Html {
+    fn respond(self) -> Result<Response> {
+        Ok(media!(self.0, "text/html; charset=utf-8"))
+    }
+}
+
+/// Indents compact JSON the same way as `serde_json::to_vec_pretty`.
+pub(crate) fn pretty(json: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(json.len() * 2);
+    let mut depth = 0;
+    let mut bytes = json.iter().copied().peekable();
+    let newline = |output: &mut Vec<u8>, depth: usize| {
+        output.push(b'\n');
+        output.resize(output.len() + depth * 2, b' ');
+    };
+
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'"' => {
+                output.push(byte);
+
+                while let Some(byte) = bytes.next() {
+                    output.push(byte);
+
+                    match byte {
+                        b'\\' => output.extend(bytes.next()),
+                        b'"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            b'{' | b'[' => {
+                output.push(byte);
+
+                if matches!(bytes.peek(), Some(b'}' | b']')) {
+                    output.extend(bytes.next());
+                } else {
+                    depth += 1;
+                    newline(&mut output, depth);
+                }
+            }
+            b'}' | b']' => {
+                depth -= 1;
+                newline(&mut output, depth);
+                output.push(byte);
+            }
+            b',' => {
+                output.push(byte);
+                newline(&mut output, depth);
+            }
+            b':' => output.extend_from_slice(b": "),
+            _ => output.push(byte),
+        }
+    }
+
+    output
+}
+
+impl Json {
+    fn new(result: serde_json::Result<Vec<u8>>, pretty: bool) -> Self {
+        Json {
+            body: result.map(Bytes::from).map_err(Error::from),
+            pretty,
+        }
+    }
+}
+
 impl Respond for Json {
     fn respond(self) -> Result<Response> {
-        Ok(media!(self.0?, "application/json"))
+        let body = self.body?;
+        let indent = !self.pretty && PRETTY_JSON.try_with(|enabled| *enabled).unwrap_or(false);
+
+        if indent {
+            Ok(media!(Body::from(pretty(&body)), "application/json"))
+        } else {
+            Ok(media!(Body::from(body), "application/json"))
+        }
+    }
+}
+
+impl Respond for JsonStream {
+    fn respond(self) -> Result<Response> {
+        Ok(media!(self.0, "application/x-ndjson"))
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl Respond for Msgpack {
+    fn respond(self) -> Result<Response> {
+        Ok(media!(Body::from(self.0?), "application/msgpack"))
+    }
+}
+
+#[cfg(feature = "xml")]
+impl Respond for Xml {
+    fn respond(self) -> Result<Response> {
+        Ok(media!(self.0?, "application/xml; charset=utf-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use http_body_util::BodyExt;
+    use hyper::body::Body as _;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::middleware::context::Body as RequestBody;
+
+    #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+    struct Row {
+        id: usize,
+    }
+
+    #[cfg(any(feature = "cbor", feature = "msgpack"))]
+    #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+    struct Post {
+        id: u64,
+        title: String,
+        tags: Vec<String>,
+        score: f64,
+        draft: Option<bool>,
+    }
+
+    #[cfg(any(feature = "cbor", feature = "msgpack"))]
+    impl Post {
+        fn new() -> Self {
+            Post {
+                id: 42,
+                title: "Hello, \u{1f30d}".to_owned(),
+                tags: vec!["rust".to_owned(), "http".to_owned()],
+                score: -1.5,
+                draft: None,
+            }
+        }
+    }
+
+    /// Responds with `respond`, then reads the body back as a request with
+    /// `content_type`.
+    #[cfg(any(feature = "cbor", feature = "msgpack"))]
+    async fn echo(respond: impl Respond, content_type: &str) -> (String, crate::Context) {
+        let response = http::Response::from(respond.respond().unwrap());
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        let request = http::Request::post("/posts")
+            .header("content-type", content_type)
+            .body(RequestBody::full(body))
+            .unwrap();
+
+        (
+            parts.headers["content-type"].to_str().unwrap().to_owned(),
+            crate::Context::from(request),
+        )
+    }
+
+    #[tokio::test]
+    async fn json_stream_round_trip() {
+        let polled = Arc::new(AtomicUsize::new(0));
+        let rows = stream::iter(0..3).map({
+            let polled = Arc::clone(&polled);
+            move |id| {
+                polled.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, Error>(Row { id })
+            }
+        });
+        let response = http::Response::from(json_stream(rows).respond().unwrap());
+        let (parts, mut body) = response.into_parts();
+
+        assert_eq!(parts.headers["content-type"], "application/x-ndjson");
+        assert_eq!(body.size_hint().exact(), None);
+        assert_eq!(polled.load(Ordering::SeqCst), 0);
+
+        let first = body.frame().await.unwrap().unwrap().into_data().unwrap();
+
+        assert_eq!(first, "{\"id\":0}\n");
+        assert_eq!(polled.load(Ordering::SeqCst), 1);
+
+        let rest = body.collect().await.unwrap().to_bytes();
+        let lines = RequestBody::full([first, rest].concat().into())
+            .json_lines::<Row>()
+            .collect::<Vec<_>>()
+            .await;
+        let rows: Vec<_> = lines.into_iter().map(Result::unwrap).collect();
+
+        assert_eq!(rows, [Row { id: 0 }, Row { id: 1 }, Row { id: 2 }]);
+    }
+
+    #[tokio::test]
+    async fn json_stream_ends_on_error() {
+        let rows = stream::iter([
+            Ok(Row { id: 0 }),
+            Err(crate::error::Bail::new("cursor closed")),
+            Ok(Row { id: 2 }),
+        ]);
+        let response = http::Response::from(json_stream(rows).respond().unwrap());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        assert_eq!(body, "{\"id\":0}\n");
+    }
+
+    #[tokio::test]
+    async fn json_lines_skips_blank_lines() {
+        let body = RequestBody::full("{\"id\":0}\r\n\n{\"id\":1}\nnope\n{\"id\":3}".into());
+        let lines: Vec<_> = body.json_lines::<Row>().collect().await;
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].as_ref().unwrap(), &Row { id: 0 });
+        assert_eq!(lines[1].as_ref().unwrap(), &Row { id: 1 });
+        assert!(lines[2].is_err());
+        assert_eq!(lines[3].as_ref().unwrap(), &Row { id: 3 });
+    }
+
+    #[tokio::test]
+    async fn json_pretty_matches_compact_json() {
+        let value = serde_json::json!({
+            "id": 42,
+            "tags": ["a", "b,c"],
+            "empty": {},
+            "none": [],
+            "nested": { "quote": "say \\\"hi\\\": {x}", "rows": [{ "id": 1 }] },
+        });
+        let body = |respond: Response| async {
+            let body = http::Response::from(respond).into_body();
+            body.collect().await.unwrap().to_bytes()
+        };
+        let compact = body(json(&value).respond().unwrap()).await;
+        let indented = body(json_pretty(&value).respond().unwrap()).await;
+        let enabled = PRETTY_JSON
+            .scope(true, async { body(json(&value).respond().unwrap()).await })
+            .await;
+
+        assert_eq!(pretty(&compact), indented);
+        assert_eq!(enabled, indented);
+        assert_eq!(
+            std::str::from_utf8(&indented).unwrap(),
+            include_str!("fixtures/pretty.json").trim_end()
+        );
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&indented).unwrap(),
+            serde_json::from_slice::<serde_json::Value>(&compact).unwrap()
+        );
+    }
+
+    #[cfg(feature = "xml")]
+    #[tokio::test]
+    async fn xml_golden() {
+        let event = serde_json::json!({
+            "id": 42,
+            "kind": "order.created",
+            "payload": "<![CDATA[a & b]]> \"quoted\" 'single'",
+        });
+        let response = http::Response::from(xml("webhook", &event).respond().unwrap());
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+
+        assert_eq!(
+            parts.headers["content-type"],
+            "application/xml; charset=utf-8"
+        );
+        assert_eq!(body, include_str!("fixtures/webhook.xml").trim_end());
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[tokio::test]
+    async fn msgpack_round_trip() {
+        let (content_type, mut context) = echo(msgpack(&Post::new()), "application/msgpack").await;
+
+        assert_eq!(content_type, "application/msgpack");
+        assert_eq!(context.msgpack::<Post>().await.unwrap(), Post::new());
+
+        let (_, mut context) = echo(msgpack(&Post::new()), "application/json").await;
+        let error = context.msgpack::<Post>().await.unwrap_err();
+
+        assert_eq!(error.status_code(), 415);
+
+        let (_, mut context) = echo(json(&Post::new()), "application/msgpack").await;
+        let error = context.msgpack::<Post>().await.unwrap_err();
+
+        assert_eq!(error.status_code(), 400);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[tokio::test]
+    async fn cbor_round_trip() {
+        let (content_type, mut context) = echo(cbor(&Post::new()), "application/cbor").await;
+
+        assert_eq!(content_type, "application/cbor");
+        assert_eq!(context.cbor::<Post>().await.unwrap(), Post::new());
+
+        let (_, mut context) = echo(cbor(&Post::new()), "application/msgpack").await;
+        let error = context.cbor::<Post>().await.unwrap_err();
+
+        assert_eq!(error.status_code(), 415);
+
+        let (_, mut context) = echo(cbor(&vec![1, 2, 3]), "application/cbor").await;
+        let error = context.cbor::<Post>().await.unwrap_err();
+
+        assert_eq!(error.status_code(), 400);
     }
 }
@@ -1,26 +1,69 @@
+mod body;
+mod cache_control;
+mod channel;
+mod disposition;
+mod file;
+mod render;
+mod vary;
 #[macro_use]
 mod format;
 
 use http::{
-    header::{HeaderName, HeaderValue, InvalidHeaderName, InvalidHeaderValue},
+    header::{HeaderName, HeaderValue, InvalidHeaderName, InvalidHeaderValue, CONTENT_LENGTH},
     status::{InvalidStatusCode, StatusCode},
 };
-use http_body_util::Full;
-use hyper::body::Bytes;
+use hyper::body::Body as _;
 use std::{
     convert::TryFrom,
     ops::{Deref, DerefMut},
+    time::Duration,
 };
+use tokio::io::AsyncRead;
 
 use crate::{Error, Result};
 
-pub use self::format::*;
-
-pub type Body = Full<Bytes>;
+pub(crate) use self::format::PRETTY_JSON;
+pub use self::{
+    body::Body,
+    cache_control::{CacheControl, WithCacheControl},
+    channel::Channel,
+    disposition::WithDisposition,
+    file::{Encoding, File},
+    format::*,
+    render::{render, Render},
+};
 
 pub trait Respond: Sized {
     fn respond(self) -> Result<Response>;
 
+    /// Sets the Cache-Control header, merging with the directives that are
+    /// already set.
+    fn cache_control(self, cache_control: CacheControl) -> WithCacheControl<Self> {
+        WithCacheControl::new(self, cache_control)
+    }
+
+    fn no_store(self) -> WithCacheControl<Self> {
+        self.cache_control(CacheControl::new().no_store())
+    }
+
+    /// Caches the response for a year without revalidating, for assets with
+    /// a fingerprint in their URL.
+    fn immutable(self) -> WithCacheControl<Self> {
+        let max_age = Duration::from_secs(365 * 24 * 60 * 60);
+        self.cache_control(CacheControl::new().public().max_age(max_age).immutable())
+    }
+
+    /// Sets Content-Disposition so the response is downloaded as `filename`.
+    /// The Content-Type is guessed from the extension if it wasn't set.
+    fn attachment(self, filename: impl Into<String>) -> WithDisposition<Self> {
+        WithDisposition::new(self, "attachment", filename.into())
+    }
+
+    /// Like `attachment`, but asks the browser to display the response.
+    fn inline(self, filename: impl Into<String>) -> WithDisposition<Self> {
+        WithDisposition::new(self, "inline", filename.into())
+    }
+
     fn header<K, V>(self, name: K, value: V) -> WithHeader<Self>
     where
         HeaderName: TryFrom<K, Error = InvalidHeaderName>,
@@ -90,9 +133,36 @@ impl Response {
         }
     }
 
+    /// Responds with the contents of `reader`, read in chunks of up to
+    /// `chunk_size` bytes as the client reads the body. The body is sent
+    /// chunked unless a Content-Length header is added.
+    ///
+    /// The body ends when the reader reaches EOF, even if that is before the
+    /// Content-Length. A read error aborts the response.
+    pub fn from_reader<R>(reader: R, chunk_size: usize) -> Response
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        Response::new(Body::reader(reader, chunk_size))
+    }
+
     pub fn status_code(&self) -> StatusCode {
         self.value.status()
     }
+
+    /// Drops the body without polling it, as in a response to a HEAD
+    /// request. A Content-Length is kept, or added if the body has a known
+    /// length.
+    pub(crate) fn discard_body(&mut self) {
+        let body = std::mem::take(self.body_mut());
+        let headers = self.headers_mut();
+
+        if let Some(len) = body.size_hint().exact() {
+            headers
+                .entry(CONTENT_LENGTH)
+                .or_insert(HeaderValue::from(len));
+        }
+    }
 }
 
 impl Respond for Response {
@@ -101,6 +171,12 @@ impl Respond for Response {
     }
 }
 
+impl From<http::Response<Body>> for Response {
+    fn from(value: http::Response<Body>) -> Self {
+        Response { value }
+    }
+}
+
 impl From<Response> for http::Response<Body> {
     fn from(response: Response) -> Self {
         response.value
@@ -188,3 +264,117 @@ impl Respond for serde_json::Value {
         json(&self).respond()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::{json_stream, File};
+    use crate::{Application, Context, Next, Respond};
+
+    fn app(polled: &Arc<AtomicBool>) -> Application {
+        let mut app = crate::new();
+        let polled = Arc::clone(polled);
+
+        app.at("/buffered")
+            .get(|_: Context, _: Next| async { "hello" });
+        app.at("/stream").get(move |_: Context, _: Next| {
+            let polled = Arc::clone(&polled);
+            let items = stream::poll_fn(move |_| {
+                polled.store(true, Ordering::SeqCst);
+                std::task::Poll::Ready(Some(Ok::<_, Infallible>(1)))
+            });
+
+            async { json_stream(items).respond() }
+        });
+        app.at("/file")
+            .get(|context: Context, _: Next| async move { File::serve(&context, file!()).await });
+        app.at("/explicit")
+            .head(|_: Context, _: Next| async { "".header("x-handler", "head") });
+        app.at("/explicit")
+            .get(|_: Context, _: Next| async { "get" });
+        app.shutdown_signals(false);
+        app
+    }
+
+    async fn request(address: SocketAddr, method: &str, path: &str) -> String {
+        let request = format!(
+            "{} {} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+            method, path
+        );
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut output = String::new();
+
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream.read_to_string(&mut output).await.unwrap();
+        output
+    }
+
+    async fn start(app: Application) -> SocketAddr {
+        let server = app.bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr();
+
+        tokio::spawn(server.serve());
+        address
+    }
+
+    #[tokio::test]
+    async fn answers_head_with_get_handlers() {
+        let polled = Arc::new(AtomicBool::new(false));
+        let address = start(app(&polled)).await;
+
+        let output = request(address, "HEAD", "/buffered").await;
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.contains("\r\ncontent-length: 5\r\n"));
+        assert!(output.ends_with("\r\n\r\n"));
+        assert!(request(address, "GET", "/buffered")
+            .await
+            .ends_with("hello"));
+
+        // Streams are dropped without being polled, and have no length.
+        let output = request(address, "HEAD", "/stream").await;
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.contains("\r\ncontent-type: application/x-ndjson\r\n"));
+        assert!(!output.contains("content-length"));
+        assert!(!output.contains("transfer-encoding"));
+        assert!(output.ends_with("\r\n\r\n"));
+        assert!(!polled.load(Ordering::SeqCst));
+
+        let len = std::fs::metadata(file!()).unwrap().len();
+        let output = request(address, "HEAD", "/file").await;
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.contains(&format!("\r\ncontent-length: {}\r\n", len)));
+        assert!(output.ends_with("\r\n\r\n"));
+
+        let output = request(address, "HEAD", "/explicit").await;
+        assert!(output.contains("\r\nx-handler: head\r\n"));
+    }
+
+    #[tokio::test]
+    async fn auto_head_can_be_disabled() {
+        let mut app = app(&Default::default());
+
+        app.auto_head(false);
+
+        let address = start(app).await;
+
+        assert!(request(address, "HEAD", "/buffered")
+            .await
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(request(address, "HEAD", "/explicit")
+            .await
+            .contains("\r\nx-handler: head\r\n"));
+    }
+}
@@ -0,0 +1,77 @@
+use http::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use mime::Mime;
+
+use super::{Respond, Response};
+use crate::Result;
+
+/// A view that renders itself into the body of a response. Implement this to
+/// use a template engine with `render`.
+pub trait Render {
+    fn render(&self) -> Result<(Mime, Vec<u8>)>;
+}
+
+struct Rendered(Result<(Mime, Vec<u8>)>);
+
+/// Responds with the output of `view`, using the returned media type as the
+/// Content-Type. An error rendering the view is returned as a 500 Internal
+/// Server Error.
+pub fn render(view: &impl Render) -> impl Respond {
+    Rendered(view.render())
+}
+
+impl Respond for Rendered {
+    fn respond(self) -> Result<Response> {
+        let (mime, body) = self.0?;
+        let content_type = HeaderValue::try_from(mime.as_ref())?;
+        let content_length = HeaderValue::from(body.len());
+        let mut response = Response::new(body);
+        let headers = response.headers_mut();
+
+        headers.insert(CONTENT_TYPE, content_type);
+        headers.insert(CONTENT_LENGTH, content_length);
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mime::Mime;
+
+    use super::{render, Render};
+    use crate::{error::Bail, Error, Respond, Result};
+
+    struct Greeting(&'static str);
+
+    impl Render for Greeting {
+        fn render(&self) -> Result<(Mime, Vec<u8>)> {
+            if self.0.is_empty() {
+                return Err(Error::from(Bail::new("missing name")));
+            }
+
+            let html = format!("<h1>Hello, {}!</h1>", self.0);
+            Ok((mime::TEXT_HTML_UTF_8, html.into_bytes()))
+        }
+    }
+
+    #[test]
+    fn sets_the_content_type_and_length() {
+        let response = http::Response::from(render(&Greeting("world")).respond().unwrap());
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(response.headers()["content-length"], "22");
+    }
+
+    #[test]
+    fn fails_with_a_server_error() {
+        let response = match render(&Greeting("")).respond() {
+            Ok(_) => panic!("expected the view to fail"),
+            Err(error) => http::Response::from(crate::Response::from(error)),
+        };
+
+        assert_eq!(response.status(), 500);
+    }
+}
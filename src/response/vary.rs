@@ -0,0 +1,100 @@
+use http::header::{HeaderName, HeaderValue, VARY};
+
+use super::Response;
+
+impl Response {
+    /// Adds `name` to the Vary header of the response. Names that are already
+    /// listed aren't repeated, and a Vary of `*` is left as is since it
+    /// already covers every header.
+    pub fn add_vary(&mut self, name: HeaderName) {
+        let headers = self.headers_mut();
+        let mut names: Vec<String> = Vec::new();
+
+        for value in headers.get_all(VARY).iter() {
+            let value = value.to_str().unwrap_or_default();
+
+            for existing in value.split(',').map(str::trim) {
+                let existing = existing.to_ascii_lowercase();
+
+                if !existing.is_empty() && !names.contains(&existing) {
+                    names.push(existing);
+                }
+            }
+        }
+
+        if !names.iter().any(|existing| existing == name.as_str()) {
+            names.push(name.as_str().to_owned());
+        }
+
+        let value = if names.iter().any(|existing| existing == "*") {
+            HeaderValue::from_static("*")
+        } else {
+            match HeaderValue::try_from(names.join(", ")) {
+                Ok(value) => value,
+                Err(_) => return,
+            }
+        };
+
+        headers.insert(VARY, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::{HeaderName, ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, VARY};
+
+    use crate::Response;
+
+    fn vary(existing: &[&'static str], names: &[HeaderName]) -> String {
+        let mut response = Response::new("");
+
+        for value in existing {
+            response.headers_mut().append(VARY, value.parse().unwrap());
+        }
+
+        for name in names {
+            response.add_vary(name.clone());
+        }
+
+        assert_eq!(response.headers().get_all(VARY).iter().count(), 1);
+        response.headers()[VARY].to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn adds_to_an_empty_vary() {
+        assert_eq!(vary(&[], &[ACCEPT_ENCODING]), "accept-encoding");
+        assert_eq!(
+            vary(&[], &[ACCEPT, ACCEPT_ENCODING]),
+            "accept, accept-encoding"
+        );
+    }
+
+    #[test]
+    fn merges_without_duplicates() {
+        assert_eq!(
+            vary(&["Accept-Encoding"], &[ACCEPT_ENCODING]),
+            "accept-encoding"
+        );
+        assert_eq!(
+            vary(&["Origin,  accept"], &[ACCEPT, ACCEPT_LANGUAGE, ACCEPT]),
+            "origin, accept, accept-language"
+        );
+        assert_eq!(
+            vary(&["accept", "Origin, ACCEPT"], &[ACCEPT_ENCODING]),
+            "accept, origin, accept-encoding"
+        );
+    }
+
+    #[test]
+    fn keeps_a_wildcard() {
+        assert_eq!(vary(&["*"], &[ACCEPT_ENCODING]), "*");
+        assert_eq!(vary(&["accept", "*"], &[ACCEPT]), "*");
+        assert_eq!(
+            vary(
+                &["accept"],
+                &[HeaderName::from_static("*"), ACCEPT_ENCODING]
+            ),
+            "*"
+        );
+    }
+}
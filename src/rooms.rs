@@ -0,0 +1,236 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{sync::mpsc::Sender, task::AbortHandle};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Presence<K> {
+    Join(K),
+    Leave(K),
+}
+
+pub struct Membership<K, M>
+where
+    K: Clone + Eq + Hash + Send + 'static,
+    M: Clone + Send + 'static,
+{
+    id: u64,
+    member: K,
+    /// Stops the task that removes the member when its connection closes.
+    /// The task holds a clone of the sender, so it has to be aborted for the
+    /// receiver to see the channel close after `leave`.
+    removal: AbortHandle,
+    room: String,
+    rooms: Rooms<K, M>,
+}
+
+pub struct Rooms<K, M> {
+    inner: Arc<Inner<K, M>>,
+}
+
+struct Inner<K, M> {
+    announce: Option<fn(Presence<K>) -> M>,
+    next_id: AtomicU64,
+    rooms: Mutex<HashMap<String, Room<K, M>>>,
+}
+
+struct Room<K, M> {
+    members: HashMap<K, Vec<(u64, Sender<M>)>>,
+}
+
+impl<K, M> Membership<K, M>
+where
+    K: Clone + Eq + Hash + Send + 'static,
+    M: Clone + Send + 'static,
+{
+    pub fn leave(self) {
+        self.removal.abort();
+        self.rooms.remove(&self.room, &self.member, self.id);
+    }
+
+    pub fn member(&self) -> &K {
+        &self.member
+    }
+
+    pub fn room(&self) -> &str {
+        &self.room
+    }
+}
+
+impl<K, M> Rooms<K, M>
+where
+    K: Clone + Eq + Hash + Send + 'static,
+    M: Clone + Send + 'static,
+{
+    pub fn new() -> Self {
+        Rooms {
+            inner: Arc::new(Inner {
+                announce: None,
+                next_id: AtomicU64::new(0),
+                rooms: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn announce(announce: fn(Presence<K>) -> M) -> Self {
+        Rooms {
+            inner: Arc::new(Inner {
+                announce: Some(announce),
+                next_id: AtomicU64::new(0),
+                rooms: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn broadcast(&self, room: &str, message: M) {
+        if let Some(room) = self.inner.rooms.lock().unwrap().get(room) {
+            room.send(message);
+        }
+    }
+
+    /// Presence is reference counted per member, so a member that joins from
+    /// several connections stays in the room until the last one leaves.
+    pub fn join(&self, room: &str, member: K, sender: Sender<M>) -> Membership<K, M> {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let closed = sender.clone();
+
+        {
+            let mut rooms = self.inner.rooms.lock().unwrap();
+            let entry = rooms.entry(room.to_owned()).or_insert_with(|| Room {
+                members: HashMap::new(),
+            });
+            let connections = entry.members.entry(member.clone()).or_default();
+
+            connections.push((id, sender));
+
+            if let (1, Some(announce)) = (connections.len(), self.inner.announce) {
+                entry.send(announce(Presence::Join(member.clone())));
+            }
+        }
+
+        let rooms = self.clone();
+        let removal = {
+            let member = member.clone();
+            let room = room.to_owned();
+
+            tokio::spawn(async move {
+                closed.closed().await;
+                rooms.remove(&room, &member, id);
+            })
+        };
+
+        Membership {
+            id,
+            member,
+            removal: removal.abort_handle(),
+            room: room.to_owned(),
+            rooms: self.clone(),
+        }
+    }
+
+    pub fn members(&self, room: &str) -> Vec<K> {
+        match self.inner.rooms.lock().unwrap().get(room) {
+            Some(room) => room.members.keys().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn remove(&self, room: &str, member: &K, id: u64) {
+        let mut rooms = self.inner.rooms.lock().unwrap();
+        let entry = match rooms.get_mut(room) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let connections = match entry.members.get_mut(member) {
+            Some(connections) => connections,
+            None => return,
+        };
+
+        connections.retain(|(other, _)| *other != id);
+
+        if !connections.is_empty() {
+            return;
+        }
+
+        entry.members.remove(member);
+
+        if let Some(announce) = self.inner.announce {
+            entry.send(announce(Presence::Leave(member.clone())));
+        }
+
+        if entry.members.is_empty() {
+            rooms.remove(room);
+        }
+    }
+}
+
+impl<K, M> Clone for Rooms<K, M> {
+    fn clone(&self) -> Self {
+        Rooms {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<K, M> Default for Rooms<K, M>
+where
+    K: Clone + Eq + Hash + Send + 'static,
+    M: Clone + Send + 'static,
+{
+    fn default() -> Self {
+        Rooms::new()
+    }
+}
+
+impl<K, M: Clone> Room<K, M> {
+    fn send(&self, message: M) {
+        for (_, sender) in self.members.values().flatten() {
+            let _ = sender.try_send(message.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Presence, Rooms};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn presence_is_reference_counted() {
+        let rooms = Rooms::announce(|presence| presence);
+        let (observer, mut events) = mpsc::channel(8);
+        let (first, _first) = mpsc::channel(8);
+        let (second, second_rx) = mpsc::channel(8);
+
+        rooms.join("lobby", "observer", observer);
+        let tab = rooms.join("lobby", "alice", first);
+        rooms.join("lobby", "alice", second);
+
+        assert_eq!(events.recv().await, Some(Presence::Join("observer")));
+        assert_eq!(events.recv().await, Some(Presence::Join("alice")));
+
+        tab.leave();
+        assert_eq!(rooms.members("lobby").len(), 2);
+
+        drop(second_rx);
+        assert_eq!(events.recv().await, Some(Presence::Leave("alice")));
+        assert_eq!(rooms.members("lobby"), vec!["observer"]);
+    }
+
+    #[tokio::test]
+    async fn leave_closes_the_channel() {
+        let rooms = Rooms::<&str, u8>::new();
+        let (sender, mut receiver) = mpsc::channel(8);
+
+        rooms.join("lobby", "alice", sender).leave();
+
+        let closed = tokio::time::timeout(std::time::Duration::from_secs(1), receiver.recv());
+        assert_eq!(closed.await, Ok(None));
+        assert!(rooms.members("lobby").is_empty());
+    }
+}
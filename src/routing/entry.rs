@@ -0,0 +1,107 @@
+use router::{Pattern, Segment, Verb};
+use std::fmt::{self, Display, Formatter};
+
+use super::Route;
+
+const VERBS: [(Verb, &str); 9] = [
+    (Verb::CONNECT, "CONNECT"),
+    (Verb::DELETE, "DELETE"),
+    (Verb::GET, "GET"),
+    (Verb::HEAD, "HEAD"),
+    (Verb::OPTIONS, "OPTIONS"),
+    (Verb::PATCH, "PATCH"),
+    (Verb::POST, "POST"),
+    (Verb::PUT, "PUT"),
+    (Verb::TRACE, "TRACE"),
+];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RouteEntry {
+    conflicts: Vec<&'static str>,
+    methods: Vec<&'static str>,
+    params: Vec<&'static str>,
+    pattern: String,
+}
+
+fn names(verbs: Verb) -> Vec<&'static str> {
+    VERBS
+        .iter()
+        .filter(|(verb, _)| verbs.intersects(*verb))
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+impl RouteEntry {
+    pub(crate) fn new(segments: &[Segment], route: &Route) -> Self {
+        RouteEntry {
+            conflicts: names(route.conflicts),
+            methods: names(route.verbs),
+            params: segments
+                .iter()
+                .filter_map(|(pattern, _)| match pattern {
+                    Pattern::CatchAll(name) | Pattern::Dynamic(name) => Some(*name),
+                    _ => None,
+                })
+                .collect(),
+            pattern: super::path(segments),
+        }
+    }
+
+    /// Methods that were registered more than once for this pattern.
+    pub fn conflicts(&self) -> &[&'static str] {
+        &self.conflicts
+    }
+
+    pub fn methods(&self) -> &[&'static str] {
+        &self.methods
+    }
+
+    pub fn params(&self) -> &[&'static str] {
+        &self.params
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl Display for RouteEntry {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let methods = if self.methods.is_empty() {
+            "*".to_owned()
+        } else {
+            self.methods.join(",")
+        };
+
+        write!(f, "{:<16} {}", methods, self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Router;
+    use crate::{Context, Next};
+
+    async fn show(_: Context, _: Next) -> &'static str {
+        ""
+    }
+
+    #[test]
+    fn lists_routes_and_conflicts() {
+        let mut router = Router::default();
+
+        router.at("/users/:id").get(show);
+        router.at("/users/:id").delete(show);
+        router.at("/users/:id").get(show);
+        router.at("/files/*path").include(show);
+
+        let routes: Vec<_> = router.routes().collect();
+
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].to_string(), "*                /files/*path");
+        assert_eq!(routes[1].pattern(), "/users/:id");
+        assert_eq!(routes[1].params(), ["id"]);
+        assert_eq!(routes[1].methods(), ["DELETE", "GET"]);
+        assert_eq!(routes[1].conflicts(), ["GET"]);
+    }
+}
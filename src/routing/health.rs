@@ -0,0 +1,152 @@
+use futures::future::{Future, FutureExt};
+
+use super::Router;
+use crate::{BoxFuture, Respond, Result};
+
+type Check = Box<dyn Fn() -> BoxFuture<Result<()>> + Send + Sync>;
+
+/// Paths that are answered before rewrites, the router, and the middleware
+/// it would call, so that frequent probes from a load balancer stay cheap.
+#[derive(Default)]
+pub(crate) struct HealthChecks(Vec<(&'static str, Option<Check>)>);
+
+impl HealthChecks {
+    pub(crate) fn push(&mut self, path: &'static str) {
+        self.0.push((path, None));
+    }
+
+    pub(crate) fn push_with<F, T>(&mut self, path: &'static str, check: F)
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+        T: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.0
+            .push((path, Some(Box::new(move || Box::pin(check())))));
+    }
+
+    /// Returns a conflict for each route registered at a health check path.
+    pub(crate) fn conflicts(&self, router: &Router) -> Vec<String> {
+        router
+            .routes()
+            .filter(|route| self.0.iter().any(|(path, _)| *path == route.pattern()))
+            .map(|route| format!("{} (health check)", route.pattern()))
+            .collect()
+    }
+
+    pub(crate) fn respond(&self, path: &str) -> Option<BoxFuture<Result>> {
+        let (_, check) = self.0.iter().find(|(candidate, _)| *candidate == path)?;
+
+        Some(match check {
+            Some(check) => Box::pin(check().map(|result| match result {
+                Ok(()) => "OK".respond(),
+                Err(_) => "Service Unavailable".status(503).respond(),
+            })),
+            None => Box::pin(async { "OK".respond() }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::{error::Bail, Application, Context, Error, Next};
+
+    async fn start(app: Application) -> SocketAddr {
+        let server = app.bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr();
+
+        tokio::spawn(server.serve());
+        address
+    }
+
+    async fn get(address: SocketAddr, path: &str) -> String {
+        let request = format!(
+            "GET {} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+            path
+        );
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut output = String::new();
+
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream.read_to_string(&mut output).await.unwrap();
+        output
+    }
+
+    #[tokio::test]
+    async fn bypasses_the_middleware_stack() {
+        let mut app = crate::new();
+
+        app.include(|_: Context, _: Next| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            "blocked"
+        });
+        app.health_check("/healthz").shutdown_signals(false);
+
+        let address = start(app).await;
+        let output = tokio::time::timeout(Duration::from_secs(1), get(address, "/healthz"));
+        let output = output.await.unwrap();
+
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\nOK"));
+    }
+
+    #[tokio::test]
+    async fn responds_with_the_readiness_of_the_check() {
+        let ready = Arc::new(AtomicBool::new(true));
+        let mut app = crate::new();
+        let check = Arc::clone(&ready);
+
+        app.health_check_with("/readyz", move || {
+            let ready = check.load(Ordering::Relaxed);
+
+            async move {
+                if !ready {
+                    return Err(Error::from(Bail::new("database is unreachable")));
+                }
+
+                Ok(())
+            }
+        });
+        app.shutdown_signals(false);
+
+        let address = start(app).await;
+
+        assert!(get(address, "/readyz")
+            .await
+            .starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(get(address, "/readyz/")
+            .await
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        ready.store(false, Ordering::Relaxed);
+        assert!(get(address, "/readyz")
+            .await
+            .starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    }
+
+    #[test]
+    fn rejects_routes_at_a_health_check_path() {
+        let mut app = crate::new();
+
+        app.health_check("/healthz");
+        app.at("/healthz")
+            .get(|_: Context, _: Next| async { "route" });
+
+        match app.bind("127.0.0.1:0") {
+            Ok(_) => panic!("expected the route to conflict"),
+            Err(error) => assert!(error.to_string().contains("/healthz (health check)")),
+        }
+    }
+}
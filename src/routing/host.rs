@@ -0,0 +1,161 @@
+use http::header::HOST;
+
+use super::{Location, Router};
+use crate::{Context, Next};
+
+#[derive(Default)]
+pub(crate) struct Hosts {
+    entries: Vec<(Host, Router)>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Host {
+    Exact(String),
+    Wildcard(String),
+}
+
+fn host(context: &Context) -> Option<String> {
+    let value = match context.headers().get(HOST) {
+        Some(value) => value.to_str().ok()?,
+        None => context.uri().host()?,
+    };
+    let host = match value.strip_prefix('[') {
+        Some(rest) => &value[..rest.find(']')? + 2],
+        None => value.split(':').next()?,
+    };
+
+    Some(host.trim_end_matches('.').to_ascii_lowercase())
+}
+
+impl Host {
+    fn parse(pattern: &str) -> Self {
+        let pattern = pattern.to_ascii_lowercase();
+
+        match pattern.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') => Host::Wildcard(suffix.to_owned()),
+            Some(_) => panic!(r#"invalid host pattern "{}""#, pattern),
+            None => Host::Exact(pattern),
+        }
+    }
+
+    fn subdomain<'a>(&self, host: &'a str) -> Option<Option<&'a str>> {
+        match self {
+            Host::Exact(name) => (name == host).then_some(None),
+            Host::Wildcard(suffix) => match host.strip_suffix(suffix.as_str()) {
+                Some(subdomain) if !subdomain.is_empty() => Some(Some(subdomain)),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl Hosts {
+    pub(crate) fn at(&mut self, pattern: &str) -> Location<'_> {
+        let host = Host::parse(pattern);
+        let index = match self.entries.iter().position(|(other, _)| *other == host) {
+            Some(index) => index,
+            None => {
+                // Exact hosts are kept ahead of wildcards so they take precedence.
+                let index = match host {
+                    Host::Exact(_) => self
+                        .entries
+                        .iter()
+                        .position(|(other, _)| matches!(other, Host::Wildcard(_)))
+                        .unwrap_or(self.entries.len()),
+                    Host::Wildcard(_) => self.entries.len(),
+                };
+
+                self.entries.insert(index, (host, Router::default()));
+                index
+            }
+        };
+
+        self.entries[index].1.at("/")
+    }
+
+    pub(crate) fn conflicts(&self) -> impl Iterator<Item = String> + '_ {
+        self.entries.iter().flat_map(|(host, router)| {
+            let host = match host {
+                Host::Exact(name) => name.clone(),
+                Host::Wildcard(suffix) => format!("*{}", suffix),
+            };
+
+            router
+                .conflicts()
+                .into_iter()
+                .map(move |conflict| format!("{}: {}", host, conflict))
+        })
+    }
+
+    pub(crate) fn visit(&self, global: &Router, context: &mut Context) -> Option<Next> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        let host = host(context)?;
+        let path = context.uri().path();
+        let (subdomain, router) = self.entries.iter().find_map(|(pattern, router)| {
+            let subdomain = pattern.subdomain(&host)?;
+            router.matches(path).then_some((subdomain, router))
+        })?;
+
+        if let Some(value) = subdomain {
+            let (parameters, ..) = context.locate();
+            parameters.insert("subdomain", value.to_owned());
+        }
+
+        Some(router.visit_with(context, &global.0.stack))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hosts, Router};
+    use crate::{middleware::context::Body, Context, Next};
+
+    async fn respond(_: Context, _: Next) -> &'static str {
+        ""
+    }
+
+    fn visit(hosts: &Hosts, router: &Router, host: &str, path: &str) -> Option<String> {
+        let request = http::Request::get(path).header("host", host);
+        let mut context = Context::from(request.body(Body::full("".into())).unwrap());
+
+        hosts.visit(router, &mut context)?;
+        context.route_pattern().map(str::to_owned)
+    }
+
+    #[test]
+    fn host_routes_take_precedence() {
+        let mut hosts = Hosts::default();
+        let mut router = Router::default();
+
+        hosts.at("*.example.com").at("/users").get(respond);
+        hosts.at("API.example.com").at("/users/:id").get(respond);
+        router.at("/users/:id").get(respond);
+
+        assert_eq!(
+            visit(&hosts, &router, "api.example.com:8080", "/users/1").as_deref(),
+            Some("/users/:id")
+        );
+        assert_eq!(
+            visit(&hosts, &router, "Tenant.Example.com", "/users").as_deref(),
+            Some("/users")
+        );
+        assert_eq!(visit(&hosts, &router, "www.example.com", "/users/1"), None);
+        assert_eq!(visit(&hosts, &router, "example.com", "/users"), None);
+        assert_eq!(visit(&hosts, &router, "other.test", "/users"), None);
+    }
+
+    #[test]
+    fn captures_subdomain() {
+        let mut hosts = Hosts::default();
+        let request = http::Request::get("/").header("host", "acme.example.com");
+        let mut context = Context::from(request.body(Body::full("".into())).unwrap());
+
+        hosts.at("*.example.com").get(respond);
+        hosts.visit(&Router::default(), &mut context).unwrap();
+
+        assert_eq!(context.params().get::<String>("subdomain").unwrap(), "acme");
+    }
+}
@@ -0,0 +1,126 @@
+use serde_json::{json, Value};
+use std::{fmt::Write, sync::Arc};
+
+use super::{RouteEntry, Router};
+use crate::{middleware::deprecation::Deprecated, response, Context, Next, Respond};
+
+struct Entry {
+    deprecated: bool,
+    middleware: Vec<&'static str>,
+    route: RouteEntry,
+}
+
+pub(crate) fn mount(router: &mut Router, path: &'static str) {
+    let entries = Arc::new(entries(router));
+
+    router.at(path).get(move |context: Context, _: Next| {
+        let entries = Arc::clone(&entries);
+
+        async move {
+            let accept = context.headers().get("accept");
+
+            if accept.is_some_and(|value| value.as_bytes().starts_with(b"application/json")) {
+                response::json(&render_json(&entries)).respond()
+            } else {
+                response::html(render_html(&entries)).respond()
+            }
+        }
+    });
+}
+
+fn entries(router: &Router) -> Vec<Entry> {
+    let mut entries: Vec<_> = router
+        .0
+        .routes()
+        .into_iter()
+        .filter(|(_, route)| !route.stack.is_empty())
+        .map(|(patterns, route)| Entry {
+            deprecated: route.meta.get::<Deprecated>().is_some(),
+            middleware: route.names.clone(),
+            route: RouteEntry::new(&patterns, route),
+        })
+        .collect();
+
+    entries.sort_by(|a, b| a.route.pattern().cmp(b.route.pattern()));
+    entries
+}
+
+fn escape(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_html(entries: &[Entry]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head><title>Routes</title></head>\n<body>\n<table>\n\
+         <tr><th>Path</th><th>Methods</th><th>Params</th><th>Middleware</th><th>Deprecated</th></tr>\n",
+    );
+
+    for entry in entries {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(entry.route.pattern()),
+            entry.route.methods().join(", "),
+            escape(&entry.route.params().join(", ")),
+            escape(&entry.middleware.join(", ")),
+            entry.deprecated,
+        );
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn render_json(entries: &[Entry]) -> Value {
+    Value::Array(
+        entries
+            .iter()
+            .map(|entry| {
+                json!({
+                    "deprecated": entry.deprecated,
+                    "methods": entry.route.methods(),
+                    "middleware": entry.middleware,
+                    "params": entry.route.params(),
+                    "path": entry.route.pattern(),
+                })
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entries, render_json, Router};
+    use crate::{middleware::deprecation::Deprecated, Context, Next};
+
+    async fn show(_: Context, _: Next) -> &'static str {
+        ""
+    }
+
+    #[test]
+    fn lists_routes_in_stable_order() {
+        let mut router = Router::default();
+
+        router.at("/users/:id").get(show);
+        router.at("/users/:id").patch(show);
+        router.at("/files/*path").get(show);
+        router.at("/v1/users").meta(Deprecated::new()).get(show);
+
+        let json = render_json(&entries(&router));
+        let paths: Vec<_> = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["path"].as_str().unwrap())
+            .collect();
+
+        assert_eq!(paths, ["/files/*path", "/users/:id", "/v1/users"]);
+        assert_eq!(json[1]["methods"], serde_json::json!(["GET", "PATCH"]));
+        assert_eq!(json[1]["params"], serde_json::json!(["id"]));
+        assert_eq!(json[2]["deprecated"], true);
+    }
+}
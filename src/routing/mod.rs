@@ -1,7 +1,25 @@
-use router::{Router as GenericRouter, Verb};
-use std::sync::Arc;
+mod entry;
 
-use crate::{middleware::DynMiddleware, Context, Middleware, Next};
+pub(crate) mod health;
+pub(crate) mod host;
+pub(crate) mod index;
+pub(crate) mod names;
+mod normalize;
+mod redirect;
+mod rewrite;
+
+use http::StatusCode;
+use router::{Pattern, Router as GenericRouter, Segment, Verb};
+use std::{any, fmt::Write, sync::Arc};
+
+pub use entry::RouteEntry;
+pub use normalize::NormalizePath;
+#[cfg(feature = "lru-cache")]
+pub use router::{CacheMonitor, CacheStats};
+
+use self::redirect::Redirect;
+pub(crate) use self::rewrite::{OriginalUri, Rewrites};
+use crate::{middleware::DynMiddleware, Context, Middleware, Next, Respond, Result};
 
 pub type Location<'a> = router::Location<'a, Route>;
 
@@ -16,9 +34,68 @@ pub trait Endpoint {
 #[derive(Default)]
 pub struct Router(GenericRouter<Route>);
 
+/// Inserted by the application into HEAD requests when GET handlers may
+/// answer them.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AutoHead;
+
+/// Inserted into HEAD requests to a route that registers GET but not HEAD,
+/// which lets its GET handlers run.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct HeadAsGet;
+
+#[derive(Clone, Debug)]
+pub(crate) struct RoutePattern(pub(crate) String);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TrailingSlash {
+    /// `/posts/` and `/posts` match the same route.
+    #[default]
+    Ignore,
+    /// Redirects `/posts/` to `/posts` with a 301, or a 308 for methods other
+    /// than GET and HEAD.
+    Redirect,
+    /// `/posts/` does not match `/posts`.
+    Strict,
+}
+
 #[derive(Default)]
 pub struct Route {
+    conflicts: Verb,
+    fallback: Option<DynMiddleware>,
+    meta: http::Extensions,
+    name: Option<&'static str>,
+    names: Vec<&'static str>,
+    otherwise: Option<DynMiddleware>,
     stack: Vec<DynMiddleware>,
+    verbs: Verb,
+}
+
+pub(crate) fn path(segments: &[Segment]) -> String {
+    let mut path = String::new();
+
+    for (pattern, constraint) in segments {
+        let _ = match (pattern, constraint) {
+            (Pattern::CatchAll(name), _) => write!(path, "/*{}", name),
+            (Pattern::Dynamic(name), Some(constraint)) => {
+                write!(path, "/:{}<{}>", name, constraint)
+            }
+            (Pattern::Dynamic(name), None) => write!(path, "/:{}", name),
+            (Pattern::Static(name), _) => write!(path, "/{}", name),
+            _ => Ok(()),
+        };
+    }
+
+    if path.is_empty() {
+        path.push('/');
+    }
+
+    path
+}
+
+fn is_endpoint(component: &router::Component<Route>) -> bool {
+    (component.is_exact_match || matches!(component.pattern, Pattern::CatchAll(_)))
+        && !component.stack.is_empty()
 }
 
 impl<'a> Endpoint for Location<'a> {
@@ -64,31 +141,222 @@ impl Route {
         self.handle(Verb::TRACE, middleware);
     }
 
-    pub fn handle(&mut self, verb: Verb, middleware: impl Middleware) {
-        self.include(move |context: Context, next: Next| {
-            if verb.intersects(context.method().into()) {
-                middleware.call(context, next)
-            } else {
-                next.call(context)
-            }
-        });
+    pub fn handle<T: Middleware>(&mut self, verb: Verb, middleware: T) {
+        self.conflicts = self.conflicts | (self.verbs & verb);
+        self.verbs = self.verbs | verb;
+        self.push(
+            any::type_name::<T>(),
+            move |context: Context, next: Next| {
+                let method = context.method().into();
+                let head_as_get = method == Verb::HEAD
+                    && verb.intersects(Verb::GET)
+                    && context.get::<HeadAsGet>().is_ok();
+
+                if head_as_get || verb.intersects(method) {
+                    middleware.call(context, next)
+                } else {
+                    next.call(context)
+                }
+            },
+        );
     }
 
-    pub fn include(&mut self, middleware: impl Middleware) -> &mut Self {
-        self.stack.push(Arc::new(middleware));
+    pub fn include<T: Middleware>(&mut self, middleware: T) -> &mut Self {
+        self.push(any::type_name::<T>(), middleware);
+        self
+    }
+
+    pub fn meta<T>(&mut self, value: T) -> &mut Self
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.meta.insert(value);
         self
     }
+
+    pub fn name(&mut self, name: &'static str) -> &mut Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Registers a handler that runs when no endpoint in this scope responds.
+    /// Calling `next` delegates to the fallback of the enclosing scope.
+    pub fn not_found(&mut self, middleware: impl Middleware) -> &mut Self {
+        self.fallback = Some(Arc::new(middleware));
+        self
+    }
+
+    /// Registers a handler for requests with a method that isn't registered
+    /// on this route, such as one that responds with 405 Method Not Allowed.
+    /// It runs after the rest of the route's middleware. Without one, those
+    /// requests fall through to the `not_found` handler.
+    pub fn otherwise(&mut self, middleware: impl Middleware) -> &mut Self {
+        self.otherwise = Some(Arc::new(middleware));
+        self
+    }
+
+    /// Responds to GET and HEAD requests with a redirect to `target`. Params
+    /// such as `:id` in `target` are replaced with the matching value from the
+    /// request path. The query string is forwarded to relative targets.
+    pub fn redirect(&mut self, status: StatusCode, target: &'static str) -> &mut Self {
+        self.handle(Verb::GET | Verb::HEAD, Redirect::new(status, target));
+        self
+    }
+
+    fn merge(&mut self, other: Route) {
+        self.conflicts = self.conflicts | other.conflicts | (self.verbs & other.verbs);
+        self.fallback = self.fallback.take().or(other.fallback);
+        self.meta.extend(other.meta);
+        self.name = self.name.or(other.name);
+        self.names.extend(other.names);
+        self.otherwise = self.otherwise.take().or(other.otherwise);
+        self.stack.extend(other.stack);
+        self.verbs = self.verbs | other.verbs;
+    }
+
+    fn push(&mut self, name: &'static str, middleware: impl Middleware) {
+        self.names.push(name);
+        self.stack.push(Arc::new(middleware));
+    }
+}
+
+impl TrailingSlash {
+    pub(crate) fn apply(self, context: &Context) -> Option<Result> {
+        let path = context.uri().path();
+        let pattern = context.route_pattern()?;
+
+        // Wildcards receive the path as-is since the slash may be meaningful.
+        if self == TrailingSlash::Ignore
+            || path == "/"
+            || !path.ends_with('/')
+            || pattern.rsplit('/').next()?.starts_with('*')
+        {
+            return None;
+        }
+
+        if self == TrailingSlash::Strict {
+            return Some("Not Found".status(404).respond());
+        }
+
+        // Rebuild the location from the non-empty segments so a path like
+        // `//evil.com/` can't become a protocol-relative redirect.
+        let mut location = String::with_capacity(path.len());
+        let status = match *context.method() {
+            http::Method::GET | http::Method::HEAD => 301,
+            _ => 308,
+        };
+
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            location.push('/');
+            location.push_str(segment);
+        }
+
+        if location.is_empty() {
+            location.push('/');
+        }
+
+        if let Some(query) = context.uri().query() {
+            location.push('?');
+            location.push_str(query);
+        }
+
+        Some("".status(status).header("location", location).respond())
+    }
 }
 
 impl Router {
-    pub fn at(&mut self, pattern: &'static str) -> Location {
+    pub fn at(&mut self, pattern: &'static str) -> Location<'_> {
         self.0.at(pattern)
     }
 
+    pub fn mount(&mut self, prefix: &'static str, router: Router) {
+        self.at(prefix).mount(router.0, Route::merge);
+    }
+
+    /// Describes every route that is shadowed by a sibling or that registers
+    /// the same method more than once.
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        let routes = self.0.routes();
+
+        for (shadowed, by) in self.0.shadowed() {
+            let reachable = routes.iter().any(|(patterns, route)| {
+                patterns.starts_with(&shadowed) && !route.stack.is_empty()
+            });
+
+            if reachable {
+                conflicts.push(format!(
+                    "{} is shadowed by {}",
+                    self::path(&shadowed),
+                    self::path(&by)
+                ));
+            }
+        }
+
+        for entry in self.routes() {
+            if !entry.conflicts().is_empty() {
+                conflicts.push(format!(
+                    "{} registers {} more than once",
+                    entry.pattern(),
+                    entry.conflicts().join(", ")
+                ));
+            }
+        }
+
+        conflicts
+    }
+
+    #[cfg(feature = "lru-cache")]
+    pub fn cache_monitor(&self) -> CacheMonitor {
+        self.0.cache_monitor()
+    }
+
+    #[cfg(feature = "lru-cache")]
+    pub fn cache_stats(&self) -> CacheStats {
+        self.0.cache_stats()
+    }
+
+    #[cfg(feature = "lru-cache")]
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.0.set_cache_capacity(capacity);
+    }
+
+    pub fn routes(&self) -> impl Iterator<Item = RouteEntry> {
+        let mut entries: Vec<_> = self
+            .0
+            .routes()
+            .into_iter()
+            .filter(|(_, route)| !route.stack.is_empty())
+            .map(|(patterns, route)| RouteEntry::new(&patterns, route))
+            .collect();
+
+        entries.sort_by(|a, b| a.pattern().cmp(b.pattern()));
+        entries.into_iter()
+    }
+
     pub fn visit(&self, context: &mut Context) -> Next {
+        self.visit_with(context, &[])
+    }
+
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        self.0
+            .visit(path)
+            .last()
+            .is_some_and(|route| is_endpoint(&route))
+    }
+
+    pub(crate) fn visit_with(&self, context: &mut Context, prefix: &[DynMiddleware]) -> Next {
+        let verb = context.method().into();
+        let auto_head = verb == Verb::HEAD && context.get::<AutoHead>().is_ok();
         let (parameters, _, path) = context.locate();
+        let mut meta = Vec::new();
+        let mut patterns = Vec::new();
+        let mut endpoint = false;
+        let mut head_as_get = false;
+        let mut fallbacks = Vec::new();
+        let mut stack: Vec<_> = prefix.iter().collect();
 
-        Next::new(self.0.visit(path).flat_map(|route| {
+        stack.extend(self.0.visit(path).flat_map(|route| {
             match route.param {
                 Some(("", _)) | Some((_, "")) | None => {}
                 Some((name, value)) => {
@@ -96,7 +364,317 @@ impl Router {
                 }
             }
 
-            route.stack.iter()
-        }))
+            endpoint = is_endpoint(&route);
+            head_as_get = endpoint
+                && auto_head
+                && route.verbs.intersects(Verb::GET)
+                && !route.verbs.intersects(Verb::HEAD);
+            fallbacks.extend(&route.fallback);
+            patterns.push((route.pattern, route.constraint));
+            meta.push(&route.meta);
+
+            let otherwise = route
+                .otherwise
+                .as_ref()
+                .filter(|_| endpoint && !head_as_get && !route.verbs.intersects(verb));
+
+            route.stack.iter().chain(otherwise)
+        }));
+        stack.extend(fallbacks.into_iter().rev());
+
+        let next = Next::new(stack.into_iter());
+
+        for extensions in meta {
+            context.extend(extensions.clone());
+        }
+
+        if endpoint {
+            context.insert(RoutePattern(self::path(&patterns)));
+        }
+
+        if head_as_get {
+            context.insert(HeadAsGet);
+        }
+
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::{Router, TrailingSlash};
+    use crate::{middleware::context::Body, response, Context, Middleware, Next, Respond};
+
+    async fn show(context: Context, _: Next) -> String {
+        context.params().get::<String>("id").unwrap_or_default()
+    }
+
+    fn tag(name: &'static str) -> impl Middleware {
+        move |context: Context, next: Next| async move {
+            let mut response = next.call(context).await?;
+
+            response
+                .headers_mut()
+                .append("x-order", name.parse().unwrap());
+            Ok::<_, crate::Error>(response)
+        }
+    }
+
+    fn redirect(router: &Router, method: &str, uri: &str) -> Option<(u16, String)> {
+        let request = http::Request::builder().method(method).uri(uri);
+        let mut context = Context::from(request.body(Body::full("".into())).unwrap());
+
+        router.visit(&mut context);
+
+        let response = TrailingSlash::Redirect.apply(&context)?.unwrap();
+        let response = http::Response::from(response);
+        let location = response.headers().get("location").unwrap();
+
+        Some((
+            response.status().as_u16(),
+            location.to_str().unwrap().to_owned(),
+        ))
+    }
+
+    async fn call(router: &Router, path: &str) -> http::Response<crate::response::Body> {
+        let request = http::Request::get(path).body(Body::full("".into()));
+        let mut context = Context::from(request.unwrap());
+        let next = router.visit(&mut context);
+
+        next.call(context).await.unwrap_or_else(Into::into).into()
+    }
+
+    fn route_pattern(router: &Router, path: &str) -> Option<String> {
+        let request = http::Request::get(path).body(Body::full("".into()));
+        let mut context = Context::from(request.unwrap());
+
+        router.visit(&mut context);
+        context.route_pattern().map(str::to_owned)
+    }
+
+    #[test]
+    fn records_matched_pattern() {
+        let mut router = Router::default();
+
+        router.at("/api/threads/:thread-id/messages").get(show);
+        router.at("/files/*path").get(show);
+        router.at("/users/:id<u64>").get(show);
+        router.at("/api/threads").include(show);
+
+        assert_eq!(
+            route_pattern(&router, "/api/threads/1/messages").as_deref(),
+            Some("/api/threads/:thread-id/messages")
+        );
+        assert_eq!(
+            route_pattern(&router, "/files/a/b.txt").as_deref(),
+            Some("/files/*path")
+        );
+        assert_eq!(
+            route_pattern(&router, "/users/42").as_deref(),
+            Some("/users/:id<u64>")
+        );
+        assert_eq!(route_pattern(&router, "/api/threads/1").as_deref(), None);
+        assert_eq!(route_pattern(&router, "/missing").as_deref(), None);
+    }
+
+    #[tokio::test]
+    async fn mount_preserves_middleware_order() {
+        let mut admin = Router::default();
+        let mut router = Router::default();
+
+        admin.at("/").include(tag("child"));
+        admin.at("/users/:id").get(show);
+        admin.at("/*path").get(tag("admin-fallback"));
+        router.at("/").include(tag("parent"));
+        router.at("/users/:id").get(tag("public"));
+        router.mount("/admin", admin);
+
+        let response = call(&router, "/admin/users/7").await;
+        let order: Vec<_> = response.headers().get_all("x-order").iter().collect();
+
+        assert_eq!(order, ["child", "parent"]);
+        assert_eq!(response.status(), 200);
+
+        let response = call(&router, "/other").await;
+        let order: Vec<_> = response.headers().get_all("x-order").iter().collect();
+
+        assert_eq!(order, ["parent"]);
+        assert_eq!(response.status(), 404);
+    }
+
+    #[test]
+    fn trailing_slash_redirects() {
+        let mut router = Router::default();
+
+        router.at("/posts").get(show);
+        router.at("/posts").post(show);
+        router.at("/files/*path").get(show);
+        router.at("/:slug").get(show);
+
+        assert_eq!(
+            redirect(&router, "GET", "//evil.com/"),
+            Some((301, "/evil.com".to_owned()))
+        );
+        assert_eq!(
+            redirect(&router, "GET", "/posts/?page=2"),
+            Some((301, "/posts?page=2".to_owned()))
+        );
+        assert_eq!(
+            redirect(&router, "POST", "/posts/"),
+            Some((308, "/posts".to_owned()))
+        );
+        assert_eq!(redirect(&router, "GET", "/posts"), None);
+        assert_eq!(redirect(&router, "GET", "/files/docs/"), None);
+        assert_eq!(redirect(&router, "GET", "/missing/route/"), None);
+    }
+
+    #[tokio::test]
+    async fn nested_not_found() {
+        let mut router = Router::default();
+
+        router.at("/").not_found(|_: Context, _: Next| async {
+            response::html("<h1>Not Found</h1>").status(404)
+        });
+        router.at("/api").not_found(|_: Context, _: Next| async {
+            response::json(&serde_json::json!({ "error": "not found" })).status(404)
+        });
+        router.at("/api/users/:id").get(show);
+
+        let api = call(&router, "/api/posts").await;
+        let html = call(&router, "/posts").await;
+
+        assert_eq!(api.status(), 404);
+        assert_eq!(api.headers()["content-type"], "application/json");
+        assert_eq!(html.status(), 404);
+        assert_eq!(html.headers()["content-type"], "text/html; charset=utf-8");
+        assert_eq!(call(&router, "/api/users/1").await.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn otherwise_handles_unregistered_methods() {
+        let mut admin = Router::default();
+        let mut router = Router::default();
+        let status = |router: &Router, method: &str, path: &str| {
+            let request = http::Request::builder().method(method).uri(path);
+            let mut context = Context::from(request.body(Body::full("".into())).unwrap());
+            let next = router.visit(&mut context);
+
+            async move {
+                let response = next.call(context).await.unwrap_or_else(Into::into);
+                let response = http::Response::from(response);
+                let headers = response.headers();
+                let header = |name| Some(headers.get(name)?.to_str().ok()?.to_owned());
+
+                (
+                    response.status().as_u16(),
+                    header("x-order"),
+                    header("allow"),
+                )
+            }
+        };
+        let not_allowed = |allow: &'static str| {
+            move |_: Context, _: Next| async move {
+                "Method Not Allowed".status(405).header("allow", allow)
+            }
+        };
+
+        router.at("/posts").include(tag("posts")).get(show);
+        router.at("/posts").post(show);
+        router.at("/posts").otherwise(not_allowed("GET, POST"));
+        router.at("/admin").otherwise(not_allowed("GET")).get(show);
+        admin.at("/").otherwise(not_allowed("DELETE")).delete(show);
+        router.mount("/admin", admin);
+
+        assert_eq!(status(&router, "GET", "/posts").await.0, 200);
+        assert_eq!(status(&router, "POST", "/posts").await.0, 200);
+        assert_eq!(status(&router, "PUT", "/posts/1").await.0, 404);
+
+        // The route's other middleware runs first.
+        let (status_code, order, allow) = status(&router, "PUT", "/posts").await;
+
+        assert_eq!(status_code, 405);
+        assert_eq!(order.as_deref(), Some("posts"));
+        assert_eq!(allow.as_deref(), Some("GET, POST"));
+
+        // Mounted routes combine methods, and the first otherwise is kept.
+        let entry = router.routes().find(|entry| entry.pattern() == "/admin");
+
+        assert_eq!(entry.unwrap().methods(), ["DELETE", "GET"]);
+        assert_eq!(status(&router, "DELETE", "/admin").await.0, 200);
+        assert_eq!(
+            status(&router, "PATCH", "/admin").await.2.as_deref(),
+            Some("GET")
+        );
+    }
+
+    #[tokio::test]
+    async fn shares_an_arc_between_routes() {
+        struct Counter(AtomicUsize);
+
+        impl Middleware for Counter {
+            fn call(&self, _: Context, _: Next) -> crate::BoxFuture<crate::Result> {
+                let count = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+                Box::pin(async move { count.to_string().respond() })
+            }
+        }
+
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let mut router = Router::default();
+
+        router.at("/a").get(Arc::clone(&counter));
+        router.at("/b").get(Arc::clone(&counter));
+
+        for path in ["/a", "/b", "/a"] {
+            call(&router, path).await;
+        }
+
+        assert_eq!(counter.0.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn reports_every_conflict() {
+        let mut router = Router::default();
+
+        router.at("/users/new").get(show);
+        router.at("/users/:id").get(show);
+        router.at("/users/:slug/posts").get(show);
+        router.at("/users/*rest").post(show);
+        router.at("/users/*rest").post(show);
+        router.at("/teams/:id").get(show);
+        router.at("/teams/:name");
+
+        assert_eq!(
+            router.conflicts(),
+            [
+                "/users/:slug is shadowed by /users/:id",
+                "/users/*rest is shadowed by /users/:id",
+                "/users/*rest registers POST more than once",
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_params_after_matching() {
+        let mut router = Router::default();
+        let request = http::Request::get("/files/a%2Fb%FF").body(Body::full("".into()));
+        let mut context = Context::from(request.unwrap());
+
+        router.at("/files/:name").get(show);
+        router.visit(&mut context);
+
+        let params = context.params();
+        let error = params.decode("name").unwrap_err();
+
+        assert_eq!(context.route_pattern(), Some("/files/:name"));
+        assert_eq!(params.get::<String>("name").unwrap(), "a%2Fb%FF");
+        assert_eq!(params.decode_lossy("name").unwrap(), "a/b\u{fffd}");
+        assert_eq!(&*params.decode_bytes("name").unwrap(), b"a/b\xff");
+        assert_eq!(response::Response::from(error).status_code(), 400);
+        assert_eq!(route_pattern(&router, "/files/a/b"), None);
     }
 }
@@ -0,0 +1,144 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use router::Pattern;
+use std::collections::HashMap;
+
+use super::Router;
+use crate::{Error, Result};
+
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+#[derive(Debug, Default)]
+pub(crate) struct Names {
+    entries: HashMap<&'static str, Vec<Pattern>>,
+}
+
+fn param<'a>(params: &[(&str, &'a str)], name: &str) -> Result<&'a str> {
+    match params.iter().find(|(key, _)| *key == name) {
+        Some((_, value)) => Ok(value),
+        None => crate::bail!(r#"missing parameter "{}""#, name),
+    }
+}
+
+impl Names {
+    pub(crate) fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String> {
+        let patterns = match self.entries.get(name) {
+            Some(patterns) => patterns,
+            None => crate::bail!(r#"unknown route "{}""#, name),
+        };
+        let mut path = String::new();
+
+        for pattern in patterns {
+            match pattern {
+                Pattern::CatchAll(name) => {
+                    for segment in param(params, name)?.split('/') {
+                        path.push('/');
+                        path.extend(utf8_percent_encode(segment, SEGMENT));
+                    }
+                }
+                Pattern::Dynamic(name) => {
+                    path.push('/');
+                    path.extend(utf8_percent_encode(param(params, name)?, SEGMENT));
+                }
+                Pattern::Static(value) => {
+                    path.push('/');
+                    path.push_str(value);
+                }
+                _ => {}
+            }
+        }
+
+        if path.is_empty() {
+            path.push('/');
+        }
+
+        Ok(path)
+    }
+}
+
+impl<'a> TryFrom<&'a Router> for Names {
+    type Error = Error;
+
+    /// Fails if a name is given to more than one route.
+    fn try_from(router: &'a Router) -> Result<Self> {
+        let mut duplicates = Vec::new();
+        let mut entries = HashMap::new();
+
+        for (patterns, route) in router.0.routes() {
+            if let Some(name) = route.name {
+                let patterns = patterns.into_iter().map(|(pattern, _)| pattern).collect();
+
+                if entries.insert(name, patterns).is_some() && !duplicates.contains(&name) {
+                    duplicates.push(name);
+                }
+            }
+        }
+
+        if !duplicates.is_empty() {
+            crate::bail!(
+                "route names registered more than once:\n  {}",
+                duplicates.join("\n  ")
+            );
+        }
+
+        Ok(Names { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Names, Router};
+
+    fn names() -> Names {
+        let mut router = Router::default();
+        let mut api = router.at("/api");
+
+        api.at("/threads/:thread-id/messages")
+            .name("thread.messages");
+        api.at("/files/*path").name("files");
+        router.at("/").name("root");
+
+        Names::try_from(&router).unwrap()
+    }
+
+    #[test]
+    fn url_for_nested_and_wildcard_routes() {
+        let names = names();
+
+        assert_eq!(
+            names
+                .url_for("thread.messages", &[("thread-id", "a b/c")])
+                .unwrap(),
+            "/api/threads/a%20b%2Fc/messages"
+        );
+        assert_eq!(
+            names
+                .url_for("files", &[("path", "docs/read me.md")])
+                .unwrap(),
+            "/api/files/docs/read%20me.md"
+        );
+        assert_eq!(names.url_for("root", &[]).unwrap(), "/");
+        assert!(names.url_for("thread.messages", &[]).is_err());
+        assert!(names.url_for("missing", &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let mut router = Router::default();
+
+        router.at("/a").name("files");
+        router.at("/b").name("files");
+        router.at("/c").name("files");
+        router.at("/d").name("root");
+
+        let error = Names::try_from(&router).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "route names registered more than once:\n  files"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use http::Uri;
+
+use crate::{error::Bail, Context, Error, Respond, Result};
+
+/// How request paths are normalized before they are routed. Repeated slashes
+/// are collapsed and `.` and `..` segments, including percent-encoded ones,
+/// are resolved without escaping the root. Paths that contain an encoded NUL
+/// are rejected with 400 Bad Request.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum NormalizePath {
+    /// Paths are routed as they are received.
+    #[default]
+    Disabled,
+    /// Routes the normalized path. The path that was received is available
+    /// with `Context::original_uri`.
+    Rewrite,
+    /// Redirects to the normalized path with a 308, which preserves the
+    /// method and body of the request.
+    Redirect,
+}
+
+fn is_dot(segment: &str) -> bool {
+    segment == "." || segment.eq_ignore_ascii_case("%2e")
+}
+
+fn is_dot_dot(segment: &str) -> bool {
+    ["..", ".%2e", "%2e.", "%2e%2e"]
+        .iter()
+        .any(|dots| segment.eq_ignore_ascii_case(dots))
+}
+
+/// Returns the normalized form of `path`, or `None` if it contains an
+/// encoded NUL.
+fn normalize(path: &str) -> Option<String> {
+    if path.contains("%00") {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    let mut trailing_slash = false;
+
+    for segment in path.split('/') {
+        // A path that ends with an empty or dot segment refers to a
+        // directory, so it keeps its trailing slash.
+        trailing_slash = true;
+
+        if is_dot_dot(segment) {
+            segments.pop();
+        } else if !segment.is_empty() && !is_dot(segment) {
+            segments.push(segment);
+            trailing_slash = false;
+        }
+    }
+
+    let mut normalized = String::with_capacity(path.len());
+
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+
+    if trailing_slash || normalized.is_empty() {
+        normalized.push('/');
+    }
+
+    Some(normalized)
+}
+
+impl NormalizePath {
+    /// Normalizes the path of the request. Returns a response if the request
+    /// is redirected or rejected instead.
+    pub(crate) fn apply(self, context: &mut Context) -> Option<Result> {
+        if self == NormalizePath::Disabled {
+            return None;
+        }
+
+        let path = context.uri().path();
+        let mut normalized = match normalize(path) {
+            Some(normalized) if normalized == path => return None,
+            Some(normalized) => normalized,
+            None => return Some(Err(Error::from(Bail::new("Bad Request")).status(400))),
+        };
+
+        if let Some(query) = context.uri().query() {
+            normalized.push('?');
+            normalized.push_str(query);
+        }
+
+        if self == NormalizePath::Redirect {
+            return Some("".status(308).header("location", normalized).respond());
+        }
+
+        match normalized.parse::<Uri>() {
+            Ok(uri) => {
+                context.rewrite(uri);
+                None
+            }
+            Err(error) => Some(Err(Error::from(error).status(400))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, NormalizePath};
+    use crate::{middleware::context::Body, Context};
+
+    fn context(uri: &str) -> Context {
+        Context::from(http::Request::get(uri).body(Body::full("".into())).unwrap())
+    }
+
+    #[test]
+    fn normalizes_nasty_paths() {
+        for (path, expected) in [
+            ("/", Some("/")),
+            ("/api/posts", Some("/api/posts")),
+            ("/api/posts/", Some("/api/posts/")),
+            ("//api///posts", Some("/api/posts")),
+            ("/api//", Some("/api/")),
+            ("/api/./posts", Some("/api/posts")),
+            ("/api/posts/.", Some("/api/posts/")),
+            ("/api/../posts", Some("/posts")),
+            ("/api/posts/..", Some("/api/")),
+            ("/api/%2e%2e/posts", Some("/posts")),
+            ("/api/%2E./posts", Some("/posts")),
+            ("/api/.%2E/posts", Some("/posts")),
+            ("/api/%2e/posts", Some("/api/posts")),
+            ("/..", Some("/")),
+            ("/../../etc/passwd", Some("/etc/passwd")),
+            ("/%2e%2e/%2e%2e/etc/passwd", Some("/etc/passwd")),
+            ("//..//..//", Some("/")),
+            ("/files/a%2Fb", Some("/files/a%2Fb")),
+            ("/files/..%2F..%2Fetc", Some("/files/..%2F..%2Fetc")),
+            ("/...", Some("/...")),
+            ("/a/.hidden", Some("/a/.hidden")),
+            ("/a%00b", None),
+            ("/a/%00/../b", None),
+        ] {
+            assert_eq!(normalize(path).as_deref(), expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn rewrites_or_redirects() {
+        let mut rewritten = context("//api/../posts?page=2");
+
+        assert!(NormalizePath::Rewrite.apply(&mut rewritten).is_none());
+        assert_eq!(rewritten.uri(), "/posts?page=2");
+        assert_eq!(rewritten.original_uri(), "//api/../posts?page=2");
+
+        let response = NormalizePath::Redirect
+            .apply(&mut context("//api/../posts?page=2"))
+            .unwrap()
+            .unwrap();
+        let response = http::Response::from(response);
+
+        assert_eq!(response.status(), 308);
+        assert_eq!(response.headers()["location"], "/posts?page=2");
+
+        for policy in [NormalizePath::Rewrite, NormalizePath::Redirect] {
+            assert!(policy.apply(&mut context("/posts")).is_none());
+
+            let rejected = policy.apply(&mut context("/a%00")).unwrap().err().unwrap();
+            assert_eq!(rejected.status_code(), 400);
+        }
+
+        assert!(NormalizePath::Disabled
+            .apply(&mut context("//a/%00"))
+            .is_none());
+    }
+}
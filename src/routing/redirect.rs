@@ -0,0 +1,120 @@
+use http::{StatusCode, Uri};
+
+use crate::{BoxFuture, Context, Middleware, Next, Respond, Result};
+
+pub(crate) struct Redirect {
+    status: StatusCode,
+    target: &'static str,
+}
+
+impl Redirect {
+    pub(crate) fn new(status: StatusCode, target: &'static str) -> Self {
+        if let Err(error) = target.parse::<Uri>() {
+            panic!(r#"invalid redirect target "{}": {}"#, target, error);
+        }
+
+        Redirect { status, target }
+    }
+
+    fn location(&self, context: &Context) -> Result<String> {
+        let (path, query) = match self.target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (self.target, None),
+        };
+        let mut location = String::with_capacity(self.target.len());
+
+        for (index, segment) in path.split('/').enumerate() {
+            if index > 0 {
+                location.push('/');
+            }
+
+            match segment.strip_prefix(':') {
+                Some(name) => location.push_str(&context.params().get::<String>(name)?),
+                None => location.push_str(segment),
+            }
+        }
+
+        let relative = path.starts_with('/') && !path.starts_with("//");
+        let queries: Vec<_> = match context.uri().query() {
+            Some(forwarded) if relative => query.into_iter().chain([forwarded]).collect(),
+            _ => query.into_iter().collect(),
+        };
+
+        if !queries.is_empty() {
+            location.push('?');
+            location.push_str(&queries.join("&"));
+        }
+
+        Ok(location)
+    }
+}
+
+impl Middleware for Redirect {
+    fn call(&self, context: Context, _: Next) -> BoxFuture<Result> {
+        let status = self.status;
+        let location = self.location(&context);
+
+        Box::pin(async move {
+            "".status(status.as_u16())
+                .header("location", location?)
+                .respond()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use crate::{middleware::context::Body, routing::Router, Context};
+
+    async fn call(router: &Router, method: &str, uri: &str) -> (u16, Option<String>) {
+        let request = http::Request::builder().method(method).uri(uri);
+        let mut context = Context::from(request.body(Body::full("".into())).unwrap());
+        let next = router.visit(&mut context);
+        let response = http::Response::from(next.call(context).await.unwrap_or_else(Into::into));
+        let location = response.headers().get("location");
+
+        (
+            response.status().as_u16(),
+            location.map(|value| value.to_str().unwrap().to_owned()),
+        )
+    }
+
+    #[tokio::test]
+    async fn redirects_with_params_and_query() {
+        let mut router = Router::default();
+
+        router
+            .at("/blog")
+            .redirect(StatusCode::MOVED_PERMANENTLY, "/posts");
+        router
+            .at("/old/:id")
+            .redirect(StatusCode::FOUND, "/new/:id?from=old");
+        router
+            .at("/docs")
+            .redirect(StatusCode::MOVED_PERMANENTLY, "https://docs.example.com");
+
+        assert_eq!(
+            call(&router, "GET", "/blog?page=2").await,
+            (301, Some("/posts?page=2".to_owned()))
+        );
+        assert_eq!(
+            call(&router, "HEAD", "/old/42?page=2").await,
+            (302, Some("/new/42?from=old&page=2".to_owned()))
+        );
+        assert_eq!(
+            call(&router, "GET", "/docs?page=2").await,
+            (301, Some("https://docs.example.com".to_owned()))
+        );
+        assert_eq!(call(&router, "POST", "/blog").await.1, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid redirect target")]
+    fn rejects_invalid_targets() {
+        Router::default()
+            .at("/blog")
+            .redirect(StatusCode::FOUND, "/posts with spaces");
+    }
+}
@@ -0,0 +1,113 @@
+use http::Uri;
+
+use crate::{error::Bail, Context, Error, Result};
+
+/// The maximum number of times the URI of a request can be rewritten before
+/// the request is rejected as a rewrite loop.
+const MAX_REWRITES: usize = 8;
+
+type Rewrite = Box<dyn Fn(&mut Context) -> Option<Uri> + Send + Sync>;
+
+#[derive(Clone, Debug)]
+pub(crate) struct OriginalUri(pub(crate) Uri);
+
+#[derive(Default)]
+pub(crate) struct Rewrites(Vec<Rewrite>);
+
+impl Rewrites {
+    pub(crate) fn push<F>(&mut self, rewrite: F)
+    where
+        F: Fn(&mut Context) -> Option<Uri> + Send + Sync + 'static,
+    {
+        self.0.push(Box::new(rewrite));
+    }
+
+    /// Runs the rewrites in the order they were added until none of them
+    /// return a new URI. Every rewrite starts another pass.
+    pub(crate) fn apply(&self, context: &mut Context) -> Result<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+
+        for _ in 0..MAX_REWRITES {
+            match self.0.iter().find_map(|rewrite| rewrite(context)) {
+                Some(uri) => context.rewrite(uri),
+                None => return Ok(()),
+            }
+        }
+
+        Err(Error::from(Bail::new("Too many rewrites")).status(500))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Uri;
+
+    use super::Rewrites;
+    use crate::{middleware::context::Body, routing::Router, Context, Next};
+
+    async fn show(context: Context, _: Next) -> String {
+        context.params().get::<String>("id").unwrap_or_default()
+    }
+
+    fn context(uri: &str) -> Context {
+        Context::from(http::Request::get(uri).body(Body::full("".into())).unwrap())
+    }
+
+    #[derive(Clone)]
+    struct Locale(String);
+
+    fn rewrites() -> Rewrites {
+        let mut rewrites = Rewrites::default();
+
+        rewrites.push(|context: &mut Context| {
+            let uri = context.uri().path_and_query()?.as_str();
+            let (locale, rest) = uri.strip_prefix('/')?.split_once('/')?;
+
+            if locale.len() != 2 || !locale.bytes().all(|byte| byte.is_ascii_lowercase()) {
+                return None;
+            }
+
+            let uri = format!("/{}", rest).parse().ok()?;
+
+            context.insert(Locale(locale.to_owned()));
+            Some(uri)
+        });
+        rewrites.push(|context: &mut Context| {
+            let uri = context.uri().path_and_query()?.as_str();
+            format!("/v2/{}", uri.strip_prefix("/v1/")?).parse().ok()
+        });
+
+        rewrites
+    }
+
+    #[test]
+    fn rewrites_before_routing() {
+        let mut router = Router::default();
+        let mut context = context("/en/v1/posts/1?page=2");
+
+        router.at("/v2/posts/:id").get(show);
+        rewrites().apply(&mut context).unwrap();
+        router.visit(&mut context);
+
+        assert_eq!(context.uri(), "/v2/posts/1?page=2");
+        assert_eq!(context.original_uri(), "/en/v1/posts/1?page=2");
+        assert_eq!(context.params().get::<String>("id").unwrap(), "1");
+        assert_eq!(context.get::<Locale>().unwrap().0, "en");
+    }
+
+    #[test]
+    fn rejects_rewrite_loops() {
+        let mut rewrites = Rewrites::default();
+        let mut context = context("/a");
+
+        rewrites.push(|context: &mut Context| {
+            let next = if context.uri() == "/a" { "/b" } else { "/a" };
+            Some(Uri::from_static(next))
+        });
+
+        assert!(rewrites.apply(&mut context).is_err());
+        assert_eq!(context.original_uri(), "/a");
+    }
+}
@@ -0,0 +1,129 @@
+use std::io;
+use tokio::runtime::{Builder, Runtime};
+
+/// Options for the multi-threaded runtime that `Application::run` builds.
+/// Options that aren't set use the defaults of tokio.
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeOptions {
+    max_blocking_threads: Option<usize>,
+    thread_name: Option<String>,
+    thread_stack_size: Option<usize>,
+    workers: Option<usize>,
+}
+
+impl RuntimeOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The maximum number of threads used for `spawn_blocking`, such as for
+    /// reading files. Defaults to 512.
+    pub fn max_blocking_threads(mut self, max: usize) -> Self {
+        self.max_blocking_threads = Some(max);
+        self
+    }
+
+    pub fn thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = Some(name.into());
+        self
+    }
+
+    pub fn thread_stack_size(mut self, size: usize) -> Self {
+        self.thread_stack_size = Some(size);
+        self
+    }
+
+    /// The number of threads that handle connections. Defaults to the number
+    /// of CPUs.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = Some(workers);
+        self
+    }
+
+    pub(crate) fn build(&self) -> io::Result<Runtime> {
+        let mut builder = Builder::new_multi_thread();
+
+        builder.enable_all();
+
+        if let Some(max) = self.max_blocking_threads {
+            builder.max_blocking_threads(max);
+        }
+
+        if let Some(name) = &self.thread_name {
+            builder.thread_name(name);
+        }
+
+        if let Some(size) = self.thread_stack_size {
+            builder.thread_stack_size(size);
+        }
+
+        if let Some(workers) = self.workers {
+            builder.worker_threads(workers);
+        }
+
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process::ExitCode, thread, time::Duration};
+    use tokio::sync::oneshot;
+
+    use super::RuntimeOptions;
+    use crate::{Context, Next};
+
+    #[test]
+    fn runs_the_application_on_its_own_runtime() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap();
+        let (shutdown, trigger) = oneshot::channel::<()>();
+        let mut app = crate::new();
+
+        app.at("/thread").get(|_: Context, _: Next| async {
+            thread::current().name().unwrap_or_default().to_owned()
+        });
+        app.shutdown_signals(false).with_shutdown(async {
+            let _ = trigger.await;
+        });
+
+        let options = RuntimeOptions::new()
+            .workers(2)
+            .max_blocking_threads(4)
+            .thread_name("via-worker");
+        let running = thread::spawn(move || app.run(address, options));
+        let output = loop {
+            use std::io::{Read, Write};
+
+            let mut stream = match std::net::TcpStream::connect(address) {
+                Ok(stream) => stream,
+                Err(_) => {
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+            };
+            let mut output = String::new();
+
+            stream
+                .write_all(b"GET /thread HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+                .unwrap();
+            stream.read_to_string(&mut output).unwrap();
+            break output;
+        };
+
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\nvia-worker"));
+
+        shutdown.send(()).unwrap();
+        assert_eq!(running.join().unwrap().unwrap(), ExitCode::SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn refuses_to_run_within_a_runtime() {
+        match crate::new().run("127.0.0.1:0", RuntimeOptions::new()) {
+            Ok(_) => panic!("expected run to fail"),
+            Err(error) => assert!(error.to_string().contains("use listen instead")),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::{net::SocketAddr, process::ExitCode};
+use tokio::net::TcpListener;
+
+#[cfg(feature = "rustls")]
+use std::sync::Arc;
+
+use crate::{shutdown::ShutdownHandle, Application, Result};
+
+/// An application with listeners that are bound but not yet accepting
+/// connections. Returned by `Application::bind` so that the addresses are
+/// known before serving, such as the port the OS picked for port 0.
+pub struct Server {
+    addresses: Vec<SocketAddr>,
+    application: Application,
+    listeners: Vec<TcpListener>,
+}
+
+impl Server {
+    pub(crate) fn new(application: Application, listeners: Vec<TcpListener>) -> Result<Self> {
+        let addresses = listeners
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Server {
+            addresses,
+            application,
+            listeners,
+        })
+    }
+
+    /// The address of the first listener.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addresses[0]
+    }
+
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    /// Returns a handle that shuts the server down from another task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.application.shutdown.handle()
+    }
+
+    /// Accepts connections until the server is shut down. See
+    /// `Application::listen`.
+    pub async fn serve(self) -> Result<ExitCode> {
+        self.application.accept(self.listeners).await
+    }
+
+    #[cfg(feature = "rustls")]
+    pub async fn serve_rustls(self, config: Arc<crate::rustls::ServerConfig>) -> Result<ExitCode> {
+        self.application.accept_rustls(self.listeners, config).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::ExitCode;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::{Context, Next};
+
+    #[tokio::test]
+    async fn serves_on_an_ephemeral_port() {
+        let mut app = crate::new();
+
+        app.at("/hello")
+            .get(|_: Context, _: Next| async { "hello" });
+        app.shutdown_signals(false);
+
+        let server = app.bind(("127.0.0.1", 0)).unwrap();
+        let address = server.local_addr();
+        let handle = server.shutdown_handle();
+
+        assert_ne!(address.port(), 0);
+        assert_eq!(server.local_addrs(), [address]);
+
+        let serving = tokio::spawn(server.serve());
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut output = String::new();
+
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        stream.read_to_string(&mut output).await.unwrap();
+
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\nhello"));
+
+        handle.shutdown();
+        assert_eq!(serving.await.unwrap().unwrap(), ExitCode::SUCCESS);
+        assert!(TcpStream::connect(address).await.is_err());
+    }
+
+    #[test]
+    fn fails_to_bind_with_duplicate_route_names() {
+        let mut app = crate::new();
+
+        app.at("/posts").name("posts");
+        app.at("/articles").name("posts");
+
+        let error = app.bind(("127.0.0.1", 0)).err().unwrap();
+
+        assert!(error.to_string().contains("posts"));
+    }
+}
@@ -1,16 +1,70 @@
-use super::{Application, CallFuture, HttpRequest, HttpResponse};
-use futures::future::{ready, Ready};
+use super::{
+    activity::{Activity, Timeouts},
+    Application, HttpRequest, HttpResponse,
+};
+use futures::future::{ready, BoxFuture, FutureExt, Ready};
+use http::header::{HeaderValue, CONNECTION};
 use hyper::service::Service as HyperService;
-use std::{convert, sync::Arc};
+use std::{
+    convert,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
 
 type Result<T = ()> = crate::Result<T, convert::Infallible>;
 
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectionInfo {
+    local_addr: Option<SocketAddr>,
+    opened_at: Instant,
+    remote_addr: Option<SocketAddr>,
+    requests: u64,
+}
+
 pub struct MakeService {
     service: Service,
 }
 
 pub struct Service {
+    activity: Option<Arc<Activity>>,
     application: Arc<Application>,
+    exhausted: Arc<Notify>,
+    extensions: http::Extensions,
+    local_addr: Option<SocketAddr>,
+    opened_at: Instant,
+    remote_addr: Option<SocketAddr>,
+    requests: AtomicU64,
+}
+
+impl ConnectionInfo {
+    pub fn age(&self) -> Duration {
+        self.opened_at.elapsed()
+    }
+
+    /// The address the connection was accepted on. Useful to tell listeners
+    /// apart when `listen` binds several addresses.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    pub fn opened_at(&self) -> Instant {
+        self.opened_at
+    }
+
+    /// The address of the peer. This is the last proxy rather than the client
+    /// when the application is deployed behind one.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
 }
 
 impl From<Application> for MakeService {
@@ -27,32 +81,223 @@ impl<T> HyperService<T> for MakeService {
     type Response = Service;
 
     fn call(&self, _: T) -> Self::Future {
-        ready(Ok(self.service.clone()))
+        ready(Ok(self.service.connect(None, None)))
     }
 }
 
-impl Clone for Service {
-    fn clone(&self) -> Self {
+impl Service {
+    pub(crate) fn connect(
+        &self,
+        local_addr: Option<SocketAddr>,
+        remote_addr: Option<SocketAddr>,
+    ) -> Self {
         Service {
+            activity: None,
             application: Arc::clone(&self.application),
+            exhausted: Default::default(),
+            extensions: self.extensions.clone(),
+            local_addr,
+            opened_at: Instant::now(),
+            remote_addr,
+            requests: AtomicU64::new(0),
         }
     }
+
+    pub(crate) fn insert<T>(&mut self, value: T)
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.extensions.insert(value);
+    }
+
+    pub(crate) fn events(&self) -> crate::Events {
+        self.application.events.clone()
+    }
+
+    /// Notified once the connection reached its maximum number of requests
+    /// or age, so that it can be shut down gracefully. A `Connection: close`
+    /// header is enough for HTTP/1.1, but HTTP/2 needs a GOAWAY frame.
+    pub(crate) fn exhausted(&self) -> Arc<Notify> {
+        Arc::clone(&self.exhausted)
+    }
+
+    pub(crate) fn http2(&self) -> &crate::Http2Options {
+        &self.application.http2
+    }
+
+    pub(crate) fn timeouts(&self) -> Timeouts {
+        self.application.limits.timeouts
+    }
+
+    /// Marks the connection busy while each of its requests is handled.
+    pub(crate) fn track(&mut self, activity: Arc<Activity>) {
+        self.activity = Some(activity);
+    }
+
+    fn is_exhausted(&self, info: &ConnectionInfo) -> bool {
+        let limits = &self.application.limits;
+
+        limits.max_requests.is_some_and(|max| info.requests >= max)
+            || limits.max_age.is_some_and(|max| info.age() >= max)
+    }
 }
 
 impl From<Application> for Service {
     fn from(application: Application) -> Self {
         Service {
+            activity: None,
             application: Arc::new(application),
+            exhausted: Default::default(),
+            extensions: Default::default(),
+            local_addr: None,
+            opened_at: Instant::now(),
+            remote_addr: None,
+            requests: AtomicU64::new(0),
         }
     }
 }
 
 impl HyperService<HttpRequest> for Service {
     type Error = convert::Infallible;
-    type Future = CallFuture;
+    type Future = BoxFuture<'static, Result<HttpResponse>>;
     type Response = HttpResponse;
 
-    fn call(&self, request: HttpRequest) -> Self::Future {
-        self.application.call(request)
+    fn call(&self, mut request: HttpRequest) -> Self::Future {
+        let info = ConnectionInfo {
+            local_addr: self.local_addr,
+            opened_at: self.opened_at,
+            remote_addr: self.remote_addr,
+            requests: self.requests.fetch_add(1, Ordering::Relaxed) + 1,
+        };
+        let extensions = request.extensions_mut();
+
+        extensions.extend(self.extensions.clone());
+        extensions.insert(info);
+
+        let busy = self.activity.as_ref().map(Activity::busy);
+        let exhausted = self.is_exhausted(&info);
+
+        if exhausted {
+            self.exhausted.notify_one();
+        }
+
+        Box::pin(self.application.call(request).map(move |result| {
+            drop(busy);
+            result.map(|mut response| {
+                if exhausted {
+                    let value = HeaderValue::from_static("close");
+                    response.headers_mut().insert(CONNECTION, value);
+                }
+
+                response
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http_body_util::Empty;
+    use hyper::client::conn::http2;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use std::{net::SocketAddr, time::Duration};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::{Application, Context, Next};
+
+    const REQUEST: &[u8] = b"GET /hello HTTP/1.1\r\nhost: localhost\r\n\r\n";
+
+    fn serve(mut app: Application) -> SocketAddr {
+        app.at("/hello")
+            .get(|_: Context, _: Next| async { "hello" });
+        app.shutdown_signals(false);
+
+        let server = app.bind(("127.0.0.1", 0)).unwrap();
+        let address = server.local_addr();
+
+        tokio::spawn(server.serve());
+        address
+    }
+
+    /// Reads the next response on `stream`, which has a body of `hello`.
+    async fn response(stream: &mut TcpStream) -> String {
+        let mut output = Vec::new();
+
+        while !output.ends_with(b"\r\n\r\nhello") {
+            let mut buffer = [0; 1024];
+            let len = stream.read(&mut buffer).await.unwrap();
+
+            assert_ne!(len, 0, "the connection closed early");
+            output.extend_from_slice(&buffer[..len]);
+        }
+
+        String::from_utf8(output).unwrap().to_ascii_lowercase()
+    }
+
+    #[tokio::test]
+    async fn closes_after_the_max_requests() {
+        let mut app = crate::new();
+
+        app.max_requests_per_connection(2);
+
+        let mut stream = TcpStream::connect(serve(app)).await.unwrap();
+
+        stream.write_all(REQUEST).await.unwrap();
+        assert!(!response(&mut stream).await.contains("connection: close"));
+
+        stream.write_all(REQUEST).await.unwrap();
+        assert!(response(&mut stream).await.contains("connection: close"));
+        assert_eq!(stream.read(&mut [0; 1]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn closes_after_the_max_age() {
+        let mut app = crate::new();
+
+        app.max_connection_age(Duration::from_millis(100));
+
+        let mut stream = TcpStream::connect(serve(app)).await.unwrap();
+
+        stream.write_all(REQUEST).await.unwrap();
+        assert!(!response(&mut stream).await.contains("connection: close"));
+
+        // The age is checked when the next request arrives.
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        stream.write_all(REQUEST).await.unwrap();
+        assert!(response(&mut stream).await.contains("connection: close"));
+        assert_eq!(stream.read(&mut [0; 1]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn sends_goaway_after_the_max_requests() {
+        let mut app = crate::new();
+
+        app.http2_cleartext(true).max_requests_per_connection(2);
+
+        let stream = TcpStream::connect(serve(app)).await.unwrap();
+        let (mut sender, connection) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+        let connection = tokio::spawn(connection);
+
+        for _ in 0..2 {
+            let request = http::Request::get("http://localhost/hello")
+                .body(Empty::<Bytes>::new())
+                .unwrap();
+
+            sender.ready().await.unwrap();
+            assert_eq!(sender.send_request(request).await.unwrap().status(), 200);
+        }
+
+        // The server closes the connection after its GOAWAY frame.
+        let closed = tokio::time::timeout(Duration::from_secs(1), connection).await;
+
+        assert!(closed.unwrap().unwrap().is_ok());
+        assert!(sender.is_closed());
     }
 }
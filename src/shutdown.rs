@@ -0,0 +1,452 @@
+use futures::future::{pending, BoxFuture, Future};
+use std::{
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{watch, Notify},
+    task::JoinSet,
+};
+
+use crate::{Events, ServerEvent};
+
+type Hook = Box<dyn FnOnce() -> BoxFuture<'static, crate::Result<()>> + Send>;
+
+/// Stops `listen` from accepting connections and drains the ones that are
+/// open when a signal is received or the `with_shutdown` future completes.
+pub(crate) struct Shutdown {
+    pub(crate) hook_timeout: Duration,
+    pub(crate) signals: bool,
+    pub(crate) timeout: Duration,
+    hooks: Mutex<Vec<Hook>>,
+    stop: Arc<Notify>,
+    trigger: Mutex<Option<BoxFuture<'static, ()>>>,
+}
+
+/// Shuts down a `Server` from another task.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    stop: Arc<Notify>,
+}
+
+/// The hooks that run once the connections of a server are drained.
+pub(crate) struct Hooks {
+    events: Events,
+    hooks: Vec<Hook>,
+    timeout: Duration,
+}
+
+/// Tracks the connections of a server so they can be drained.
+pub(crate) struct Connections {
+    events: Events,
+    sender: watch::Sender<bool>,
+    tasks: JoinSet<()>,
+}
+
+impl Shutdown {
+    pub(crate) fn handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            stop: Arc::clone(&self.stop),
+        }
+    }
+
+    pub(crate) fn push_hook<F, T>(&mut self, hook: F)
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Future<Output = crate::Result<()>> + Send + 'static,
+    {
+        let hook: Hook = Box::new(move || Box::pin(hook()));
+        self.hooks.get_mut().unwrap().push(hook);
+    }
+
+    /// Takes the hooks, which report their failures to `events`.
+    pub(crate) fn hooks(&self, events: Events) -> Hooks {
+        Hooks {
+            events,
+            hooks: std::mem::take(&mut *self.hooks.lock().unwrap()),
+            timeout: self.hook_timeout,
+        }
+    }
+
+    pub(crate) fn set_trigger<F>(&mut self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        *self.trigger.get_mut().unwrap() = Some(Box::pin(future));
+    }
+
+    /// Resolves when the server should stop accepting connections. Failing to
+    /// listen for signals is reported to `events`.
+    pub(crate) fn requested(&self, events: Events) -> impl Future<Output = ()> {
+        let signals = self.signals;
+        let stop = Arc::clone(&self.stop);
+        let trigger = self.trigger.lock().unwrap().take();
+
+        async move {
+            let trigger = async {
+                match trigger {
+                    Some(trigger) => trigger.await,
+                    None => pending().await,
+                }
+            };
+
+            tokio::select! {
+                _ = stop.notified() => {}
+                _ = trigger => {}
+                _ = signal(&events), if signals => {}
+            }
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown {
+            hook_timeout: Duration::from_secs(10),
+            signals: true,
+            timeout: Duration::from_secs(30),
+            hooks: Mutex::new(Vec::new()),
+            stop: Arc::new(Notify::new()),
+            trigger: Mutex::new(None),
+        }
+    }
+}
+
+impl ShutdownHandle {
+    /// Stops the server from accepting connections and drains the ones that
+    /// are open, as if a shutdown signal was received. Takes effect once the
+    /// server is served if it isn't yet.
+    pub fn shutdown(&self) {
+        self.stop.notify_one();
+    }
+}
+
+impl Hooks {
+    /// Runs the hooks in the order they were added. Returns a failure if
+    /// `drained` is one or if any hook fails or doesn't finish in time.
+    pub(crate) async fn run(self, drained: ExitCode) -> ExitCode {
+        let mut code = drained;
+
+        for hook in self.hooks {
+            self.events
+                .report(match tokio::time::timeout(self.timeout, hook()).await {
+                    Ok(Ok(())) => continue,
+                    Ok(Err(error)) => ServerEvent::ShutdownHookFailed { error },
+                    Err(_) => ServerEvent::ShutdownHookTimedOut,
+                });
+
+            code = ExitCode::FAILURE;
+        }
+
+        code
+    }
+}
+
+impl Connections {
+    /// Creates an empty set of connections that reports the ones it has to
+    /// abort to `events`.
+    pub(crate) fn new(events: Events) -> Self {
+        Connections {
+            events,
+            sender: watch::channel(false).0,
+            tasks: JoinSet::new(),
+        }
+    }
+
+    /// Spawns `serve` with a receiver that is notified when the server shuts
+    /// down. Connections should finish the response in flight and close.
+    pub(crate) fn spawn<F, T>(&mut self, serve: F)
+    where
+        F: FnOnce(watch::Receiver<bool>) -> T,
+        T: Future<Output = ()> + Send + 'static,
+    {
+        // Forget the connections that have already closed.
+        while self.tasks.try_join_next().is_some() {}
+        self.tasks.spawn(serve(self.sender.subscribe()));
+    }
+
+    /// Asks every connection to close once its response in flight is sent,
+    /// waiting up to `timeout` before aborting the ones that are left.
+    pub(crate) async fn drain(mut self, timeout: Duration) -> ExitCode {
+        self.sender.send_replace(true);
+
+        let drained = tokio::time::timeout(timeout, async {
+            while self.tasks.join_next().await.is_some() {}
+        })
+        .await;
+
+        if drained.is_ok() {
+            return ExitCode::SUCCESS;
+        }
+
+        self.events.report(ServerEvent::ConnectionsAborted {
+            count: self.tasks.len(),
+        });
+        self.tasks.shutdown().await;
+        ExitCode::FAILURE
+    }
+}
+
+async fn signal(events: &Events) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(error) => {
+                events.report(ServerEvent::SignalListenFailed { error });
+                return pending().await;
+            }
+        };
+
+        tokio::select! {
+            Ok(()) = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    if let Err(error) = tokio::signal::ctrl_c().await {
+        events.report(ServerEvent::SignalListenFailed { error });
+        pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        process::ExitCode,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::{mpsc, oneshot, Notify},
+        task::JoinHandle,
+    };
+
+    use crate::{error::Bail, Application, Context, Error, Next, Result, ServerEvent};
+
+    struct Server {
+        address: std::net::SocketAddr,
+        events: Arc<Mutex<Vec<String>>>,
+        listening: JoinHandle<Result<ExitCode>>,
+        received: Arc<Notify>,
+        shutdown: oneshot::Sender<()>,
+    }
+
+    /// Listens with an endpoint that takes `delay` to respond.
+    async fn start(delay: Duration, timeout: Duration) -> Server {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let received = Arc::new(Notify::new());
+        let (shutdown, trigger) = oneshot::channel();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut app = crate::new();
+        let notify = Arc::clone(&received);
+
+        app.at("/slow").get(move |_: Context, _: Next| {
+            let notify = Arc::clone(&notify);
+
+            async move {
+                notify.notify_one();
+                tokio::time::sleep(delay).await;
+                Ok::<_, crate::Error>("done")
+            }
+        });
+        app.on_event({
+            let events = Arc::clone(&events);
+            move |event| events.lock().unwrap().push(event.to_string())
+        });
+        app.shutdown_signals(false)
+            .shutdown_timeout(timeout)
+            .with_shutdown(async {
+                let _ = trigger.await;
+            });
+
+        let listening = tokio::spawn(app.listen_on(listener));
+
+        Server {
+            address,
+            events,
+            listening,
+            received,
+            shutdown,
+        }
+    }
+
+    async fn request(address: std::net::SocketAddr) -> TcpStream {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        stream
+    }
+
+    #[tokio::test]
+    async fn drains_responses_in_flight() {
+        let server = start(Duration::from_millis(200), Duration::from_secs(5)).await;
+        let mut stream = request(server.address).await;
+        let mut output = String::new();
+
+        server.received.notified().await;
+        server.shutdown.send(()).unwrap();
+        stream.read_to_string(&mut output).await.unwrap();
+
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\ndone"));
+        assert_eq!(server.listening.await.unwrap().unwrap(), ExitCode::SUCCESS);
+        assert!(server.events.lock().unwrap().is_empty());
+        assert!(TcpStream::connect(server.address).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn aborts_connections_after_the_timeout() {
+        let server = start(Duration::from_secs(60), Duration::from_millis(50)).await;
+        let mut stream = request(server.address).await;
+        let mut output = String::new();
+
+        server.received.notified().await;
+        server.shutdown.send(()).unwrap();
+
+        assert_eq!(server.listening.await.unwrap().unwrap(), ExitCode::FAILURE);
+        assert_eq!(
+            *server.events.lock().unwrap(),
+            ["Aborting 1 connection(s) that did not close in time"]
+        );
+        let _ = stream.read_to_string(&mut output).await;
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn listens_on_every_address() {
+        let addresses = [(); 2].map(|_| {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        });
+        let (shutdown, trigger) = oneshot::channel::<()>();
+        let mut app = crate::new();
+
+        app.at("/").get(|_: Context, _: Next| async { "hello" });
+        app.shutdown_signals(false).with_shutdown(async {
+            let _ = trigger.await;
+        });
+
+        let listening = tokio::spawn(async move { app.listen(&addresses[..]).await });
+
+        for address in &addresses {
+            let mut stream = loop {
+                match TcpStream::connect(address).await {
+                    Ok(stream) => break stream,
+                    Err(_) => tokio::task::yield_now().await,
+                }
+            };
+            let mut output = String::new();
+
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            stream.read_to_string(&mut output).await.unwrap();
+            assert!(output.ends_with("\r\n\r\nhello"));
+        }
+
+        shutdown.send(()).unwrap();
+        assert_eq!(listening.await.unwrap().unwrap(), ExitCode::SUCCESS);
+
+        for address in &addresses {
+            assert!(TcpStream::connect(address).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_if_any_address_is_taken() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let free = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap();
+        let addresses = [free, taken.local_addr().unwrap()];
+
+        assert!(crate::new().listen(&addresses[..]).await.is_err());
+        assert!(TcpStream::connect(free).await.is_err());
+    }
+
+    /// Listens until shutdown, returning the exit code and what the hooks
+    /// added by `configure` sent.
+    async fn hooks(
+        configure: impl FnOnce(&mut Application, mpsc::UnboundedSender<&'static str>),
+    ) -> (ExitCode, Vec<&'static str>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (shutdown, trigger) = oneshot::channel::<()>();
+        let mut app = crate::new();
+        let mut sent = Vec::new();
+
+        configure(&mut app, sender);
+        app.shutdown_signals(false).with_shutdown(async {
+            let _ = trigger.await;
+        });
+
+        let listening = tokio::spawn(app.listen_on(listener));
+
+        shutdown.send(()).unwrap();
+
+        let code = listening.await.unwrap().unwrap();
+
+        while let Ok(message) = receiver.try_recv() {
+            sent.push(message);
+        }
+
+        (code, sent)
+    }
+
+    #[tokio::test]
+    async fn runs_hooks_in_order_before_returning() {
+        let (code, sent) = hooks(|app, sender| {
+            for name in ["pool", "scheduler"] {
+                let sender = sender.clone();
+
+                app.on_shutdown(move || async move {
+                    sender.send(name).unwrap();
+                    Ok(())
+                });
+            }
+        })
+        .await;
+
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(sent, ["pool", "scheduler"]);
+    }
+
+    #[tokio::test]
+    async fn fails_if_a_hook_fails_or_hangs() {
+        let (code, sent) = hooks(|app, sender| {
+            let events = sender.clone();
+
+            app.on_event(move |event| match event {
+                ServerEvent::ShutdownHookFailed { error } => {
+                    assert_eq!(error.to_string(), "broker is gone");
+                    events.send("failed").unwrap();
+                }
+                ServerEvent::ShutdownHookTimedOut => events.send("timed out").unwrap(),
+                _ => {}
+            });
+            app.shutdown_hook_timeout(Duration::from_millis(50))
+                .on_shutdown(futures::future::pending)
+                .on_shutdown(|| async { Err(Error::from(Bail::new("broker is gone"))) })
+                .on_shutdown(move || async move {
+                    sender.send("flushed").unwrap();
+                    Ok(())
+                });
+        })
+        .await;
+
+        assert_eq!(code, ExitCode::FAILURE);
+        assert_eq!(sent, ["timed out", "failed", "flushed"]);
+    }
+}
@@ -0,0 +1,209 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{io, net::SocketAddr};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+/// Options for the sockets that `listen` binds and the connections it
+/// accepts. Only `nodelay` applies to a listener passed to `listen_on`,
+/// since the others must be set before the socket is bound.
+#[derive(Clone, Debug)]
+pub struct TcpOptions {
+    backlog: u32,
+    nodelay: bool,
+    recv_buffer_size: Option<u32>,
+    #[cfg(all(
+        unix,
+        not(target_os = "solaris"),
+        not(target_os = "illumos"),
+        not(target_os = "cygwin"),
+        not(target_os = "nuttx"),
+    ))]
+    reuse_port: bool,
+    send_buffer_size: Option<u32>,
+}
+
+impl TcpOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The number of connections that can wait to be accepted. Defaults to
+    /// 1024.
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Sets `TCP_NODELAY` on accepted connections so that small writes are
+    /// sent without waiting to be coalesced.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    pub fn recv_buffer_size(mut self, size: u32) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets `SO_REUSEPORT` so that several processes can listen on the same
+    /// address, with the kernel balancing connections between them.
+    #[cfg(all(
+        unix,
+        not(target_os = "solaris"),
+        not(target_os = "illumos"),
+        not(target_os = "cygwin"),
+        not(target_os = "nuttx"),
+    ))]
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = reuse_port;
+        self
+    }
+
+    pub fn send_buffer_size(mut self, size: u32) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    pub(crate) fn accepted(&self, stream: &TcpStream) -> io::Result<()> {
+        if self.nodelay {
+            stream.set_nodelay(true)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn bind(&self, address: SocketAddr) -> io::Result<TcpListener> {
+        let socket = self.socket(address)?;
+
+        socket.bind(address)?;
+        socket.listen(self.backlog)
+    }
+
+    fn socket(&self, address: SocketAddr) -> io::Result<TcpSocket> {
+        let socket = if address.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            // Without IPV6_V6ONLY, a socket bound to `[::]` also claims the
+            // IPv4 port on Linux, so `0.0.0.0` and `[::]` couldn't both be
+            // bound on the same port.
+            let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+
+            socket.set_only_v6(true)?;
+            socket.set_nonblocking(true)?;
+            TcpSocket::from_std_stream(socket.into())
+        };
+
+        // Matches TcpListener::bind, so that a restarted server can bind an
+        // address that still has connections in TIME_WAIT.
+        #[cfg(not(windows))]
+        socket.set_reuseaddr(true)?;
+
+        #[cfg(all(
+            unix,
+            not(target_os = "solaris"),
+            not(target_os = "illumos"),
+            not(target_os = "cygwin"),
+            not(target_os = "nuttx"),
+        ))]
+        if self.reuse_port {
+            socket.set_reuseport(true)?;
+        }
+
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        Ok(socket)
+    }
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        TcpOptions {
+            backlog: 1024,
+            nodelay: false,
+            recv_buffer_size: None,
+            #[cfg(all(
+                unix,
+                not(target_os = "solaris"),
+                not(target_os = "illumos"),
+                not(target_os = "cygwin"),
+                not(target_os = "nuttx"),
+            ))]
+            reuse_port: false,
+            send_buffer_size: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpStream;
+
+    use super::TcpOptions;
+
+    #[tokio::test]
+    async fn configures_the_socket() {
+        let address = "127.0.0.1:0".parse().unwrap();
+        let options = TcpOptions::new()
+            .recv_buffer_size(64 * 1024)
+            .send_buffer_size(64 * 1024);
+        let socket = options.socket(address).unwrap();
+
+        // Linux doubles buffer sizes to leave room for bookkeeping.
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+
+        #[cfg(not(windows))]
+        assert!(socket.reuseaddr().unwrap());
+
+        #[cfg(target_os = "linux")]
+        {
+            let options = options.reuse_port(true);
+
+            assert!(options.socket(address).unwrap().reuseport().unwrap());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn shares_a_port_between_listeners() {
+        let options = TcpOptions::new().reuse_port(true);
+        let first = options.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = first.local_addr().unwrap();
+
+        assert!(options.bind(address).is_ok());
+        assert!(TcpOptions::new().bind(address).is_err());
+    }
+
+    #[tokio::test]
+    async fn binds_both_families_on_the_same_port() {
+        let options = TcpOptions::new();
+        let v4 = options.bind("0.0.0.0:0".parse().unwrap()).unwrap();
+        let port = v4.local_addr().unwrap().port();
+        let v6 = options.bind(format!("[::]:{}", port).parse().unwrap());
+
+        assert_eq!(v6.unwrap().local_addr().unwrap().port(), port);
+    }
+
+    #[tokio::test]
+    async fn sets_nodelay_on_accepted_streams() {
+        let listener = TcpOptions::new()
+            .bind("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        TcpOptions::new().accepted(&stream).unwrap();
+        assert!(!stream.nodelay().unwrap());
+
+        TcpOptions::new().nodelay(true).accepted(&stream).unwrap();
+        assert!(stream.nodelay().unwrap());
+    }
+}